@@ -0,0 +1,253 @@
+//! Actors: state owned by a task, changed only by the messages sent to its mailbox.
+
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+
+use futures::channel::oneshot;
+use wasm_bindgen::JsValue;
+
+use crate::channel::mpsc;
+use crate::task::{self, panic, r#async::JoinHandle};
+
+const DEFAULT_MAILBOX: usize = 64;
+
+/// A component whose state is only touched by the task running it, one message at a
+/// time, so that it needs no locks.
+///
+/// ```ignore
+/// struct Counter(u64);
+///
+/// enum Message {
+///     Add(u64),
+///     Get(Reply<u64>),
+/// }
+///
+/// impl Actor for Counter {
+///     type Message = Message;
+///
+///     async fn handle(&mut self, message: Message) {
+///         match message {
+///             Message::Add(n) => self.0 += n,
+///             Message::Get(reply) => reply.send(self.0),
+///         }
+///     }
+/// }
+///
+/// let (counter, _) = actor::spawn(Counter(0));
+/// counter.send(Message::Add(2)).await?;
+/// let total = counter.call(Message::Get).await?;
+/// ```
+pub trait Actor: 'static {
+    type Message: 'static;
+
+    /// Handles a message. The next one isn't received before the returned future
+    /// completes.
+    fn handle(&mut self, message: Self::Message) -> impl Future<Output = ()>;
+
+    /// Called once the mailbox is closed and drained, before the task completes.
+    fn stopped(&mut self) -> impl Future<Output = ()> {
+        async {}
+    }
+}
+
+/// Runs `actor` as a task on the runtime's workers, like [`task::spawn`], with a mailbox
+/// of 64 messages.
+///
+/// The actor stops once every [`Addr`] is dropped and the messages left in its mailbox
+/// are handled. The returned handle then completes with the actor, so that its final
+/// state can be read back.
+#[track_caller]
+pub fn spawn<A: Actor>(actor: A) -> (Addr<A>, JoinHandle<A>) {
+    spawn_with_mailbox(actor, DEFAULT_MAILBOX)
+}
+
+/// Like [`spawn`], with a mailbox of `capacity` messages. Senders wait while it is full.
+///
+/// # Panics
+///
+/// Panics if `capacity` is 0.
+#[track_caller]
+pub fn spawn_with_mailbox<A: Actor>(mut actor: A, capacity: usize) -> (Addr<A>, JoinHandle<A>) {
+    let (tx, mut rx) = mpsc::channel(capacity);
+    let handle = task::spawn(async move {
+        // Drops the messages left when the actor stops, so that the calls they carry fail
+        // instead of waiting forever, even when a panic traps the worker.
+        let close = Arc::new(rx.closer());
+        panic::on_abort({
+            let close = close.clone();
+            move || close()
+        });
+        let _close = CloseOnDrop(close);
+        while let Some(message) = rx.recv().await {
+            actor.handle(message).await;
+        }
+        actor.stopped().await;
+        actor
+    });
+    (Addr { tx }, handle)
+}
+
+struct CloseOnDrop(Arc<dyn Fn()>);
+
+impl Drop for CloseOnDrop {
+    fn drop(&mut self) {
+        (self.0)();
+    }
+}
+
+/// The address of an actor, to send it messages from any task or worker.
+pub struct Addr<A: Actor> {
+    tx: mpsc::Sender<A::Message>,
+}
+
+impl<A: Actor> Addr<A> {
+    /// Sends `message`, waiting while the mailbox is full. Fails once the actor stopped,
+    /// e.g. because it panicked.
+    pub async fn send(&self, message: A::Message) -> Result<(), mpsc::SendError<A::Message>> {
+        self.tx.send(message).await
+    }
+
+    pub fn try_send(&self, message: A::Message) -> Result<(), mpsc::TrySendError<A::Message>> {
+        self.tx.try_send(message)
+    }
+
+    /// Sends the message built by `f` around a [`Reply`], and waits for the actor to
+    /// answer through it.
+    pub async fn call<R>(&self, f: impl FnOnce(Reply<R>) -> A::Message) -> Result<R, Stopped> {
+        let (tx, rx) = oneshot::channel();
+        self.tx.send(f(Reply(tx))).await.map_err(|_| Stopped)?;
+        rx.await.map_err(|_| Stopped)
+    }
+
+    /// Whether the actor stopped, in which case messages can't be sent anymore.
+    pub fn is_stopped(&self) -> bool {
+        self.tx.is_closed()
+    }
+}
+
+impl<A: Actor> Clone for Addr<A> {
+    fn clone(&self) -> Self {
+        Addr {
+            tx: self.tx.clone(),
+        }
+    }
+}
+
+impl<A: Actor> fmt::Debug for Addr<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Addr")
+            .field("stopped", &self.is_stopped())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The sending half of an answer to an [`Addr::call`], carried by a message.
+pub struct Reply<R>(oneshot::Sender<R>);
+
+impl<R> Reply<R> {
+    /// Answers the call. Nothing happens if the caller stopped waiting.
+    pub fn send(self, value: R) {
+        self.0.send(value).ok();
+    }
+}
+
+impl<R> fmt::Debug for Reply<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reply").finish_non_exhaustive()
+    }
+}
+
+/// Error of an [`Addr::call`] whose actor stopped before answering, or dropped the
+/// [`Reply`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stopped;
+
+impl fmt::Display for Stopped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "actor stopped without answering")
+    }
+}
+
+impl std::error::Error for Stopped {}
+
+impl From<Stopped> for JsValue {
+    fn from(err: Stopped) -> Self {
+        js_sys::Error::new(&err.to_string()).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    struct Counter {
+        total: u64,
+        stopped: bool,
+    }
+
+    enum Message {
+        Add(u64),
+        Get(Reply<u64>),
+        Panic,
+    }
+
+    impl Actor for Counter {
+        type Message = Message;
+
+        async fn handle(&mut self, message: Message) {
+            match message {
+                Message::Add(n) => self.total += n,
+                Message::Get(reply) => reply.send(self.total),
+                Message::Panic => panic!("boom"),
+            }
+        }
+
+        async fn stopped(&mut self) {
+            self.stopped = true;
+        }
+    }
+
+    fn counter() -> Counter {
+        Counter {
+            total: 0,
+            stopped: false,
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_messages_from_several_workers() {
+        let (addr, handle) = spawn_with_mailbox(counter(), 4);
+        let senders = (0..4)
+            .map(|_| {
+                let addr = addr.clone();
+                task::spawn(async move {
+                    for n in 1..=10 {
+                        addr.send(Message::Add(n)).await.unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for sender in senders {
+            sender.await.unwrap();
+        }
+        assert_eq!(addr.call(Message::Get).await, Ok(220));
+        drop(addr);
+        let counter = handle.await.unwrap();
+        assert_eq!(counter.total, 220);
+        assert!(counter.stopped);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_call_fails_once_stopped() {
+        let (addr, handle) = spawn(counter());
+        addr.send(Message::Panic).await.unwrap();
+        assert!(handle.await.is_err());
+        assert_eq!(addr.call(Message::Get).await, Err(Stopped));
+        assert!(addr.is_stopped());
+    }
+}
@@ -0,0 +1,11 @@
+#[cfg(feature = "alloc-accounting")]
+mod accounting;
+mod arena;
+
+#[cfg(feature = "alloc-accounting")]
+pub use accounting::{
+    current_task_allocations, task_allocations, AccountingAllocator, TaskAllocations, TaskKind,
+};
+#[cfg(feature = "alloc-accounting")]
+pub(crate) use accounting::{Accounted, TaskMemory};
+pub use arena::{ArenaBuf, SharedArena, SharedBuf};
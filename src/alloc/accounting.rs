@@ -0,0 +1,240 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::Pin;
+use std::ptr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+
+/// A global allocator attributing allocations to the task that performs them.
+///
+/// Install it with `#[global_allocator]` to get per-task figures from
+/// [`task_allocations`]. Bytes are accounted to whichever task is being polled (or
+/// whichever blocking closure is running) when the allocation or deallocation happens,
+/// so memory freed by another task than the one that allocated it is only approximated.
+pub struct AccountingAllocator<A = System> {
+    inner: A,
+}
+
+impl<A> AccountingAllocator<A> {
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for AccountingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        if !ptr.is_null() {
+            with_current(|memory| memory.record_alloc(layout.size()));
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            with_current(|memory| memory.record_alloc(layout.size()));
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+        with_current(|memory| memory.record_dealloc(layout.size()));
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            with_current(|memory| {
+                memory.record_dealloc(layout.size());
+                memory.record_alloc(new_size);
+            });
+        }
+        new_ptr
+    }
+}
+
+thread_local! {
+    // A raw pointer rather than an `Arc` so that reading it from the allocator never
+    // allocates nor runs destructors.
+    static CURRENT: Cell<*const TaskMemory> = const { Cell::new(ptr::null()) };
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static TASKS: Mutex<Vec<Weak<TaskMemory>>> = Mutex::new(Vec::new());
+
+fn with_current(f: impl FnOnce(&TaskMemory)) {
+    let current = CURRENT.try_with(Cell::get).unwrap_or(ptr::null());
+    // SAFETY: `CURRENT` is only set while the `Arc` it points into is kept alive by the
+    // enclosing `enter` call.
+    if let Some(memory) = unsafe { current.as_ref() } {
+        f(memory);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TaskKind {
+    Async,
+    Local,
+    Blocking,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TaskAllocations {
+    pub id: u64,
+    pub kind: TaskKind,
+    /// Total number of bytes allocated by the task so far.
+    pub allocated: usize,
+    /// Bytes allocated minus bytes deallocated by the task.
+    pub live: isize,
+}
+
+pub(crate) struct TaskMemory {
+    id: u64,
+    kind: TaskKind,
+    allocated: AtomicUsize,
+    deallocated: AtomicUsize,
+}
+
+impl TaskMemory {
+    pub(crate) fn register(kind: TaskKind) -> Arc<TaskMemory> {
+        let memory = Arc::new(TaskMemory {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            kind,
+            allocated: AtomicUsize::new(0),
+            deallocated: AtomicUsize::new(0),
+        });
+        let mut tasks = TASKS.lock().unwrap();
+        tasks.retain(|task| task.strong_count() > 0);
+        tasks.push(Arc::downgrade(&memory));
+        memory
+    }
+
+    fn record_alloc(&self, size: usize) {
+        self.allocated.fetch_add(size, Ordering::Relaxed);
+    }
+
+    fn record_dealloc(&self, size: usize) {
+        self.deallocated.fetch_add(size, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> TaskAllocations {
+        let allocated = self.allocated.load(Ordering::Relaxed);
+        let deallocated = self.deallocated.load(Ordering::Relaxed);
+        TaskAllocations {
+            id: self.id,
+            kind: self.kind,
+            allocated,
+            live: allocated as isize - deallocated as isize,
+        }
+    }
+
+    /// Runs `f` with the allocations attributed to this task.
+    pub(crate) fn enter<R>(self: &Arc<Self>, f: impl FnOnce() -> R) -> R {
+        struct Restore(*const TaskMemory);
+
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                CURRENT.with(|current| current.set(self.0));
+            }
+        }
+
+        let _restore = Restore(CURRENT.with(|current| current.replace(Arc::as_ptr(self))));
+        f()
+    }
+}
+
+pub(crate) struct Accounted<F> {
+    future: Pin<Box<F>>,
+    memory: Arc<TaskMemory>,
+}
+
+impl<F: Future> Accounted<F> {
+    pub(crate) fn new(kind: TaskKind, future: F) -> Self {
+        Accounted {
+            future: Box::pin(future),
+            memory: TaskMemory::register(kind),
+        }
+    }
+}
+
+impl<F: Future> Future for Accounted<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        this.memory.enter(|| this.future.as_mut().poll(cx))
+    }
+}
+
+/// Allocation figures of the task currently running on this thread, if any.
+pub fn current_task_allocations() -> Option<TaskAllocations> {
+    let mut snapshot = None;
+    with_current(|memory| snapshot = Some(memory.snapshot()));
+    snapshot
+}
+
+/// Allocation figures of every task that hasn't completed yet, on every worker.
+pub fn task_allocations() -> Vec<TaskAllocations> {
+    TASKS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .map(|memory| memory.snapshot())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::task;
+    use crate::time::sleep;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[global_allocator]
+    static ALLOCATOR: AccountingAllocator = AccountingAllocator::new(System);
+
+    #[wasm_bindgen_test]
+    async fn test_task_allocations() {
+        let handle = task::spawn(async move {
+            let buffer = vec![0u8; 1 << 20];
+            let allocations = current_task_allocations().unwrap();
+            assert_eq!(allocations.kind, TaskKind::Async);
+            assert!(allocations.allocated >= 1 << 20);
+            assert!(allocations.live >= 1 << 20);
+            sleep(Duration::from_millis(100)).await.unwrap();
+            buffer.len()
+        });
+        sleep(Duration::from_millis(50)).await.unwrap();
+        assert!(task_allocations()
+            .iter()
+            .any(|allocations| allocations.live >= 1 << 20));
+        assert_eq!(handle.join().await.unwrap(), 1 << 20);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_blocking_task_allocations() {
+        let handle = task::spawn_blocking(|| {
+            drop(vec![0u8; 1024]);
+            current_task_allocations().unwrap()
+        });
+        let allocations = handle.join().await.unwrap();
+        assert_eq!(allocations.kind, TaskKind::Blocking);
+        assert!(allocations.allocated >= 1024);
+        assert!(allocations.live < 1024);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_no_current_task() {
+        assert_eq!(current_task_allocations(), None);
+    }
+}
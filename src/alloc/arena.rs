@@ -0,0 +1,343 @@
+use std::alloc::{self, Layout};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, Weak};
+
+// Buffers start on this alignment, so that they can be reinterpreted as arrays of any
+// primitive type, e.g. with `js_sys::Float32Array::view`.
+const ALIGN: usize = 16;
+
+/// An arena of shared memory handing out byte buffers that tasks on any worker can read
+/// and write in place, e.g. to pass video frames or sensor batches along a pipeline
+/// without copying them.
+///
+/// Buffers are carved out of fixed-size chunks, one chunk per epoch. The arena moves to
+/// a new epoch when the current chunk is full, or explicitly with
+/// [`advance`](Self::advance), e.g. once per frame. An epoch's chunk is reclaimed as a
+/// whole once every buffer allocated in it has been dropped, typically when the tasks
+/// processing them complete, and is then reused by a later epoch. A single long-lived
+/// buffer keeps its whole epoch alive.
+///
+/// ```ignore
+/// let arena = SharedArena::new(16 << 20);
+/// let mut frame = arena.alloc(width * height * 4);
+/// decode_into(&mut frame);
+/// let frame = frame.into_shared();
+/// task::spawn_blocking(move || encode(&frame));
+/// ```
+#[derive(Clone)]
+pub struct SharedArena {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    chunk_size: usize,
+    state: Mutex<State>,
+}
+
+struct State {
+    epoch: u64,
+    current: Option<Arc<Chunk>>,
+    head: usize,
+    // Chunks of reclaimed epochs, ready to be reused.
+    free: Vec<Memory>,
+}
+
+// A chunk of memory allocated with `ALIGN`.
+struct Memory {
+    ptr: *mut u8,
+    size: usize,
+}
+
+// SAFETY: the memory is only accessed through buffers covering disjoint ranges of it.
+unsafe impl Send for Memory {}
+unsafe impl Sync for Memory {}
+
+impl Memory {
+    fn new(size: usize) -> Memory {
+        let layout = Layout::from_size_align(size.max(1), ALIGN).expect("arena chunk too large");
+        // Zeroed, so that buffers never expose uninitialized bytes.
+        // SAFETY: the layout has a non-zero size.
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        Memory { ptr, size }
+    }
+}
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        let layout = Layout::from_size_align(self.size.max(1), ALIGN).unwrap();
+        // SAFETY: allocated with the same layout in `Memory::new`.
+        unsafe { alloc::dealloc(self.ptr, layout) };
+    }
+}
+
+// The memory of an epoch, kept alive by the buffers allocated in it and by the arena
+// while the epoch is current.
+struct Chunk {
+    memory: Option<Memory>,
+    epoch: u64,
+    arena: Weak<Inner>,
+}
+
+impl Drop for Chunk {
+    fn drop(&mut self) {
+        let (Some(memory), Some(arena)) = (self.memory.take(), self.arena.upgrade()) else {
+            return;
+        };
+        // Oversized chunks made for a single buffer aren't worth keeping.
+        if memory.size == arena.chunk_size {
+            arena.state.lock().unwrap().free.push(memory);
+        }
+    }
+}
+
+impl SharedArena {
+    /// Creates an arena allocating memory in chunks of `chunk_size` bytes. Buffers larger
+    /// than that get a chunk and an epoch of their own.
+    pub fn new(chunk_size: usize) -> Self {
+        SharedArena {
+            inner: Arc::new(Inner {
+                chunk_size,
+                state: Mutex::new(State {
+                    epoch: 0,
+                    current: None,
+                    head: 0,
+                    free: Vec::new(),
+                }),
+            }),
+        }
+    }
+
+    /// Allocates a buffer of `len` bytes in the current epoch, moving to the next epoch
+    /// if it doesn't fit in the current chunk.
+    ///
+    /// The buffer holds whatever the reused memory held before: zeroes the first time,
+    /// data of a reclaimed epoch afterwards.
+    pub fn alloc(&self, len: usize) -> ArenaBuf {
+        let mut state = self.inner.state.lock().unwrap();
+        let offset = state.head.next_multiple_of(ALIGN);
+        let fits = state.current.is_some() && offset + len <= self.inner.chunk_size;
+        // Dropped once the lock is released, since it may be reclaimed right away.
+        let mut previous = None;
+        let offset = if fits {
+            offset
+        } else {
+            let reused = if len <= self.inner.chunk_size {
+                state.free.pop()
+            } else {
+                None
+            };
+            let memory = reused.unwrap_or_else(|| Memory::new(self.inner.chunk_size.max(len)));
+            state.epoch += 1;
+            let chunk = Arc::new(Chunk {
+                memory: Some(memory),
+                epoch: state.epoch,
+                arena: Arc::downgrade(&self.inner),
+            });
+            previous = state.current.replace(chunk);
+            0
+        };
+        state.head = offset + len;
+        let chunk = state.current.clone().unwrap();
+        drop(state);
+        drop(previous);
+        // SAFETY: `offset + len` is within the chunk, and no other buffer covers this
+        // range until the chunk is reclaimed, which this buffer prevents.
+        let ptr = unsafe { chunk.memory.as_ref().unwrap().ptr.add(offset) };
+        ArenaBuf { chunk, ptr, len }
+    }
+
+    /// Ends the current epoch: later allocations go to a new chunk, and the current one
+    /// is reclaimed as soon as its buffers are dropped.
+    pub fn advance(&self) {
+        let current = self.inner.state.lock().unwrap().current.take();
+        drop(current);
+    }
+
+    /// The epoch buffers are currently allocated in. Epochs start at 1 and only grow.
+    pub fn epoch(&self) -> u64 {
+        let state = self.inner.state.lock().unwrap();
+        match state.current {
+            Some(_) => state.epoch,
+            None => state.epoch + 1,
+        }
+    }
+
+    /// Number of reclaimed chunks waiting to be reused.
+    pub fn free_chunks(&self) -> usize {
+        self.inner.state.lock().unwrap().free.len()
+    }
+}
+
+impl std::fmt::Debug for SharedArena {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedArena")
+            .field("chunk_size", &self.inner.chunk_size)
+            .field("epoch", &self.epoch())
+            .finish_non_exhaustive()
+    }
+}
+
+/// A buffer allocated from a [`SharedArena`], owned by a single task at a time.
+///
+/// It can be sent to another worker as is, split into disjoint parts written by several
+/// workers at once with [`split_at`](Self::split_at), or turned into a read-only
+/// [`SharedBuf`] for many readers.
+pub struct ArenaBuf {
+    chunk: Arc<Chunk>,
+    ptr: *mut u8,
+    len: usize,
+}
+
+// SAFETY: the buffer has exclusive access to its range of the chunk.
+unsafe impl Send for ArenaBuf {}
+unsafe impl Sync for ArenaBuf {}
+
+impl ArenaBuf {
+    /// The epoch the buffer was allocated in.
+    pub fn epoch(&self) -> u64 {
+        self.chunk.epoch
+    }
+
+    /// Splits the buffer into `[0, mid)` and `[mid, len)`, both keeping the epoch alive.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `mid > len`.
+    pub fn split_at(self, mid: usize) -> (ArenaBuf, ArenaBuf) {
+        assert!(mid <= self.len, "mid > len");
+        let tail = ArenaBuf {
+            chunk: self.chunk.clone(),
+            // SAFETY: `mid` is within the buffer.
+            ptr: unsafe { self.ptr.add(mid) },
+            len: self.len - mid,
+        };
+        let head = ArenaBuf { len: mid, ..self };
+        (head, tail)
+    }
+
+    /// Freezes the buffer so that it can be cloned and read from several tasks at once.
+    pub fn into_shared(self) -> SharedBuf {
+        SharedBuf {
+            chunk: self.chunk,
+            ptr: self.ptr,
+            len: self.len,
+        }
+    }
+}
+
+impl Deref for ArenaBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the range is initialized and kept allocated by `self.chunk`.
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl DerefMut for ArenaBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as above, and no other buffer covers the range.
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl std::fmt::Debug for ArenaBuf {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ArenaBuf")
+            .field("epoch", &self.epoch())
+            .field("len", &self.len)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A read-only buffer of a [`SharedArena`], cheap to clone and to send to other workers.
+#[derive(Clone)]
+pub struct SharedBuf {
+    chunk: Arc<Chunk>,
+    ptr: *const u8,
+    len: usize,
+}
+
+// SAFETY: the range is never written to while shared.
+unsafe impl Send for SharedBuf {}
+unsafe impl Sync for SharedBuf {}
+
+impl SharedBuf {
+    /// The epoch the buffer was allocated in.
+    pub fn epoch(&self) -> u64 {
+        self.chunk.epoch
+    }
+}
+
+impl Deref for SharedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the range is initialized and kept allocated by `self.chunk`.
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl std::fmt::Debug for SharedBuf {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedBuf")
+            .field("epoch", &self.epoch())
+            .field("len", &self.len)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn test_epochs_are_reclaimed() {
+        let arena = SharedArena::new(1024);
+        let first = arena.alloc(600);
+        assert_eq!(first.epoch(), 1);
+        assert_eq!(first.as_ptr() as usize % ALIGN, 0);
+        // Doesn't fit next to `first`.
+        let second = arena.alloc(600);
+        assert_eq!(second.epoch(), 2);
+        let first_ptr = first.as_ptr();
+        drop(first);
+        assert_eq!(arena.free_chunks(), 1);
+        arena.advance();
+        let third = arena.alloc(10);
+        assert_eq!((third.epoch(), third.as_ptr()), (3, first_ptr));
+        drop((second, third));
+        // Only the epochs that ended are reclaimed.
+        assert_eq!(arena.free_chunks(), 1);
+        let oversized = arena.alloc(4096);
+        assert_eq!(oversized.len(), 4096);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_buffers_are_shared_with_workers() {
+        let arena = SharedArena::new(1 << 16);
+        let (mut left, mut right) = arena.alloc(200).split_at(100);
+        left.fill(1);
+        let writer = task::spawn_blocking(move || {
+            right.fill(2);
+            right
+        });
+        let right = writer.join().await.unwrap();
+        let (left, right) = (left.into_shared(), right.into_shared());
+        let sum = task::spawn_blocking(move || {
+            left.iter()
+                .chain(right.iter())
+                .map(|&byte| byte as u32)
+                .sum::<u32>()
+        });
+        assert_eq!(sum.join().await.unwrap(), 300);
+    }
+}
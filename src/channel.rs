@@ -0,0 +1,107 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::Error;
+
+pub mod bridge;
+pub mod broadcast;
+pub mod mpsc;
+pub mod transfer;
+pub mod watch;
+
+/// Re-exported to wait on several channels at once without depending on `futures`. The
+/// receive and send futures of the channels are cancel-safe, so the branches that lose a
+/// `select!` lose no message:
+///
+/// ```ignore
+/// use wasmt::channel::{select, FutureExt};
+///
+/// loop {
+///     select! {
+///         job = jobs.recv().fuse() => run(job?),
+///         _ = config.changed().fuse() => reload(&config.borrow()),
+///     }
+/// }
+/// ```
+pub use futures::{select, select_biased, FutureExt};
+
+/// Creates a channel backed by a `MessageChannel`, for values that travel by structured
+/// clone rather than through the shared memory of the module.
+///
+/// Either end can be handed to a worker or iframe that doesn't share the module's
+/// memory, e.g. a worker of another module or a cross-origin frame, by transferring the
+/// port returned by its `into_port` method, and rebuilding the end from the port there
+/// with [`bridge::Sender::new`] or [`bridge::Receiver::new`]. See [`bridge`] for the
+/// protocol, which a JS end can speak too.
+pub fn port_channel<T>() -> Result<(bridge::Sender<T>, bridge::Receiver<T>), Error>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let channel = web_sys::MessageChannel::new()?;
+    Ok((
+        bridge::Sender::new(channel.port1()),
+        bridge::Receiver::new(channel.port2()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::task;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_select_loses_no_message() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let (config_tx, mut config_rx) = watch::channel(0);
+        let sender = task::spawn(async move {
+            for i in 0..100 {
+                tx.send(i).await.unwrap();
+                if i % 10 == 0 {
+                    config_tx.send(i).unwrap();
+                }
+            }
+        });
+        let mut received = Vec::new();
+        loop {
+            select! {
+                message = rx.recv().fuse() => match message {
+                    Some(message) => received.push(message),
+                    None => break,
+                },
+                // Keeps winning races against the receives.
+                _ = config_rx.changed().fuse() => {}
+            }
+        }
+        sender.await.unwrap();
+        assert_eq!(received, (0..100).collect::<Vec<_>>());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_port_channel_to_worker() {
+        let (mut tx, rx) = port_channel::<String>().unwrap();
+        let (handle, worker) = task::spawn_with_worker(async move {
+            let port = task::worker_messages().next().await.unwrap();
+            let mut rx = bridge::Receiver::<String>::new(port.into());
+            let mut received = Vec::new();
+            while let Some(value) = rx.recv().await.unwrap() {
+                received.push(value);
+            }
+            received
+        });
+        let port = rx.into_port();
+        worker
+            .post_message_with_transfer(&port, &js_sys::Array::of1(&port))
+            .unwrap();
+        for word in ["a", "b", "c"] {
+            tx.send(&word.to_string()).await.unwrap();
+        }
+        drop(tx);
+        assert_eq!(handle.join().await.unwrap(), ["a", "b", "c"]);
+    }
+}
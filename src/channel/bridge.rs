@@ -0,0 +1,347 @@
+use std::marker::PhantomData;
+
+use futures::channel::mpsc;
+use futures::StreamExt;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use wasm_bindgen::prelude::{Closure, JsValue};
+use wasm_bindgen::JsCast;
+use web_sys::{MessageEvent, MessagePort};
+
+use crate::Error;
+
+const DEFAULT_CAPACITY: u32 = 16;
+
+// The key of every message of the protocol, whose value is the kind of the message.
+pub(super) const KEY: &str = "wasmtBridge";
+
+// Listens to a port, like the one of `codec`. The handler is removed on drop, so that the
+// port doesn't call into a freed closure. Also used by `transfer`, which speaks the same
+// protocol.
+pub(super) struct Listener {
+    port: MessagePort,
+    messages: mpsc::UnboundedReceiver<JsValue>,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    // Whether the port was given back, in which case the channel isn't closed on drop.
+    handed_over: bool,
+}
+
+impl Listener {
+    pub(super) fn new(port: MessagePort) -> Self {
+        let (tx, messages) = mpsc::unbounded();
+        let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+            tx.unbounded_send(event.data()).ok();
+        });
+        // Setting `onmessage` also starts the port.
+        port.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        Listener {
+            port,
+            messages,
+            _on_message: on_message,
+            handed_over: false,
+        }
+    }
+
+    // Stops listening without closing the channel, so that the port can be handed over.
+    pub(super) fn into_port(mut self) -> MessagePort {
+        self.handed_over = true;
+        self.port.clone()
+    }
+
+    // The next message of the protocol, skipping anything else sent on the port.
+    pub(super) async fn next(&mut self) -> Message {
+        loop {
+            // The sender lives in the closure owned by `self`, so the stream never ends.
+            let data = self.messages.next().await.unwrap_or(JsValue::UNDEFINED);
+            if let Some(message) = Message::parse(&data) {
+                return message;
+            }
+        }
+    }
+
+    pub(super) fn post(&self, kind: &str, field: Option<(&str, &JsValue)>) -> Result<(), JsValue> {
+        self.port.post_message(&message(kind, field)?)
+    }
+
+    // Posts a message moving the objects of `transfer` to the other end instead of
+    // cloning them.
+    pub(super) fn post_with_transfer(
+        &self,
+        kind: &str,
+        field: Option<(&str, &JsValue)>,
+        transfer: &js_sys::Array,
+    ) -> Result<(), JsValue> {
+        self.port
+            .post_message_with_transferable(&message(kind, field)?, transfer)
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        // Tells the other end, which may be in another module that can't tell otherwise.
+        if !self.handed_over {
+            self.post("close", None).ok();
+        }
+        self.port.set_onmessage(None);
+    }
+}
+
+fn message(kind: &str, field: Option<(&str, &JsValue)>) -> Result<JsValue, JsValue> {
+    let message = js_sys::Object::new();
+    js_sys::Reflect::set(&message, &KEY.into(), &kind.into())?;
+    if let Some((name, value)) = field {
+        js_sys::Reflect::set(&message, &name.into(), value)?;
+    }
+    Ok(message.into())
+}
+
+pub(super) enum Message {
+    Data(JsValue),
+    Credit(u32),
+    Close,
+}
+
+impl Message {
+    fn parse(data: &JsValue) -> Option<Message> {
+        if !data.is_object() {
+            return None;
+        }
+        let kind = js_sys::Reflect::get(data, &KEY.into()).ok()?.as_string()?;
+        let field = |name: &str| js_sys::Reflect::get(data, &name.into()).ok();
+        match kind.as_str() {
+            "data" => Some(Message::Data(field("value")?)),
+            "credit" => Some(Message::Credit(field("count")?.as_f64()? as u32)),
+            "close" => Some(Message::Close),
+            _ => None,
+        }
+    }
+}
+
+fn closed_error(end: &str) -> Error {
+    Error::Js(js_sys::Error::new(&format!("bridge: the {end} was dropped")).into())
+}
+
+/// Sends values of type `T` over a `MessagePort` to a [`Receiver`], which may run in a
+/// worker of another wasm module, with its own memory, also using this crate.
+///
+/// Values are serialized with `serde-wasm-bindgen` and structured-cloned by the port, so
+/// both ends only need to agree on the serialized form of `T`, not on its layout. The
+/// sender only sends as many values as the receiver granted it credits for, so a slow
+/// receiver holds the sender back instead of buffering without bound.
+///
+/// The protocol is made of plain objects, which a JS end can speak too:
+/// `{ wasmtBridge: "data", value }` carries a value, `{ wasmtBridge: "credit", count }`
+/// lets the sender send `count` more values, and `{ wasmtBridge: "close" }` tells the
+/// other end that this one was dropped. Other messages on the port are ignored.
+///
+/// ```ignore
+/// // In the first module, which spawned a worker of the second one.
+/// let channel = web_sys::MessageChannel::new()?;
+/// other_module_worker.post_message_with_transfer(&channel.port2(), &js_sys::Array::of1(&channel.port2()))?;
+/// let mut tx = bridge::Sender::<Frame>::new(channel.port1());
+/// tx.send(&frame).await?;
+///
+/// // In the second module, once it received the port.
+/// let mut rx = bridge::Receiver::<Frame>::new(port);
+/// while let Some(frame) = rx.recv().await? {
+///     render(frame);
+/// }
+/// ```
+pub struct Sender<T> {
+    listener: Listener,
+    credits: u32,
+    closed: bool,
+    _marker: PhantomData<fn(T)>,
+}
+
+impl<T: Serialize> Sender<T> {
+    pub fn new(port: MessagePort) -> Self {
+        Sender {
+            listener: Listener::new(port),
+            credits: 0,
+            closed: false,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Sends `value`, waiting for the receiver to grant a credit if it has none left.
+    /// Fails once the receiver was dropped, or if `value` can't be serialized.
+    pub async fn send(&mut self, value: &T) -> Result<(), Error> {
+        while self.credits == 0 && !self.closed {
+            match self.listener.next().await {
+                Message::Credit(count) => self.credits += count,
+                Message::Close => self.closed = true,
+                Message::Data(_) => {}
+            }
+        }
+        if self.closed {
+            return Err(closed_error("receiver"));
+        }
+        let value = serde_wasm_bindgen::to_value(value).map_err(JsValue::from)?;
+        self.listener.post("data", Some(("value", &value)))?;
+        self.credits -= 1;
+        Ok(())
+    }
+
+    /// Gives back the port, e.g. to transfer it to the worker or iframe that sends the
+    /// values instead. Credits received but not used yet are lost, so this should be
+    /// called before the receiver starts receiving.
+    pub fn into_port(self) -> MessagePort {
+        self.listener.into_port()
+    }
+}
+
+impl<T> std::fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sender")
+            .field("credits", &self.credits)
+            .field("closed", &self.closed)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Receives the values sent by a bridge [`Sender`] on the other end of a `MessagePort`.
+pub struct Receiver<T> {
+    listener: Listener,
+    capacity: u32,
+    // Whether the sender was granted its first credits, which happens on the first
+    // receive, so that the capacity can be set before.
+    started: bool,
+    closed: bool,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned> Receiver<T> {
+    pub fn new(port: MessagePort) -> Self {
+        Receiver {
+            listener: Listener::new(port),
+            capacity: DEFAULT_CAPACITY,
+            started: false,
+            closed: false,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Number of values the sender may send ahead of the receiver. Defaults to 16.
+    pub fn with_capacity(mut self, capacity: u32) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Receives the next value. Returns `None` once the sender was dropped, and fails if
+    /// a value can't be deserialized into `T`.
+    pub async fn recv(&mut self) -> Result<Option<T>, Error> {
+        if !self.started {
+            self.started = true;
+            self.listener
+                .post("credit", Some(("count", &self.capacity.into())))?;
+        }
+        while !self.closed {
+            match self.listener.next().await {
+                Message::Data(value) => {
+                    // Granted before deserializing, since the value was received either way.
+                    self.listener.post("credit", Some(("count", &1.into())))?;
+                    let value = serde_wasm_bindgen::from_value(value).map_err(JsValue::from)?;
+                    return Ok(Some(value));
+                }
+                Message::Close => self.closed = true,
+                Message::Credit(_) => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Gives back the port, e.g. to transfer it to the worker or iframe that receives
+    /// the values instead. Values received but not read yet are lost, so this should be
+    /// called before the first receive.
+    pub fn into_port(self) -> MessagePort {
+        self.listener.into_port()
+    }
+}
+
+impl<T> std::fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Receiver")
+            .field("capacity", &self.capacity)
+            .field("closed", &self.closed)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_bridge() {
+        let channel = web_sys::MessageChannel::new().unwrap();
+        let mut tx = Sender::<(u32, String)>::new(channel.port1());
+        let mut rx = Receiver::<(u32, String)>::new(channel.port2()).with_capacity(2);
+        let send = async move {
+            for i in 0..5 {
+                tx.send(&(i, i.to_string())).await.unwrap();
+            }
+        };
+        let recv = async {
+            let mut received = Vec::new();
+            while let Some(value) = rx.recv().await.unwrap() {
+                received.push(value);
+            }
+            received
+        };
+        let ((), received) = futures::join!(send, recv);
+        let expected = (0..5).map(|i| (i, i.to_string())).collect::<Vec<_>>();
+        assert_eq!(received, expected);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_sender_waits_for_credits() {
+        let channel = web_sys::MessageChannel::new().unwrap();
+        let mut tx = Sender::<u32>::new(channel.port1());
+        let mut rx = Receiver::<u32>::new(channel.port2()).with_capacity(1);
+        {
+            let send = tx.send(&1);
+            futures::pin_mut!(send);
+            // No credit until the receiver starts receiving.
+            assert!(futures::poll!(send.as_mut()).is_pending());
+            let (sent, received) = futures::join!(send, rx.recv());
+            sent.unwrap();
+            assert_eq!(received.unwrap(), Some(1));
+        }
+        drop(rx);
+        let mut closed = false;
+        for _ in 0..10 {
+            if tx.send(&2).await.is_err() {
+                closed = true;
+                break;
+            }
+        }
+        assert!(closed);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_js_peer() {
+        let channel = web_sys::MessageChannel::new().unwrap();
+        let mut rx = Receiver::<Vec<u8>>::new(channel.port2());
+        let peer = channel.port1();
+        let data = js_sys::Object::new();
+        js_sys::Reflect::set(&data, &KEY.into(), &"data".into()).unwrap();
+        js_sys::Reflect::set(
+            &data,
+            &"value".into(),
+            &js_sys::Uint8Array::from(&[1u8, 2][..]),
+        )
+        .unwrap();
+        peer.post_message(&"ignored".into()).unwrap();
+        peer.post_message(&data).unwrap();
+        let close = js_sys::Object::new();
+        js_sys::Reflect::set(&close, &KEY.into(), &"close".into()).unwrap();
+        peer.post_message(&close).unwrap();
+        assert_eq!(rx.recv().await.unwrap(), Some(vec![1, 2]));
+        assert_eq!(rx.recv().await.unwrap(), None);
+    }
+}
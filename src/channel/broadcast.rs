@@ -0,0 +1,373 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::panic::Location;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::sync::deadlock;
+use crate::sync::futex::Futex;
+use crate::sync::BlockingContextError;
+
+// The last `capacity` messages, in shared memory, along with the position of the oldest
+// one. Each receiver keeps the position of the next message it reads: a receiver that
+// falls more than `capacity` messages behind has missed the ones that were overwritten.
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    capacity: usize,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+    // Notified when a message is sent or the last sender is dropped.
+    readable: Futex,
+}
+
+struct State<T> {
+    buffer: VecDeque<T>,
+    // Position of `buffer[0]`.
+    head: u64,
+}
+
+impl<T> State<T> {
+    fn tail(&self) -> u64 {
+        self.head + self.buffer.len() as u64
+    }
+}
+
+/// Creates a channel whose receivers each see every message sent after they subscribed,
+/// keeping the last `capacity` messages for receivers that are behind.
+///
+/// Receivers wait for messages like those of [`mpsc`](super::mpsc): blocking workers with
+/// [`recv_blocking`](Receiver::recv_blocking), or asynchronously on any thread with
+/// [`recv`](Receiver::recv). Senders never wait: a receiver more than `capacity` messages
+/// behind misses the oldest ones, and is told how many with [`RecvError::Lagged`].
+///
+/// # Panics
+///
+/// Panics if `capacity` is 0.
+pub fn channel<T: Clone>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "broadcast channel capacity must be positive");
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            buffer: VecDeque::with_capacity(capacity),
+            head: 0,
+        }),
+        capacity,
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+        readable: Futex::new(),
+    });
+    let receiver = Receiver {
+        shared: shared.clone(),
+        next: 0,
+    };
+    (Sender { shared }, receiver)
+}
+
+/// The sending side of a broadcast [`channel`], which can be cloned and sent to other
+/// workers.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: Clone> Sender<T> {
+    /// Sends `value` to every receiver, returning how many there are. Fails if there are
+    /// none, in which case the message isn't kept for later subscribers.
+    pub fn send(&self, value: T) -> Result<usize, SendError<T>> {
+        let mut state = self.shared.state.lock().unwrap();
+        // Read under the lock, which `subscribe` takes too.
+        let receivers = self.shared.receivers.load(Ordering::SeqCst);
+        if receivers == 0 {
+            return Err(SendError(value));
+        }
+        if state.buffer.len() == self.shared.capacity {
+            state.buffer.pop_front();
+            state.head += 1;
+        }
+        state.buffer.push_back(value);
+        drop(state);
+        deadlock::notifier(&self.shared.readable);
+        self.shared.readable.notify();
+        Ok(receivers)
+    }
+
+    /// Creates a receiver of the messages sent from now on.
+    pub fn subscribe(&self) -> Receiver<T> {
+        let state = self.shared.state.lock().unwrap();
+        self.shared.receivers.fetch_add(1, Ordering::SeqCst);
+        Receiver {
+            shared: self.shared.clone(),
+            next: state.tail(),
+        }
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.shared.receivers.load(Ordering::SeqCst)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::SeqCst);
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.shared.readable.notify();
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sender")
+            .field("receivers", &self.shared.receivers.load(Ordering::SeqCst))
+            .finish_non_exhaustive()
+    }
+}
+
+/// The receiving side of a broadcast [`channel`]. Cloning it creates a receiver at the
+/// same position, which then reads the messages independently.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    // Position of the next message to read.
+    next: u64,
+}
+
+impl<T: Clone> Receiver<T> {
+    /// Receives the next message, waiting without blocking the current thread. Fails
+    /// with [`RecvError::Closed`] once every sender is dropped and the receiver has read
+    /// the remaining messages.
+    ///
+    /// Cancel-safe: if the future is dropped before completing, the receiver's position
+    /// didn't move.
+    #[track_caller]
+    pub fn recv(&mut self) -> impl Future<Output = Result<T, RecvError>> + '_ {
+        let location = Location::caller();
+        async move {
+            let shared = self.shared.clone();
+            let _waiting =
+                deadlock::waiting(&shared.readable, "broadcast::Receiver::recv", location);
+            shared
+                .readable
+                .wait_until_async(|| self.recv_attempt())
+                .await
+        }
+    }
+
+    /// Like [`recv`](Self::recv), blocking the current worker while there is no message.
+    #[track_caller]
+    pub fn recv_blocking(&mut self) -> Result<Result<T, RecvError>, BlockingContextError> {
+        let shared = self.shared.clone();
+        let _waiting = deadlock::waiting(
+            &shared.readable,
+            "broadcast::Receiver::recv_blocking",
+            Location::caller(),
+        );
+        shared.readable.wait_until(|| self.recv_attempt())
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        // Read before the messages, so that the last ones of a closed channel are still
+        // received.
+        let closed = self.shared.senders.load(Ordering::SeqCst) == 0;
+        let state = self.shared.state.lock().unwrap();
+        if self.next < state.head {
+            let missed = state.head - self.next;
+            self.next = state.head;
+            return Err(TryRecvError::Lagged(missed));
+        }
+        if self.next < state.tail() {
+            let value = state.buffer[(self.next - state.head) as usize].clone();
+            self.next += 1;
+            return Ok(value);
+        }
+        Err(if closed {
+            TryRecvError::Closed
+        } else {
+            TryRecvError::Empty
+        })
+    }
+
+    fn recv_attempt(&mut self) -> Option<Result<T, RecvError>> {
+        match self.try_recv() {
+            Ok(value) => Some(Ok(value)),
+            Err(TryRecvError::Lagged(missed)) => Some(Err(RecvError::Lagged(missed))),
+            Err(TryRecvError::Closed) => Some(Err(RecvError::Closed)),
+            Err(TryRecvError::Empty) => None,
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Number of messages the receiver hasn't read yet, including those it missed.
+    pub fn len(&self) -> usize {
+        let tail = self.shared.state.lock().unwrap().tail();
+        (tail - self.next) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.receivers.fetch_add(1, Ordering::SeqCst);
+        Receiver {
+            shared: self.shared.clone(),
+            next: self.next,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receivers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl<T> std::fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Receiver")
+            .field("len", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Returned when sending on a channel without receivers, with the message.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> std::fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SendError").finish_non_exhaustive()
+    }
+}
+
+impl<T> std::fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the channel has no receivers")
+    }
+}
+
+impl<T> std::error::Error for SendError<T> {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecvError {
+    /// Every sender was dropped and the receiver read every message.
+    Closed,
+    /// The receiver fell behind and missed this many of the oldest messages. The next
+    /// receive returns the oldest message still kept.
+    Lagged(u64),
+}
+
+impl std::fmt::Display for RecvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecvError::Closed => write!(f, "every sender of the channel was dropped"),
+            RecvError::Lagged(missed) => write!(f, "the receiver missed {missed} messages"),
+        }
+    }
+}
+
+impl std::error::Error for RecvError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    /// Every sender was dropped and the receiver read every message.
+    Closed,
+    /// The receiver fell behind and missed this many of the oldest messages.
+    Lagged(u64),
+}
+
+impl std::fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TryRecvError::Empty => write!(f, "the channel is empty"),
+            TryRecvError::Closed => write!(f, "every sender of the channel was dropped"),
+            TryRecvError::Lagged(missed) => write!(f, "the receiver missed {missed} messages"),
+        }
+    }
+}
+
+impl std::error::Error for TryRecvError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_every_receiver_sees_every_message() {
+        let (tx, mut rx) = channel(16);
+        let workers = (0..2)
+            .map(|_| {
+                let mut rx = tx.subscribe();
+                task::spawn_blocking(move || {
+                    let mut received = Vec::new();
+                    while let Ok(value) = rx.recv_blocking().unwrap() {
+                        received.push(value);
+                    }
+                    received
+                })
+            })
+            .collect::<Vec<_>>();
+        let sender = task::spawn_blocking(move || {
+            for i in 0..10 {
+                assert_eq!(tx.send(i).unwrap(), 3);
+            }
+        });
+        let mut received = Vec::new();
+        loop {
+            match rx.recv().await {
+                Ok(value) => received.push(value),
+                Err(RecvError::Closed) => break,
+                Err(err) => panic!("{err}"),
+            }
+        }
+        sender.join().await.unwrap();
+        let expected = (0..10).collect::<Vec<_>>();
+        assert_eq!(received, expected);
+        for worker in workers {
+            assert_eq!(worker.join().await.unwrap(), expected);
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_lagging_receiver() {
+        let (tx, mut rx) = channel(2);
+        let mut late = rx.clone();
+        for i in 0..5 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(rx.len(), 5);
+        assert_eq!(rx.recv().await, Err(RecvError::Lagged(3)));
+        assert_eq!(rx.recv().await, Ok(3));
+        assert_eq!(rx.try_recv(), Ok(4));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(late.try_recv(), Err(TryRecvError::Lagged(3)));
+        let mut subscriber = tx.subscribe();
+        drop(tx);
+        assert_eq!(late.recv().await, Ok(3));
+        assert_eq!(subscriber.recv().await, Err(RecvError::Closed));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_send_without_receivers() {
+        let (tx, rx) = channel(4);
+        drop(rx);
+        assert_eq!(tx.send(1), Err(SendError(1)));
+        let mut rx = tx.subscribe();
+        tx.send(2).unwrap();
+        assert_eq!(rx.try_recv(), Ok(2));
+    }
+}
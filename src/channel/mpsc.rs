@@ -0,0 +1,569 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::panic::Location;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures::stream::FusedStream;
+use futures::Stream;
+
+use crate::sync::deadlock;
+use crate::sync::futex::Futex;
+use crate::sync::BlockingContextError;
+
+// The channel's messages, in a ring buffer in the module's shared memory that bounded
+// channels allocate once. Receivers and senders waiting for room park on futexes, which
+// wake blocked workers with `Atomics.notify` and async waiters, including the main
+// thread, through `Atomics.waitAsync`, so that no side polls.
+struct Chan<T> {
+    queue: Mutex<VecDeque<T>>,
+    capacity: Option<usize>,
+    senders: AtomicUsize,
+    receiver_closed: AtomicBool,
+    // Notified when a message is sent or the last sender is dropped.
+    readable: Futex,
+    // Notified when a message is received or the receiver is dropped.
+    writable: Futex,
+}
+
+impl<T> Chan<T> {
+    fn new(capacity: Option<usize>) -> Arc<Self> {
+        let queue = match capacity {
+            Some(capacity) => VecDeque::with_capacity(capacity),
+            None => VecDeque::new(),
+        };
+        Arc::new(Chan {
+            queue: Mutex::new(queue),
+            capacity,
+            senders: AtomicUsize::new(1),
+            receiver_closed: AtomicBool::new(false),
+            readable: Futex::new(),
+            writable: Futex::new(),
+        })
+    }
+
+    fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        if self.receiver_closed.load(Ordering::SeqCst) {
+            return Err(TrySendError::Closed(value));
+        }
+        let mut queue = self.queue.lock().unwrap();
+        if self
+            .capacity
+            .is_some_and(|capacity| queue.len() >= capacity)
+        {
+            return Err(TrySendError::Full(value));
+        }
+        queue.push_back(value);
+        drop(queue);
+        deadlock::notifier(&self.readable);
+        self.readable.notify();
+        Ok(())
+    }
+
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        // Read before the queue, so that the last messages of a closed channel are still
+        // received.
+        let disconnected = self.senders.load(Ordering::SeqCst) == 0;
+        match self.queue.lock().unwrap().pop_front() {
+            Some(value) => {
+                if self.capacity.is_some() {
+                    deadlock::notifier(&self.writable);
+                    self.writable.notify();
+                }
+                Ok(value)
+            }
+            None if disconnected => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    // Adapts `try_*` results to the futexes' retry loops.
+    fn send_attempt(&self, value: &mut Option<T>) -> Option<Result<(), SendError<T>>> {
+        match self.try_send(value.take().unwrap()) {
+            Ok(()) => Some(Ok(())),
+            Err(TrySendError::Closed(value)) => Some(Err(SendError(value))),
+            Err(TrySendError::Full(full)) => {
+                *value = Some(full);
+                None
+            }
+        }
+    }
+
+    fn recv_attempt(&self) -> Option<Option<T>> {
+        match self.try_recv() {
+            Ok(value) => Some(Some(value)),
+            Err(TryRecvError::Disconnected) => Some(None),
+            Err(TryRecvError::Empty) => None,
+        }
+    }
+
+    fn recv_many_attempt(&self, buffer: &mut Vec<T>, limit: usize) -> Option<usize> {
+        let disconnected = self.senders.load(Ordering::SeqCst) == 0;
+        let mut queue = self.queue.lock().unwrap();
+        let count = queue.len().min(limit);
+        if count == 0 {
+            return disconnected.then_some(0);
+        }
+        buffer.extend(queue.drain(..count));
+        drop(queue);
+        if self.capacity.is_some() {
+            deadlock::notifier(&self.writable);
+            self.writable.notify();
+        }
+        Some(count)
+    }
+}
+
+/// Creates a channel holding at most `capacity` messages, whose senders wait for room
+/// when it is full.
+///
+/// Unlike `futures::channel::mpsc`, whose wake-ups go through the receiver's `Waker`, the
+/// channel's own futexes let a receiver block in a worker with
+/// [`recv_blocking`](Receiver::recv_blocking), and wake async receivers on other threads
+/// with `Atomics.waitAsync`. Where `waitAsync` isn't supported, async waits retry on
+/// every turn of the event loop instead.
+///
+/// # Panics
+///
+/// Panics if `capacity` is 0.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "mpsc channel capacity must be positive");
+    let chan = Chan::new(Some(capacity));
+    (Sender { chan: chan.clone() }, Receiver::new(chan))
+}
+
+/// Creates a channel without a bound on the number of messages it holds.
+pub fn unbounded<T>() -> (UnboundedSender<T>, Receiver<T>) {
+    let chan = Chan::new(None);
+    (UnboundedSender { chan: chan.clone() }, Receiver::new(chan))
+}
+
+/// The sending side of a [`channel`], which can be cloned and sent to other workers.
+pub struct Sender<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends `value`, waiting without blocking the current thread while the channel is
+    /// full. Fails once the receiver is dropped.
+    ///
+    /// Cancel-safe: if the future is dropped before completing, e.g. because another
+    /// branch of a `select!` completed first, `value` wasn't sent.
+    #[track_caller]
+    pub fn send(&self, value: T) -> impl Future<Output = Result<(), SendError<T>>> + '_ {
+        let location = Location::caller();
+        async move {
+            let mut value = Some(value);
+            let chan = &self.chan;
+            let _waiting = deadlock::waiting(&chan.writable, "mpsc::Sender::send", location);
+            chan.writable
+                .wait_until_async(|| chan.send_attempt(&mut value))
+                .await
+        }
+    }
+
+    /// Like [`send`](Self::send), blocking the current worker while the channel is full.
+    #[track_caller]
+    pub fn send_blocking(
+        &self,
+        value: T,
+    ) -> Result<Result<(), SendError<T>>, BlockingContextError> {
+        let mut value = Some(value);
+        let chan = &self.chan;
+        let _waiting = deadlock::waiting(
+            &chan.writable,
+            "mpsc::Sender::send_blocking",
+            Location::caller(),
+        );
+        chan.writable.wait_until(|| chan.send_attempt(&mut value))
+    }
+
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        self.chan.try_send(value)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.chan.receiver_closed.load(Ordering::SeqCst)
+    }
+}
+
+/// The sending side of an [`unbounded`] channel, which can be cloned and sent to other
+/// workers.
+pub struct UnboundedSender<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> UnboundedSender<T> {
+    /// Sends `value` right away. Fails once the receiver is dropped.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.chan.try_send(value).map_err(|err| match err {
+            TrySendError::Full(value) | TrySendError::Closed(value) => SendError(value),
+        })
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.chan.receiver_closed.load(Ordering::SeqCst)
+    }
+}
+
+macro_rules! impl_sender {
+    ($sender:ident) => {
+        impl<T> Clone for $sender<T> {
+            fn clone(&self) -> Self {
+                self.chan.senders.fetch_add(1, Ordering::SeqCst);
+                $sender {
+                    chan: self.chan.clone(),
+                }
+            }
+        }
+
+        impl<T> Drop for $sender<T> {
+            fn drop(&mut self) {
+                if self.chan.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+                    self.chan.readable.notify();
+                }
+            }
+        }
+
+        impl<T> std::fmt::Debug for $sender<T> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.debug_struct(stringify!($sender))
+                    .field("is_closed", &self.is_closed())
+                    .finish()
+            }
+        }
+    };
+}
+
+impl_sender!(Sender);
+impl_sender!(UnboundedSender);
+
+type Recv<T> = Pin<Box<dyn Future<Output = Option<T>>>>;
+
+/// The receiving side of a channel, which can be sent to another worker. Also a
+/// [`Stream`] of the messages.
+pub struct Receiver<T> {
+    chan: Arc<Chan<T>>,
+    // The pending receive of `poll_next`.
+    next: Option<Recv<T>>,
+    // Whether `poll_next` returned `None`.
+    terminated: bool,
+}
+
+impl<T> Receiver<T> {
+    fn new(chan: Arc<Chan<T>>) -> Self {
+        Receiver {
+            chan,
+            next: None,
+            terminated: false,
+        }
+    }
+
+    /// Receives the next message, waiting without blocking the current thread. Returns
+    /// `None` once every sender is dropped and the channel is empty.
+    ///
+    /// Cancel-safe: if the future is dropped before completing, no message was taken
+    /// out of the channel.
+    #[track_caller]
+    pub fn recv(&mut self) -> impl Future<Output = Option<T>> + '_ {
+        let location = Location::caller();
+        async move {
+            let chan = &self.chan;
+            let _waiting = deadlock::waiting(&chan.readable, "mpsc::Receiver::recv", location);
+            chan.readable.wait_until_async(|| chan.recv_attempt()).await
+        }
+    }
+
+    /// Receives the messages waiting in the channel into `buffer`, at most `limit` of
+    /// them, waiting for one if there is none. Returns how many were received, which is
+    /// 0 only if `limit` is 0, or once every sender is dropped and the channel is empty.
+    ///
+    /// Cancel-safe like [`recv`](Self::recv).
+    #[track_caller]
+    pub fn recv_many<'a>(
+        &'a mut self,
+        buffer: &'a mut Vec<T>,
+        limit: usize,
+    ) -> impl Future<Output = usize> + 'a {
+        let location = Location::caller();
+        async move {
+            if limit == 0 {
+                return 0;
+            }
+            let chan = &self.chan;
+            let _waiting = deadlock::waiting(&chan.readable, "mpsc::Receiver::recv_many", location);
+            chan.readable
+                .wait_until_async(|| chan.recv_many_attempt(buffer, limit))
+                .await
+        }
+    }
+
+    /// Like [`recv`](Self::recv), blocking the current worker while the channel is empty.
+    #[track_caller]
+    pub fn recv_blocking(&mut self) -> Result<Option<T>, BlockingContextError> {
+        let chan = &self.chan;
+        let _waiting = deadlock::waiting(
+            &chan.readable,
+            "mpsc::Receiver::recv_blocking",
+            Location::caller(),
+        );
+        chan.readable.wait_until(|| chan.recv_attempt())
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        self.chan.try_recv()
+    }
+
+    // Returns a function closing the channel and dropping the messages left in it, for
+    // owners that may stop without dropping the receiver, e.g. when a panic traps their
+    // worker.
+    pub(crate) fn closer(&self) -> impl Fn() + 'static
+    where
+        T: 'static,
+    {
+        let chan = self.chan.clone();
+        move || {
+            chan.receiver_closed.store(true, Ordering::SeqCst);
+            // Only tried, in case the panic happened while the lock was held.
+            let left = chan
+                .queue
+                .try_lock()
+                .map(|mut queue| std::mem::take(&mut *queue));
+            chan.writable.notify();
+            drop(left);
+        }
+    }
+
+    /// Number of messages waiting to be received.
+    pub fn len(&self) -> usize {
+        self.chan.queue.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: 'static> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let chan = self.chan.clone();
+        let next = self.next.get_or_insert_with(|| {
+            Box::pin(async move { chan.readable.wait_until_async(|| chan.recv_attempt()).await })
+        });
+        let poll = next.as_mut().poll(cx);
+        if let Poll::Ready(message) = &poll {
+            self.next = None;
+            self.terminated = message.is_none();
+        }
+        poll
+    }
+}
+
+/// Lets `select!` poll `rx.next()` directly, the receiver being `Unpin`.
+impl<T: 'static> FusedStream for Receiver<T> {
+    fn is_terminated(&self) -> bool {
+        self.terminated
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.chan.receiver_closed.store(true, Ordering::SeqCst);
+        self.chan.writable.notify();
+    }
+}
+
+impl<T> std::fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Receiver")
+            .field("len", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Returned when sending on a channel whose receiver was dropped, with the message.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> std::fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SendError").finish_non_exhaustive()
+    }
+}
+
+impl<T> std::fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the receiver of the channel was dropped")
+    }
+}
+
+impl<T> std::error::Error for SendError<T> {}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// The channel holds as many messages as its capacity.
+    Full(T),
+    /// The receiver was dropped.
+    Closed(T),
+}
+
+impl<T> TrySendError<T> {
+    pub fn into_inner(self) -> T {
+        match self {
+            TrySendError::Full(value) | TrySendError::Closed(value) => value,
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrySendError::Full(_) => write!(f, "Full(..)"),
+            TrySendError::Closed(_) => write!(f, "Closed(..)"),
+        }
+    }
+}
+
+impl<T> std::fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrySendError::Full(_) => write!(f, "the channel is full"),
+            TrySendError::Closed(_) => write!(f, "the receiver of the channel was dropped"),
+        }
+    }
+}
+
+impl<T> std::error::Error for TrySendError<T> {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    /// Every sender was dropped and the channel is empty.
+    Disconnected,
+}
+
+impl std::fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TryRecvError::Empty => write!(f, "the channel is empty"),
+            TryRecvError::Disconnected => write!(f, "every sender of the channel was dropped"),
+        }
+    }
+}
+
+impl std::error::Error for TryRecvError {}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::task;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_bounded_backpressure() {
+        let (tx, mut rx) = channel(2);
+        tx.try_send(1).unwrap();
+        tx.try_send(2).unwrap();
+        assert!(matches!(tx.try_send(3), Err(TrySendError::Full(3))));
+        let mut send = Box::pin(tx.send(3));
+        assert!(futures::poll!(send.as_mut()).is_pending());
+        assert_eq!(rx.recv().await, Some(1));
+        send.await.unwrap();
+        drop(tx);
+        assert_eq!(rx.by_ref().collect::<Vec<_>>().await, [2, 3]);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_recv_many() {
+        let (tx, mut rx) = channel(8);
+        for i in 0..5 {
+            tx.try_send(i).unwrap();
+        }
+        let mut buffer = vec![-1];
+        assert_eq!(rx.recv_many(&mut buffer, 3).await, 3);
+        assert_eq!(rx.recv_many(&mut buffer, 3).await, 2);
+        assert_eq!(buffer, [-1, 0, 1, 2, 3, 4]);
+        assert_eq!(rx.recv_many(&mut buffer, 0).await, 0);
+        let mut recv = Box::pin(rx.recv_many(&mut buffer, 3));
+        assert!(futures::poll!(recv.as_mut()).is_pending());
+        tx.send(5).await.unwrap();
+        drop(tx);
+        assert_eq!(recv.await, 1);
+        assert_eq!(rx.recv_many(&mut buffer, 3).await, 0);
+        assert!(!rx.is_terminated());
+        assert_eq!(rx.next().await, None);
+        assert!(rx.is_terminated());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_worker_receiver_blocks() {
+        let (tx, mut rx) = unbounded();
+        let receiver = task::spawn_blocking(move || {
+            let mut sum = 0;
+            while let Some(value) = rx.recv_blocking().unwrap() {
+                sum += value;
+            }
+            sum
+        });
+        for i in 0..100 {
+            tx.send(i).unwrap();
+            if i % 10 == 0 {
+                crate::time::sleep(std::time::Duration::from_millis(1))
+                    .await
+                    .unwrap();
+            }
+        }
+        drop(tx);
+        assert_eq!(receiver.join().await.unwrap(), 4950);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_main_thread_receives_from_workers() {
+        let (tx, mut rx) = channel(4);
+        let senders = (0..3)
+            .map(|worker| {
+                let tx = tx.clone();
+                task::spawn_blocking(move || {
+                    for i in 0..10 {
+                        tx.send_blocking((worker, i)).unwrap().unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        drop(tx);
+        let mut received = Vec::new();
+        while let Some(message) = rx.recv().await {
+            received.push(message);
+        }
+        for sender in senders {
+            sender.join().await.unwrap();
+        }
+        assert_eq!(received.len(), 30);
+        for worker in 0..3 {
+            let order = received
+                .iter()
+                .filter(|(from, _)| *from == worker)
+                .map(|(_, i)| *i)
+                .collect::<Vec<_>>();
+            assert_eq!(order, (0..10).collect::<Vec<_>>());
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_send_after_receiver_dropped() {
+        let (tx, rx) = unbounded();
+        drop(rx);
+        assert!(tx.is_closed());
+        assert_eq!(tx.send(1), Err(SendError(1)));
+    }
+}
@@ -0,0 +1,244 @@
+use js_sys::{ArrayBuffer, Uint8Array};
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::MessagePort;
+
+use super::bridge::{Listener, Message};
+use crate::Error;
+
+const DEFAULT_CAPACITY: u32 = 4;
+
+/// Creates a channel moving `ArrayBuffer`s between threads without copying them, backed
+/// by a `MessageChannel`.
+///
+/// Buffers are transferred by `postMessage`, which hands their memory over to the
+/// receiving end and detaches them on the sending one, so even large blobs like decoded
+/// video frames or file chunks cost the same to send as small ones. Either end can be
+/// moved to a worker by transferring the port returned by its `into_port` method, and
+/// rebuilding the end from the port there.
+///
+/// The ends speak the [`bridge`](super::bridge) protocol, with buffers as the values, so
+/// a JS end can take part too.
+///
+/// ```ignore
+/// let (mut tx, rx) = transfer::channel()?;
+/// let port = rx.into_port();
+/// worker.post_message_with_transfer(&port, &js_sys::Array::of1(&port))?;
+/// while let Some(chunk) = file_chunks.next().await {
+///     tx.send(chunk).await?;
+/// }
+/// ```
+pub fn channel() -> Result<(Sender, Receiver), Error> {
+    let channel = web_sys::MessageChannel::new()?;
+    Ok((Sender::new(channel.port1()), Receiver::new(channel.port2())))
+}
+
+fn closed_error(end: &str) -> Error {
+    Error::Js(js_sys::Error::new(&format!("transfer: the {end} was dropped")).into())
+}
+
+/// Sending end of a [`channel`], waiting for the receiver to grant credits like a bridge
+/// sender so that buffers don't pile up in the receiver's thread.
+pub struct Sender {
+    listener: Listener,
+    credits: u32,
+    closed: bool,
+}
+
+impl Sender {
+    pub fn new(port: MessagePort) -> Self {
+        Sender {
+            listener: Listener::new(port),
+            credits: 0,
+            closed: false,
+        }
+    }
+
+    /// Transfers `buffer` to the receiver, which leaves it detached, with a length of
+    /// zero. Fails once the receiver was dropped, or if the buffer can't be transferred,
+    /// e.g. because it is already detached.
+    pub async fn send(&mut self, buffer: ArrayBuffer) -> Result<(), Error> {
+        while self.credits == 0 && !self.closed {
+            match self.listener.next().await {
+                Message::Credit(count) => self.credits += count,
+                Message::Close => self.closed = true,
+                Message::Data(_) => {}
+            }
+        }
+        if self.closed {
+            return Err(closed_error("receiver"));
+        }
+        self.listener.post_with_transfer(
+            "data",
+            Some(("value", &buffer)),
+            &js_sys::Array::of1(&buffer),
+        )?;
+        self.credits -= 1;
+        Ok(())
+    }
+
+    /// Sends the bytes of `bytes`, transferring its buffer when the array spans all of
+    /// it. Otherwise, e.g. for views into the module's memory, which is shared and can't
+    /// be transferred, the bytes are copied into a buffer of their own first.
+    pub async fn send_bytes(&mut self, bytes: &Uint8Array) -> Result<(), Error> {
+        let buffer = JsValue::from(bytes.buffer());
+        let whole = buffer.is_instance_of::<ArrayBuffer>()
+            && bytes.byte_offset() == 0
+            && bytes.byte_length() == buffer.unchecked_ref::<ArrayBuffer>().byte_length();
+        let buffer = if whole {
+            buffer.unchecked_into()
+        } else {
+            bytes.slice(0, bytes.length()).buffer()
+        };
+        self.send(buffer).await
+    }
+
+    /// Gives back the port, e.g. to transfer it to the worker that sends the buffers
+    /// instead. Credits received but not used yet are lost, so this should be called
+    /// before the receiver starts receiving.
+    pub fn into_port(self) -> MessagePort {
+        self.listener.into_port()
+    }
+}
+
+impl std::fmt::Debug for Sender {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sender")
+            .field("credits", &self.credits)
+            .field("closed", &self.closed)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Receiving end of a [`channel`].
+pub struct Receiver {
+    listener: Listener,
+    capacity: u32,
+    // Whether the sender was granted its first credits, see `bridge::Receiver`.
+    started: bool,
+    closed: bool,
+}
+
+impl Receiver {
+    pub fn new(port: MessagePort) -> Self {
+        Receiver {
+            listener: Listener::new(port),
+            capacity: DEFAULT_CAPACITY,
+            started: false,
+            closed: false,
+        }
+    }
+
+    /// Number of buffers the sender may send ahead of the receiver. Defaults to 4, since
+    /// buffers sent through this channel tend to be large.
+    pub fn with_capacity(mut self, capacity: u32) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Receives the next buffer, or `None` once the sender was dropped. Fails if the
+    /// other end sent something other than an `ArrayBuffer`.
+    pub async fn recv(&mut self) -> Result<Option<ArrayBuffer>, Error> {
+        if !self.started {
+            self.started = true;
+            self.listener
+                .post("credit", Some(("count", &self.capacity.into())))?;
+        }
+        while !self.closed {
+            match self.listener.next().await {
+                Message::Data(value) => {
+                    self.listener.post("credit", Some(("count", &1.into())))?;
+                    let buffer = value.dyn_into::<ArrayBuffer>().map_err(|_| {
+                        Error::Js(
+                            js_sys::TypeError::new("transfer: expected an ArrayBuffer").into(),
+                        )
+                    })?;
+                    return Ok(Some(buffer));
+                }
+                Message::Close => self.closed = true,
+                Message::Credit(_) => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Like [`recv`](Self::recv), viewing the buffer as bytes.
+    pub async fn recv_bytes(&mut self) -> Result<Option<Uint8Array>, Error> {
+        Ok(self.recv().await?.map(|buffer| Uint8Array::new(&buffer)))
+    }
+
+    /// Gives back the port, e.g. to transfer it to the worker that receives the buffers
+    /// instead. Buffers received but not read yet are lost, so this should be called
+    /// before the first receive.
+    pub fn into_port(self) -> MessagePort {
+        self.listener.into_port()
+    }
+}
+
+impl std::fmt::Debug for Receiver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Receiver")
+            .field("capacity", &self.capacity)
+            .field("closed", &self.closed)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::task;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_buffers_are_moved() {
+        let (mut tx, mut rx) = channel().unwrap();
+        let buffer = Uint8Array::from(&[1u8, 2, 3][..]).buffer();
+        let (sent, received) = futures::join!(tx.send(buffer.clone()), rx.recv());
+        sent.unwrap();
+        assert_eq!(
+            Uint8Array::new(&received.unwrap().unwrap()).to_vec(),
+            [1, 2, 3]
+        );
+        // Detached by the transfer.
+        assert_eq!(buffer.byte_length(), 0);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_views_of_shared_memory_are_copied() {
+        let (mut tx, mut rx) = channel().unwrap();
+        let bytes = vec![4u8, 5, 6];
+        // SAFETY: the view isn't used after `bytes` is dropped, nor across allocations.
+        let view = unsafe { Uint8Array::view(&bytes) };
+        let (sent, received) = futures::join!(tx.send_bytes(&view), rx.recv_bytes());
+        sent.unwrap();
+        assert_eq!(received.unwrap().unwrap().to_vec(), bytes);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_transfer_to_worker() {
+        let (mut tx, rx) = channel().unwrap();
+        let (handle, worker) = task::spawn_with_worker(async move {
+            let port = task::worker_messages().next().await.unwrap();
+            let mut rx = Receiver::new(port.into());
+            let mut total = 0;
+            while let Some(buffer) = rx.recv().await.unwrap() {
+                total += buffer.byte_length();
+            }
+            total
+        });
+        let port = rx.into_port();
+        worker
+            .post_message_with_transfer(&port, &js_sys::Array::of1(&port))
+            .unwrap();
+        for _ in 0..8 {
+            tx.send(ArrayBuffer::new(1 << 20)).await.unwrap();
+        }
+        drop(tx);
+        assert_eq!(handle.join().await.unwrap(), 8 << 20);
+    }
+}
@@ -0,0 +1,336 @@
+use std::future::Future;
+use std::ops::Deref;
+use std::panic::Location;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+
+use crate::sync::deadlock;
+use crate::sync::futex::Futex;
+use crate::sync::BlockingContextError;
+
+// The latest value in shared memory, along with a version bumped by every send. Each
+// receiver keeps the version it last saw, and waits on the futex for a newer one.
+struct Shared<T> {
+    state: RwLock<State<T>>,
+    receivers: AtomicUsize,
+    sender_dropped: AtomicBool,
+    // Notified when a value is sent or the sender is dropped.
+    changed: Futex,
+}
+
+struct State<T> {
+    value: T,
+    version: u64,
+}
+
+/// Creates a channel holding a single value, `init` until the sender replaces it.
+///
+/// Receivers only see the latest value: those that were busy while several values were
+/// sent skip the intermediate ones, which suits state like a configuration or the
+/// progress of a computation. Like those of [`mpsc`](super::mpsc), receivers wait for
+/// changes by blocking workers or asynchronously on any thread.
+pub fn channel<T>(init: T) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        state: RwLock::new(State {
+            value: init,
+            version: 0,
+        }),
+        receivers: AtomicUsize::new(1),
+        sender_dropped: AtomicBool::new(false),
+        changed: Futex::new(),
+    });
+    let receiver = Receiver {
+        shared: shared.clone(),
+        version: 0,
+    };
+    (Sender { shared }, receiver)
+}
+
+/// The sending side of a watch [`channel`], which can be sent to another worker.
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Replaces the value and notifies the receivers. Fails if there are none, in which
+    /// case the value is left untouched.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        if self.shared.receivers.load(Ordering::SeqCst) == 0 {
+            return Err(SendError(value));
+        }
+        self.send_replace(value);
+        Ok(())
+    }
+
+    /// Replaces the value and notifies the receivers, even if there are none, returning
+    /// the previous value.
+    pub fn send_replace(&self, value: T) -> T {
+        let mut previous = value;
+        self.send_modify(|value| std::mem::swap(value, &mut previous));
+        previous
+    }
+
+    /// Modifies the value in place and notifies the receivers, even if there are none.
+    pub fn send_modify(&self, modify: impl FnOnce(&mut T)) {
+        {
+            let mut state = self.shared.state.write().unwrap();
+            modify(&mut state.value);
+            state.version += 1;
+        }
+        deadlock::notifier(&self.shared.changed);
+        self.shared.changed.notify();
+    }
+
+    /// The current value. The value can't be sent while the returned guard is alive.
+    pub fn borrow(&self) -> Ref<'_, T> {
+        Ref {
+            guard: self.shared.state.read().unwrap(),
+        }
+    }
+
+    /// Creates a receiver that sees the current value as already seen.
+    pub fn subscribe(&self) -> Receiver<T> {
+        let version = self.shared.state.read().unwrap().version;
+        self.shared.receivers.fetch_add(1, Ordering::SeqCst);
+        Receiver {
+            shared: self.shared.clone(),
+            version,
+        }
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.shared.receivers.load(Ordering::SeqCst)
+    }
+
+    /// Whether every receiver was dropped.
+    pub fn is_closed(&self) -> bool {
+        self.receiver_count() == 0
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.sender_dropped.store(true, Ordering::SeqCst);
+        self.shared.changed.notify();
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sender")
+            .field("value", &*self.borrow())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The receiving side of a watch [`channel`]. Cloning it creates a receiver that saw the
+/// same versions of the value.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    // Version of the value last marked as seen.
+    version: u64,
+}
+
+impl<T> Receiver<T> {
+    /// The current value, without marking it as seen. The value can't be sent while the
+    /// returned guard is alive, so it should be dropped quickly.
+    pub fn borrow(&self) -> Ref<'_, T> {
+        Ref {
+            guard: self.shared.state.read().unwrap(),
+        }
+    }
+
+    /// The current value, marking it as seen.
+    pub fn borrow_and_update(&mut self) -> Ref<'_, T> {
+        let guard = self.shared.state.read().unwrap();
+        self.version = guard.version;
+        Ref { guard }
+    }
+
+    /// Whether the value changed since it was last marked as seen. Fails once the sender
+    /// was dropped.
+    pub fn has_changed(&self) -> Result<bool, RecvError> {
+        if self.shared.sender_dropped.load(Ordering::SeqCst) {
+            return Err(RecvError);
+        }
+        Ok(self.shared.state.read().unwrap().version != self.version)
+    }
+
+    /// Waits without blocking the current thread for a value that wasn't seen yet, and
+    /// marks it as seen. Fails once the sender was dropped, unless the last value it sent
+    /// wasn't seen yet.
+    ///
+    /// Cancel-safe: if the future is dropped before completing, the value isn't marked
+    /// as seen.
+    #[track_caller]
+    pub fn changed(&mut self) -> impl Future<Output = Result<(), RecvError>> + '_ {
+        let location = Location::caller();
+        async move {
+            let shared = self.shared.clone();
+            let _waiting = deadlock::waiting(&shared.changed, "watch::Receiver::changed", location);
+            shared.changed.wait_until_async(|| self.change()).await
+        }
+    }
+
+    /// Like [`changed`](Self::changed), blocking the current worker until the value
+    /// changes.
+    #[track_caller]
+    pub fn changed_blocking(&mut self) -> Result<Result<(), RecvError>, BlockingContextError> {
+        let shared = self.shared.clone();
+        let _waiting = deadlock::waiting(
+            &shared.changed,
+            "watch::Receiver::changed_blocking",
+            Location::caller(),
+        );
+        shared.changed.wait_until(|| self.change())
+    }
+
+    fn change(&mut self) -> Option<Result<(), RecvError>> {
+        // Read before the version, so that the last value sent is still seen.
+        let sender_dropped = self.shared.sender_dropped.load(Ordering::SeqCst);
+        let version = self.shared.state.read().unwrap().version;
+        if version != self.version {
+            self.version = version;
+            return Some(Ok(()));
+        }
+        sender_dropped.then_some(Err(RecvError))
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.receivers.fetch_add(1, Ordering::SeqCst);
+        Receiver {
+            shared: self.shared.clone(),
+            version: self.version,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receivers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Receiver")
+            .field("value", &*self.borrow())
+            .finish_non_exhaustive()
+    }
+}
+
+/// A borrow of the value of a watch [`channel`].
+pub struct Ref<'a, T> {
+    guard: RwLockReadGuard<'a, State<T>>,
+}
+
+impl<T> Deref for Ref<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard.value
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for Ref<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.guard.value.fmt(f)
+    }
+}
+
+/// Returned when sending on a channel without receivers, with the value.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> std::fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SendError").finish_non_exhaustive()
+    }
+}
+
+impl<T> std::fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the channel has no receivers")
+    }
+}
+
+impl<T> std::error::Error for SendError<T> {}
+
+/// Returned by receivers once the sender was dropped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecvError;
+
+impl std::fmt::Display for RecvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the sender of the channel was dropped")
+    }
+}
+
+impl std::error::Error for RecvError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_progress_from_worker() {
+        let (tx, mut rx) = channel(0u32);
+        let worker = task::spawn_blocking(move || {
+            for progress in 1..=100 {
+                tx.send(progress).unwrap();
+            }
+        });
+        let mut seen = Vec::new();
+        while rx.changed().await.is_ok() {
+            seen.push(*rx.borrow());
+        }
+        worker.join().await.unwrap();
+        // Intermediate values may be skipped, but the last one is always seen.
+        assert_eq!(seen.last(), Some(&100));
+        assert!(seen.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_worker_waits_for_changes() {
+        let (tx, rx) = channel(String::new());
+        let mut worker_rx = rx.clone();
+        let worker = task::spawn_blocking(move || {
+            worker_rx.changed_blocking().unwrap().unwrap();
+            worker_rx.borrow_and_update().clone()
+        });
+        crate::time::sleep(std::time::Duration::from_millis(50))
+            .await
+            .unwrap();
+        tx.send_modify(|value| value.push_str("ready"));
+        assert_eq!(worker.join().await.unwrap(), "ready");
+        assert_eq!(rx.has_changed(), Ok(true));
+        assert_eq!(*rx.borrow(), "ready");
+    }
+
+    #[wasm_bindgen_test]
+    fn test_subscribe_and_close() {
+        let (tx, rx) = channel(1);
+        drop(rx);
+        assert!(tx.is_closed());
+        assert_eq!(tx.send(2), Err(SendError(2)));
+        assert_eq!(tx.send_replace(3), 1);
+        let mut rx = tx.subscribe();
+        assert_eq!(rx.has_changed(), Ok(false));
+        tx.send(4).unwrap();
+        drop(tx);
+        assert_eq!(rx.has_changed(), Err(RecvError));
+        assert_eq!(futures::FutureExt::now_or_never(rx.changed()), Some(Ok(())));
+        assert_eq!(*rx.borrow(), 4);
+        assert_eq!(
+            futures::FutureExt::now_or_never(rx.changed()),
+            Some(Err(RecvError))
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use futures::channel::mpsc;
+use futures::StreamExt;
+use wasm_bindgen::prelude::{Closure, JsValue};
+use wasm_bindgen::JsCast;
+use web_sys::{MessageEvent, MessagePort};
+
+use crate::Error;
+
+mod media;
+
+pub use media::{Closeable, Media, VideoFrame};
+
+const DEFAULT_CHUNK_SIZE: usize = 1 << 20;
+const DEFAULT_WINDOW: usize = 4;
+
+// Listens to a port and buffers the data of its messages. The handler is removed on
+// drop, so that the port doesn't call into a freed closure.
+struct Listener {
+    port: MessagePort,
+    messages: mpsc::UnboundedReceiver<JsValue>,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+}
+
+impl Listener {
+    fn new(port: MessagePort) -> Self {
+        let (tx, messages) = mpsc::unbounded();
+        let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+            tx.unbounded_send(event.data()).ok();
+        });
+        // Setting `onmessage` also starts the port.
+        port.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        Listener {
+            port,
+            messages,
+            _on_message: on_message,
+        }
+    }
+
+    async fn next(&mut self) -> JsValue {
+        // The sender lives in the closure owned by `self`, so the stream never ends.
+        self.messages.next().await.unwrap_or(JsValue::UNDEFINED)
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.port.set_onmessage(None);
+    }
+}
+
+fn protocol_error(message: &str) -> Error {
+    Error::Js(js_sys::Error::new(&format!("chunked transfer: {message}")).into())
+}
+
+/// Sends large byte payloads over a `MessagePort` in slices.
+///
+/// A single `postMessage` of a multi-hundred-MB buffer blocks the sender for as long as
+/// the structured clone takes. Instead, the payload is copied out of the wasm memory one
+/// chunk at a time, and each chunk's buffer is transferred rather than cloned. At most
+/// `window` chunks are in flight: the writer waits for the [`ChunkedReader`] on the other
+/// end to acknowledge older chunks before sending more.
+pub struct ChunkedWriter {
+    listener: Listener,
+    chunk_size: usize,
+    window: usize,
+}
+
+impl ChunkedWriter {
+    pub fn new(port: MessagePort) -> Self {
+        ChunkedWriter {
+            listener: Listener::new(port),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            window: DEFAULT_WINDOW,
+        }
+    }
+
+    /// Size of the slices the payload is split into. Defaults to 1 MiB.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Number of chunks sent ahead of the reader's acknowledgements. Defaults to 4.
+    pub fn with_window(mut self, window: usize) -> Self {
+        self.window = window.max(1);
+        self
+    }
+
+    /// Sends `data`, calling `progress` with the number of bytes acknowledged by the
+    /// reader so far and the total size of the payload.
+    pub async fn write(
+        &mut self,
+        data: &[u8],
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<(), Error> {
+        let header = js_sys::Object::new();
+        js_sys::Reflect::set(&header, &"len".into(), &data.len().into())?;
+        self.listener.port.post_message(&header)?;
+        let mut in_flight = 0;
+        for chunk in data.chunks(self.chunk_size) {
+            while in_flight >= self.window {
+                progress(self.acknowledged().await?, data.len());
+                in_flight -= 1;
+            }
+            let array = js_sys::Uint8Array::new_with_length(chunk.len() as u32);
+            array.copy_from(chunk);
+            self.listener
+                .port
+                .post_message_with_transferable(&array, &js_sys::Array::of1(&array.buffer()))?;
+            in_flight += 1;
+        }
+        while in_flight > 0 {
+            progress(self.acknowledged().await?, data.len());
+            in_flight -= 1;
+        }
+        Ok(())
+    }
+
+    async fn acknowledged(&mut self) -> Result<usize, Error> {
+        let ack = self.listener.next().await;
+        ack.as_f64()
+            .map(|received| received as usize)
+            .ok_or_else(|| protocol_error("expected an acknowledgement"))
+    }
+}
+
+/// Receives the payloads sent by a [`ChunkedWriter`] on the other end of the channel.
+pub struct ChunkedReader {
+    listener: Listener,
+}
+
+impl ChunkedReader {
+    pub fn new(port: MessagePort) -> Self {
+        ChunkedReader {
+            listener: Listener::new(port),
+        }
+    }
+
+    /// Waits for the next payload and reassembles it.
+    pub async fn read(&mut self) -> Result<Vec<u8>, Error> {
+        let header = self.listener.next().await;
+        let len = js_sys::Reflect::get(&header, &"len".into())
+            .ok()
+            .and_then(|len| len.as_f64())
+            .ok_or_else(|| protocol_error("expected a payload header"))? as usize;
+        let mut data = Vec::with_capacity(len);
+        while data.len() < len {
+            let chunk = self
+                .listener
+                .next()
+                .await
+                .dyn_into::<js_sys::Uint8Array>()
+                .map_err(|_| protocol_error("expected a chunk"))?;
+            let start = data.len();
+            data.resize(start + chunk.length() as usize, 0);
+            if data.len() > len {
+                return Err(protocol_error("payload larger than announced"));
+            }
+            chunk.copy_to(&mut data[start..]);
+            self.listener.port.post_message(&data.len().into())?;
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_chunked_transfer() {
+        let channel = web_sys::MessageChannel::new().unwrap();
+        let mut writer = ChunkedWriter::new(channel.port1())
+            .with_chunk_size(1000)
+            .with_window(2);
+        let mut reader = ChunkedReader::new(channel.port2());
+        let data = (0..10_500).map(|i| i as u8).collect::<Vec<_>>();
+        let mut progress = Vec::new();
+        let (written, read) = futures::join!(
+            writer.write(&data, |done, total| progress.push((done, total))),
+            reader.read()
+        );
+        written.unwrap();
+        assert_eq!(read.unwrap(), data);
+        assert_eq!(progress.len(), 11);
+        assert_eq!(progress.last(), Some(&(10_500, 10_500)));
+        assert!(progress.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_empty_payload() {
+        let channel = web_sys::MessageChannel::new().unwrap();
+        let mut writer = ChunkedWriter::new(channel.port1());
+        let mut reader = ChunkedReader::new(channel.port2());
+        let (written, read) = futures::join!(writer.write(&[], |_, _| {}), reader.read());
+        written.unwrap();
+        assert!(read.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,197 @@
+use std::ops::Deref;
+
+use wasm_bindgen::prelude::{wasm_bindgen, JsValue};
+use wasm_bindgen::JsCast;
+use web_sys::MessagePort;
+
+use crate::task::WorkerRef;
+
+#[wasm_bindgen]
+extern "C" {
+    /// A frame of the WebCodecs API, which `web-sys` only exposes behind its unstable
+    /// APIs flag.
+    #[wasm_bindgen(extends = js_sys::Object, typescript_type = "VideoFrame")]
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub type VideoFrame;
+
+    #[wasm_bindgen(method, getter, js_name = displayWidth)]
+    pub fn display_width(this: &VideoFrame) -> u32;
+
+    #[wasm_bindgen(method, getter, js_name = displayHeight)]
+    pub fn display_height(this: &VideoFrame) -> u32;
+
+    #[wasm_bindgen(method)]
+    fn close(this: &VideoFrame);
+}
+
+/// Media objects holding decoded pixels, possibly in GPU memory, that have to be closed
+/// explicitly to be freed early, and that can be transferred between threads.
+pub trait Closeable: JsCast {
+    /// Frees the pixels. Does nothing on objects already closed or transferred away.
+    fn close(&self);
+}
+
+impl Closeable for web_sys::ImageBitmap {
+    fn close(&self) {
+        web_sys::ImageBitmap::close(self);
+    }
+}
+
+impl Closeable for VideoFrame {
+    fn close(&self) {
+        VideoFrame::close(self);
+    }
+}
+
+/// An `ImageBitmap` or [`VideoFrame`] owned by the current thread, closed when dropped.
+///
+/// Decoders hand out frames faster than the garbage collector reclaims them, and
+/// `VideoDecoder` stalls once too many of its frames are still open, so frames should be
+/// closed as soon as they are processed. Sending a frame to a worker with
+/// [`send`](Self::send) transfers its pixels instead of copying them, and hands the
+/// responsibility of closing it over to the receiving side, which takes it with
+/// [`from_message`](Self::from_message).
+///
+/// ```ignore
+/// let (handle, worker) = task::spawn_with_worker(async move {
+///     let frame = task::worker_messages().next().await.unwrap();
+///     let frame = Media::<VideoFrame>::from_message(frame).unwrap();
+///     let result = process(&frame);
+///     // `frame` is closed here.
+///     result
+/// });
+/// Media::new(frame).send(&worker)?;
+/// ```
+#[derive(Debug)]
+pub struct Media<T: Closeable> {
+    inner: Option<T>,
+}
+
+impl<T: Closeable> Media<T> {
+    pub fn new(inner: T) -> Self {
+        Media { inner: Some(inner) }
+    }
+
+    /// Takes ownership of the media object received in a message, or returns `None` if
+    /// the message holds something else.
+    pub fn from_message(data: JsValue) -> Option<Self> {
+        data.dyn_into::<T>().ok().map(Media::new)
+    }
+
+    /// Transfers the object to the worker running a task, see
+    /// [`spawn_with_worker`](crate::task::spawn_with_worker). On failure the object is
+    /// still owned by the current thread, and closed.
+    pub fn send(self, worker: &WorkerRef) -> Result<(), JsValue> {
+        let inner: JsValue = self.into_inner().into();
+        worker.post_message_with_transfer(&inner, &js_sys::Array::of1(&inner))
+    }
+
+    /// Transfers the object over `port`, e.g. to reach a worker through a
+    /// `MessageChannel`.
+    pub fn send_to_port(self, port: &MessagePort) -> Result<(), JsValue> {
+        let inner: JsValue = self.into_inner().into();
+        port.post_message_with_transferable(&inner, &js_sys::Array::of1(&inner))
+    }
+
+    /// Transfers the object from the worker running the current task back to the thread
+    /// that spawned it, where it is received by the listeners of the
+    /// [`WorkerRef`] for the `message` event.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a worker.
+    pub fn send_to_parent(self) -> Result<(), JsValue> {
+        let inner: JsValue = self.into_inner().into();
+        let post_message = js_sys::Reflect::get(&js_sys::global(), &"postMessage".into())
+            .ok()
+            .and_then(|post_message| post_message.dyn_into::<js_sys::Function>().ok())
+            .expect("`send_to_parent` called outside of a worker");
+        post_message.call2(&js_sys::global(), &inner, &js_sys::Array::of1(&inner))?;
+        Ok(())
+    }
+
+    /// Gives up ownership of the object, leaving closing it to the caller.
+    pub fn into_inner(mut self) -> T {
+        self.inner.take().unwrap()
+    }
+}
+
+impl<T: Closeable> Deref for Media<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.inner.as_ref().unwrap()
+    }
+}
+
+impl<T: Closeable> Drop for Media<T> {
+    fn drop(&mut self) {
+        if let Some(inner) = &self.inner {
+            inner.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use wasm_bindgen::prelude::Closure;
+    use wasm_bindgen_futures::JsFuture;
+
+    use super::*;
+    use crate::task;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    async fn image_bitmap(width: u32, height: u32) -> web_sys::ImageBitmap {
+        let create = js_sys::Function::new_with_args(
+            "width, height",
+            "return createImageBitmap(new ImageData(width, height));",
+        );
+        let promise = create
+            .call2(&JsValue::UNDEFINED, &width.into(), &height.into())
+            .unwrap();
+        JsFuture::from(promise.unchecked_into::<js_sys::Promise>())
+            .await
+            .unwrap()
+            .unchecked_into()
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_image_bitmap_round_trip() {
+        let (handle, worker) = task::spawn_with_worker(async move {
+            let message = task::worker_messages().next().await.unwrap();
+            let bitmap = Media::<web_sys::ImageBitmap>::from_message(message).unwrap();
+            let size = (bitmap.width(), bitmap.height());
+            bitmap.send_to_parent().unwrap();
+            size
+        });
+        let (tx, rx) = futures::channel::oneshot::channel();
+        let mut tx = Some(tx);
+        let on_message = Closure::<dyn FnMut(web_sys::MessageEvent)>::new(
+            move |event: web_sys::MessageEvent| {
+                if let Some(bitmap) = Media::<web_sys::ImageBitmap>::from_message(event.data()) {
+                    if let Some(tx) = tx.take() {
+                        tx.send(bitmap).ok();
+                    }
+                }
+            },
+        );
+        worker
+            .add_event_listener("message", on_message.as_ref().unchecked_ref())
+            .unwrap();
+        let bitmap = image_bitmap(4, 2).await;
+        Media::new(bitmap.clone()).send(&worker).unwrap();
+        // Transferred away, so detached here.
+        assert_eq!(bitmap.width(), 0);
+        assert_eq!(handle.join().await.unwrap(), (4, 2));
+        let bitmap = rx.await.unwrap();
+        assert_eq!((bitmap.width(), bitmap.height()), (4, 2));
+        let inner = bitmap.deref().clone();
+        drop(bitmap);
+        // Closed on drop.
+        assert_eq!(inner.width(), 0);
+    }
+}
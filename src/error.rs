@@ -0,0 +1,127 @@
+use wasm_bindgen::{JsCast, JsValue};
+
+use crate::task::{JoinError, SpawnError};
+use crate::time::{Elapsed, TimeoutError};
+
+/// Error type shared by the fallible APIs of the crate.
+#[derive(Debug)]
+pub enum Error {
+    Join(JoinError),
+    Spawn(SpawnError),
+    Timeout(TimeoutError),
+    Io(std::io::Error),
+    Js(JsValue),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Join(err) => write!(f, "{err}"),
+            Error::Spawn(err) => write!(f, "{err}"),
+            Error::Timeout(err) => write!(f, "{err}"),
+            Error::Io(err) => write!(f, "{err}"),
+            Error::Js(value) => match value.dyn_ref::<js_sys::Error>() {
+                Some(err) => write!(f, "{}", String::from(err.message())),
+                None => match value.as_string() {
+                    Some(message) => write!(f, "{message}"),
+                    None => write!(f, "{value:?}"),
+                },
+            },
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Join(err) => Some(err),
+            Error::Spawn(err) => Some(err),
+            Error::Timeout(err) => Some(err),
+            Error::Io(err) => Some(err),
+            Error::Js(_) => None,
+        }
+    }
+}
+
+impl From<JoinError> for Error {
+    fn from(err: JoinError) -> Self {
+        Error::Join(err)
+    }
+}
+
+impl From<SpawnError> for Error {
+    fn from(err: SpawnError) -> Self {
+        Error::Spawn(err)
+    }
+}
+
+impl From<Elapsed> for Error {
+    fn from(err: Elapsed) -> Self {
+        Error::Timeout(err.into())
+    }
+}
+
+impl From<TimeoutError> for Error {
+    fn from(err: TimeoutError) -> Self {
+        Error::Timeout(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<JsValue> for Error {
+    fn from(value: JsValue) -> Self {
+        Error::Js(value)
+    }
+}
+
+impl From<Error> for JsValue {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Js(value) => value,
+            err => js_sys::Error::new(&err.to_string()).into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn test_display() {
+        assert_eq!(
+            Error::from(JoinError::Aborted).to_string(),
+            "thread was aborted"
+        );
+        assert_eq!(Error::from(Elapsed).to_string(), "deadline has elapsed");
+        assert_eq!(Error::from(JsValue::from_str("boom")).to_string(), "boom");
+        let err = Error::from(JsValue::from(js_sys::Error::new("boom")));
+        assert_eq!(err.to_string(), "boom");
+        let err = Error::from(SpawnError::new(JsValue::from_str("blocked")));
+        assert_eq!(err.to_string(), "failed to spawn worker: blocked");
+    }
+
+    #[wasm_bindgen_test]
+    fn test_into_js_value() {
+        let value = JsValue::from_str("boom");
+        assert_eq!(JsValue::from(Error::from(value.clone())), value);
+        let value = JsValue::from(Error::from(JoinError::unknown_panic()));
+        let err = value.dyn_into::<js_sys::Error>().unwrap();
+        assert_eq!(String::from(err.message()), "thread panicked");
+    }
+
+    #[wasm_bindgen_test]
+    fn test_match_kind() {
+        let err = Error::from(std::io::Error::new(std::io::ErrorKind::NotFound, "missing"));
+        assert!(matches!(err, Error::Io(ref err) if err.kind() == std::io::ErrorKind::NotFound));
+    }
+}
@@ -0,0 +1,147 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures::future;
+
+use crate::sync::web_lock::{try_web_lock, web_lock, WebLockGuard};
+use crate::task::{self, blocking};
+
+// Browsers only allow a limited number of `FileSystemSyncAccessHandle`s to be open at
+// once and throw `InvalidStateError` past that. File tasks are queued behind as many
+// Web Locks, shared by every tab and worker of the origin like the handles themselves.
+// The locks are taken by the workers of the tasks before running the closures, so that
+// they're released when a worker is done or terminated.
+const DEFAULT_MAX_SYNC_ACCESS_HANDLES: usize = 8;
+
+static LIMIT: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_SYNC_ACCESS_HANDLES);
+
+/// How many closures of [`spawn_blocking`] may run at once across the origin, 8 unless
+/// changed with [`set_max_sync_access_handles`].
+pub fn max_sync_access_handles() -> usize {
+    LIMIT.load(Ordering::SeqCst)
+}
+
+/// Sets how many closures of [`spawn_blocking`] may run at once across the origin.
+///
+/// Tabs and workers using different limits share the first slots, so the lowest limit
+/// applies to the closures of every one of them.
+///
+/// # Panics
+///
+/// Panics if `max` is zero.
+#[track_caller]
+pub fn set_max_sync_access_handles(max: usize) {
+    assert!(max > 0, "`max_sync_access_handles` must be at least 1");
+    LIMIT.store(max, Ordering::SeqCst);
+}
+
+/// Like [`task::spawn_blocking`], but waits for a free sync-access-handle slot of the
+/// origin before running the closure, so the closure may open one OPFS sync access
+/// handle. The worker holds the slot until the closure returns or the worker is
+/// terminated, e.g. with [`abort_hard`](blocking::JoinHandle::abort_hard); a closure
+/// aborted while it waits for a slot never runs.
+///
+/// Without the Web Locks API, closures run without a limit.
+#[track_caller]
+pub fn spawn_blocking<T>(f: impl FnOnce() -> T + 'static) -> blocking::JoinHandle<T>
+where
+    T: 'static,
+{
+    task::try_spawn_blocking_gated(None, Some(acquire_slot()), f)
+        .unwrap_or_else(|err| panic!("{err}"))
+}
+
+fn slot_name(slot: usize) -> String {
+    format!("wasmt:fs:sync-access-handle:{slot}")
+}
+
+// Takes the first free slot, or waits for every slot when they're all taken and keeps
+// the first one granted. The other requests are dropped, which releases their slots as
+// soon as they're granted.
+async fn acquire_slot() -> Option<WebLockGuard> {
+    let max = max_sync_access_handles();
+    for slot in 0..max {
+        match try_web_lock(&slot_name(slot)).await {
+            Ok(Some(guard)) => return Some(guard),
+            Ok(None) => {}
+            Err(_) => return None,
+        }
+    }
+    let requests = (0..max).map(|slot| Box::pin(async move { web_lock(&slot_name(slot)).await }));
+    let (guard, _, _) = future::select_all(requests).await;
+    guard.ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::task::JoinError;
+    use crate::time::{sleep, sleep_blocking, Instant};
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_spawn_blocking_is_limited() {
+        set_max_sync_access_handles(1);
+        let running = Arc::new(AtomicUsize::new(0));
+        let handles = (0..3)
+            .map(|_| {
+                let running = running.clone();
+                spawn_blocking(move || {
+                    let concurrent = running.fetch_add(1, Ordering::SeqCst);
+                    sleep_blocking(Duration::from_millis(50));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    concurrent
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            assert_eq!(handle.join().await.unwrap(), 0);
+        }
+        set_max_sync_access_handles(DEFAULT_MAX_SYNC_ACCESS_HANDLES);
+        assert_eq!(max_sync_access_handles(), DEFAULT_MAX_SYNC_ACCESS_HANDLES);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_waits_for_any_slot() {
+        set_max_sync_access_handles(2);
+        let mut first = spawn_blocking(|| sleep_blocking(Duration::from_secs(10)));
+        let mut second = spawn_blocking(|| sleep_blocking(Duration::from_secs(10)));
+        sleep(Duration::from_millis(50)).await.unwrap();
+        let queued = spawn_blocking(|| ());
+        sleep(Duration::from_millis(50)).await.unwrap();
+        // Whichever slot frees up first is taken.
+        let start = Instant::now();
+        assert!(second.abort_hard());
+        queued.join().await.unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(first.abort_hard());
+        set_max_sync_access_handles(DEFAULT_MAX_SYNC_ACCESS_HANDLES);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_aborted_spawns_free_their_slot() {
+        set_max_sync_access_handles(1);
+        let mut holding = spawn_blocking(|| sleep_blocking(Duration::from_secs(10)));
+        let ran = Arc::new(AtomicBool::new(false));
+        let mut queued = spawn_blocking({
+            let ran = ran.clone();
+            move || ran.store(true, Ordering::SeqCst)
+        });
+        sleep(Duration::from_millis(50)).await.unwrap();
+        queued.abort();
+        assert!(holding.abort_hard());
+        // The terminated worker gave its slot back to the origin.
+        let start = Instant::now();
+        spawn_blocking(|| ()).join().await.unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(queued.join().await, Err(JoinError::Aborted));
+        assert!(!ran.load(Ordering::SeqCst));
+        set_max_sync_access_handles(DEFAULT_MAX_SYNC_ACCESS_HANDLES);
+    }
+}
@@ -0,0 +1,193 @@
+use std::io;
+use std::pin::Pin;
+use std::rc::Rc;
+
+use futures::io::{AsyncRead, AsyncReadExt};
+use futures::lock::Mutex;
+use futures::stream::{self, LocalBoxStream, Stream, StreamExt};
+use wasm_bindgen::prelude::{Closure, JsValue};
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+use crate::Error;
+
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+type Body = Pin<Box<dyn Stream<Item = io::Result<Vec<u8>>>>>;
+
+/// Whether `fetch` accepts a `ReadableStream` as request body in the current browser.
+///
+/// Uses the detection recommended by the Fetch spec authors: browsers supporting
+/// streaming uploads read the `duplex` option and don't treat the stream as a string body.
+pub fn supports_streaming_uploads() -> bool {
+    js_sys::Function::new_no_args(
+        "
+        try {
+            let duplexAccessed = false;
+            const hasContentType = new Request('data:,', {
+                body: new ReadableStream(),
+                method: 'POST',
+                get duplex() {
+                    duplexAccessed = true;
+                    return 'half';
+                },
+            }).headers.has('Content-Type');
+            return duplexAccessed && !hasContentType;
+        } catch {
+            return false;
+        }
+        ",
+    )
+    .call0(&JsValue::UNDEFINED)
+    .ok()
+    .and_then(|supported| supported.as_bool())
+    .unwrap_or(false)
+}
+
+/// Sends a request whose body is read from `body` as it is being uploaded, without
+/// loading it fully into memory.
+///
+/// Browsers without streaming uploads get the whole body buffered first. Errors of the
+/// stream abort the request.
+pub async fn upload<S>(url: &str, method: &str, body: S) -> Result<web_sys::Response, Error>
+where
+    S: Stream<Item = io::Result<Vec<u8>>> + 'static,
+{
+    let mut body: Body = Box::pin(body);
+    let init = js_sys::Object::new();
+    js_sys::Reflect::set(&init, &"method".into(), &method.into())?;
+    // The pull callback is kept alive until the request completes, since the browser
+    // reads the body from it.
+    let pull = if supports_streaming_uploads() {
+        let (stream, pull) = readable_stream(body)?;
+        js_sys::Reflect::set(&init, &"body".into(), &stream)?;
+        js_sys::Reflect::set(&init, &"duplex".into(), &"half".into())?;
+        Some(pull)
+    } else {
+        let mut buffer = Vec::new();
+        while let Some(chunk) = body.next().await {
+            buffer.extend_from_slice(&chunk?);
+        }
+        let buffer = js_sys::Uint8Array::from(&buffer[..]);
+        js_sys::Reflect::set(&init, &"body".into(), &buffer)?;
+        None
+    };
+    let fetch = js_sys::Reflect::get(&js_sys::global(), &"fetch".into())?
+        .unchecked_into::<js_sys::Function>();
+    let promise = fetch
+        .call2(&JsValue::UNDEFINED, &url.into(), &init)?
+        .unchecked_into::<js_sys::Promise>();
+    let response = JsFuture::from(promise).await;
+    drop(pull);
+    Ok(response?.unchecked_into())
+}
+
+/// Like [`upload`], reading the body from `reader` in 64 KiB chunks.
+pub async fn upload_reader<R>(
+    url: &str,
+    method: &str,
+    reader: R,
+) -> Result<web_sys::Response, Error>
+where
+    R: AsyncRead + Unpin + 'static,
+{
+    upload(url, method, read_chunks(reader)).await
+}
+
+fn read_chunks<R>(reader: R) -> LocalBoxStream<'static, io::Result<Vec<u8>>>
+where
+    R: AsyncRead + Unpin + 'static,
+{
+    stream::unfold(Some(reader), |reader| async move {
+        let mut reader = reader?;
+        let mut chunk = vec![0; READ_CHUNK_SIZE];
+        match reader.read(&mut chunk).await {
+            Ok(0) => None,
+            Ok(n) => {
+                chunk.truncate(n);
+                Some((Ok(chunk), Some(reader)))
+            }
+            Err(err) => Some((Err(err), None)),
+        }
+    })
+    .boxed_local()
+}
+
+type Pull = Closure<dyn FnMut(JsValue) -> js_sys::Promise>;
+
+// Wraps `body` in a `ReadableStream` pulling one chunk at a time, so that the browser
+// only reads ahead as far as its own buffering goes.
+fn readable_stream(body: Body) -> Result<(JsValue, Pull), Error> {
+    let body = Rc::new(Mutex::new(body));
+    let pull = Closure::<dyn FnMut(JsValue) -> js_sys::Promise>::new(move |controller| {
+        let body = body.clone();
+        wasm_bindgen_futures::future_to_promise(async move {
+            let next = body.lock().await.next().await;
+            let (method, argument) = match next {
+                Some(Ok(chunk)) => ("enqueue", js_sys::Uint8Array::from(&chunk[..]).into()),
+                Some(Err(err)) => ("error", js_sys::Error::new(&err.to_string()).into()),
+                None => ("close", JsValue::UNDEFINED),
+            };
+            js_sys::Reflect::get(&controller, &method.into())?
+                .unchecked_into::<js_sys::Function>()
+                .call1(&controller, &argument)?;
+            Ok(JsValue::UNDEFINED)
+        })
+    });
+    let source = js_sys::Object::new();
+    js_sys::Reflect::set(&source, &"pull".into(), pull.as_ref())?;
+    let constructor = js_sys::Reflect::get(&js_sys::global(), &"ReadableStream".into())?
+        .unchecked_into::<js_sys::Function>();
+    let stream = js_sys::Reflect::construct(&constructor, &js_sys::Array::of1(&source))?;
+    Ok((stream, pull))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_readable_stream_body() {
+        let chunks = (0..3u8).map(|i| Ok(vec![i; 1000])).collect::<Vec<_>>();
+        let (stream, _pull) = readable_stream(Box::pin(stream::iter(chunks))).unwrap();
+        let constructor = js_sys::Reflect::get(&js_sys::global(), &"Response".into())
+            .unwrap()
+            .unchecked_into::<js_sys::Function>();
+        let response =
+            js_sys::Reflect::construct(&constructor, &js_sys::Array::of1(&stream)).unwrap();
+        let buffer = js_sys::Reflect::get(&response, &"arrayBuffer".into())
+            .unwrap()
+            .unchecked_into::<js_sys::Function>()
+            .call0(&response)
+            .unwrap();
+        let buffer = JsFuture::from(buffer.unchecked_into::<js_sys::Promise>())
+            .await
+            .unwrap();
+        let bytes = js_sys::Uint8Array::new(&buffer).to_vec();
+        assert_eq!(bytes.len(), 3000);
+        assert_eq!(bytes[2999], 2);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_read_chunks() {
+        let data = vec![7u8; READ_CHUNK_SIZE + 10];
+        let chunks = read_chunks(futures::io::Cursor::new(data))
+            .map(Result::unwrap)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(
+            chunks.iter().map(Vec::len).collect::<Vec<_>>(),
+            [READ_CHUNK_SIZE, 10]
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_supports_streaming_uploads() {
+        // Only checks that the detection doesn't throw, support depends on the browser.
+        supports_streaming_uploads();
+    }
+}
@@ -1,8 +1,35 @@
+pub mod actor;
+pub mod alloc;
+pub mod channel;
+pub mod codec;
+mod error;
+pub mod fs;
+pub mod http;
+pub mod main_thread;
+pub mod net;
+pub mod pipeline;
+pub mod pool;
+pub mod registry;
+pub mod runtime;
+pub mod supervisor;
+pub mod sync;
 pub mod task;
+pub mod telemetry;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+pub mod thread;
 pub mod time;
 pub mod utils;
 mod worker;
 
+pub use error::Error;
+
+// Used by the crate's macros.
+#[doc(hidden)]
+pub mod __private {
+    pub use futures;
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 compile_error!("This crate can only be compiled for wasm32-unknown-unknown target");
 #[cfg(not(any(
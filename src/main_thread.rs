@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+use crate::task::schedule::{next_turn, Schedule};
+use crate::time::Instant;
+
+/// Calls `f` on every item of `iter` on the current thread, yielding to the event loop
+/// whenever a chunk of calls took longer than `budget_ms`, and returns the outputs in
+/// order.
+///
+/// Meant for work that has to stay on the main thread, e.g. because it touches the DOM,
+/// but would otherwise freeze the page. Input and rendering are handled in between
+/// chunks, so `f` should be short compared to the budget.
+pub async fn chunked<I, F, R>(iter: I, budget_ms: f64, mut f: F) -> Vec<R>
+where
+    I: IntoIterator,
+    F: FnMut(I::Item) -> R,
+{
+    let budget = Duration::from_secs_f64(budget_ms.max(0.0) / 1000.0);
+    let iter = iter.into_iter();
+    let mut outputs = Vec::with_capacity(iter.size_hint().0);
+    let mut chunk_start = Instant::now();
+    for item in iter {
+        if chunk_start.elapsed() >= budget {
+            next_turn(Schedule::Macrotask).await.ok();
+            chunk_start = Instant::now();
+        }
+        outputs.push(f(item));
+    }
+    outputs
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::task::spawn_local;
+    use crate::time::{sleep, Instant};
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_chunked_yields_to_event_loop() {
+        let timer_fired = Rc::new(Cell::new(false));
+        let timer = spawn_local({
+            let timer_fired = timer_fired.clone();
+            async move {
+                sleep(Duration::ZERO).await.unwrap();
+                timer_fired.set(true);
+            }
+        });
+        let outputs = chunked(0..20, 5.0, |i| {
+            let start = Instant::now();
+            while start.elapsed() < Duration::from_millis(1) {}
+            (i, timer_fired.get())
+        })
+        .await;
+        assert_eq!(outputs.len(), 20);
+        assert!(outputs.iter().enumerate().all(|(i, &(item, _))| item == i));
+        assert!(!outputs[0].1);
+        assert!(outputs[19].1);
+        timer.join().await.unwrap();
+    }
+}
@@ -0,0 +1,294 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::channel::mpsc;
+use futures::{FutureExt, Stream, StreamExt};
+use wasm_bindgen::prelude::{Closure, JsValue};
+use wasm_bindgen::JsCast;
+
+use crate::time::{sleep, Sleep};
+use crate::Error;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// A server-sent event.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Event {
+    /// The `event:` field, `"message"` when the server didn't set it.
+    pub event: String,
+    pub data: String,
+    /// The last `id:` field received so far on the connection, possibly from an earlier
+    /// event.
+    pub id: String,
+}
+
+/// Connects to the server-sent events endpoint at `url` with the default options, see
+/// [`Builder`].
+pub fn connect(url: &str) -> Result<EventStream, Error> {
+    Builder::new(url).connect()
+}
+
+/// Configures a connection to a server-sent events endpoint.
+///
+/// ```ignore
+/// let mut events = sse::Builder::new("/prices").event("tick").connect()?;
+/// while let Some(event) = events.next().await {
+///     update(&event.data);
+/// }
+/// ```
+pub struct Builder {
+    url: String,
+    with_credentials: bool,
+    events: Vec<String>,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Builder {
+    pub fn new(url: &str) -> Self {
+        Builder {
+            url: url.to_owned(),
+            with_credentials: false,
+            events: vec!["message".to_owned()],
+            initial_backoff: INITIAL_BACKOFF,
+            max_backoff: MAX_BACKOFF,
+        }
+    }
+
+    /// Whether cross-origin requests send cookies, like the `withCredentials` option of
+    /// `EventSource`.
+    pub fn with_credentials(mut self, with_credentials: bool) -> Self {
+        self.with_credentials = with_credentials;
+        self
+    }
+
+    /// Also receives the events whose `event:` field is `event`. Only unnamed events,
+    /// of type `"message"`, are received by default.
+    pub fn event(mut self, event: &str) -> Self {
+        self.events.push(event.to_owned());
+        self
+    }
+
+    /// The delay before reconnecting after the connection failed, doubling on every
+    /// consecutive failure up to `max`. Defaults to 1 second, up to 30 seconds.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    /// Opens the connection. Fails if `url` can't be parsed.
+    pub fn connect(self) -> Result<EventStream, Error> {
+        let (tx, rx) = mpsc::unbounded();
+        let backoff = self.initial_backoff;
+        let mut stream = EventStream {
+            builder: self,
+            tx,
+            rx,
+            connection: None,
+            generation: 0,
+            reconnect: None,
+            backoff,
+        };
+        stream.open()?;
+        Ok(stream)
+    }
+}
+
+enum Signal {
+    Open,
+    Event(Event),
+    Error,
+}
+
+type Listener = Closure<dyn FnMut(web_sys::MessageEvent)>;
+
+// An open `EventSource`, closed when dropped.
+struct Connection {
+    source: web_sys::EventSource,
+    listeners: Vec<(String, Listener)>,
+    _on_open: Closure<dyn FnMut()>,
+    _on_error: Closure<dyn FnMut()>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.source.close();
+        for (event, listener) in &self.listeners {
+            self.source
+                .remove_event_listener_with_callback(event, listener.as_ref().unchecked_ref())
+                .ok();
+        }
+        self.source.set_onopen(None);
+        self.source.set_onerror(None);
+    }
+}
+
+/// Stream of the events of a server-sent events endpoint, returned by [`connect`].
+///
+/// The connection is reopened whenever it fails, e.g. on network errors, error responses
+/// or when the server ends the response, with an exponential backoff timed by
+/// [`time::sleep`](crate::time::sleep) that resets once a connection succeeds. The
+/// browser's own reconnection is bypassed, so the `Last-Event-ID` header isn't sent and
+/// the server's `retry:` field is ignored. The stream never ends, and the connection is
+/// closed when it is dropped.
+///
+/// Works on the main thread and in workers, but stays on the thread that created it.
+pub struct EventStream {
+    builder: Builder,
+    tx: mpsc::UnboundedSender<(u64, Signal)>,
+    rx: mpsc::UnboundedReceiver<(u64, Signal)>,
+    connection: Option<Connection>,
+    // Tags the signals of each connection, to drop those of closed ones still queued.
+    generation: u64,
+    reconnect: Option<Sleep>,
+    backoff: Duration,
+}
+
+impl EventStream {
+    fn open(&mut self) -> Result<(), Error> {
+        self.generation += 1;
+        let generation = self.generation;
+        let init = web_sys::EventSourceInit::new();
+        init.set_with_credentials(self.builder.with_credentials);
+        let source =
+            web_sys::EventSource::new_with_event_source_init_dict(&self.builder.url, &init)?;
+        let signal = |signal: fn(JsValue) -> Signal| {
+            let tx = self.tx.clone();
+            move |value: JsValue| {
+                tx.unbounded_send((generation, signal(value))).ok();
+            }
+        };
+        let listeners = self
+            .builder
+            .events
+            .iter()
+            .map(|event| {
+                let send = signal(|value| {
+                    let message = value.unchecked_into::<web_sys::MessageEvent>();
+                    Signal::Event(Event {
+                        event: message.type_(),
+                        data: message.data().as_string().unwrap_or_default(),
+                        id: message.last_event_id(),
+                    })
+                });
+                let listener =
+                    Listener::new(move |message: web_sys::MessageEvent| send(message.into()));
+                source
+                    .add_event_listener_with_callback(event, listener.as_ref().unchecked_ref())?;
+                Ok((event.clone(), listener))
+            })
+            .collect::<Result<Vec<_>, JsValue>>()?;
+        let open = signal(|_| Signal::Open);
+        let on_open = Closure::<dyn FnMut()>::new(move || open(JsValue::UNDEFINED));
+        source.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+        let error = signal(|_| Signal::Error);
+        let on_error = Closure::<dyn FnMut()>::new(move || error(JsValue::UNDEFINED));
+        source.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+        self.connection = Some(Connection {
+            source,
+            listeners,
+            _on_open: on_open,
+            _on_error: on_error,
+        });
+        Ok(())
+    }
+}
+
+impl Stream for EventStream {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        let this = &mut *self;
+        loop {
+            if let Some(reconnect) = &mut this.reconnect {
+                let Poll::Ready(slept) = reconnect.poll_unpin(cx) else {
+                    return Poll::Pending;
+                };
+                this.reconnect = None;
+                if slept.is_err() {
+                    // The runtime shut down while waiting to reconnect.
+                    return Poll::Ready(None);
+                }
+                // The URL was valid for the first connection, so it is for this one.
+                this.open().ok();
+            }
+            let Poll::Ready(Some((generation, signal))) = this.rx.poll_next_unpin(cx) else {
+                return Poll::Pending;
+            };
+            if generation != this.generation {
+                continue;
+            }
+            match signal {
+                Signal::Open => this.backoff = this.builder.initial_backoff,
+                Signal::Event(event) => return Poll::Ready(Some(event)),
+                Signal::Error => {
+                    this.connection = None;
+                    this.reconnect = Some(sleep(this.backoff));
+                    this.backoff = (this.backoff * 2).min(this.builder.max_backoff);
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for EventStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventStream")
+            .field("url", &self.builder.url)
+            .field("connected", &self.connection.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn event_stream_url(body: &str) -> String {
+        let options = web_sys::BlobPropertyBag::new();
+        options.set_type("text/event-stream");
+        let parts = js_sys::Array::of1(&body.into());
+        let blob = web_sys::Blob::new_with_str_sequence_and_options(&parts, &options).unwrap();
+        web_sys::Url::create_object_url_with_blob(&blob).unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_events_in_worker() {
+        let handle = task::spawn(async move {
+            let url = event_stream_url("id: 1\ndata: hello\n\nevent: tick\ndata: 2\n\n");
+            let events = Builder::new(&url)
+                .event("tick")
+                .backoff(Duration::from_millis(10), Duration::from_millis(10))
+                .connect()
+                .unwrap();
+            // The response ends after two events, so the stream reconnects and receives
+            // them again.
+            events.take(4).collect::<Vec<_>>().await
+        });
+        let events = handle.join().await.unwrap();
+        let hello = Event {
+            event: "message".to_owned(),
+            data: "hello".to_owned(),
+            id: "1".to_owned(),
+        };
+        let tick = Event {
+            event: "tick".to_owned(),
+            data: "2".to_owned(),
+            id: "1".to_owned(),
+        };
+        assert_eq!(events, [hello.clone(), tick.clone(), hello, tick]);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_invalid_url() {
+        assert!(connect("http://[::1").is_err());
+    }
+}
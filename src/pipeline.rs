@@ -0,0 +1,192 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use futures::channel::mpsc;
+use futures::lock::Mutex;
+use futures::{SinkExt, StreamExt};
+
+use crate::task::{self, r#async::JoinHandle, JoinError};
+
+const DEFAULT_CAPACITY: usize = 16;
+
+/// A chain of processing stages running on workers, connected by bounded channels.
+///
+/// ```ignore
+/// let mut pipeline = Pipeline::<Vec<u8>>::new()
+///     .stage(decode, 2)
+///     .stage(transform, 4)
+///     .sink(upload);
+/// for chunk in chunks {
+///     pipeline.send(chunk).await?;
+/// }
+/// pipeline.close();
+/// pipeline.join().await?;
+/// ```
+///
+/// Each stage pulls items from the previous one with `parallelism` tasks, so items may
+/// leave a stage out of order. When a channel is full, the upstream stage waits, which
+/// bounds the number of in-flight items.
+pub struct Pipeline<I, O = I> {
+    input: mpsc::Sender<I>,
+    output: mpsc::Receiver<O>,
+    tasks: Vec<JoinHandle<()>>,
+    capacity: usize,
+}
+
+impl<I: 'static> Pipeline<I, I> {
+    pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+
+    /// Creates a pipeline whose channels buffer up to `capacity` items between stages.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let (input, output) = mpsc::channel(capacity);
+        Pipeline {
+            input,
+            output,
+            tasks: Vec::new(),
+            capacity,
+        }
+    }
+}
+
+impl<I: 'static> Default for Pipeline<I, I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I, O: 'static> Pipeline<I, O> {
+    pub fn stage<N, F, Fut>(mut self, f: F, parallelism: usize) -> Pipeline<I, N>
+    where
+        F: Fn(O) -> Fut + Clone + 'static,
+        Fut: Future<Output = N> + 'static,
+        N: 'static,
+    {
+        let (tx, output) = mpsc::channel(self.capacity);
+        let input = Arc::new(Mutex::new(self.output));
+        for _ in 0..parallelism.max(1) {
+            let f = f.clone();
+            let input = input.clone();
+            let mut tx = tx.clone();
+            self.tasks.push(task::spawn(async move {
+                loop {
+                    let item = input.lock().await.next().await;
+                    let Some(item) = item else {
+                        break;
+                    };
+                    if tx.send(f(item).await).await.is_err() {
+                        break;
+                    }
+                }
+            }));
+        }
+        Pipeline {
+            input: self.input,
+            output,
+            tasks: self.tasks,
+            capacity: self.capacity,
+        }
+    }
+
+    /// Terminates the pipeline with a task consuming the output of the last stage.
+    pub fn sink<F, Fut>(mut self, mut f: F) -> PipelineHandle<I>
+    where
+        F: FnMut(O) -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        let mut output = self.output;
+        self.tasks.push(task::spawn(async move {
+            while let Some(item) = output.next().await {
+                f(item).await;
+            }
+        }));
+        PipelineHandle {
+            input: self.input,
+            tasks: self.tasks,
+        }
+    }
+
+    /// Splits the pipeline into its input sender and the output of the last stage, for
+    /// consuming the results from the current thread.
+    pub fn into_parts(self) -> (PipelineHandle<I>, mpsc::Receiver<O>) {
+        let handle = PipelineHandle {
+            input: self.input,
+            tasks: self.tasks,
+        };
+        (handle, self.output)
+    }
+}
+
+pub struct PipelineHandle<I> {
+    input: mpsc::Sender<I>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl<I> PipelineHandle<I> {
+    /// Feeds an item to the first stage, waiting while its channel is full.
+    pub async fn send(&mut self, item: I) -> Result<(), mpsc::SendError> {
+        self.input.send(item).await
+    }
+
+    /// Signals that no more items will be sent. Stages exit once they drained their input.
+    pub fn close(&mut self) {
+        self.input.close_channel();
+    }
+
+    /// Closes the input and waits until every stage has finished.
+    pub async fn join(mut self) -> Result<(), JoinError> {
+        self.close();
+        for task in self.tasks {
+            task.join().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_pipeline_sink() {
+        let results = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut pipeline = Pipeline::<u32>::new()
+            .stage(|x| async move { x * 2 }, 2)
+            .stage(|x| async move { x + 1 }, 3)
+            .sink({
+                let results = results.clone();
+                move |x| {
+                    let results = results.clone();
+                    async move {
+                        results.lock().unwrap().push(x);
+                    }
+                }
+            });
+        for x in 0..10 {
+            pipeline.send(x).await.unwrap();
+        }
+        pipeline.join().await.unwrap();
+        let mut results = results.lock().unwrap().clone();
+        results.sort();
+        assert_eq!(results, (0..10).map(|x| x * 2 + 1).collect::<Vec<_>>());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_pipeline_into_parts() {
+        let (mut pipeline, output) = Pipeline::<u32>::with_capacity(1)
+            .stage(|x| async move { x.to_string() }, 1)
+            .into_parts();
+        for x in 0..3 {
+            pipeline.send(x).await.unwrap();
+        }
+        pipeline.close();
+        let results = output.collect::<Vec<_>>().await;
+        assert_eq!(results, ["0", "1", "2"]);
+        pipeline.join().await.unwrap();
+    }
+}
@@ -0,0 +1,214 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use futures::future::{AbortHandle, Abortable};
+use wasm_bindgen::prelude::{wasm_bindgen, JsValue};
+use wasm_bindgen::JsCast;
+
+use crate::runtime::{self, TaskSource};
+use crate::sync::Semaphore;
+use crate::task::{JoinError, SpawnError};
+use crate::worker;
+
+const DEFAULT_WORKERS: usize = 4;
+
+/// A pool of workers for running JS functions off the main thread.
+///
+/// Each pool bounds its own number of concurrently running workers and can be shut down
+/// independently of the others. Functions are shipped to the workers as source code, so
+/// they can't capture variables from the enclosing scope, and their results are passed
+/// back through `JSON.stringify`.
+#[wasm_bindgen]
+pub struct TaskPool {
+    limiter: Arc<Semaphore>,
+    workers: js_sys::Set,
+    tasks: Rc<RefCell<HashMap<u64, AbortHandle>>>,
+    next_id: Cell<u64>,
+    closed: Rc<Cell<bool>>,
+}
+
+#[wasm_bindgen]
+impl TaskPool {
+    #[wasm_bindgen(constructor)]
+    pub fn new(options: Option<js_sys::Object>) -> TaskPool {
+        let workers = options
+            .and_then(|options| js_sys::Reflect::get(&options, &"workers".into()).ok())
+            .and_then(|workers| workers.as_f64())
+            .map(|workers| workers.max(1.0) as usize)
+            .unwrap_or_else(hardware_concurrency);
+        TaskPool {
+            limiter: Arc::new(Semaphore::new(workers)),
+            workers: js_sys::Set::new(&JsValue::UNDEFINED),
+            tasks: Rc::new(RefCell::new(HashMap::new())),
+            next_id: Cell::new(0),
+            closed: Rc::new(Cell::new(false)),
+        }
+    }
+
+    pub fn spawn(&self, f: &js_sys::Function) -> js_sys::Promise {
+        let source = String::from(f.to_string());
+        let limiter = self.limiter.clone();
+        let workers = self.workers.clone();
+        let closed = self.closed.clone();
+        let tasks = self.tasks.clone();
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        self.tasks.borrow_mut().insert(id, abort_handle);
+        let task = Abortable::new(
+            async move {
+                let _permit = limiter.acquire_owned().await;
+                let _share = runtime::share::acquire(TaskSource::Js).await;
+                let (tx, rx) = futures::channel::oneshot::channel();
+                let worker = match worker::spawn(None, async move {
+                    tx.send(run_source(&source).await).ok();
+                }) {
+                    Ok(worker) => worker,
+                    Err(err) => return Ok(Err(SpawnError::new(err).to_string())),
+                };
+                workers.add(&worker);
+                let result = rx.await;
+                workers.delete(&worker);
+                result
+            },
+            abort_registration,
+        );
+        wasm_bindgen_futures::future_to_promise(async move {
+            if closed.get() {
+                return Err(JoinError::Aborted.into());
+            }
+            let result = task.await;
+            tasks.borrow_mut().remove(&id);
+            match result {
+                Ok(Ok(Ok(Some(json)))) => js_sys::JSON::parse(&json),
+                Ok(Ok(Ok(None))) => Ok(JsValue::UNDEFINED),
+                Ok(Ok(Err(message))) => Err(js_sys::Error::new(&message).into()),
+                Ok(Err(_)) => Err(JoinError::unknown_panic().into()),
+                Err(_) => Err(JoinError::Aborted.into()),
+            }
+        })
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn workers(&self) -> u32 {
+        self.workers.size()
+    }
+
+    /// Rejects all the pending and running tasks of the pool and terminates its workers.
+    pub fn shutdown(&self) {
+        self.closed.set(true);
+        for (_, abort_handle) in self.tasks.borrow_mut().drain() {
+            abort_handle.abort();
+        }
+        self.workers.for_each(&mut |worker, _, _| {
+            worker.unchecked_into::<web_sys::Worker>().terminate();
+        });
+        self.workers.clear();
+    }
+}
+
+pub(crate) fn hardware_concurrency() -> usize {
+    js_sys::Reflect::get(&js_sys::global(), &"navigator".into())
+        .and_then(|navigator| js_sys::Reflect::get(&navigator, &"hardwareConcurrency".into()))
+        .ok()
+        .and_then(|concurrency| concurrency.as_f64())
+        .map(|concurrency| concurrency as usize)
+        .unwrap_or(DEFAULT_WORKERS)
+}
+
+async fn run_source(source: &str) -> Result<Option<String>, String> {
+    let f = js_sys::Function::new_no_args(&format!("return ({source})();"));
+    let value = f.call0(&JsValue::UNDEFINED).map_err(error_message)?;
+    let value = wasm_bindgen_futures::JsFuture::from(js_sys::Promise::resolve(&value))
+        .await
+        .map_err(error_message)?;
+    if value.is_undefined() {
+        return Ok(None);
+    }
+    js_sys::JSON::stringify(&value)
+        .map(|json| Some(json.into()))
+        .map_err(error_message)
+}
+
+fn error_message(err: JsValue) -> String {
+    match err.dyn_ref::<js_sys::Error>() {
+        Some(err) => err.message().into(),
+        None => err.as_string().unwrap_or_else(|| format!("{err:?}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wasm_bindgen_futures::JsFuture;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_spawn() {
+        let pool = TaskPool::new(None);
+        let f = js_sys::Function::new_no_args("return { answer: 6 * 7 };");
+        let result = JsFuture::from(pool.spawn(&f)).await.unwrap();
+        let answer = js_sys::Reflect::get(&result, &"answer".into()).unwrap();
+        assert_eq!(answer.as_f64(), Some(42.0));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_spawn_async() {
+        let pool = TaskPool::new(None);
+        let f =
+            js_sys::Function::new_no_args("return new Promise(r => setTimeout(() => r(1), 10));");
+        let result = JsFuture::from(pool.spawn(&f)).await.unwrap();
+        assert_eq!(result.as_f64(), Some(1.0));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_spawn_error() {
+        let pool = TaskPool::new(None);
+        let f = js_sys::Function::new_no_args("throw new Error('boom');");
+        let err = JsFuture::from(pool.spawn(&f)).await.unwrap_err();
+        assert_eq!(error_message(err), "boom");
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_shutdown() {
+        let options = js_sys::Object::new();
+        js_sys::Reflect::set(&options, &"workers".into(), &1.into()).unwrap();
+        let pool = TaskPool::new(Some(options));
+        // Functions can't capture anything, so the running one reports through a
+        // broadcast channel.
+        let started = js_sys::Function::new_no_args(
+            "
+            const channel = new BroadcastChannel('wasmt-test-pool-shutdown');
+            return new Promise(resolve => channel.onmessage = () => {
+                channel.close();
+                resolve();
+            });
+            ",
+        )
+        .call0(&JsValue::UNDEFINED)
+        .unwrap();
+        let f = js_sys::Function::new_no_args(
+            "
+            new BroadcastChannel('wasmt-test-pool-shutdown').postMessage('started');
+            while (true) {}
+            ",
+        );
+        let running = JsFuture::from(pool.spawn(&f));
+        let queued = JsFuture::from(pool.spawn(&f));
+        JsFuture::from(started.unchecked_into::<js_sys::Promise>())
+            .await
+            .unwrap();
+        assert_eq!(pool.workers(), 1);
+        pool.shutdown();
+        let aborted = JoinError::Aborted.to_string();
+        assert_eq!(error_message(running.await.unwrap_err()), aborted);
+        assert_eq!(error_message(queued.await.unwrap_err()), aborted);
+        assert_eq!(pool.workers(), 0);
+    }
+}
@@ -0,0 +1,463 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+
+use futures::channel::{mpsc, oneshot};
+use futures::StreamExt;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use wasm_bindgen::prelude::{wasm_bindgen, JsValue};
+use wasm_bindgen::JsCast;
+
+use crate::task::{self, JoinError};
+
+type Handler = Rc<dyn Fn(u32, JsValue) -> Result<CallHandle, JsValue>>;
+
+thread_local! {
+    static HANDLERS: RefCell<HashMap<String, Handler>> = RefCell::new(HashMap::new());
+}
+
+static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+
+/// The lifecycle events of a call, as seen by JS.
+///
+/// Every call emits any number of `Progress` envelopes, delivered to the `onProgress`
+/// callbacks, and ends with exactly one of `Result`, `Error` or `Cancel`: the output
+/// resolves `CallHandle.result`, while `Error` and `Cancel` envelopes are its rejection
+/// reasons. Envelopes are plain `{ id, kind, value }` objects on the JS side.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnvelopeKind {
+    Progress,
+    Result,
+    Error,
+    Cancel,
+}
+
+impl EnvelopeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EnvelopeKind::Progress => "progress",
+            EnvelopeKind::Result => "result",
+            EnvelopeKind::Error => "error",
+            EnvelopeKind::Cancel => "cancel",
+        }
+    }
+}
+
+fn envelope(id: u32, kind: EnvelopeKind, value: &JsValue) -> JsValue {
+    let envelope = js_sys::Object::new();
+    js_sys::Reflect::set(&envelope, &"id".into(), &id.into()).unwrap();
+    js_sys::Reflect::set(&envelope, &"kind".into(), &kind.as_str().into()).unwrap();
+    js_sys::Reflect::set(&envelope, &"value".into(), value).unwrap();
+    envelope.into()
+}
+
+/// Handed to registered handlers to report progress and observe cancellation.
+pub struct Context {
+    id: u32,
+    cancelled: Arc<AtomicBool>,
+    progress: mpsc::UnboundedSender<String>,
+}
+
+impl Context {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Sends a progress envelope to the JS caller.
+    pub fn progress<P: Serialize>(&self, value: &P) {
+        if let Ok(json) = serde_json::to_string(value) {
+            self.progress.unbounded_send(json).ok();
+        }
+    }
+
+    /// Whether the JS caller asked to cancel the call. Handlers are also aborted at their
+    /// next await point, so this is mostly useful to stop long synchronous sections.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+}
+
+/// Registers a handler callable from JS with `call_handler(name, payload)`.
+///
+/// Calls run on their own worker: the payload is deserialized on the calling thread,
+/// then `handler` runs in a new task and its output is serialized back to a JS value.
+pub fn register<I, O, E, F, Fut>(name: &str, handler: F)
+where
+    I: DeserializeOwned + 'static,
+    O: Serialize + 'static,
+    E: std::fmt::Display + 'static,
+    F: Fn(Context, I) -> Fut + Clone + 'static,
+    Fut: Future<Output = Result<O, E>> + 'static,
+{
+    register_with(
+        name,
+        |payload| Ok((serde_wasm_bindgen::from_value::<I>(payload)?, None)),
+        handler,
+    );
+}
+
+/// Registers a handler taking the bytes of a typed array, `DataView`, `ArrayBuffer` or
+/// `SharedArrayBuffer` payload, callable from JS like the ones of [`register`].
+///
+/// Payloads viewing the module's memory, e.g. a `Uint8Array` over a buffer allocated by
+/// Rust and filled by JS, are handed to `handler` without copying, as a
+/// [`SharedPayload`] borrowing them. Since the memory is shared by every worker, the
+/// handler reads the bytes where JS wrote them. Other payloads, including
+/// `SharedArrayBuffer`s other than the module's memory, which Rust can't address, are
+/// copied into the memory once. So are views created before the memory last grew, whose
+/// buffer is no longer the memory's.
+///
+/// The call's `result` promise only settles once the handler dropped the payload, unless
+/// the handler panicked, so JS may reuse or free the region as soon as it does.
+///
+/// # Safety
+///
+/// Until then, the region is borrowed immutably by the handler: JS and every other
+/// thread must neither write to it nor free it, e.g. by dropping the Rust allocation
+/// backing it.
+pub unsafe fn register_shared<O, E, F, Fut>(name: &str, handler: F)
+where
+    O: Serialize + 'static,
+    E: std::fmt::Display + 'static,
+    F: Fn(Context, SharedPayload) -> Fut + Clone + 'static,
+    Fut: Future<Output = Result<O, E>> + 'static,
+{
+    register_with(
+        name,
+        |payload| {
+            let (payload, released) = SharedPayload::new(&payload)?;
+            Ok((payload, Some(released)))
+        },
+        handler,
+    );
+}
+
+// Registers `handler` with the input made by `decode` on the calling thread, along with
+// a receiver the result waits for, if any, before settling.
+fn register_with<I, O, E, D, F, Fut>(name: &str, decode: D, handler: F)
+where
+    I: 'static,
+    O: Serialize + 'static,
+    E: std::fmt::Display + 'static,
+    D: Fn(JsValue) -> Result<(I, Option<oneshot::Receiver<()>>), JsValue> + 'static,
+    F: Fn(Context, I) -> Fut + Clone + 'static,
+    Fut: Future<Output = Result<O, E>> + 'static,
+{
+    let handler: Handler = Rc::new(move |id, payload| {
+        let (input, released) = decode(payload)?;
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let (progress, mut progress_rx) = mpsc::unbounded();
+        let context = Context {
+            id,
+            cancelled: cancelled.clone(),
+            progress,
+        };
+        let handler = handler.clone();
+        let handle =
+            task::spawn(
+                async move { handler(context, input).await.map_err(|err| err.to_string()) },
+            );
+        let abort_handle = handle.abort_handle.clone();
+        let listeners = Rc::new(RefCell::new(Vec::<js_sys::Function>::new()));
+
+        let result = wasm_bindgen_futures::future_to_promise({
+            let listeners = listeners.clone();
+            let cancelled = cancelled.clone();
+            async move {
+                let forward_progress = async {
+                    while let Some(json) = progress_rx.next().await {
+                        let value = js_sys::JSON::parse(&json).unwrap_or(JsValue::UNDEFINED);
+                        let envelope = envelope(id, EnvelopeKind::Progress, &value);
+                        for listener in listeners.borrow().iter() {
+                            listener.call1(&JsValue::UNDEFINED, &envelope).ok();
+                        }
+                    }
+                };
+                // The progress channel closes when the task completes and drops the context.
+                let (result, ()) = futures::join!(handle.join(), forward_progress);
+                // Aborted handlers drop their input on their worker, after the join
+                // handle completed, while a panic may trap the worker before it does.
+                if let Some(released) = released {
+                    if !matches!(result, Err(JoinError::Panic(_))) {
+                        released.await.ok();
+                    }
+                }
+                match result {
+                    Ok(Ok(output)) => Ok(serde_wasm_bindgen::to_value(&output)?),
+                    Ok(Err(message)) => Err(envelope(
+                        id,
+                        EnvelopeKind::Error,
+                        &js_sys::Error::new(&message).into(),
+                    )),
+                    Err(_) if cancelled.load(Ordering::Acquire) => {
+                        Err(envelope(id, EnvelopeKind::Cancel, &JsValue::UNDEFINED))
+                    }
+                    Err(err) => Err(envelope(id, EnvelopeKind::Error, &err.into())),
+                }
+            }
+        });
+        Ok(CallHandle {
+            id,
+            result,
+            listeners,
+            cancel: Rc::new(move || {
+                cancelled.store(true, Ordering::Release);
+                abort_handle.abort();
+            }),
+        })
+    });
+    HANDLERS.with(|handlers| handlers.borrow_mut().insert(name.to_owned(), handler));
+}
+
+pub fn unregister(name: &str) -> bool {
+    HANDLERS.with(|handlers| handlers.borrow_mut().remove(name).is_some())
+}
+
+/// The bytes of a payload handed to a handler registered with [`register_shared`],
+/// either borrowed from the module's memory or copied into it.
+///
+/// Dropping the payload ends the borrow, letting the call's `result` promise settle.
+pub struct SharedPayload {
+    bytes: Bytes,
+    released: Option<oneshot::Sender<()>>,
+}
+
+enum Bytes {
+    // A region of the module's memory, which stays valid and unchanged as long as the
+    // JS caller follows the rules of `register_shared`.
+    Borrowed { ptr: *const u8, len: usize },
+    Copied(Vec<u8>),
+}
+
+impl SharedPayload {
+    fn new(payload: &JsValue) -> Result<(Self, oneshot::Receiver<()>), JsValue> {
+        let get = |name: &str| js_sys::Reflect::get(payload, &name.into());
+        let (buffer, offset, len) = if js_sys::ArrayBuffer::is_view(payload) {
+            (get("buffer")?, get("byteOffset")?, get("byteLength")?)
+        } else if payload.is_instance_of::<js_sys::ArrayBuffer>()
+            || payload.is_instance_of::<js_sys::SharedArrayBuffer>()
+        {
+            (payload.clone(), 0.into(), get("byteLength")?)
+        } else {
+            return Err(js_sys::TypeError::new(
+                "expected a typed array, DataView, ArrayBuffer or SharedArrayBuffer",
+            )
+            .into());
+        };
+        let offset = offset.as_f64().unwrap_or(0.0) as u32;
+        let len = len.as_f64().unwrap_or(0.0) as u32;
+        let memory = wasm_bindgen::memory().unchecked_into::<js_sys::WebAssembly::Memory>();
+        // Empty regions are copied too, since their offset may be 0, which isn't a valid
+        // pointer for a slice.
+        let bytes = if buffer == memory.buffer() && len > 0 {
+            Bytes::Borrowed {
+                ptr: offset as usize as *const u8,
+                len: len as usize,
+            }
+        } else {
+            Bytes::Copied(
+                js_sys::Uint8Array::new_with_byte_offset_and_length(&buffer, offset, len).to_vec(),
+            )
+        };
+        let (tx, rx) = oneshot::channel();
+        Ok((
+            SharedPayload {
+                bytes,
+                released: Some(tx),
+            },
+            rx,
+        ))
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        match &self.bytes {
+            // SAFETY: the region lies within the memory, which is never shrunk, and isn't
+            // written to or freed until dropped, per the contract of `register_shared`.
+            Bytes::Borrowed { ptr, len } => unsafe { std::slice::from_raw_parts(*ptr, *len) },
+            Bytes::Copied(bytes) => bytes,
+        }
+    }
+
+    /// Whether the bytes are borrowed from the JS caller's view rather than copied.
+    pub fn is_borrowed(&self) -> bool {
+        matches!(self.bytes, Bytes::Borrowed { .. })
+    }
+}
+
+impl std::ops::Deref for SharedPayload {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl Drop for SharedPayload {
+    fn drop(&mut self) {
+        if let Some(released) = self.released.take() {
+            released.send(()).ok();
+        }
+    }
+}
+
+/// Calls the handler registered under `name` with a JS `payload`.
+#[wasm_bindgen(js_name = call_handler)]
+pub fn call(name: &str, payload: JsValue) -> Result<CallHandle, JsValue> {
+    let handler = HANDLERS
+        .with(|handlers| handlers.borrow().get(name).cloned())
+        .ok_or_else(|| JsValue::from_str(&format!("no handler registered as `{name}`")))?;
+    handler(NEXT_ID.fetch_add(1, Ordering::Relaxed), payload)
+}
+
+/// The JS side of a call: a result promise plus progress and cancellation hooks.
+///
+/// `result` resolves with the handler's output, or rejects with an `error` or `cancel`
+/// envelope.
+#[wasm_bindgen]
+pub struct CallHandle {
+    id: u32,
+    result: js_sys::Promise,
+    listeners: Rc<RefCell<Vec<js_sys::Function>>>,
+    cancel: Rc<dyn Fn()>,
+}
+
+#[wasm_bindgen]
+impl CallHandle {
+    #[wasm_bindgen(getter)]
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn result(&self) -> js_sys::Promise {
+        self.result.clone()
+    }
+
+    /// Registers `callback` to be called with every progress envelope.
+    #[wasm_bindgen(js_name = onProgress)]
+    pub fn on_progress(&self, callback: js_sys::Function) {
+        self.listeners.borrow_mut().push(callback);
+    }
+
+    pub fn cancel(&self) {
+        (self.cancel)();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::time::sleep;
+
+    use wasm_bindgen::prelude::Closure;
+    use wasm_bindgen::JsCast;
+    use wasm_bindgen_futures::JsFuture;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn field(value: &JsValue, name: &str) -> JsValue {
+        js_sys::Reflect::get(value, &name.into()).unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_call_with_progress() {
+        register("count", |ctx: Context, n: u32| async move {
+            for i in 0..n {
+                ctx.progress(&i);
+                sleep(Duration::from_millis(10)).await.unwrap();
+            }
+            Ok::<_, String>(n * 2)
+        });
+        let handle = call("count", 3.into()).unwrap();
+        let progress = Rc::new(RefCell::new(Vec::new()));
+        let callback = Closure::<dyn Fn(JsValue)>::new({
+            let progress = progress.clone();
+            move |envelope: JsValue| {
+                assert_eq!(field(&envelope, "kind"), "progress");
+                progress
+                    .borrow_mut()
+                    .push(field(&envelope, "value").as_f64().unwrap());
+            }
+        });
+        handle.on_progress(
+            callback
+                .as_ref()
+                .unchecked_ref::<js_sys::Function>()
+                .clone(),
+        );
+        let result = JsFuture::from(handle.result()).await.unwrap();
+        assert_eq!(result.as_f64(), Some(6.0));
+        assert_eq!(*progress.borrow(), [0.0, 1.0, 2.0]);
+        assert!(unregister("count"));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_call_error() {
+        register(
+            "fail",
+            |_: Context, _: ()| async move { Err::<(), _>("boom") },
+        );
+        let handle = call("fail", JsValue::NULL).unwrap();
+        let err = JsFuture::from(handle.result()).await.unwrap_err();
+        assert_eq!(field(&err, "kind"), "error");
+        assert_eq!(field(&err, "id"), handle.id());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_call_cancel() {
+        register("forever", |ctx: Context, _: ()| async move {
+            while !ctx.is_cancelled() {
+                sleep(Duration::from_millis(10)).await.unwrap();
+            }
+            Ok::<_, String>(())
+        });
+        let handle = call("forever", JsValue::NULL).unwrap();
+        handle.cancel();
+        let err = JsFuture::from(handle.result()).await.unwrap_err();
+        assert_eq!(field(&err, "kind"), "cancel");
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_call_shared() {
+        // SAFETY: the payloads below are left alone until the calls settle.
+        unsafe {
+            register_shared("sum", |_: Context, payload: SharedPayload| async move {
+                let sum = payload.iter().map(|&byte| u32::from(byte)).sum::<u32>();
+                Ok::<_, String>((payload.is_borrowed(), sum, payload.as_ptr() as usize))
+            });
+        }
+        let sum = |payload: JsValue| async move {
+            let result = JsFuture::from(call("sum", payload).unwrap().result())
+                .await
+                .unwrap();
+            serde_wasm_bindgen::from_value::<(bool, u32, usize)>(result).unwrap()
+        };
+        let bytes = [1u8, 2, 3, 4];
+        let memory = wasm_bindgen::memory().unchecked_into::<js_sys::WebAssembly::Memory>();
+        let view = js_sys::Uint8Array::new_with_byte_offset_and_length(
+            &memory.buffer(),
+            bytes.as_ptr() as u32,
+            bytes.len() as u32,
+        );
+        assert_eq!(sum(view.into()).await, (true, 10, bytes.as_ptr() as usize));
+        let copied = js_sys::Uint8Array::from(&[5u8, 6][..]);
+        assert!(matches!(sum(copied.into()).await, (false, 11, _)));
+        let shared = js_sys::SharedArrayBuffer::new(3);
+        js_sys::Uint8Array::new(&shared).fill(1, 0, 3);
+        assert!(matches!(sum(shared.into()).await, (false, 3, _)));
+        assert!(call("sum", "not bytes".into()).is_err());
+        assert!(unregister("sum"));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_call_unknown_handler() {
+        assert!(call("unknown", JsValue::NULL).is_err());
+    }
+}
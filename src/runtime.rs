@@ -0,0 +1,382 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use wasm_bindgen::prelude::{wasm_bindgen, JsValue};
+
+use crate::sync::{Semaphore, SemaphorePermit};
+use crate::task::{self, tree, JoinError};
+use crate::time;
+use crate::worker;
+
+mod builder;
+pub(crate) mod coordinator;
+pub(crate) mod executor;
+mod fifo;
+pub(crate) mod metrics;
+pub(crate) mod share;
+mod watchdog;
+mod worker_error;
+
+pub use builder::Builder;
+pub use fifo::spawn_blocking_fifo;
+pub use metrics::{metrics, reset_metrics, Latency, Metrics};
+pub use share::TaskSource;
+pub use tree::TaskInfo;
+pub use watchdog::Watchdog;
+pub(crate) use worker_error::report as report_worker_error;
+pub use worker_error::{clear_error_handler, set_error_handler, WorkerError, WorkerErrorKind};
+
+pub(crate) struct Config {
+    pub(crate) allow_nested_spawn: bool,
+    pub(crate) forward_console: bool,
+    pub(crate) worker_timers: bool,
+    pub(crate) local_fallback: bool,
+    pub(crate) coordinator_worker: bool,
+    pub(crate) panic_policy: PanicPolicy,
+    pub(crate) on_panic: Option<PanicCallback>,
+    pub(crate) propagate_cancellation: bool,
+    pub(crate) check_blocking: bool,
+    pub(crate) local_budget: Option<Duration>,
+}
+
+pub(crate) type PanicCallback = Arc<dyn Fn(&JoinError) + Send + Sync>;
+
+pub(crate) static CONFIG: Mutex<Config> = Mutex::new(Config {
+    allow_nested_spawn: true,
+    forward_console: false,
+    worker_timers: false,
+    local_fallback: false,
+    coordinator_worker: false,
+    panic_policy: PanicPolicy::Restart,
+    on_panic: None,
+    propagate_cancellation: false,
+    check_blocking: cfg!(debug_assertions),
+    local_budget: None,
+});
+
+/// What happens to the worker of a task that panics, set with [`Builder::panic_policy`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// The worker is considered broken: a shared worker is replaced by a new one before
+    /// the next task is dispatched, while its other tasks finish.
+    #[default]
+    Restart,
+    /// The panic is contained to its task, and the worker keeps running the others.
+    ///
+    /// With the default `panic = "abort"` strategy a panic traps the whole worker, which
+    /// is then replaced as with [`Restart`](Self::Restart).
+    Ignore,
+    /// Like [`Restart`](Self::Restart), and the panic is also passed to the callback set
+    /// with [`Builder::on_panic`], on the thread of the panicking task.
+    Propagate,
+}
+
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Returned by [`init`] when the runtime was already initialized, either explicitly or
+/// implicitly by a spawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AlreadyInitialized;
+
+impl std::fmt::Display for AlreadyInitialized {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the wasmt runtime is already initialized")
+    }
+}
+
+impl std::error::Error for AlreadyInitialized {}
+
+impl From<AlreadyInitialized> for JsValue {
+    fn from(err: AlreadyInitialized) -> Self {
+        JsValue::from_str(&err.to_string())
+    }
+}
+
+/// Returned, inside a [`SpawnError`](crate::task::SpawnError), by the fallible spawns when
+/// the `explicit-init` feature is enabled and the runtime wasn't initialized with [`init`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NotInitialized;
+
+impl std::fmt::Display for NotInitialized {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the wasmt runtime must be initialized with `runtime::init` before spawning"
+        )
+    }
+}
+
+impl std::error::Error for NotInitialized {}
+
+impl From<NotInitialized> for JsValue {
+    fn from(err: NotInitialized) -> Self {
+        JsValue::from_str(&err.to_string())
+    }
+}
+
+/// Initializes the runtime with the configuration of `builder`, once.
+///
+/// Unless the `explicit-init` feature is enabled, the first spawn initializes the
+/// runtime with the default configuration, after which `init` fails. Use
+/// [`Builder::build`] to change the configuration of an initialized runtime.
+pub fn init(builder: Builder) -> Result<(), AlreadyInitialized> {
+    if INITIALIZED.swap(true, Ordering::SeqCst) {
+        return Err(AlreadyInitialized);
+    }
+    builder.build();
+    Ok(())
+}
+
+pub fn is_initialized() -> bool {
+    INITIALIZED.load(Ordering::SeqCst)
+}
+
+// Called by every spawn path before touching the runtime configuration. The infallible
+// ones panic with the error.
+pub(crate) fn ensure_initialized() -> Result<(), NotInitialized> {
+    if is_initialized() {
+        return Ok(());
+    }
+    if cfg!(feature = "explicit-init") {
+        return Err(NotInitialized);
+    }
+    INITIALIZED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Whether the current thread can spawn workers sharing the module's memory: that takes
+/// the `Worker` constructor and `SharedArrayBuffer`, which browsers only expose to
+/// cross-origin isolated pages.
+pub fn workers_supported() -> bool {
+    let global = js_sys::global();
+    let has = |name: &str| {
+        js_sys::Reflect::get(&global, &name.into()).is_ok_and(|value| !value.is_undefined())
+    };
+    let isolated = js_sys::Reflect::get(&global, &"crossOriginIsolated".into())
+        .map_or(true, |isolated| isolated.as_bool() != Some(false));
+    has("Worker") && has("SharedArrayBuffer") && isolated
+}
+
+/// Lists the tasks that are alive across all workers, with the task that spawned each
+/// of them, in spawn order, along with the timers pending on the current thread.
+///
+/// Formatting the dump with `{}` prints the tasks as a tree, e.g. to be logged when the
+/// application seems stuck.
+pub fn dump() -> Dump {
+    Dump {
+        tasks: tree::snapshot(),
+        timers: time::pending_timers(),
+    }
+}
+
+/// The tasks and timers returned by [`dump`].
+#[derive(Clone, Debug)]
+pub struct Dump {
+    pub tasks: Vec<TaskInfo>,
+    /// The timers of the thread that took the dump, see [`time::pending_timers`].
+    pub timers: time::PendingTimers,
+}
+
+impl Dump {
+    /// The tasks spawned by `parent`, or the root tasks for `None`.
+    pub fn children(&self, parent: Option<task::Id>) -> impl Iterator<Item = &TaskInfo> {
+        self.tasks.iter().filter(move |task| task.parent == parent)
+    }
+
+    fn fmt_subtree(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        parent: Option<task::Id>,
+        depth: usize,
+    ) -> std::fmt::Result {
+        for task in self.children(parent) {
+            write!(f, "{:indent$}task {}", "", task.id, indent = depth * 2)?;
+            #[cfg(feature = "spawn-location")]
+            write!(f, " at {}", task.location)?;
+            writeln!(f)?;
+            self.fmt_subtree(f, Some(task.id), depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Dump {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_subtree(f, None, 0)?;
+        write!(f, "{}", self.timers)
+    }
+}
+
+// Bounds the number of workers of a kind alive at once, shared by every thread of the
+// module instance. Unlimited by default, which is modelled with a huge permit count so
+// that limits can be lowered while workers are running.
+pub(crate) struct WorkerLimit {
+    semaphore: Semaphore,
+    limit: AtomicUsize,
+}
+
+const UNLIMITED: usize = isize::MAX as usize / 2;
+
+impl WorkerLimit {
+    const fn new() -> Self {
+        WorkerLimit {
+            semaphore: Semaphore::new(UNLIMITED),
+            limit: AtomicUsize::new(UNLIMITED),
+        }
+    }
+
+    pub(crate) fn set(&self, max: Option<usize>) {
+        let max = max.unwrap_or(UNLIMITED);
+        let previous = self.limit.swap(max, Ordering::SeqCst);
+        if max > previous {
+            self.semaphore.add_permits(max - previous);
+        } else {
+            self.semaphore.forget_permits(previous - max);
+        }
+    }
+
+    pub(crate) fn limit(&self) -> usize {
+        self.limit.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn try_acquire(&'static self) -> Option<WorkerPermit> {
+        let limit = self.semaphore.try_acquire()?;
+        let share = share::try_acquire(TaskSource::Rust)?;
+        Some(WorkerPermit {
+            _limit: limit,
+            _share: share,
+        })
+    }
+
+    pub(crate) async fn acquire(&'static self) -> WorkerPermit {
+        let limit = self.semaphore.acquire().await;
+        let share = share::acquire(TaskSource::Rust).await;
+        WorkerPermit {
+            _limit: limit,
+            _share: share,
+        }
+    }
+}
+
+// Held by a dedicated worker until its task is done: a slot of its kind's limit, and one
+// of the workers shared with JS tasks.
+pub(crate) struct WorkerPermit {
+    _limit: SemaphorePermit<'static>,
+    _share: share::Permit,
+}
+
+pub(crate) static ASYNC_WORKERS: WorkerLimit = WorkerLimit::new();
+pub(crate) static BLOCKING_WORKERS: WorkerLimit = WorkerLimit::new();
+
+/// The number of blocking workers that may run at once under the configured limits, which
+/// is huge when unlimited.
+pub(crate) fn blocking_worker_limit() -> usize {
+    BLOCKING_WORKERS.limit().min(share::limit())
+}
+
+/// Number of workers spawned from this thread that are still alive, including the ones
+/// left behind by previous instances of the module.
+#[wasm_bindgen]
+pub fn live_workers() -> u32 {
+    worker::registry().size()
+}
+
+/// Terminates every worker spawned from this thread.
+///
+/// Meant to be called when the module is about to be replaced, e.g. from a dev server's
+/// hot-reload dispose hook, so that workers don't pile up on every reload. Tasks running
+/// on the terminated workers never complete and their join handles never resolve.
+#[wasm_bindgen]
+pub fn shutdown() {
+    time::shut_down();
+    executor::stop();
+    fifo::stop();
+    terminate_where(|_| true);
+}
+
+/// Terminates the workers that were spawned by a previous instance of the module, leaving
+/// the ones owned by the current instance untouched.
+///
+/// Calling this right after (re-)instantiating the module is the alternative to
+/// [`shutdown`] for hosts that can't hook into the teardown of the old instance.
+#[wasm_bindgen]
+pub fn terminate_stale_workers() {
+    let memory = wasm_bindgen::memory();
+    terminate_where(|owner| owner != &memory);
+}
+
+fn terminate_where(mut f: impl FnMut(&wasm_bindgen::JsValue) -> bool) {
+    let registry = worker::registry();
+    let mut doomed = Vec::new();
+    registry.for_each(&mut |owner, worker| {
+        if f(&owner) {
+            doomed.push(worker);
+        }
+    });
+    for worker in doomed {
+        worker::kill(&registry, worker);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::task;
+    use crate::time::{sleep, sleep_blocking};
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn test_init_once() {
+        init(Builder::new()).ok();
+        assert!(is_initialized());
+        assert_eq!(init(Builder::new()), Err(AlreadyInitialized));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_finished_workers_are_unregistered() {
+        let handle = task::spawn(async move { 1 });
+        assert_eq!(handle.join().await.unwrap(), 1);
+        sleep(Duration::from_millis(100)).await.unwrap();
+        assert_eq!(live_workers(), 0);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_dump_task_tree() {
+        let (tx, rx) = futures::channel::oneshot::channel::<()>();
+        let (child_tx, child_rx) = futures::channel::oneshot::channel();
+        let parent = task::spawn_local(async move {
+            let child = task::spawn_local(async move {
+                rx.await.ok();
+            });
+            child_tx.send((task::try_id(), child.id())).ok();
+            child.await.unwrap();
+        });
+        let (current, child) = child_rx.await.unwrap();
+        assert_eq!(current, Some(parent.id()));
+        let tasks = dump();
+        let children = tasks.children(Some(parent.id())).map(|task| task.id);
+        assert_eq!(children.collect::<Vec<_>>(), [child]);
+        let indented = format!("  task {child}");
+        let tree = tasks.to_string();
+        assert!(tree.lines().any(|line| line.starts_with(&indented)));
+        tx.send(()).ok();
+        parent.await.unwrap();
+        assert!(dump().tasks.iter().all(|task| task.id != child));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_terminate_stale_workers_keeps_own_workers() {
+        let handle = task::spawn(async move {
+            sleep_blocking(Duration::from_millis(100));
+            1
+        });
+        terminate_stale_workers();
+        assert_eq!(handle.join().await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,512 @@
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{
+    coordinator, executor, share, PanicCallback, PanicPolicy, TaskSource, ASYNC_WORKERS,
+    BLOCKING_WORKERS, CONFIG, INITIALIZED,
+};
+use crate::task::JoinError;
+
+/// Configures the runtime shared by every worker of the module.
+///
+/// ```ignore
+/// wasmt::runtime::Builder::new().allow_nested_spawn(false).build();
+/// ```
+pub struct Builder {
+    allow_nested_spawn: bool,
+    forward_console: bool,
+    worker_timers: bool,
+    max_async_workers: Option<usize>,
+    max_blocking_workers: Option<usize>,
+    shared_async_workers: Option<usize>,
+    max_workers: Option<usize>,
+    worker_quotas: [Option<usize>; 2],
+    local_fallback: bool,
+    coordinator_worker: bool,
+    panic_policy: PanicPolicy,
+    on_panic: Option<PanicCallback>,
+    propagate_cancellation: bool,
+    check_blocking: bool,
+    local_budget: Option<Duration>,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Builder {
+            allow_nested_spawn: true,
+            forward_console: false,
+            worker_timers: false,
+            max_async_workers: None,
+            max_blocking_workers: None,
+            shared_async_workers: None,
+            max_workers: None,
+            worker_quotas: [None, None],
+            local_fallback: false,
+            coordinator_worker: false,
+            panic_policy: PanicPolicy::default(),
+            on_panic: None,
+            propagate_cancellation: false,
+            check_blocking: cfg!(debug_assertions),
+            local_budget: None,
+        }
+    }
+
+    /// Whether tasks spawned from a worker get their own worker spawned by that worker.
+    ///
+    /// When disabled, spawning from a worker hands the task over to the thread that
+    /// built the runtime, so that all workers are children of that thread.
+    pub fn allow_nested_spawn(mut self, allow: bool) -> Self {
+        self.allow_nested_spawn = allow;
+        self
+    }
+
+    /// Whether `console.*` calls made inside workers are forwarded to the spawning
+    /// thread and logged there with a `[wasmt worker <id>]` prefix.
+    ///
+    /// Useful with hosts that drop worker console output, like headless test runners.
+    /// Only affects workers spawned after the runtime is built. Disabled by default,
+    /// which leaves the workers' native console untouched.
+    pub fn forward_console(mut self, forward: bool) -> Self {
+        self.forward_console = forward;
+        self
+    }
+
+    /// Whether timers on the main thread, like [`sleep`](crate::time::sleep) and
+    /// [`Interval`](crate::time::Interval), are driven by a dedicated worker.
+    ///
+    /// Browsers clamp the timers of hidden tabs to a second or more, while workers are
+    /// throttled far less, so this keeps timing-based logic running close to its cadence
+    /// in background tabs, at the cost of a message round-trip per timer.
+    pub fn worker_timers(mut self, enable: bool) -> Self {
+        self.worker_timers = enable;
+        self
+    }
+
+    /// How long [`spawn_local`](crate::task::spawn_local) tasks may be polled in a row
+    /// before the thread yields to the event loop, letting it handle input and rendering.
+    ///
+    /// Local tasks are polled on the microtask queue, which the browser drains before
+    /// anything else, so tasks that keep waking each other up can otherwise freeze the
+    /// page. A poll that takes longer than the budget still runs to completion. Unlimited
+    /// by default.
+    pub fn local_budget(mut self, budget: Duration) -> Self {
+        self.local_budget = Some(budget);
+        self
+    }
+
+    /// Whether [`sleep_blocking`](crate::time::sleep_blocking) checks that the current
+    /// thread may block, panicking with "cannot block the main browser thread" instead
+    /// of the engine's cryptic `Atomics.wait` error when it can't.
+    ///
+    /// The check is cached per thread but still costs a lock per call, so it defaults to
+    /// debug builds only.
+    pub fn check_blocking(mut self, check: bool) -> Self {
+        self.check_blocking = check;
+        self
+    }
+
+    /// Maximum number of workers running tasks from [`task::spawn`](crate::task::spawn)
+    /// at once. Unlimited by default.
+    ///
+    /// Tasks spawned past the limit are queued, in spawn order, until a worker finishes.
+    /// Workers that never finish, e.g. because they were terminated or their task
+    /// panicked, keep their slot.
+    pub fn max_async_workers(mut self, max: usize) -> Self {
+        self.max_async_workers = Some(max);
+        self
+    }
+
+    /// Maximum number of workers running closures from
+    /// [`task::spawn_blocking`](crate::task::spawn_blocking) at once, queued like
+    /// [`max_async_workers`](Self::max_async_workers). Unlimited by default.
+    pub fn max_blocking_workers(mut self, max: usize) -> Self {
+        self.max_blocking_workers = Some(max);
+        self
+    }
+
+    /// Runs the tasks from [`task::spawn`](crate::task::spawn) on a fixed set of
+    /// `workers` workers, each multiplexing many tasks, instead of a worker per task.
+    ///
+    /// Tasks go to the worker with the fewest running tasks. This suits many lightweight
+    /// tasks, but a task that blocks its worker stalls every task sharing it. Replaces
+    /// [`max_async_workers`](Self::max_async_workers), which only applies to
+    /// dedicated workers.
+    pub fn shared_async_workers(mut self, workers: usize) -> Self {
+        self.shared_async_workers = Some(workers);
+        self
+    }
+
+    /// Maximum number of dedicated workers running at once across the tasks spawned from
+    /// Rust and the functions spawned from JS with
+    /// [`TaskPool::spawn`](crate::pool::TaskPool::spawn). Unlimited by default.
+    ///
+    /// While tasks of both sources are waiting for a worker, freed workers go to each
+    /// source in turn, and a source already holding its
+    /// [`worker_quota`](Self::worker_quota) waits for the other one, so that neither
+    /// starves the other. Workers are never taken back from a running task. Applies on
+    /// top of [`max_async_workers`](Self::max_async_workers),
+    /// [`max_blocking_workers`](Self::max_blocking_workers) and the pools' own limits.
+    pub fn max_workers(mut self, max: usize) -> Self {
+        self.max_workers = Some(max);
+        self
+    }
+
+    /// Number of the [`max_workers`](Self::max_workers) that tasks from `source` may hold
+    /// while tasks from the other source are waiting. Defaults to half of `max_workers`,
+    /// rounded up. A source alone can always use every worker.
+    pub fn worker_quota(mut self, source: TaskSource, quota: usize) -> Self {
+        self.worker_quotas[source.index()] = Some(quota);
+        self
+    }
+
+    /// Whether [`task::spawn`](crate::task::spawn) runs the task on the current thread,
+    /// like [`task::spawn_local`](crate::task::spawn_local), when the environment doesn't
+    /// support workers (see [`workers_supported`](super::workers_supported)), instead of
+    /// failing to spawn.
+    ///
+    /// This lets the same binary run single-threaded in degraded mode. A warning is
+    /// logged the first time a task falls back, and tasks that block then block the
+    /// current thread. Other spawn failures aren't covered, use
+    /// [`task::try_spawn`](crate::task::try_spawn) to handle them. Disabled by default.
+    pub fn local_fallback(mut self, fallback: bool) -> Self {
+        self.local_fallback = fallback;
+        self
+    }
+
+    /// Whether every dedicated worker is spawned by a worker set aside for that, rather
+    /// than by the thread spawning the task.
+    ///
+    /// Spawns then only post a message, and tasks queued behind
+    /// [`max_async_workers`](Self::max_async_workers) or
+    /// [`max_blocking_workers`](Self::max_blocking_workers) get their worker even while
+    /// the thread that spawned them is busy, e.g. rendering. Workers are tracked by the
+    /// coordinator worker, so [`live_workers`](super::live_workers),
+    /// [`shutdown`](super::shutdown) and `abort_hard` called from other threads don't see
+    /// them. Disabled by default.
+    pub fn coordinator_worker(mut self, enable: bool) -> Self {
+        self.coordinator_worker = enable;
+        self
+    }
+
+    /// What happens to the worker of a task that panics. Defaults to
+    /// [`PanicPolicy::Restart`].
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> Self {
+        self.panic_policy = policy;
+        self
+    }
+
+    /// Callback receiving the panics of tasks under [`PanicPolicy::Propagate`], e.g. to
+    /// report them or to shut the runtime down. It runs on the thread of the panicking
+    /// task, before the task's join handle completes.
+    pub fn on_panic(mut self, f: impl Fn(&JoinError) + Send + Sync + 'static) -> Self {
+        self.on_panic = Some(Arc::new(f));
+        self
+    }
+
+    /// Whether aborting a task also aborts the tasks it spawned, and the ones they spawned
+    /// in turn, as listed by [`dump`](super::dump).
+    ///
+    /// Async tasks are aborted as with their `abort`, and closures spawned with
+    /// [`spawn_blocking_cancellable`](crate::task::spawn_blocking_cancellable) see their
+    /// flag set. Other blocking closures keep running. Disabled by default.
+    pub fn propagate_cancellation(mut self, propagate: bool) -> Self {
+        self.propagate_cancellation = propagate;
+        self
+    }
+
+    /// Applies the configuration, initializing the runtime if it wasn't already. Must be
+    /// called from the thread meant to coordinate the workers, usually the main thread.
+    pub fn build(self) {
+        INITIALIZED.store(true, Ordering::SeqCst);
+        {
+            let mut config = CONFIG.lock().unwrap();
+            config.allow_nested_spawn = self.allow_nested_spawn;
+            config.forward_console = self.forward_console;
+            config.worker_timers = self.worker_timers;
+            config.local_fallback = self.local_fallback;
+            config.coordinator_worker = self.coordinator_worker;
+            config.panic_policy = self.panic_policy;
+            config.on_panic = self.on_panic;
+            config.propagate_cancellation = self.propagate_cancellation;
+            config.check_blocking = self.check_blocking;
+            config.local_budget = self.local_budget;
+        }
+        ASYNC_WORKERS.set(self.max_async_workers);
+        BLOCKING_WORKERS.set(self.max_blocking_workers);
+        share::configure(self.max_workers, self.worker_quotas);
+        match self.shared_async_workers {
+            Some(workers) => executor::start(workers),
+            None => executor::stop(),
+        }
+        if self.coordinator_worker {
+            coordinator::start(true);
+        } else if !self.allow_nested_spawn {
+            coordinator::start(false);
+        } else {
+            coordinator::stop();
+        }
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Restores the default configuration when dropped, for tests that change it while other
+// tests share the instance.
+#[cfg(test)]
+pub(crate) struct TestConfig(());
+
+#[cfg(test)]
+impl Builder {
+    pub(crate) fn build_for_test(self) -> TestConfig {
+        self.build();
+        TestConfig(())
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestConfig {
+    fn drop(&mut self) {
+        Builder::new().build();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use wasm_bindgen::{JsCast, JsValue};
+
+    use super::*;
+    use crate::task;
+    use crate::time::sleep_blocking;
+    use crate::utils::is_worker_scope;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_disallow_nested_spawn() {
+        let _config = Builder::new().allow_nested_spawn(false).build_for_test();
+        let handle = task::spawn(async move {
+            let handle = task::spawn(async move { is_worker_scope() });
+            let blocking = task::spawn_blocking(is_worker_scope);
+            (
+                handle.join().await.unwrap(),
+                blocking.join().await.unwrap(),
+                crate::runtime::live_workers(),
+            )
+        });
+        // Both grandchildren ran on workers spawned and tracked by the main thread, so the
+        // child worker didn't register any worker of its own.
+        assert_eq!(handle.join().await.unwrap(), (true, true, 0));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_coordinator_worker() {
+        let config = Builder::new()
+            .coordinator_worker(true)
+            .max_blocking_workers(1)
+            .build_for_test();
+        let handles = (0..3)
+            .map(|i| task::spawn_blocking(move || i))
+            .collect::<Vec<_>>();
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(handle.join().await.unwrap(), i);
+        }
+        // Only the coordinator worker was spawned from the main thread.
+        assert_eq!(crate::runtime::live_workers(), 1);
+        drop(config);
+        crate::time::sleep(Duration::from_millis(100))
+            .await
+            .unwrap();
+        assert_eq!(crate::runtime::live_workers(), 0);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_max_workers() {
+        let _config = Builder::new()
+            .max_async_workers(1)
+            .max_blocking_workers(2)
+            .build_for_test();
+        let running = Arc::new(AtomicUsize::new(0));
+        let max = Arc::new(AtomicUsize::new(0));
+        let track = |running: Arc<AtomicUsize>, max: Arc<AtomicUsize>| {
+            let concurrent = running.fetch_add(1, Ordering::SeqCst) + 1;
+            max.fetch_max(concurrent, Ordering::SeqCst);
+            sleep_blocking(Duration::from_millis(50));
+            running.fetch_sub(1, Ordering::SeqCst);
+        };
+        let handles = (0..3)
+            .map(|_| {
+                let (running, max) = (running.clone(), max.clone());
+                task::spawn(async move { track(running, max) })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().await.unwrap();
+        }
+        assert_eq!(max.swap(0, Ordering::SeqCst), 1);
+        let handles = (0..4)
+            .map(|_| {
+                let (running, max) = (running.clone(), max.clone());
+                task::spawn_blocking(move || track(running, max))
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().await.unwrap();
+        }
+        assert_eq!(max.load(Ordering::SeqCst), 2);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_shared_async_workers() {
+        let config = Builder::new().shared_async_workers(2).build_for_test();
+        let handles = (0..20)
+            .map(|i| {
+                task::spawn(async move {
+                    crate::time::sleep(Duration::from_millis(10)).await.unwrap();
+                    i
+                })
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(crate::runtime::live_workers(), 2);
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(handle.join().await.unwrap(), i);
+        }
+        drop(config);
+        crate::time::sleep(Duration::from_millis(100))
+            .await
+            .unwrap();
+        assert_eq!(crate::runtime::live_workers(), 0);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_local_fallback() {
+        // Test runners are cross-origin isolated, so the fallback never kicks in.
+        assert!(crate::runtime::workers_supported());
+        let _config = Builder::new().local_fallback(true).build_for_test();
+        let handle = task::spawn(async move { is_worker_scope() });
+        assert!(handle.join().await.unwrap());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_propagate_panics() {
+        let panics = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let _config = Builder::new()
+            .shared_async_workers(1)
+            .panic_policy(PanicPolicy::Propagate)
+            .on_panic({
+                let panics = panics.clone();
+                move |err| {
+                    let message = err.panic_message().map(str::to_owned);
+                    panics.lock().unwrap().push(message);
+                }
+            })
+            .build_for_test();
+        let handle = task::spawn(async move { panic!("boom") });
+        assert!(handle.join().await.unwrap_err().is_panic());
+        assert_eq!(*panics.lock().unwrap(), [Some("boom".to_owned())]);
+        // The broken worker was replaced.
+        assert_eq!(task::spawn(async move { 1 }).join().await.unwrap(), 1);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_propagate_cancellation() {
+        let _config = Builder::new().propagate_cancellation(true).build_for_test();
+        let (child_tx, child_rx) = futures::channel::oneshot::channel();
+        let mut parent = task::spawn_local(async move {
+            let child = task::spawn_local(async move {
+                let grandchild = task::spawn_local(futures::future::pending::<()>());
+                grandchild.await
+            });
+            child_tx.send(child).ok();
+            futures::future::pending::<()>().await
+        });
+        let child = child_rx.await.unwrap();
+        parent.abort();
+        assert_eq!(child.await, Err(JoinError::Aborted));
+        // Unrelated tasks are left alone.
+        assert_eq!(task::spawn_local(async move { 1 }).await.unwrap(), 1);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_forward_console() {
+        // Records the main thread's `console.log` and `console.warn` calls until restored.
+        let hook = js_sys::Function::new_no_args(
+            "
+            const calls = [];
+            const original = { log: console.log, warn: console.warn };
+            for (const level of ['log', 'warn']) {
+                console[level] = (...args) => calls.push([level, ...args]);
+            }
+            return { calls, restore: () => Object.assign(console, original) };
+            ",
+        )
+        .call0(&JsValue::UNDEFINED)
+        .unwrap();
+        let get = |key: &str| js_sys::Reflect::get(&hook, &key.into()).unwrap();
+        let calls: js_sys::Array = get("calls").unchecked_into();
+        let restore: js_sys::Function = get("restore").unchecked_into();
+
+        let config = Builder::new().forward_console(true).build_for_test();
+        let (handle, worker) = task::spawn_with_worker(async move {
+            web_sys::console::log_2(&"forwarded".into(), &1.into());
+            // Functions can't be cloned and get forwarded as strings.
+            web_sys::console::log_1(&js_sys::Function::new_no_args(""));
+            let nested = task::spawn_blocking(|| web_sys::console::warn_1(&"nested".into()));
+            nested.join().await.unwrap();
+            1
+        });
+        let result = handle.await;
+        // The messages may arrive after the task completed.
+        for _ in 0..100 {
+            if calls.length() >= 3 {
+                break;
+            }
+            crate::time::sleep(Duration::from_millis(10)).await.unwrap();
+        }
+        restore.call0(&JsValue::UNDEFINED).unwrap();
+        drop(config);
+        assert_eq!(result.unwrap(), 1);
+
+        let prefix = JsValue::from_str(&format!("[wasmt worker {}]", worker.id()));
+        let calls: Vec<js_sys::Array> = calls.iter().map(JsCast::unchecked_into).collect();
+        let [forwarded, function, nested] = &calls[..] else {
+            panic!("expected 3 console calls, got {calls:?}");
+        };
+        assert_eq!(
+            forwarded.to_vec(),
+            [
+                JsValue::from_str("log"),
+                prefix.clone(),
+                "forwarded".into(),
+                1.into()
+            ]
+        );
+        assert_eq!(function.length(), 3);
+        assert_eq!(function.get(0), "log");
+        assert_eq!(function.get(1), prefix);
+        let function = function.get(2).as_string().unwrap();
+        assert!(function.starts_with("function anonymous("), "{function}");
+        // Forwarded through the console of the worker that spawned the nested one, which
+        // adds its own prefix.
+        assert_eq!(nested.length(), 4);
+        assert_eq!(nested.get(0), "warn");
+        assert_eq!(nested.get(1), prefix);
+        let nested_prefix = nested.get(2).as_string().unwrap();
+        assert!(
+            nested_prefix.starts_with("[wasmt worker "),
+            "{nested_prefix}"
+        );
+        assert_ne!(nested.get(2), prefix);
+        assert_eq!(nested.get(3), "nested");
+    }
+}
@@ -0,0 +1,60 @@
+use std::sync::Mutex;
+
+use futures::channel::mpsc;
+use futures::StreamExt;
+
+use crate::worker;
+
+// Jobs run on the coordinating thread, which is either the one that built the runtime or
+// a worker dedicated to coordination.
+struct Job(Box<dyn FnOnce()>);
+
+// SAFETY: jobs only capture tasks about to be handed to a new worker, which the crate
+// already moves across threads regardless of `Send` (see `worker::spawn`).
+unsafe impl Send for Job {}
+
+static COORDINATOR: Mutex<Option<mpsc::UnboundedSender<Job>>> = Mutex::new(None);
+
+/// Starts running jobs on the current thread, or on a new worker if `dedicated`.
+pub(crate) fn start(dedicated: bool) {
+    let (tx, mut rx) = mpsc::unbounded::<Job>();
+    if let Some(previous) = COORDINATOR.lock().unwrap().replace(tx) {
+        previous.close_channel();
+    }
+    let run = async move {
+        while let Some(job) = rx.next().await {
+            (job.0)();
+        }
+    };
+    if dedicated {
+        worker::spawn(Some("wasmt-coordinator"), run).expect("failed to spawn coordinator worker");
+    } else {
+        wasm_bindgen_futures::spawn_local(run);
+    }
+}
+
+/// Stops the coordinator, after which jobs are run by the threads submitting them.
+pub(crate) fn stop() {
+    if let Some(previous) = COORDINATOR.lock().unwrap().take() {
+        previous.close_channel();
+    }
+}
+
+/// Runs `job` on the coordinating thread, discarding its output, or gives it back if
+/// there is none.
+pub(crate) fn submit<F, R>(job: F) -> Result<(), F>
+where
+    F: FnOnce() -> R + 'static,
+{
+    let coordinator = COORDINATOR.lock().unwrap();
+    match &*coordinator {
+        Some(tx) if !tx.is_closed() => {
+            tx.unbounded_send(Job(Box::new(move || {
+                job();
+            })))
+            .ok();
+            Ok(())
+        }
+        _ => Err(job),
+    }
+}
@@ -0,0 +1,193 @@
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use futures::channel::mpsc;
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+
+use crate::task::SpawnError;
+use crate::worker;
+
+// Tasks handed to one of the shared workers.
+struct Task(Pin<Box<dyn Future<Output = ()>>>);
+
+// SAFETY: tasks are moved to a worker once and only ever polled there, like the futures
+// handed to `worker::spawn`.
+unsafe impl Send for Task {}
+
+struct Executor {
+    workers: Vec<SharedWorker>,
+}
+
+struct SharedWorker {
+    tx: mpsc::UnboundedSender<Task>,
+    load: Arc<AtomicUsize>,
+    broken: Arc<AtomicBool>,
+}
+
+impl SharedWorker {
+    fn spawn() -> Result<Self, SpawnError> {
+        let (tx, rx) = mpsc::unbounded();
+        let load = Arc::new(AtomicUsize::new(0));
+        let broken = Arc::new(AtomicBool::new(false));
+        worker::spawn(
+            Some("wasmt-executor"),
+            run(rx, load.clone(), broken.clone()),
+        )
+        .map_err(SpawnError::new)?;
+        Ok(SharedWorker { tx, load, broken })
+    }
+
+    // Stands in for a worker that couldn't be spawned, which is retried like a broken one
+    // on the next submission.
+    fn failed() -> Self {
+        SharedWorker {
+            tx: mpsc::unbounded().0,
+            load: Arc::new(AtomicUsize::new(0)),
+            broken: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    fn is_broken(&self) -> bool {
+        self.broken.load(Ordering::SeqCst)
+    }
+}
+
+impl Executor {
+    // Replaces the broken workers, leaving the ones that couldn't be replaced to the next
+    // call.
+    fn replace_broken(&mut self) -> Result<(), SpawnError> {
+        let mut result = Ok(());
+        for worker in &mut self.workers {
+            if worker.is_broken() {
+                match SharedWorker::spawn() {
+                    Ok(spawned) => *worker = spawned,
+                    Err(err) => result = Err(err),
+                }
+            }
+        }
+        result
+    }
+}
+
+static EXECUTOR: Mutex<Option<Executor>> = Mutex::new(None);
+
+thread_local! {
+    // Set on shared workers, to the flag telling the executor the worker is unusable.
+    static BROKEN: RefCell<Option<Arc<AtomicBool>>> = const { RefCell::new(None) };
+}
+
+/// Spawns `workers` workers, each running every task it's handed concurrently. Workers
+/// that can't be spawned are retried on the next [`submit`], which returns the error if
+/// none of them could be.
+pub(crate) fn start(workers: usize) {
+    let workers = (0..workers.max(1))
+        .map(|_| SharedWorker::spawn().unwrap_or_else(|_| SharedWorker::failed()))
+        .collect();
+    stop_executor(EXECUTOR.lock().unwrap().replace(Executor { workers }));
+}
+
+/// Lets the shared workers exit once their current tasks are done.
+pub(crate) fn stop() {
+    stop_executor(EXECUTOR.lock().unwrap().take());
+}
+
+fn stop_executor(executor: Option<Executor>) {
+    for worker in executor.into_iter().flat_map(|executor| executor.workers) {
+        worker.tx.close_channel();
+    }
+}
+
+/// Flags the current worker, if it is a shared one, as unusable: called when a task
+/// panics with `panic = "abort"`, which traps the worker along with every task it hosts,
+/// or under [`PanicPolicy::Restart`](super::PanicPolicy::Restart).
+pub(crate) fn mark_current_worker_broken() {
+    BROKEN
+        .try_with(|broken| {
+            if let Some(broken) = &*broken.borrow() {
+                broken.store(true, Ordering::SeqCst);
+            }
+        })
+        .ok();
+}
+
+/// Has [`mark_current_worker_broken`] set `broken` when called on the current thread,
+/// for workers other than the shared ones that need replacing after a panic.
+pub(crate) fn set_broken_flag(broken: Arc<AtomicBool>) {
+    BROKEN.with(|current| *current.borrow_mut() = Some(broken));
+}
+
+/// Runs a task made by `make` on every working shared worker, returning how many tasks
+/// were made.
+#[cfg(feature = "test-util")]
+pub(crate) fn broadcast<F>(mut make: impl FnMut() -> F) -> usize
+where
+    F: Future<Output = ()> + 'static,
+{
+    let mut executor = EXECUTOR.lock().unwrap();
+    let Some(executor) = &mut *executor else {
+        return 0;
+    };
+    executor.replace_broken().ok();
+    let mut made = 0;
+    for worker in executor.workers.iter().filter(|worker| !worker.is_broken()) {
+        worker.load.fetch_add(1, Ordering::Relaxed);
+        worker.tx.unbounded_send(Task(Box::pin(make()))).ok();
+        made += 1;
+    }
+    made
+}
+
+/// Runs `task` on the least loaded shared worker, or gives it back if there is none.
+///
+/// Broken workers are replaced on the way, and those that can't be are skipped: the
+/// submission only fails if none of the workers is working. Tasks that were running on
+/// broken workers besides the one that panicked are lost: their join handles never
+/// complete.
+pub(crate) fn submit<F>(task: F) -> Result<Result<(), F>, SpawnError>
+where
+    F: Future<Output = ()> + 'static,
+{
+    let mut executor = EXECUTOR.lock().unwrap();
+    let Some(executor) = &mut *executor else {
+        return Ok(Err(task));
+    };
+    let replaced = executor.replace_broken();
+    let Some(worker) = executor
+        .workers
+        .iter()
+        .filter(|worker| !worker.is_broken())
+        .min_by_key(|worker| worker.load.load(Ordering::Relaxed))
+    else {
+        // Fails with the error of a replacement, unless the workers broke after they were
+        // replaced, in which case the task gets a dedicated worker.
+        return replaced.map(|()| Err(task));
+    };
+    worker.load.fetch_add(1, Ordering::Relaxed);
+    worker.tx.unbounded_send(Task(Box::pin(task))).ok();
+    Ok(Ok(()))
+}
+
+async fn run(
+    mut rx: mpsc::UnboundedReceiver<Task>,
+    load: Arc<AtomicUsize>,
+    broken: Arc<AtomicBool>,
+) {
+    set_broken_flag(broken);
+    let mut tasks = FuturesUnordered::new();
+    loop {
+        futures::select! {
+            task = rx.next() => match task {
+                Some(task) => tasks.push(task.0),
+                None => break,
+            },
+            () = tasks.select_next_some() => {
+                load.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+    }
+    while tasks.next().await.is_some() {}
+}
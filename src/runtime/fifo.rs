@@ -0,0 +1,223 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+use wasm_bindgen::prelude::Closure;
+use wasm_bindgen::JsCast;
+
+use super::{coordinator, executor};
+use crate::task::{blocking, panic};
+use crate::worker;
+
+// A closure queued on the FIFO lane.
+struct Job(Box<dyn FnOnce()>);
+
+// SAFETY: like the closures handed to `worker::spawn_blocking`, jobs are moved to the
+// lane's worker once and only run there.
+unsafe impl Send for Job {}
+
+struct Lane {
+    jobs: VecDeque<Job>,
+    // Set by the panic hook when the lane's worker is unusable, `None` until it is
+    // spawned.
+    worker: Option<Arc<AtomicBool>>,
+}
+
+static LANE: Mutex<Lane> = Mutex::new(Lane {
+    jobs: VecDeque::new(),
+    worker: None,
+});
+static QUEUED: Condvar = Condvar::new();
+
+/// Runs `f` on the worker dedicated to the FIFO lane, after every closure submitted to
+/// the lane before it, from any thread, has returned.
+///
+/// Unlike [`task::spawn_blocking`](crate::task::spawn_blocking), whose closures each get
+/// a worker and run in parallel, the closures of the lane run one at a time, in
+/// submission order, e.g. to issue the statements of an OPFS-backed SQLite database or
+/// the writes of an append-only log in order. The lane's worker isn't counted by
+/// [`max_blocking_workers`](super::Builder::max_blocking_workers).
+///
+/// A panicking closure doesn't stop the lane: its worker is replaced, and the closures
+/// queued behind it run on the new one. The worker is spawned by the coordinating thread
+/// if there is one, and otherwise by the thread submitting the first closure, which
+/// should then outlive the lane, like the main thread.
+#[track_caller]
+pub fn spawn_blocking_fifo<T>(f: impl FnOnce() -> T + 'static) -> blocking::JoinHandle<T>
+where
+    T: 'static,
+{
+    super::ensure_initialized().unwrap_or_else(|err| panic!("{err}"));
+    let (completion, rx) = panic::Completion::new();
+    let id = completion.id();
+    let header = completion.header().clone();
+    let job = Job(Box::new(move || panic::catch_panic_blocking(completion, f)));
+    let mut lane = LANE.lock().unwrap();
+    lane.jobs.push_back(job);
+    if lane
+        .worker
+        .as_ref()
+        .is_none_or(|broken| broken.load(Ordering::SeqCst))
+    {
+        spawn_worker(&mut lane);
+    }
+    drop(lane);
+    QUEUED.notify_all();
+    blocking::JoinHandle::new(rx, id, header)
+}
+
+fn spawn_worker(lane: &mut Lane) {
+    let broken = Arc::new(AtomicBool::new(false));
+    lane.worker = Some(broken.clone());
+    let spawn = move || {
+        let worker = match worker::spawn_blocking(Some("wasmt-fifo"), {
+            let broken = broken.clone();
+            move || run(broken)
+        }) {
+            Ok(worker) => worker,
+            Err(err) => {
+                web_sys::console::error_2(&"failed to spawn the FIFO lane worker:".into(), &err);
+                return;
+            }
+        };
+        // Blocking workers post `null` when they exit, which the lane's worker only does
+        // once replaced or after a panic: the closures queued behind the panicking one
+        // then get a new worker without waiting for the next submission.
+        let on_exit = Closure::<dyn FnMut(web_sys::MessageEvent)>::new(
+            move |event: web_sys::MessageEvent| {
+                if !event.data().is_null() {
+                    return;
+                }
+                let mut lane = LANE.lock().unwrap();
+                if is_current(&lane, &broken) {
+                    if lane.jobs.is_empty() {
+                        lane.worker = None;
+                    } else {
+                        spawn_worker(&mut lane);
+                    }
+                }
+            },
+        );
+        worker
+            .add_event_listener_with_callback("message", on_exit.as_ref().unchecked_ref())
+            .ok();
+        // Lives as long as the worker, which is rarely replaced.
+        on_exit.forget();
+    };
+    if let Err(spawn) = coordinator::submit(spawn) {
+        spawn();
+    }
+}
+
+/// Forgets the lane's worker, once terminated by [`shutdown`](super::shutdown), so that
+/// the next closure gets a new one.
+pub(crate) fn stop() {
+    LANE.lock().unwrap().worker = None;
+}
+
+fn run(broken: Arc<AtomicBool>) {
+    executor::set_broken_flag(broken.clone());
+    loop {
+        let mut lane = LANE.lock().unwrap();
+        let job = loop {
+            // Replaced after a panic caught by unwinding: leaves the jobs to the new
+            // worker, so that they still run one at a time.
+            if broken.load(Ordering::SeqCst) || !is_current(&lane, &broken) {
+                return;
+            }
+            match lane.jobs.pop_front() {
+                Some(job) => break job,
+                None => lane = QUEUED.wait(lane).unwrap(),
+            }
+        };
+        drop(lane);
+        (job.0)();
+    }
+}
+
+fn is_current(lane: &Lane, broken: &Arc<AtomicBool>) -> bool {
+    lane.worker
+        .as_ref()
+        .is_some_and(|current| Arc::ptr_eq(current, broken))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::task;
+    use crate::time::sleep_blocking;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_runs_in_submission_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let handles = (0..10u64)
+            .map(|i| {
+                let log = log.clone();
+                spawn_blocking_fifo(move || {
+                    // Earlier closures take longer, so parallel runs would reorder them.
+                    sleep_blocking(Duration::from_millis(20 - i));
+                    log.lock().unwrap().push(i);
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().await.unwrap();
+        }
+        assert_eq!(*log.lock().unwrap(), (0..10).collect::<Vec<_>>());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_one_at_a_time_across_submitters() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let running = Arc::new(AtomicUsize::new(0));
+        let submitters = (0..3)
+            .map(|submitter| {
+                let (log, running) = (log.clone(), running.clone());
+                task::spawn(async move {
+                    let handles = (0..5)
+                        .map(|i| {
+                            let (log, running) = (log.clone(), running.clone());
+                            spawn_blocking_fifo(move || {
+                                assert_eq!(running.fetch_add(1, Ordering::SeqCst), 0);
+                                sleep_blocking(Duration::from_millis(2));
+                                log.lock().unwrap().push((submitter, i));
+                                running.fetch_sub(1, Ordering::SeqCst);
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    for handle in handles {
+                        handle.join().await.unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for submitter in submitters {
+            submitter.join().await.unwrap();
+        }
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 15);
+        for submitter in 0..3 {
+            let order = log
+                .iter()
+                .filter(|(from, _)| *from == submitter)
+                .map(|(_, i)| *i)
+                .collect::<Vec<_>>();
+            assert_eq!(order, (0..5).collect::<Vec<_>>());
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_lane_survives_panics() {
+        let panicked = spawn_blocking_fifo(|| panic!("boom"));
+        let next = spawn_blocking_fifo(|| 1);
+        assert!(panicked.join().await.unwrap_err().is_panic());
+        assert_eq!(next.join().await.unwrap(), 1);
+    }
+}
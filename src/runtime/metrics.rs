@@ -0,0 +1,136 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::time::Instant;
+
+// Number of samples kept, the oldest being dropped first, so that the percentiles follow
+// changes of the configuration or of the page's load.
+const SAMPLES: usize = 1024;
+
+static SPAWN_LATENCIES: Mutex<VecDeque<Duration>> = Mutex::new(VecDeque::new());
+static LOCAL_POLL_TIMES: Mutex<VecDeque<Duration>> = Mutex::new(VecDeque::new());
+
+/// Metrics of the runtime, across every thread of the module instance.
+#[derive(Clone, Debug)]
+pub struct Metrics {
+    /// Time from spawning a worker to its task starting to run on it, for the last 1024
+    /// workers spawned, including the shared and coordinator workers. Covers fetching
+    /// and compiling the worker's script and instantiating the module in the worker.
+    pub spawn_latency: Latency,
+    /// Time taken by single polls of [`spawn_local`](crate::task::spawn_local) tasks, for
+    /// the last 1024 polls on any thread. Long polls delay every other local task of
+    /// their thread, and on the main thread, input handling and rendering.
+    pub local_poll_time: Latency,
+}
+
+/// Latency samples, with their percentiles.
+#[derive(Clone, Debug, Default)]
+pub struct Latency {
+    // Sorted.
+    samples: Vec<Duration>,
+}
+
+impl Latency {
+    pub fn count(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// The latency that `percentile` percent of the samples don't exceed, using the
+    /// nearest-rank method, or `None` without samples.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `percentile` isn't between 0 and 100.
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        assert!(
+            (0.0..=100.0).contains(&percentile),
+            "percentile must be between 0 and 100"
+        );
+        let rank = (percentile / 100.0 * self.samples.len() as f64).ceil() as usize;
+        self.samples.get(rank.saturating_sub(1)).copied()
+    }
+
+    pub fn p50(&self) -> Option<Duration> {
+        self.percentile(50.0)
+    }
+
+    pub fn p90(&self) -> Option<Duration> {
+        self.percentile(90.0)
+    }
+
+    pub fn p99(&self) -> Option<Duration> {
+        self.percentile(99.0)
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.samples.last().copied()
+    }
+}
+
+/// Collects the current [`Metrics`].
+pub fn metrics() -> Metrics {
+    Metrics {
+        spawn_latency: latency(&SPAWN_LATENCIES),
+        local_poll_time: latency(&LOCAL_POLL_TIMES),
+    }
+}
+
+fn latency(samples: &Mutex<VecDeque<Duration>>) -> Latency {
+    let mut samples = Vec::from(samples.lock().unwrap().clone());
+    samples.sort_unstable();
+    Latency { samples }
+}
+
+/// Clears the samples of [`metrics`], e.g. before measuring the effect of a change of
+/// configuration.
+pub fn reset_metrics() {
+    SPAWN_LATENCIES.lock().unwrap().clear();
+    LOCAL_POLL_TIMES.lock().unwrap().clear();
+}
+
+/// Records the latency of the current worker, called from its entry point.
+pub(crate) fn record_spawn_latency(spawned_at: Instant) {
+    // Both sides use their own `performance.timeOrigin`, which are only as consistent as
+    // the browser's clock, hence the saturating difference.
+    let latency = Instant::performance_now().duration_since(spawned_at);
+    record(&SPAWN_LATENCIES, latency);
+}
+
+/// Records the duration of a poll of a local task.
+pub(crate) fn record_local_poll(duration: Duration) {
+    record(&LOCAL_POLL_TIMES, duration);
+}
+
+fn record(samples: &Mutex<VecDeque<Duration>>, sample: Duration) {
+    let mut samples = samples.lock().unwrap();
+    if samples.len() == SAMPLES {
+        samples.pop_front();
+    }
+    samples.push_back(sample);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_spawn_latency() {
+        reset_metrics();
+        assert_eq!(metrics().spawn_latency.p50(), None);
+        for i in 0..4 {
+            assert_eq!(task::spawn_blocking(move || i).join().await.unwrap(), i);
+        }
+        let latency = metrics().spawn_latency;
+        assert_eq!(latency.count(), 4);
+        assert!(latency.p50().unwrap() > Duration::ZERO);
+        assert!(latency.p50() <= latency.p90());
+        assert_eq!(latency.percentile(100.0), latency.max());
+        assert_eq!(latency.percentile(0.0), latency.samples.first().copied());
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll, Waker};
+
+/// Where a task running on a worker was spawned from, to share workers between sources
+/// with [`Builder::max_workers`](super::Builder::max_workers).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TaskSource {
+    /// Functions spawned from JS with [`TaskPool::spawn`](crate::pool::TaskPool::spawn).
+    Js,
+    /// Tasks and closures spawned from Rust on dedicated workers, e.g. with
+    /// [`task::spawn`](crate::task::spawn) or
+    /// [`task::spawn_blocking`](crate::task::spawn_blocking).
+    Rust,
+}
+
+impl TaskSource {
+    pub(crate) fn index(self) -> usize {
+        match self {
+            TaskSource::Js => 0,
+            TaskSource::Rust => 1,
+        }
+    }
+
+    fn other(self) -> TaskSource {
+        match self {
+            TaskSource::Js => TaskSource::Rust,
+            TaskSource::Rust => TaskSource::Js,
+        }
+    }
+}
+
+// Workers shared by both sources, handed out in turns while both have tasks waiting, so
+// that a burst of tasks from one source doesn't hold every worker until it drains. Like
+// `WorkerLimit`, unlimited by default and shared by every thread of the module instance.
+struct State {
+    limit: usize,
+    quotas: [Option<usize>; 2],
+    running: [usize; 2],
+    waiters: [VecDeque<Waiter>; 2],
+    next_id: u64,
+    // The source served next when both are waiting and within their quotas.
+    turn: TaskSource,
+}
+
+struct Waiter {
+    id: u64,
+    waker: Waker,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    limit: super::UNLIMITED,
+    quotas: [None, None],
+    running: [0, 0],
+    waiters: [VecDeque::new(), VecDeque::new()],
+    next_id: 0,
+    turn: TaskSource::Js,
+});
+
+pub(crate) fn configure(limit: Option<usize>, quotas: [Option<usize>; 2]) {
+    let mut state = STATE.lock().unwrap();
+    state.limit = limit.unwrap_or(super::UNLIMITED);
+    state.quotas = quotas;
+    state.wake_fronts();
+}
+
+pub(crate) fn limit() -> usize {
+    STATE.lock().unwrap().limit
+}
+
+impl State {
+    // Defaults to half of the workers, so that each source gets at least that many once
+    // the workers of the other one free up.
+    fn quota(&self, source: TaskSource) -> usize {
+        self.quotas[source.index()]
+            .unwrap_or(self.limit.div_ceil(2))
+            .max(1)
+    }
+
+    fn may_run(&self, source: TaskSource) -> bool {
+        if self.running[0] + self.running[1] >= self.limit {
+            return false;
+        }
+        let other = source.other();
+        if self.waiters[other.index()].is_empty() {
+            return true;
+        }
+        let over_quota = |source: TaskSource| self.running[source.index()] >= self.quota(source);
+        match (over_quota(source), over_quota(other)) {
+            (false, true) => true,
+            (true, false) => false,
+            _ => self.turn == source,
+        }
+    }
+
+    fn grant(&mut self, source: TaskSource) -> Permit {
+        self.running[source.index()] += 1;
+        self.turn = source.other();
+        Permit { source }
+    }
+
+    fn wake_fronts(&self) {
+        for waiters in &self.waiters {
+            if let Some(waiter) = waiters.front() {
+                waiter.waker.wake_by_ref();
+            }
+        }
+    }
+}
+
+pub(crate) fn try_acquire(source: TaskSource) -> Option<Permit> {
+    let mut state = STATE.lock().unwrap();
+    if state.waiters[source.index()].is_empty() && state.may_run(source) {
+        Some(state.grant(source))
+    } else {
+        None
+    }
+}
+
+/// Waits for a worker to be available to `source`, in spawn order among its tasks.
+pub(crate) fn acquire(source: TaskSource) -> Acquire {
+    Acquire { source, id: None }
+}
+
+pub(crate) struct Acquire {
+    source: TaskSource,
+    id: Option<u64>,
+}
+
+impl Future for Acquire {
+    type Output = Permit;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Permit> {
+        let mut state = STATE.lock().unwrap();
+        let index = self.source.index();
+        let first = match (self.id, state.waiters[index].front()) {
+            (_, None) => true,
+            (Some(id), Some(front)) => id == front.id,
+            (None, Some(_)) => false,
+        };
+        if first && state.may_run(self.source) {
+            if self.id.take().is_some() {
+                state.waiters[index].pop_front();
+            }
+            let permit = state.grant(self.source);
+            state.wake_fronts();
+            return Poll::Ready(permit);
+        }
+        match self.id {
+            Some(id) => {
+                if let Some(waiter) = state.waiters[index].iter_mut().find(|w| w.id == id) {
+                    waiter.waker.clone_from(cx.waker());
+                }
+            }
+            None => {
+                let id = state.next_id;
+                state.next_id += 1;
+                state.waiters[index].push_back(Waiter {
+                    id,
+                    waker: cx.waker().clone(),
+                });
+                self.id = Some(id);
+                // The other source may have been let through only because this one
+                // wasn't waiting.
+                state.wake_fronts();
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Acquire {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            let mut state = STATE.lock().unwrap();
+            state.waiters[self.source.index()].retain(|waiter| waiter.id != id);
+            state.wake_fronts();
+        }
+    }
+}
+
+/// A worker of [`TaskSource`], released when dropped.
+#[must_use]
+pub(crate) struct Permit {
+    source: TaskSource,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut state = STATE.lock().unwrap();
+        state.running[self.source.index()] -= 1;
+        state.wake_fronts();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::pool::TaskPool;
+    use crate::runtime::Builder;
+    use crate::task;
+    use crate::time::sleep_blocking;
+
+    use wasm_bindgen_futures::JsFuture;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_js_tasks_are_not_starved() {
+        let _config = Builder::new().max_workers(2).build_for_test();
+        let finished = Arc::new(AtomicUsize::new(0));
+        let handles = (0..6)
+            .map(|_| {
+                let finished = finished.clone();
+                task::spawn_blocking(move || {
+                    sleep_blocking(Duration::from_millis(100));
+                    finished.fetch_add(1, Ordering::SeqCst);
+                })
+            })
+            .collect::<Vec<_>>();
+        let pool = TaskPool::new(None);
+        let f = js_sys::Function::new_no_args("return 1;");
+        let result = JsFuture::from(pool.spawn(&f)).await.unwrap();
+        assert_eq!(result.as_f64(), Some(1.0));
+        // The JS task took the first worker freed by the Rust tasks, instead of waiting
+        // for all of them.
+        assert!(finished.load(Ordering::SeqCst) <= 2);
+        for handle in handles {
+            handle.join().await.unwrap();
+        }
+    }
+}
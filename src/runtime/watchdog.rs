@@ -0,0 +1,163 @@
+use std::rc::Rc;
+use std::time::Duration;
+
+use wasm_bindgen::prelude::JsValue;
+use wasm_bindgen::JsCast;
+
+use crate::task::{self, r#async::JoinHandle};
+use crate::time::sleep;
+use crate::worker::{self, MISSED_PINGS_KEY, WATCHED_KEY};
+
+type UnresponsiveCallback = Rc<dyn Fn(&web_sys::Worker)>;
+
+/// Periodically pings the workers running async tasks spawned from this thread and
+/// reports the ones that stopped answering, e.g. because a task is stuck in a loop that
+/// never yields.
+///
+/// Workers running blocking tasks are never pinged, as they aren't expected to answer.
+pub struct Watchdog {
+    interval: Duration,
+    max_missed_pings: u32,
+    terminate_unresponsive: bool,
+    on_unresponsive: Option<UnresponsiveCallback>,
+}
+
+impl Watchdog {
+    pub fn new() -> Self {
+        Watchdog {
+            interval: Duration::from_secs(1),
+            max_missed_pings: 3,
+            terminate_unresponsive: false,
+            on_unresponsive: None,
+        }
+    }
+
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Number of consecutive unanswered pings after which a worker is unresponsive.
+    pub fn max_missed_pings(mut self, max_missed_pings: u32) -> Self {
+        self.max_missed_pings = max_missed_pings;
+        self
+    }
+
+    /// Terminates unresponsive workers. The tasks they were running are lost and their
+    /// join handles never resolve.
+    pub fn terminate_unresponsive(mut self, terminate: bool) -> Self {
+        self.terminate_unresponsive = terminate;
+        self
+    }
+
+    pub fn on_unresponsive(mut self, f: impl Fn(&web_sys::Worker) + 'static) -> Self {
+        self.on_unresponsive = Some(Rc::new(f));
+        self
+    }
+
+    /// Starts watching on the current thread until the returned handle is aborted, or
+    /// the runtime shuts down.
+    pub fn start(self) -> JoinHandle<()> {
+        task::spawn_local(async move {
+            while sleep(self.interval).await.is_ok() {
+                self.check();
+            }
+        })
+    }
+
+    fn check(&self) {
+        let registry = worker::registry();
+        let mut unresponsive = Vec::new();
+        registry.for_each(&mut |_, worker| {
+            let watched = js_sys::Reflect::get(&worker, &WATCHED_KEY.into())
+                .map(|watched| watched.is_truthy())
+                .unwrap_or(false);
+            if !watched {
+                return;
+            }
+            let worker = worker.unchecked_into::<web_sys::Worker>();
+            let missed = js_sys::Reflect::get(&worker, &MISSED_PINGS_KEY.into())
+                .ok()
+                .and_then(|missed| missed.as_f64())
+                .unwrap_or(0.0) as u32;
+            if missed >= self.max_missed_pings {
+                unresponsive.push(worker);
+                return;
+            }
+            js_sys::Reflect::set(&worker, &MISSED_PINGS_KEY.into(), &(missed + 1).into()).ok();
+            worker.post_message(&JsValue::from_str("wasmt:ping")).ok();
+        });
+        for worker in unresponsive {
+            // Only report a worker once per streak of missed pings.
+            js_sys::Reflect::set(&worker, &MISSED_PINGS_KEY.into(), &0.into()).ok();
+            if let Some(on_unresponsive) = &self.on_unresponsive {
+                on_unresponsive(&worker);
+            }
+            if self.terminate_unresponsive {
+                registry.delete(&worker);
+                worker.terminate();
+            }
+        }
+    }
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::runtime::live_workers;
+    use crate::time::sleep_blocking;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_watchdog_reports_stuck_worker() {
+        let reported = Rc::new(Cell::new(0));
+        let mut watchdog = Watchdog::new()
+            .interval(Duration::from_millis(50))
+            .max_missed_pings(2)
+            .terminate_unresponsive(true)
+            .on_unresponsive({
+                let reported = reported.clone();
+                move |_| reported.set(reported.get() + 1)
+            })
+            .start();
+        let _stuck = task::spawn(async move {
+            sleep_blocking(Duration::from_secs(10));
+        });
+        let responsive = task::spawn(async move {
+            sleep(Duration::from_millis(500)).await.unwrap();
+            1
+        });
+        assert_eq!(responsive.join().await.unwrap(), 1);
+        assert_eq!(reported.get(), 1);
+        assert_eq!(live_workers(), 0);
+        watchdog.abort();
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_watchdog_ignores_blocking_workers() {
+        let reported = Rc::new(Cell::new(false));
+        let mut watchdog = Watchdog::new()
+            .interval(Duration::from_millis(20))
+            .max_missed_pings(1)
+            .on_unresponsive({
+                let reported = reported.clone();
+                move |_| reported.set(true)
+            })
+            .start();
+        let handle = task::spawn_blocking(|| sleep_blocking(Duration::from_millis(200)));
+        handle.join().await.unwrap();
+        assert!(!reported.get());
+        watchdog.abort();
+    }
+}
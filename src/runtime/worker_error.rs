@@ -0,0 +1,135 @@
+use std::sync::{Arc, Mutex};
+
+use wasm_bindgen::prelude::JsValue;
+
+type ErrorHandler = Arc<dyn Fn(&WorkerError) + Send + Sync>;
+
+static ERROR_HANDLER: Mutex<Option<ErrorHandler>> = Mutex::new(None);
+
+/// What a worker failed to handle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WorkerErrorKind {
+    /// An exception thrown and never caught, e.g. from an event listener.
+    Error,
+    /// A promise rejected without any rejection handler.
+    UnhandledRejection,
+}
+
+/// An error a worker didn't handle, passed to the handler set with [`set_error_handler`].
+#[derive(Clone, Debug)]
+pub struct WorkerError {
+    pub kind: WorkerErrorKind,
+    /// The id of the worker, as used in the prefix of its forwarded console messages.
+    pub worker: u32,
+    pub message: String,
+    /// The script and position the error was thrown from, when known.
+    pub filename: Option<String>,
+    pub lineno: Option<u32>,
+    pub colno: Option<u32>,
+    pub stack: Option<String>,
+}
+
+impl std::fmt::Display for WorkerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.kind {
+            WorkerErrorKind::Error => "uncaught error",
+            WorkerErrorKind::UnhandledRejection => "unhandled rejection",
+        };
+        write!(f, "{kind} in worker {}: {}", self.worker, self.message)?;
+        if let Some(filename) = &self.filename {
+            write!(f, " at {filename}")?;
+            if let (Some(lineno), Some(colno)) = (self.lineno, self.colno) {
+                write!(f, ":{lineno}:{colno}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for WorkerError {}
+
+impl From<WorkerError> for JsValue {
+    fn from(err: WorkerError) -> Self {
+        js_sys::Error::new(&err.to_string()).into()
+    }
+}
+
+/// Calls `f` with every error that a worker throws without catching it, and every promise
+/// it rejects without handling the rejection, replacing the previous handler.
+///
+/// Errors are still logged to the worker's console. The handler runs on the thread that
+/// spawned the worker, usually the main thread. Panics of tasks aren't reported here, see
+/// [`Builder::on_panic`](super::Builder::on_panic).
+pub fn set_error_handler(f: impl Fn(&WorkerError) + Send + Sync + 'static) {
+    *ERROR_HANDLER.lock().unwrap() = Some(Arc::new(f));
+}
+
+/// Removes the handler set with [`set_error_handler`].
+pub fn clear_error_handler() {
+    ERROR_HANDLER.lock().unwrap().take();
+}
+
+// Called with the `wasmtError` messages posted by the workers' error listeners.
+pub(crate) fn report(worker: u32, info: &JsValue) {
+    let Some(handler) = ERROR_HANDLER.lock().unwrap().clone() else {
+        return;
+    };
+    let get = |key: &str| js_sys::Reflect::get(info, &key.into()).unwrap_or(JsValue::UNDEFINED);
+    let kind = match get("kind").as_string().as_deref() {
+        Some("unhandledrejection") => WorkerErrorKind::UnhandledRejection,
+        _ => WorkerErrorKind::Error,
+    };
+    let error = WorkerError {
+        kind,
+        worker,
+        message: get("message").as_string().unwrap_or_default(),
+        filename: get("filename")
+            .as_string()
+            .filter(|filename| !filename.is_empty()),
+        lineno: get("lineno").as_f64().map(|lineno| lineno as u32),
+        colno: get("colno").as_f64().map(|colno| colno as u32),
+        stack: get("stack").as_string(),
+    };
+    handler(&error);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::task;
+    use crate::time::sleep;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_error_handler() {
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        set_error_handler({
+            let errors = errors.clone();
+            move |err| errors.lock().unwrap().push((err.kind, err.message.clone()))
+        });
+        let handle = task::spawn(async move {
+            js_sys::Function::new_no_args(
+                "
+                setTimeout(() => { throw new Error('thrown'); });
+                Promise.reject(new Error('rejected'));
+                ",
+            )
+            .call0(&JsValue::UNDEFINED)
+            .unwrap();
+            sleep(Duration::from_millis(50)).await.unwrap();
+        });
+        handle.join().await.unwrap();
+        sleep(Duration::from_millis(50)).await.unwrap();
+        clear_error_handler();
+        let errors = errors.lock().unwrap();
+        assert!(errors.iter().any(|(kind, message)| {
+            *kind == WorkerErrorKind::Error && message.contains("thrown")
+        }));
+        assert!(errors.contains(&(WorkerErrorKind::UnhandledRejection, "rejected".to_owned())));
+    }
+}
@@ -0,0 +1,435 @@
+//! Supervision of long-lived tasks, restarting them when they panic or their worker dies.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::task::Poll;
+use std::time::Duration;
+
+use futures::future::LocalBoxFuture;
+use wasm_bindgen::JsValue;
+
+use crate::task::{self, r#async::JoinHandle, JoinError};
+use crate::time::{self, Instant};
+
+// How often the workers of running children are checked, since a terminated worker
+// never completes its task's join.
+const LIVENESS_INTERVAL: Duration = Duration::from_millis(500);
+
+type OnExit = Box<dyn FnMut(&str, &Exit)>;
+
+/// Which children are restarted when one of them exits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strategy {
+    /// Only the child that exited.
+    #[default]
+    OneForOne,
+    /// All the children, the others being aborted first, for children that depend on
+    /// each other's state.
+    OneForAll,
+}
+
+/// When a child is restarted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Restart {
+    /// Whenever it exits, even successfully.
+    #[default]
+    Permanent,
+    /// Only when it fails: panics, or its worker dies.
+    Transient,
+    /// Never.
+    Temporary,
+}
+
+/// How a child exited, as passed to [`Supervisor::on_exit`].
+#[derive(Debug)]
+pub enum Exit {
+    Completed,
+    Panicked(JoinError),
+    /// The child's worker went away without the task completing, e.g. terminated from
+    /// outside or crashed. Only detected for workers spawned by the supervisor's thread.
+    WorkerDied,
+}
+
+impl Exit {
+    pub fn is_failure(&self) -> bool {
+        !matches!(self, Exit::Completed)
+    }
+}
+
+/// Runs a set of long-lived tasks, the children, each on a worker of its own, restarting
+/// them as they exit, e.g. to keep the services of an in-browser application running
+/// through crashes.
+///
+/// Restarts are delayed by an exponential backoff, which resets once no restart happened
+/// for the restart window. Once a child needs more restarts than allowed within that
+/// window, the supervisor gives up: it aborts the remaining children and fails with
+/// [`TooManyRestarts`].
+///
+/// ```ignore
+/// let supervisor = Supervisor::new()
+///     .max_restarts(5, Duration::from_secs(60))
+///     .child("sync", || sync_loop())
+///     .child_with("indexer", Restart::Transient, || index_documents())
+///     .start();
+/// ```
+pub struct Supervisor {
+    strategy: Strategy,
+    max_restarts: usize,
+    window: Duration,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    on_exit: Option<OnExit>,
+    children: Vec<Child>,
+}
+
+struct Child {
+    name: String,
+    restart: Restart,
+    factory: Box<dyn FnMut() -> LocalBoxFuture<'static, ()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Supervisor::new()
+    }
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Supervisor {
+            strategy: Strategy::OneForOne,
+            max_restarts: 3,
+            window: Duration::from_secs(5),
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            on_exit: None,
+            children: Vec::new(),
+        }
+    }
+
+    /// Defaults to [`Strategy::OneForOne`].
+    pub fn strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// The most restarts allowed within `window`. Defaults to 3 restarts within 5
+    /// seconds.
+    pub fn max_restarts(mut self, max_restarts: usize, window: Duration) -> Self {
+        self.max_restarts = max_restarts;
+        self.window = window;
+        self
+    }
+
+    /// The delay before a restart, doubling on every restart within the restart window up
+    /// to `max`. Defaults to 100 milliseconds, up to 10 seconds.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Called with the child's name whenever a child exits, before it is restarted, e.g.
+    /// to log crashes.
+    pub fn on_exit(mut self, f: impl FnMut(&str, &Exit) + 'static) -> Self {
+        self.on_exit = Some(Box::new(f));
+        self
+    }
+
+    /// Adds a [`Restart::Permanent`] child, started by calling `factory` and again on
+    /// every restart.
+    pub fn child<F, Fut>(self, name: &str, factory: F) -> Self
+    where
+        F: FnMut() -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.child_with(name, Restart::Permanent, factory)
+    }
+
+    /// Adds a child with the given restart policy.
+    pub fn child_with<F, Fut>(mut self, name: &str, restart: Restart, mut factory: F) -> Self
+    where
+        F: FnMut() -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.children.push(Child {
+            name: name.to_owned(),
+            restart,
+            factory: Box::new(move || Box::pin(factory())),
+            handle: None,
+        });
+        self
+    }
+
+    /// Starts the children and supervises them from a task of the current thread.
+    ///
+    /// The returned handle completes once no child is left to restart, or with an error
+    /// once the supervisor gave up. Aborting it aborts the children, and so does a
+    /// runtime shutdown.
+    #[track_caller]
+    pub fn start(self) -> JoinHandle<Result<(), TooManyRestarts>> {
+        task::spawn_local(self.run())
+    }
+
+    async fn run(mut self) -> Result<(), TooManyRestarts> {
+        for index in 0..self.children.len() {
+            self.children[index].start();
+        }
+        let mut restarts = VecDeque::new();
+        let mut backoff = self.initial_backoff;
+        let mut liveness = time::interval(LIVENESS_INTERVAL);
+        loop {
+            let next = futures::future::poll_fn(|cx| {
+                for (index, child) in self.children.iter_mut().enumerate() {
+                    let Some(handle) = &mut child.handle else {
+                        continue;
+                    };
+                    if let Poll::Ready(result) = handle.poll_join(cx) {
+                        return Poll::Ready(Some((
+                            index,
+                            result.map_or_else(Exit::Panicked, |()| Exit::Completed),
+                        )));
+                    }
+                }
+                // Ticks until the timers shut down, after the joins so that a task that
+                // completed isn't mistaken for a dead worker.
+                while let Poll::Ready(tick) = liveness.poll_tick(cx) {
+                    if tick.is_err() {
+                        return Poll::Ready(None);
+                    }
+                    let dead = self.children.iter().position(|child| {
+                        child.handle.as_ref().is_some_and(JoinHandle::worker_died)
+                    });
+                    if let Some(index) = dead {
+                        return Poll::Ready(Some((index, Exit::WorkerDied)));
+                    }
+                }
+                Poll::Pending
+            })
+            .await;
+            let Some((index, exit)) = next else {
+                return Ok(());
+            };
+            let child = &mut self.children[index];
+            // Aborted, so that the tasks spawned by a child whose worker died are cancelled
+            // if the runtime propagates cancellation.
+            if let Some(mut handle) = child.handle.take() {
+                handle.abort();
+            }
+            if let Some(on_exit) = &mut self.on_exit {
+                on_exit(&child.name, &exit);
+            }
+            let restart = match child.restart {
+                Restart::Permanent => true,
+                Restart::Transient => exit.is_failure(),
+                Restart::Temporary => false,
+            };
+            if !restart {
+                if self.children.iter().all(|child| child.handle.is_none()) {
+                    return Ok(());
+                }
+                continue;
+            }
+
+            let now = Instant::now();
+            while restarts
+                .front()
+                .is_some_and(|&restart| now.duration_since(restart) > self.window)
+            {
+                restarts.pop_front();
+            }
+            if restarts.is_empty() {
+                backoff = self.initial_backoff;
+            }
+            if restarts.len() >= self.max_restarts {
+                return Err(TooManyRestarts {
+                    child: child.name.clone(),
+                });
+            }
+            restarts.push_back(now);
+            // The children to restart: the one that exited, and with `OneForAll` the
+            // siblings still running, aborted meanwhile.
+            let mut stopped = vec![index];
+            if self.strategy == Strategy::OneForAll {
+                for (i, child) in self.children.iter_mut().enumerate() {
+                    if let Some(mut handle) = child.handle.take() {
+                        handle.abort();
+                        stopped.push(i);
+                    }
+                }
+            }
+            if time::sleep(backoff).await.is_err() {
+                return Ok(());
+            }
+            backoff = (backoff * 2).min(self.max_backoff);
+            for index in stopped {
+                self.children[index].start();
+            }
+        }
+    }
+}
+
+impl Child {
+    fn start(&mut self) {
+        self.handle = Some(task::spawn((self.factory)()));
+    }
+}
+
+// The children stop along with their supervisor.
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        for child in &mut self.children {
+            if let Some(mut handle) = child.handle.take() {
+                handle.abort();
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Supervisor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Supervisor")
+            .field("strategy", &self.strategy)
+            .field("max_restarts", &self.max_restarts)
+            .field("window", &self.window)
+            .field(
+                "children",
+                &self
+                    .children
+                    .iter()
+                    .map(|child| &child.name)
+                    .collect::<Vec<_>>(),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+/// Error of a [`Supervisor`] that gave up, after a child needed more restarts than
+/// allowed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TooManyRestarts {
+    /// The name of the child whose exit made the supervisor give up.
+    pub child: String,
+}
+
+impl fmt::Display for TooManyRestarts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "child `{}` restarted too many times", self.child)
+    }
+}
+
+impl std::error::Error for TooManyRestarts {}
+
+impl From<TooManyRestarts> for JsValue {
+    fn from(err: TooManyRestarts) -> Self {
+        js_sys::Error::new(&err.to_string()).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::time::sleep;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_restarts_panicking_child() {
+        let runs = Arc::new(AtomicU32::new(0));
+        let exits = Rc::new(RefCell::new(Vec::new()));
+        let supervisor = Supervisor::new()
+            .backoff(Duration::from_millis(10), Duration::from_millis(50))
+            .on_exit({
+                let exits = exits.clone();
+                move |name, exit| {
+                    exits
+                        .borrow_mut()
+                        .push((name.to_owned(), exit.is_failure()))
+                }
+            })
+            .child_with("flaky", Restart::Transient, {
+                let runs = runs.clone();
+                move || {
+                    let runs = runs.clone();
+                    async move {
+                        if runs.fetch_add(1, Ordering::SeqCst) < 2 {
+                            panic!("boom");
+                        }
+                    }
+                }
+            })
+            .start();
+        assert_eq!(supervisor.await.unwrap(), Ok(()));
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        let flaky = |failed| ("flaky".to_owned(), failed);
+        assert_eq!(*exits.borrow(), [flaky(true), flaky(true), flaky(false)]);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_gives_up_after_max_restarts() {
+        let supervisor = Supervisor::new()
+            .max_restarts(2, Duration::from_secs(10))
+            .backoff(Duration::from_millis(1), Duration::from_millis(1))
+            .child("crashing", || async { panic!("boom") })
+            .start();
+        let err = supervisor.await.unwrap().unwrap_err();
+        assert_eq!(err.child, "crashing");
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_one_for_all_restarts_siblings() {
+        let starts = Arc::new(AtomicU32::new(0));
+        let supervisor = Supervisor::new()
+            .strategy(Strategy::OneForAll)
+            .max_restarts(1, Duration::from_secs(10))
+            .backoff(Duration::from_millis(1), Duration::from_millis(1))
+            .child("idle", {
+                let starts = starts.clone();
+                move || {
+                    starts.fetch_add(1, Ordering::SeqCst);
+                    async { sleep(Duration::from_secs(60)).await.unwrap() }
+                }
+            })
+            .child("crashing", || async { panic!("boom") })
+            .start();
+        assert!(supervisor.await.unwrap().is_err());
+        assert_eq!(starts.load(Ordering::SeqCst), 2);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_restarts_child_whose_worker_died() {
+        let runs = Arc::new(AtomicU32::new(0));
+        let supervisor = Supervisor::new()
+            .backoff(Duration::from_millis(1), Duration::from_millis(1))
+            .child_with("stuck", Restart::Transient, {
+                let runs = runs.clone();
+                move || {
+                    let runs = runs.clone();
+                    async move {
+                        if runs.fetch_add(1, Ordering::SeqCst) == 0 {
+                            sleep(Duration::from_secs(60)).await.unwrap();
+                        }
+                    }
+                }
+            })
+            .start();
+        sleep(Duration::from_millis(100)).await.unwrap();
+        // Terminates the first run's worker, the latest spawned, behind the supervisor's
+        // back.
+        let registry = crate::worker::registry();
+        let worker = js_sys::Array::from(&registry.keys().into()).pop();
+        registry.delete(&worker);
+        web_sys::Worker::from(worker).terminate();
+        assert_eq!(supervisor.await.unwrap(), Ok(()));
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,164 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use super::blocking::{can_block, BlockingContextError};
+use super::futex::Futex;
+
+/// Lets `n` tasks or threads wait for each other, phase after phase, e.g. between the
+/// steps of a numeric kernel split across [`spawn_blocking`](crate::task::spawn_blocking)
+/// workers.
+///
+/// Workers block in [`wait`](Self::wait), while the main thread can take part with
+/// [`wait_async`](Self::wait_async). The barrier can be reused: once all `n` arrived,
+/// it starts over for the next phase.
+///
+/// ```ignore
+/// let barrier = Arc::new(Barrier::new(workers));
+/// for part in 0..workers {
+///     let barrier = barrier.clone();
+///     task::spawn_blocking(move || {
+///         for step in 0..steps {
+///             compute(part, step);
+///             barrier.wait()?;
+///         }
+///         Ok(())
+///     });
+/// }
+/// ```
+pub struct Barrier {
+    n: usize,
+    arrived: AtomicUsize,
+    // Bumped by the last arrival of every phase, releasing the others.
+    generation: AtomicU32,
+    futex: Futex,
+}
+
+/// Returned by the waits of a [`Barrier`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BarrierWaitResult(bool);
+
+impl BarrierWaitResult {
+    /// Whether this waiter completed the phase. Exactly one waiter per phase is the
+    /// leader, e.g. to combine the results of the phase.
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
+
+impl Barrier {
+    /// A barrier for `n` waiters. A barrier for 0 waiters behaves like one for 1.
+    pub const fn new(n: usize) -> Self {
+        Barrier {
+            n: if n == 0 { 1 } else { n },
+            arrived: AtomicUsize::new(0),
+            generation: AtomicU32::new(0),
+            futex: Futex::new(),
+        }
+    }
+
+    /// Blocks the current worker until `n` waiters arrived in the current phase.
+    ///
+    /// Fails right away, without arriving, on threads that can't block.
+    pub fn wait(&self) -> Result<BarrierWaitResult, BlockingContextError> {
+        if !can_block() {
+            return Err(BlockingContextError);
+        }
+        let Some(generation) = self.arrive() else {
+            return Ok(BarrierWaitResult(true));
+        };
+        loop {
+            let seen = self.futex.seq();
+            if self.generation.load(Ordering::SeqCst) != generation {
+                return Ok(BarrierWaitResult(false));
+            }
+            self.futex.wait(seen, None)?;
+        }
+    }
+
+    /// Waits until `n` waiters arrived in the current phase, without blocking the current
+    /// thread.
+    ///
+    /// The waiter arrives when the future is first polled, and still counts as arrived if
+    /// the future is dropped before the phase completes.
+    pub async fn wait_async(&self) -> BarrierWaitResult {
+        let Some(generation) = self.arrive() else {
+            return BarrierWaitResult(true);
+        };
+        loop {
+            let seen = self.futex.seq();
+            if self.generation.load(Ordering::SeqCst) != generation {
+                return BarrierWaitResult(false);
+            }
+            self.futex.wait_async(seen).await;
+        }
+    }
+
+    // Counts an arrival, completing the phase if it is the last one. Returns the
+    // generation to wait for the end of otherwise.
+    fn arrive(&self) -> Option<u32> {
+        let generation = self.generation.load(Ordering::SeqCst);
+        if self.arrived.fetch_add(1, Ordering::SeqCst) + 1 < self.n {
+            return Some(generation);
+        }
+        // Reset before releasing the others, who may arrive in the next phase right away.
+        self.arrived.store(0, Ordering::SeqCst);
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.futex.notify();
+        None
+    }
+}
+
+impl std::fmt::Debug for Barrier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Barrier")
+            .field("n", &self.n)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicU64;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::task;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_phases_across_workers() {
+        const WORKERS: usize = 3;
+        const STEPS: u64 = 5;
+        // The workers and the main thread.
+        let barrier = Arc::new(Barrier::new(WORKERS + 1));
+        let cells = Arc::new((0..WORKERS).map(|_| AtomicU64::new(0)).collect::<Vec<_>>());
+        let handles = (0..WORKERS)
+            .map(|i| {
+                let (barrier, cells) = (barrier.clone(), cells.clone());
+                task::spawn_blocking(move || {
+                    let mut leaders = 0;
+                    for step in 1..=STEPS {
+                        cells[i].store(step, Ordering::SeqCst);
+                        leaders += barrier.wait()?.is_leader() as usize;
+                        // Every worker finished the step before any moves on.
+                        assert!(cells.iter().all(|cell| cell.load(Ordering::SeqCst) >= step));
+                        barrier.wait()?;
+                    }
+                    Ok::<_, BlockingContextError>(leaders)
+                })
+            })
+            .collect::<Vec<_>>();
+        assert!(matches!(barrier.wait(), Err(BlockingContextError)));
+        let mut leaders = 0;
+        for _ in 0..STEPS {
+            leaders += barrier.wait_async().await.is_leader() as usize;
+            barrier.wait_async().await;
+        }
+        for handle in handles {
+            leaders += handle.join().await.unwrap().unwrap();
+        }
+        assert_eq!(leaders, STEPS as usize);
+    }
+}
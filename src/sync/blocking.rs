@@ -0,0 +1,82 @@
+use std::cell::Cell;
+use std::future::Future;
+use std::sync::atomic::AtomicI32;
+
+use wasm_bindgen::JsCast;
+
+/// Error returned by the blocking operations of the sync primitives when the current
+/// thread isn't allowed to block, which is the case of the main browser thread and of
+/// some worklets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockingContextError;
+
+impl std::fmt::Display for BlockingContextError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "cannot block the current thread (Atomics.wait is not allowed here), use the async API instead"
+        )
+    }
+}
+
+impl std::error::Error for BlockingContextError {}
+
+impl From<BlockingContextError> for wasm_bindgen::JsValue {
+    fn from(err: BlockingContextError) -> Self {
+        wasm_bindgen::JsValue::from_str(&err.to_string())
+    }
+}
+
+thread_local! {
+    static CAN_BLOCK: Cell<Option<bool>> = const { Cell::new(None) };
+}
+
+static PROBE: AtomicI32 = AtomicI32::new(0);
+
+/// Whether the current thread may block with `Atomics.wait`.
+///
+/// Probed once per thread with a zero-timeout wait that never actually sleeps: hosts that
+/// forbid blocking throw on any `Atomics.wait` call.
+pub fn can_block() -> bool {
+    CAN_BLOCK.with(|can_block| match can_block.get() {
+        Some(can_block) => can_block,
+        None => {
+            let memory = wasm_bindgen::memory().unchecked_into::<js_sys::WebAssembly::Memory>();
+            let array = js_sys::Int32Array::new(&memory.buffer());
+            let index = PROBE.as_ptr() as u32 / 4;
+            let probed = js_sys::Atomics::wait_with_timeout(&array, index, 1, 0.0).is_ok();
+            can_block.set(Some(probed));
+            probed
+        }
+    })
+}
+
+/// Blocks the current thread until `future` completes, if blocking is allowed.
+pub(crate) fn block_on<F: Future>(future: F) -> Result<F::Output, BlockingContextError> {
+    if !can_block() {
+        return Err(BlockingContextError);
+    }
+    Ok(futures::executor::block_on(future))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn test_main_thread_cannot_block() {
+        assert!(!can_block());
+        assert_eq!(block_on(async { 1 }), Err(BlockingContextError));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_worker_can_block() {
+        let handle = task::spawn_blocking(|| (can_block(), block_on(async { 1 })));
+        assert_eq!(handle.join().await.unwrap(), (true, Ok(1)));
+    }
+}
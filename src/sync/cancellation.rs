@@ -0,0 +1,202 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+use futures::future::{self, Either};
+
+use super::notify::Notify;
+
+/// A token cancelling whole trees of tasks at once, across workers.
+///
+/// Tasks check the token with [`is_cancelled`](Self::is_cancelled), or wait for it with
+/// [`cancelled`](Self::cancelled), while whoever owns the work, e.g. a page or a route,
+/// calls [`cancel`](Self::cancel). Cancelling a token cancels the tokens derived from it
+/// with [`child_token`](Self::child_token), and theirs in turn, but not its parent, so
+/// that a sub-tree of the work can be cancelled on its own.
+///
+/// Clones share the same state and can be sent to any worker.
+///
+/// ```ignore
+/// let route = CancellationToken::new();
+/// let token = route.child_token();
+/// task::spawn(async move { token.run_until_cancelled(poll_updates()).await });
+/// // On navigation:
+/// route.cancel();
+/// ```
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    node: Arc<Node>,
+}
+
+#[derive(Default)]
+struct Node {
+    cancelled: AtomicBool,
+    notify: Notify,
+    children: Mutex<Vec<Weak<Node>>>,
+}
+
+impl Node {
+    fn cancel(&self) {
+        // The children are taken while the flag is set, so that a child added
+        // concurrently is either taken here or sees the flag.
+        let children = {
+            let mut children = self.children.lock().unwrap();
+            if self.cancelled.swap(true, Ordering::SeqCst) {
+                return;
+            }
+            std::mem::take(&mut *children)
+        };
+        self.notify.notify_waiters();
+        for child in children.iter().filter_map(Weak::upgrade) {
+            child.cancel();
+        }
+    }
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    /// A token cancelled along with this one, which can also be cancelled on its own. It
+    /// is already cancelled if this one is.
+    pub fn child_token(&self) -> CancellationToken {
+        let child = Arc::new(Node::default());
+        {
+            let mut children = self.node.children.lock().unwrap();
+            if !self.is_cancelled() {
+                // Forgets the children that were dropped since.
+                children.retain(|child| child.strong_count() > 0);
+                children.push(Arc::downgrade(&child));
+                return CancellationToken { node: child };
+            }
+        }
+        child.cancel();
+        CancellationToken { node: child }
+    }
+
+    /// Cancels this token and every token derived from it. Does nothing if it was already
+    /// cancelled.
+    pub fn cancel(&self) {
+        self.node.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.node.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Waits until the token is cancelled.
+    pub async fn cancelled(&self) {
+        loop {
+            // Created before checking the flag, so that a cancellation in between isn't
+            // missed.
+            let notified = self.node.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Runs `future` until it completes, or returns `None` once the token is cancelled,
+    /// dropping the future.
+    pub async fn run_until_cancelled<F: Future>(&self, future: F) -> Option<F::Output> {
+        let cancelled = std::pin::pin!(self.cancelled());
+        let future = std::pin::pin!(future);
+        // Checks the token first, so that a cancelled token never runs the future.
+        match future::select(cancelled, future).await {
+            Either::Left(((), _)) => None,
+            Either::Right((output, _)) => Some(output),
+        }
+    }
+
+    /// Cancels the token when the returned guard is dropped, e.g. when a component owning
+    /// the work is torn down.
+    pub fn drop_guard(self) -> DropGuard {
+        DropGuard { token: Some(self) }
+    }
+}
+
+impl std::fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("is_cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+/// Cancels its token when dropped, unless [`disarm`](Self::disarm)ed.
+#[derive(Debug)]
+pub struct DropGuard {
+    token: Option<CancellationToken>,
+}
+
+impl DropGuard {
+    /// Gives the token back without cancelling it.
+    pub fn disarm(mut self) -> CancellationToken {
+        self.token.take().unwrap()
+    }
+}
+
+impl Drop for DropGuard {
+    fn drop(&mut self) {
+        if let Some(token) = &self.token {
+            token.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::task;
+    use crate::time::sleep;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_cancels_the_tree_across_workers() {
+        let root = CancellationToken::new();
+        let route = root.child_token();
+        let waiting = task::spawn({
+            let token = route.child_token();
+            async move {
+                token
+                    .run_until_cancelled(futures::future::pending::<()>())
+                    .await
+            }
+        });
+        let polling = task::spawn_blocking({
+            let token = route.child_token();
+            move || {
+                while !token.is_cancelled() {
+                    std::hint::spin_loop();
+                }
+            }
+        });
+        sleep(Duration::from_millis(50)).await.unwrap();
+        let sibling = root.child_token();
+        route.cancel();
+        assert_eq!(waiting.join().await.unwrap(), None);
+        polling.join().await.unwrap();
+        // Only the route's sub-tree was cancelled.
+        assert!(!root.is_cancelled() && !sibling.is_cancelled());
+        root.cancel();
+        sibling.cancelled().await;
+        assert!(root.child_token().is_cancelled());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_run_until_cancelled_completes() {
+        let token = CancellationToken::new();
+        assert_eq!(token.run_until_cancelled(async { 1 }).await, Some(1));
+        let guard = token.clone().drop_guard();
+        drop(guard);
+        assert!(token.is_cancelled());
+        assert_eq!(token.run_until_cancelled(async { 1 }).await, None);
+    }
+}
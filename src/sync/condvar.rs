@@ -0,0 +1,199 @@
+use std::time::Duration;
+
+use super::blocking::BlockingContextError;
+use super::futex::Futex;
+use super::lock::MutexGuard;
+
+/// A condition variable for the [`Mutex`](super::Mutex) of this module, usable from any
+/// thread.
+///
+/// Like the standard one, waits can wake up without a notification, so the condition
+/// should be checked in a loop, or with [`wait_while`](Self::wait_while). Blocking waits
+/// fail with [`BlockingContextError`] where blocking isn't allowed, in which case the
+/// mutex is left unlocked; [`wait_async`](Self::wait_async) works on any thread.
+///
+/// ```ignore
+/// let (queue, ready) = &*shared;
+/// let mut queue = ready.wait_while(queue.lock()?, |queue| queue.is_empty())?;
+/// let item = queue.pop_front();
+/// ```
+pub struct Condvar {
+    futex: Futex,
+}
+
+/// Whether a [`Condvar::wait_timeout`] returned because its timeout elapsed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Condvar {
+            futex: Futex::new(),
+        }
+    }
+
+    /// Unlocks the mutex of `guard` and blocks the current worker until notified, then
+    /// locks the mutex again.
+    pub fn wait<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+    ) -> Result<MutexGuard<'a, T>, BlockingContextError> {
+        let mutex = MutexGuard::mutex(&guard);
+        // Read while still locked, so that a notification sent once the mutex is unlocked
+        // isn't missed.
+        let seen = self.futex.seq();
+        drop(guard);
+        self.futex.wait(seen, None)?;
+        mutex.lock()
+    }
+
+    /// Waits until `condition` returns `false`, which is checked with the mutex locked.
+    pub fn wait_while<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> Result<MutexGuard<'a, T>, BlockingContextError> {
+        while condition(&mut guard) {
+            guard = self.wait(guard)?;
+        }
+        Ok(guard)
+    }
+
+    /// Like [`wait`](Self::wait), giving up once `timeout` elapsed.
+    pub fn wait_timeout<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Duration,
+    ) -> Result<(MutexGuard<'a, T>, WaitTimeoutResult), BlockingContextError> {
+        let mutex = MutexGuard::mutex(&guard);
+        let seen = self.futex.seq();
+        drop(guard);
+        let notified = self.futex.wait(seen, Some(timeout))?;
+        Ok((mutex.lock()?, WaitTimeoutResult(!notified)))
+    }
+
+    /// Like [`wait`](Self::wait), waiting without blocking the current thread, e.g. on the
+    /// main thread.
+    ///
+    /// Wakes up on every turn of the event loop where `Atomics.waitAsync` isn't supported.
+    pub async fn wait_async<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = MutexGuard::mutex(&guard);
+        let seen = self.futex.seq();
+        drop(guard);
+        self.futex.wait_async(seen).await;
+        mutex.lock_async().await
+    }
+
+    /// Like [`wait_while`](Self::wait_while), waiting with
+    /// [`wait_async`](Self::wait_async).
+    pub async fn wait_while_async<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut guard) {
+            guard = self.wait_async(guard).await;
+        }
+        guard
+    }
+
+    /// Wakes up one waiter, if any.
+    pub fn notify_one(&self) {
+        self.futex.notify_one();
+    }
+
+    pub fn notify_all(&self) {
+        self.futex.notify();
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Condvar::new()
+    }
+}
+
+impl std::fmt::Debug for Condvar {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Condvar").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::sync::Mutex;
+    use crate::task;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_producer_consumer() {
+        let shared = Arc::new((Mutex::new(VecDeque::new()), Condvar::new()));
+        let consumer = task::spawn_blocking({
+            let shared = shared.clone();
+            move || {
+                let (queue, ready) = &*shared;
+                let mut sum = 0;
+                for _ in 0..10 {
+                    let mut queue = ready.wait_while(queue.lock()?, |queue| queue.is_empty())?;
+                    sum += queue.pop_front().unwrap();
+                }
+                Ok::<_, BlockingContextError>(sum)
+            }
+        });
+        let (queue, ready) = &*shared;
+        for i in 0..10 {
+            queue.lock_async().await.push_back(i);
+            ready.notify_one();
+            crate::time::sleep(Duration::from_millis(5)).await.unwrap();
+        }
+        assert_eq!(consumer.join().await.unwrap(), Ok(45));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_wait_async_on_main_thread() {
+        let shared = Arc::new((Mutex::new(false), Condvar::new()));
+        let (done, ready) = &*shared;
+        let guard = done.lock_async().await;
+        // The main thread can't block.
+        assert!(matches!(ready.wait(guard), Err(BlockingContextError)));
+        let producer = task::spawn_blocking({
+            let shared = shared.clone();
+            move || {
+                let (done, ready) = &*shared;
+                *done.lock().unwrap() = true;
+                ready.notify_all();
+            }
+        });
+        let guard = ready
+            .wait_while_async(done.lock_async().await, |done| !*done)
+            .await;
+        assert!(*guard);
+        drop(guard);
+        producer.join().await.unwrap();
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_wait_timeout() {
+        let shared = Arc::new((Mutex::new(()), Condvar::new()));
+        let handle = task::spawn_blocking(move || {
+            let (mutex, condvar) = &*shared;
+            let (_guard, result) =
+                condvar.wait_timeout(mutex.lock()?, Duration::from_millis(20))?;
+            Ok::<_, BlockingContextError>(result.timed_out())
+        });
+        assert_eq!(handle.join().await.unwrap(), Ok(true));
+    }
+}
@@ -0,0 +1,229 @@
+// Tracks which tasks wait on the crate's channels and locks, and which tasks may wake
+// them, to warn when tasks wait on each other in a cycle, with the
+// `deadlock-detection` feature. Such tasks never complete, yet nothing fails, so the
+// deadlock otherwise looks like a hang.
+//
+// Resources are identified by the address of the futex their waiters park on. A task
+// becomes a notifier of a resource when it does something that makes it wake the
+// resource's waiters later: locking a lock, or sending on a channel for its receiver.
+// Waits for a resource can only be ended by one of its notifiers, so a waiting task is
+// deadlocked when every task it can reach through these edges is waiting too. Notifiers
+// that finished or never waited break the chain, so tasks whose notifiers aren't known,
+// e.g. senders moved to a task that didn't send yet, are never reported.
+
+use std::panic::Location;
+
+#[cfg(feature = "deadlock-detection")]
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "deadlock-detection")]
+use std::sync::Mutex;
+
+#[cfg(feature = "deadlock-detection")]
+use crate::task::{self, Id};
+
+#[cfg(feature = "deadlock-detection")]
+struct Wait {
+    resource: usize,
+    operation: &'static str,
+    location: &'static Location<'static>,
+}
+
+#[cfg(feature = "deadlock-detection")]
+#[derive(Default)]
+struct Graph {
+    notifiers: HashMap<usize, HashSet<Id>>,
+    waits: HashMap<Id, Wait>,
+    // Sets of deadlocked tasks already warned about.
+    warned: HashSet<Vec<Id>>,
+}
+
+#[cfg(feature = "deadlock-detection")]
+static GRAPH: Mutex<Option<Graph>> = Mutex::new(None);
+
+#[cfg(feature = "deadlock-detection")]
+fn with_graph<R>(f: impl FnOnce(&mut Graph) -> R) -> Option<R> {
+    // Only tried, in case a panic happened while the lock was held.
+    let mut graph = GRAPH.try_lock().ok()?;
+    Some(f(graph.get_or_insert_with(Graph::default)))
+}
+
+/// Records the current task as one that may wake the waiters of `resource`.
+#[cfg_attr(not(feature = "deadlock-detection"), allow(unused_variables))]
+pub(crate) fn notifier<R: ?Sized>(resource: &R) {
+    #[cfg(feature = "deadlock-detection")]
+    if let Some(id) = task::try_id() {
+        with_graph(|graph| {
+            graph
+                .notifiers
+                .entry(resource as *const R as *const () as usize)
+                .or_default()
+                .insert(id);
+        });
+    }
+}
+
+/// Forgets the current task as a notifier of `resource`, or every notifier with `all`,
+/// e.g. once an exclusive lock is released.
+#[cfg_attr(not(feature = "deadlock-detection"), allow(unused_variables))]
+pub(crate) fn not_notifier<R: ?Sized>(resource: &R, all: bool) {
+    #[cfg(feature = "deadlock-detection")]
+    with_graph(|graph| {
+        let resource = resource as *const R as *const () as usize;
+        if all {
+            graph.notifiers.remove(&resource);
+        } else if let (Some(id), Some(notifiers)) =
+            (task::try_id(), graph.notifiers.get_mut(&resource))
+        {
+            notifiers.remove(&id);
+        }
+    });
+}
+
+/// Records that the current task waits on `resource` in `operation`, called at
+/// `location`, until the returned guard is dropped, and warns if that closes a cycle.
+#[cfg_attr(not(feature = "deadlock-detection"), allow(unused_variables))]
+pub(crate) fn waiting<R: ?Sized>(
+    resource: &R,
+    operation: &'static str,
+    location: &'static Location<'static>,
+) -> Waiting {
+    #[cfg(feature = "deadlock-detection")]
+    {
+        let Some(id) = task::try_id() else {
+            return Waiting { id: None };
+        };
+        let wait = Wait {
+            resource: resource as *const R as *const () as usize,
+            operation,
+            location,
+        };
+        if let Some(Some(message)) = with_graph(|graph| {
+            graph.waits.insert(id, wait);
+            diagnose(graph, id)
+        }) {
+            web_sys::console::warn_1(&format!("wasmt: {message}").into());
+        }
+        Waiting { id: Some(id) }
+    }
+    #[cfg(not(feature = "deadlock-detection"))]
+    Waiting {}
+}
+
+/// Guard of a wait recorded by [`waiting`].
+pub(crate) struct Waiting {
+    #[cfg(feature = "deadlock-detection")]
+    id: Option<Id>,
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        #[cfg(feature = "deadlock-detection")]
+        if let Some(id) = self.id {
+            with_graph(|graph| graph.waits.remove(&id));
+        }
+    }
+}
+
+// Describes the cycle `id` is stuck in, if every task it waits on, directly or not, is
+// waiting too. Each set of tasks is only reported once.
+#[cfg(feature = "deadlock-detection")]
+fn diagnose(graph: &mut Graph, id: Id) -> Option<String> {
+    let mut reached = vec![id];
+    let mut seen = HashSet::from([id]);
+    let mut next = 0;
+    while let Some(&task) = reached.get(next) {
+        next += 1;
+        let wait = graph.waits.get(&task)?;
+        let notifiers = graph.notifiers.get(&wait.resource)?;
+        if notifiers.is_empty() {
+            return None;
+        }
+        for &notifier in notifiers {
+            if seen.insert(notifier) {
+                reached.push(notifier);
+            }
+        }
+    }
+    let mut key = reached.clone();
+    key.sort();
+    if !graph.warned.insert(key) {
+        return None;
+    }
+    let waits = reached
+        .iter()
+        .map(|task| {
+            let wait = &graph.waits[task];
+            let mut notifiers = graph.notifiers[&wait.resource]
+                .iter()
+                .map(|notifier| format!("task {notifier}"))
+                .collect::<Vec<_>>();
+            notifiers.sort();
+            format!(
+                "task {task} waits in `{}` at {} for {}",
+                wait.operation,
+                wait.location,
+                notifiers.join(" or ")
+            )
+        })
+        .collect::<Vec<_>>();
+    Some(format!(
+        "possible deadlock, these tasks wait on each other: {}",
+        waits.join("; ")
+    ))
+}
+
+#[cfg(all(test, feature = "deadlock-detection"))]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::channel::mpsc;
+    use crate::sync::Mutex;
+    use crate::time::sleep;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_lock_and_channel_cycle() {
+        let lock = Arc::new(Mutex::new(()));
+        let (tx, mut rx) = mpsc::channel::<()>(1);
+        let holder = task::spawn({
+            let lock = lock.clone();
+            async move {
+                let _guard = lock.lock_async().await;
+                // Makes the task a notifier of the channel.
+                tx.send(()).await.unwrap();
+                sleep(Duration::from_millis(50)).await.unwrap();
+                // Full, and the receiver waits for the lock.
+                tx.send(()).await.unwrap();
+                tx.send(()).await.unwrap();
+            }
+        });
+        let waiter = task::spawn({
+            let lock = lock.clone();
+            async move {
+                rx.recv().await;
+                // Makes the task a notifier of the channel's senders.
+                let _guard = lock.lock_async().await;
+                rx.recv().await;
+            }
+        });
+        let mut reported = None;
+        for _ in 0..100 {
+            sleep(Duration::from_millis(10)).await.unwrap();
+            reported = with_graph(|graph| graph.warned.iter().next().cloned()).flatten();
+            if reported.is_some() {
+                break;
+            }
+        }
+        let mut expected = vec![holder.id(), waiter.id()];
+        expected.sort();
+        assert_eq!(reported, Some(expected));
+        let (mut holder, mut waiter) = (holder, waiter);
+        holder.abort_hard();
+        waiter.abort_hard();
+    }
+}
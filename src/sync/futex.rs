@@ -0,0 +1,185 @@
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::Duration;
+
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+use super::blocking::{can_block, BlockingContextError};
+use crate::task::schedule::{next_turn, Schedule};
+
+// Number of failed attempts before waiting, for locks that are only held briefly.
+const SPINS: usize = 100;
+
+// Parks threads and tasks until the state of a lock changes.
+//
+// Waiters wait on a sequence number bumped by every release, so that a release between
+// a failed attempt and the wait makes the wait return right away. Releases only call
+// into JS when someone is waiting.
+pub(crate) struct Futex {
+    seq: AtomicI32,
+    waiters: AtomicI32,
+}
+
+impl Futex {
+    pub(crate) const fn new() -> Self {
+        Futex {
+            seq: AtomicI32::new(0),
+            waiters: AtomicI32::new(0),
+        }
+    }
+
+    fn view(&self) -> (js_sys::Int32Array, u32) {
+        let memory = wasm_bindgen::memory().unchecked_into::<js_sys::WebAssembly::Memory>();
+        let array = js_sys::Int32Array::new(&memory.buffer());
+        (array, self.seq.as_ptr() as u32 / 4)
+    }
+
+    /// The sequence number to pass to [`wait`](Self::wait), read before checking the
+    /// state that the wait is for.
+    pub(crate) fn seq(&self) -> i32 {
+        self.seq.load(Ordering::SeqCst)
+    }
+
+    /// Retries `acquire` until it succeeds, blocking the current thread in between.
+    pub(crate) fn wait_until<R>(
+        &self,
+        mut acquire: impl FnMut() -> Option<R>,
+    ) -> Result<R, BlockingContextError> {
+        if let Some(acquired) = spin(&mut acquire) {
+            return Ok(acquired);
+        }
+        if !can_block() {
+            return Err(BlockingContextError);
+        }
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        let acquired = loop {
+            let seen = self.seq();
+            if let Some(acquired) = acquire() {
+                break acquired;
+            }
+            self.park(seen, None);
+        };
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+        Ok(acquired)
+    }
+
+    /// Blocks the current thread until a notification following the read of `seen`, or
+    /// until `timeout` elapses. Returns whether it was notified.
+    pub(crate) fn wait(
+        &self,
+        seen: i32,
+        timeout: Option<Duration>,
+    ) -> Result<bool, BlockingContextError> {
+        if !can_block() {
+            return Err(BlockingContextError);
+        }
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        let notified = self.park(seen, timeout);
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+        Ok(notified)
+    }
+
+    fn park(&self, seen: i32, timeout: Option<Duration>) -> bool {
+        let (array, index) = self.view();
+        let result = match timeout {
+            Some(timeout) => js_sys::Atomics::wait_with_timeout(
+                &array,
+                index,
+                seen,
+                timeout.as_secs_f64() * 1000.0,
+            ),
+            None => js_sys::Atomics::wait(&array, index, seen),
+        };
+        result.map_or(true, |result| result != "timed-out")
+    }
+
+    /// Retries `acquire` until it succeeds, yielding to the event loop in between, which
+    /// works on any thread.
+    ///
+    /// Waits with `Atomics.waitAsync` where supported, and otherwise retries on every
+    /// turn of the event loop.
+    pub(crate) async fn wait_until_async<R>(&self, mut acquire: impl FnMut() -> Option<R>) -> R {
+        if let Some(acquired) = spin(&mut acquire) {
+            return acquired;
+        }
+        let _waiting = Waiting::new(&self.waiters);
+        loop {
+            let seen = self.seq();
+            if let Some(acquired) = acquire() {
+                return acquired;
+            }
+            self.park_async(seen).await;
+        }
+    }
+
+    /// Like [`wait`](Self::wait) without a timeout, yielding to the event loop instead of
+    /// blocking. May return before a notification when `Atomics.waitAsync` isn't
+    /// supported.
+    pub(crate) async fn wait_async(&self, seen: i32) {
+        let _waiting = Waiting::new(&self.waiters);
+        self.park_async(seen).await;
+    }
+
+    async fn park_async(&self, seen: i32) {
+        let (array, index) = self.view();
+        let wait = js_sys::Atomics::wait_async(&array, index, seen).ok();
+        let promise = wait.and_then(|wait| {
+            let is_async = js_sys::Reflect::get(&wait, &"async".into()).ok()?;
+            let value = js_sys::Reflect::get(&wait, &"value".into()).ok()?;
+            is_async
+                .is_truthy()
+                .then(|| value.unchecked_into::<js_sys::Promise>())
+        });
+        match promise {
+            Some(promise) => JsFuture::from(promise).await.ok(),
+            // The value already changed, so there is no need to wait.
+            None if self.seq() != seen => None,
+            None => next_turn(Schedule::Macrotask).await.ok(),
+        };
+    }
+
+    /// Wakes every waiter, after the lock was released.
+    pub(crate) fn notify(&self) {
+        self.seq.fetch_add(1, Ordering::SeqCst);
+        if self.waiters.load(Ordering::SeqCst) > 0 {
+            let (array, index) = self.view();
+            js_sys::Atomics::notify(&array, index).ok();
+        }
+    }
+
+    /// Wakes a single thread blocked in [`wait`](Self::wait) or waiting with
+    /// `Atomics.waitAsync`. Waiters polling on every turn of the event loop all see it.
+    pub(crate) fn notify_one(&self) {
+        self.seq.fetch_add(1, Ordering::SeqCst);
+        if self.waiters.load(Ordering::SeqCst) > 0 {
+            let (array, index) = self.view();
+            js_sys::Atomics::notify_with_count(&array, index, 1).ok();
+        }
+    }
+}
+
+// Counts an async waiter until dropped, including when its future is.
+struct Waiting<'a>(&'a AtomicI32);
+
+impl<'a> Waiting<'a> {
+    fn new(waiters: &'a AtomicI32) -> Self {
+        waiters.fetch_add(1, Ordering::SeqCst);
+        Waiting(waiters)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn spin<R>(acquire: &mut impl FnMut() -> Option<R>) -> Option<R> {
+    for _ in 0..SPINS {
+        if let Some(acquired) = acquire() {
+            return Some(acquired);
+        }
+        std::hint::spin_loop();
+    }
+    None
+}
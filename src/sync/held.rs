@@ -0,0 +1,136 @@
+// Tracks the guards of the crate's locks held by the current thread in debug builds, to
+// warn when a thread blocks while holding one: every task waiting for the lock, on any
+// worker, then stalls for as long as the thread blocks, which is how code ported from
+// native threads most often deadlocks softly.
+//
+// Guards moved to another thread stay recorded on the thread that locked them, which
+// may cause spurious warnings there.
+
+use std::panic::Location;
+
+#[cfg(debug_assertions)]
+use std::cell::RefCell;
+#[cfg(debug_assertions)]
+use std::collections::HashSet;
+
+#[cfg(debug_assertions)]
+struct Held {
+    lock: *const (),
+    name: &'static str,
+    location: &'static Location<'static>,
+}
+
+#[cfg(debug_assertions)]
+thread_local! {
+    static HELD: RefCell<Vec<Held>> = const { RefCell::new(Vec::new()) };
+    // Pairs of lock and blocking call sites already warned about.
+    static WARNED: RefCell<HashSet<(usize, usize)>> = RefCell::new(HashSet::new());
+}
+
+/// Records a guard of `lock`, named after its type, locked at `location`.
+#[cfg_attr(not(debug_assertions), allow(unused_variables))]
+pub(crate) fn acquired<L: ?Sized>(lock: &L, location: &'static Location<'static>) {
+    #[cfg(debug_assertions)]
+    HELD.try_with(|held| {
+        held.borrow_mut().push(Held {
+            lock: lock as *const L as *const (),
+            name: std::any::type_name::<L>(),
+            location,
+        })
+    })
+    .ok();
+}
+
+/// Forgets a guard of `lock`, when dropped.
+#[cfg_attr(not(debug_assertions), allow(unused_variables))]
+pub(crate) fn released<L: ?Sized>(lock: &L) {
+    #[cfg(debug_assertions)]
+    HELD.try_with(|held| {
+        let mut held = held.borrow_mut();
+        let lock = lock as *const L as *const ();
+        if let Some(index) = held.iter().rposition(|held| held.lock == lock) {
+            held.remove(index);
+        }
+    })
+    .ok();
+}
+
+/// Describes the locks held by the current thread before `operation`, called at
+/// `location`, blocks it. Each pair of lock and blocking call site is only reported once
+/// per thread.
+#[cfg_attr(not(debug_assertions), allow(unused_variables))]
+pub(crate) fn diagnose(operation: &str, location: &'static Location<'static>) -> Vec<String> {
+    #[cfg(debug_assertions)]
+    return HELD
+        .try_with(|held| {
+            let task = match crate::task::try_id() {
+                Some(id) => format!("task {id}"),
+                None => "the current thread".to_owned(),
+            };
+            held.borrow()
+                .iter()
+                .filter(|held| {
+                    let key = (
+                        held.location as *const _ as usize,
+                        location as *const _ as usize,
+                    );
+                    WARNED.with(|warned| warned.borrow_mut().insert(key))
+                })
+                .map(|held| {
+                    format!(
+                        "`{operation}` at {location} blocks {task} while it holds the `{}` \
+                         locked at {}: tasks waiting for the lock stall until it returns",
+                        held.name, held.location
+                    )
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    #[cfg(not(debug_assertions))]
+    Vec::new()
+}
+
+/// Logs the diagnostics of [`diagnose`] as warnings.
+#[track_caller]
+pub(crate) fn warn_if_held(operation: &str) {
+    for message in diagnose(operation, Location::caller()) {
+        web_sys::console::warn_1(&format!("wasmt: {message}").into());
+    }
+}
+
+#[cfg(all(test, debug_assertions))]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::sync::{Mutex, RwLock};
+    use crate::task;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_diagnose_held_locks() {
+        let mutex = Arc::new(Mutex::new(0u32));
+        let lock = Arc::new(RwLock::new(()));
+        let handle = task::spawn_blocking(move || {
+            let location = Location::caller();
+            let guard = mutex.lock().unwrap();
+            let messages = diagnose("sleep_blocking", location);
+            // Reported once per call site.
+            let repeated = diagnose("sleep_blocking", location);
+            drop(guard);
+            let _read = lock.read().unwrap();
+            let after_release = diagnose("sleep_blocking", location);
+            (messages, repeated.len(), after_release)
+        });
+        let (messages, repeated, after_release) = handle.join().await.unwrap();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("Mutex<u32>"), "{}", messages[0]);
+        assert!(messages[0].contains(file!()), "{}", messages[0]);
+        assert_eq!(repeated, 0);
+        assert_eq!(after_release.len(), 1);
+        assert!(after_release[0].contains("RwLock<()>"));
+    }
+}
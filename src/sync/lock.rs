@@ -0,0 +1,542 @@
+use std::cell::UnsafeCell;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::panic::Location;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering};
+
+use super::blocking::BlockingContextError;
+use super::deadlock;
+use super::futex::Futex;
+use super::held;
+use crate::task::{self, schedule, Id};
+
+/// A mutual exclusion lock usable from any thread, including the main thread.
+///
+/// The standard `Mutex` blocks with `Atomics.wait` when contended, which browsers forbid
+/// on the main thread. Here [`lock`](Self::lock) spins, then blocks in workers but
+/// returns [`BlockingContextError`] where blocking isn't allowed, and
+/// [`lock_async`](Self::lock_async) waits without blocking anywhere, through
+/// `Atomics.waitAsync` or by yielding to the event loop.
+///
+/// The lock isn't poisoned by panics, and waiters aren't served in any particular order.
+///
+/// When a task waits in [`lock_async`](Self::lock_async) for a lock held by a task with
+/// a lower priority, i.e. [deferred](crate::task::Schedule) while the waiter isn't, the
+/// holder inherits the waiter's priority until it releases the lock.
+pub struct Mutex<T: ?Sized> {
+    locked: AtomicBool,
+    futex: Futex,
+    // The id of the task holding the lock, or 0.
+    holder: AtomicU64,
+    // The id of the holder boosted by a waiter, or 0.
+    boosted: AtomicU64,
+    value: UnsafeCell<T>,
+}
+
+// SAFETY: the lock gives access to the value to one thread at a time.
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Mutex {
+            locked: AtomicBool::new(false),
+            futex: Futex::new(),
+            holder: AtomicU64::new(0),
+            boosted: AtomicU64::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Locks the mutex, blocking the current worker while it is held elsewhere.
+    #[track_caller]
+    pub fn lock(&self) -> Result<MutexGuard<'_, T>, BlockingContextError> {
+        let location = Location::caller();
+        let _waiting = deadlock::waiting(&self.futex, "Mutex::lock", location);
+        self.futex.wait_until(|| self.try_lock_at(location))
+    }
+
+    /// Locks the mutex, waiting without blocking the current thread.
+    #[track_caller]
+    pub fn lock_async(&self) -> impl Future<Output = MutexGuard<'_, T>> {
+        let location = Location::caller();
+        async move {
+            let _waiting = deadlock::waiting(&self.futex, "Mutex::lock_async", location);
+            let waiter = task::try_id();
+            self.futex
+                .wait_until_async(|| {
+                    let guard = self.try_lock_at(location);
+                    if guard.is_none() && !waiter.is_some_and(schedule::is_deferred) {
+                        self.boost_holder();
+                    }
+                    guard
+                })
+                .await
+        }
+    }
+
+    // Lets a deferred holder run right away until it releases the lock, see
+    // `task::schedule::boost`. Only the first waiter to see it boosts it.
+    fn boost_holder(&self) {
+        let holder = self.holder.load(Ordering::SeqCst);
+        let Some(id) = Id::from_u64(holder) else {
+            return;
+        };
+        if !schedule::boost(id) {
+            return;
+        }
+        if self
+            .boosted
+            .compare_exchange(0, holder, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            schedule::unboost(id);
+            return;
+        }
+        // The lock may have been released before the boost, without undoing it.
+        if self.holder.load(Ordering::SeqCst) != holder {
+            self.unboost(holder);
+        }
+    }
+
+    fn unboost(&self, holder: u64) {
+        if self
+            .boosted
+            .compare_exchange(holder, 0, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            schedule::unboost(Id::from_u64(holder).unwrap());
+        }
+    }
+
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.try_lock_at(Location::caller())
+    }
+
+    // Debug builds record where the guards were locked, see `held`.
+    fn try_lock_at(&self, location: &'static Location<'static>) -> Option<MutexGuard<'_, T>> {
+        let locked = self
+            .locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok();
+        locked.then(|| {
+            held::acquired(self, location);
+            deadlock::notifier(&self.futex);
+            let holder = task::try_id().map_or(0, Id::as_u64);
+            self.holder.store(holder, Ordering::SeqCst);
+            MutexGuard {
+                mutex: self,
+                _not_send_sync: PhantomData,
+            }
+        })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Mutex::new(T::default())
+    }
+}
+
+impl<T: ?Sized + std::fmt::Debug> std::fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut d = f.debug_struct("Mutex");
+        match self.try_lock() {
+            Some(guard) => d.field("data", &&*guard),
+            None => d.field("data", &format_args!("<locked>")),
+        };
+        d.finish_non_exhaustive()
+    }
+}
+
+pub struct MutexGuard<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+    _not_send_sync: NotSendSync,
+}
+
+// Guards give `&T` to whoever holds a `&Guard`, so they may only be shared between
+// threads when `T` may. Like std's, they aren't `Send` either, since they are released by
+// the thread that acquired them.
+type NotSendSync = PhantomData<*const ()>;
+
+unsafe impl<T: ?Sized + Sync> Sync for MutexGuard<'_, T> {}
+
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    pub(super) fn mutex(guard: &Self) -> &'a Mutex<T> {
+        guard.mutex
+    }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the guard holds the lock.
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the guard holds the lock.
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        held::released(self.mutex);
+        deadlock::not_notifier(&self.mutex.futex, true);
+        let holder = self.mutex.holder.swap(0, Ordering::SeqCst);
+        if holder != 0 {
+            self.mutex.unboost(holder);
+        }
+        self.mutex.locked.store(false, Ordering::Release);
+        self.mutex.futex.notify();
+    }
+}
+
+// The state of a `RwLock` held for writing.
+const WRITER: i32 = -1;
+
+/// A reader-writer lock usable from any thread, including the main thread, with the
+/// same blocking and async variants as [`Mutex`].
+///
+/// Readers aren't held back by waiting writers, so a steady flow of readers can starve
+/// writers.
+pub struct RwLock<T: ?Sized> {
+    // The number of readers, or `WRITER`.
+    state: AtomicI32,
+    futex: Futex,
+    value: UnsafeCell<T>,
+}
+
+// SAFETY: the lock gives shared access to the value to readers and exclusive access to a
+// single writer.
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> Self {
+        RwLock {
+            state: AtomicI32::new(0),
+            futex: Futex::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Locks for reading, blocking the current worker while a writer holds the lock.
+    #[track_caller]
+    pub fn read(&self) -> Result<RwLockReadGuard<'_, T>, BlockingContextError> {
+        let location = Location::caller();
+        let _waiting = deadlock::waiting(&self.futex, "RwLock::read", location);
+        self.futex.wait_until(|| self.try_read_at(location))
+    }
+
+    /// Locks for writing, blocking the current worker while the lock is held.
+    #[track_caller]
+    pub fn write(&self) -> Result<RwLockWriteGuard<'_, T>, BlockingContextError> {
+        let location = Location::caller();
+        let _waiting = deadlock::waiting(&self.futex, "RwLock::write", location);
+        self.futex.wait_until(|| self.try_write_at(location))
+    }
+
+    /// Locks for reading, waiting without blocking the current thread.
+    #[track_caller]
+    pub fn read_async(&self) -> impl Future<Output = RwLockReadGuard<'_, T>> {
+        let location = Location::caller();
+        async move {
+            let _waiting = deadlock::waiting(&self.futex, "RwLock::read_async", location);
+            self.futex
+                .wait_until_async(|| self.try_read_at(location))
+                .await
+        }
+    }
+
+    /// Locks for writing, waiting without blocking the current thread.
+    #[track_caller]
+    pub fn write_async(&self) -> impl Future<Output = RwLockWriteGuard<'_, T>> {
+        let location = Location::caller();
+        async move {
+            let _waiting = deadlock::waiting(&self.futex, "RwLock::write_async", location);
+            self.futex
+                .wait_until_async(|| self.try_write_at(location))
+                .await
+        }
+    }
+
+    #[track_caller]
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        self.try_read_at(Location::caller())
+    }
+
+    #[track_caller]
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        self.try_write_at(Location::caller())
+    }
+
+    fn try_read_at(&self, location: &'static Location<'static>) -> Option<RwLockReadGuard<'_, T>> {
+        let mut state = self.state.load(Ordering::Relaxed);
+        while state != WRITER {
+            match self.state.compare_exchange_weak(
+                state,
+                state + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    held::acquired(self, location);
+                    deadlock::notifier(&self.futex);
+                    return Some(RwLockReadGuard {
+                        lock: self,
+                        _not_send_sync: PhantomData,
+                    });
+                }
+                Err(current) => state = current,
+            }
+        }
+        None
+    }
+
+    fn try_write_at(
+        &self,
+        location: &'static Location<'static>,
+    ) -> Option<RwLockWriteGuard<'_, T>> {
+        let locked = self
+            .state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok();
+        locked.then(|| {
+            held::acquired(self, location);
+            deadlock::notifier(&self.futex);
+            RwLockWriteGuard {
+                lock: self,
+                _not_send_sync: PhantomData,
+            }
+        })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        RwLock::new(T::default())
+    }
+}
+
+impl<T: ?Sized + std::fmt::Debug> std::fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut d = f.debug_struct("RwLock");
+        match self.try_read() {
+            Some(guard) => d.field("data", &&*guard),
+            None => d.field("data", &format_args!("<locked>")),
+        };
+        d.finish_non_exhaustive()
+    }
+}
+
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+    _not_send_sync: NotSendSync,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for RwLockReadGuard<'_, T> {}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the guard holds a read lock.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        held::released(self.lock);
+        deadlock::not_notifier(&self.lock.futex, false);
+        // Only writers wait while readers hold the lock.
+        if self.lock.state.fetch_sub(1, Ordering::Release) == 1 {
+            self.lock.futex.notify();
+        }
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+    _not_send_sync: NotSendSync,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for RwLockWriteGuard<'_, T> {}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the guard holds the write lock.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the guard holds the write lock.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        held::released(self.lock);
+        deadlock::not_notifier(&self.lock.futex, true);
+        self.lock.state.store(0, Ordering::Release);
+        self.lock.futex.notify();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::task;
+    use crate::time::sleep;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    // Doesn't compile if `T` is `Sync`, since `some_item` is then implemented twice.
+    trait AmbiguousIfSync<A> {
+        fn some_item() {}
+    }
+
+    impl<T: ?Sized> AmbiguousIfSync<()> for T {}
+    impl<T: ?Sized + Sync> AmbiguousIfSync<u8> for T {}
+
+    // Nor if `T` is `Send`.
+    trait AmbiguousIfSend<A> {
+        fn some_item() {}
+    }
+
+    impl<T: ?Sized> AmbiguousIfSend<()> for T {}
+    impl<T: ?Sized + Send> AmbiguousIfSend<u8> for T {}
+
+    const _: fn() = || {
+        type Cell = std::cell::Cell<u8>;
+        let _ = <MutexGuard<'static, Cell> as AmbiguousIfSync<_>>::some_item;
+        let _ = <RwLockReadGuard<'static, Cell> as AmbiguousIfSync<_>>::some_item;
+        let _ = <RwLockWriteGuard<'static, Cell> as AmbiguousIfSync<_>>::some_item;
+        let _ = <MutexGuard<'static, u8> as AmbiguousIfSend<_>>::some_item;
+        let _ = <RwLockReadGuard<'static, u8> as AmbiguousIfSend<_>>::some_item;
+        let _ = <RwLockWriteGuard<'static, u8> as AmbiguousIfSend<_>>::some_item;
+    };
+
+    #[wasm_bindgen_test]
+    fn test_guards_of_sync_values_are_sync() {
+        fn assert_sync<T: Sync>() {}
+        assert_sync::<MutexGuard<'static, u8>>();
+        assert_sync::<RwLockReadGuard<'static, u8>>();
+        assert_sync::<RwLockWriteGuard<'static, u8>>();
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_mutex_on_main_thread() {
+        let mutex = Arc::new(Mutex::new(0));
+        let guard = mutex.lock().unwrap();
+        assert!(mutex.try_lock().is_none());
+        let worker = task::spawn_blocking({
+            let mutex = mutex.clone();
+            move || {
+                for _ in 0..100 {
+                    *mutex.lock().unwrap() += 1;
+                }
+            }
+        });
+        sleep(Duration::from_millis(50)).await.unwrap();
+        // Held here, so the main thread can't block on it.
+        assert!(matches!(mutex.lock(), Err(BlockingContextError)));
+        drop(guard);
+        for _ in 0..100 {
+            *mutex.lock_async().await += 1;
+        }
+        worker.join().await.unwrap();
+        assert_eq!(*mutex.lock_async().await, 200);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_deferred_holder_inherits_priority() {
+        let mutex = Arc::new(Mutex::new(()));
+        let release = Arc::new(AtomicBool::new(false));
+        let holder = task::spawn_local_with(task::Schedule::Macrotask, {
+            let mutex = mutex.clone();
+            let release = release.clone();
+            async move {
+                let _guard = mutex.lock_async().await;
+                while !release.load(Ordering::Relaxed) {
+                    sleep(Duration::from_millis(1)).await.unwrap();
+                }
+            }
+        });
+        sleep(Duration::from_millis(20)).await.unwrap();
+        let id = holder.id();
+        assert!(mutex.is_locked());
+        assert!(schedule::is_deferred(id));
+        let waiter = task::spawn_local({
+            let mutex = mutex.clone();
+            async move {
+                drop(mutex.lock_async().await);
+            }
+        });
+        sleep(Duration::from_millis(20)).await.unwrap();
+        assert!(!schedule::is_deferred(id));
+        release.store(true, Ordering::Relaxed);
+        waiter.await.unwrap();
+        holder.await.unwrap();
+        assert!(!mutex.is_locked());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_rw_lock() {
+        let lock = Arc::new(RwLock::new(Vec::new()));
+        let reader = lock.read_async().await;
+        let second_reader = lock.try_read().unwrap();
+        assert!(lock.try_write().is_none());
+        let writer = task::spawn_blocking({
+            let lock = lock.clone();
+            move || lock.write().unwrap().push(1)
+        });
+        sleep(Duration::from_millis(50)).await.unwrap();
+        assert!(reader.is_empty() && second_reader.is_empty());
+        drop((reader, second_reader));
+        writer.join().await.unwrap();
+        lock.write_async().await.push(2);
+        assert_eq!(*lock.read_async().await, [1, 2]);
+    }
+}
@@ -0,0 +1,26 @@
+mod barrier;
+pub(crate) mod blocking;
+mod cancellation;
+mod condvar;
+pub(crate) mod deadlock;
+pub(crate) mod futex;
+pub(crate) mod held;
+mod lock;
+mod notify;
+mod once;
+mod semaphore;
+mod snapshot;
+mod task_tracker;
+pub(crate) mod web_lock;
+
+pub use barrier::{Barrier, BarrierWaitResult};
+pub use blocking::{can_block, BlockingContextError};
+pub use cancellation::{CancellationToken, DropGuard};
+pub use condvar::{Condvar, WaitTimeoutResult};
+pub use lock::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use notify::{Notified, Notify};
+pub use once::{LazyLock, OnceCell, OnceLock};
+pub use semaphore::{Acquire, OwnedSemaphorePermit, Semaphore, SemaphorePermit, WeightedSemaphore};
+pub use snapshot::{Snapshot, SnapshotReader};
+pub use task_tracker::{TaskTracker, TaskTrackerToken, TrackedFuture};
+pub use web_lock::{web_lock, WebLockGuard};
@@ -0,0 +1,261 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll, Waker};
+
+use super::blocking::{block_on, BlockingContextError};
+
+/// Wakes tasks waiting for an event, on the current thread or on any worker.
+///
+/// [`notify_one`](Self::notify_one) wakes the task that waits the longest, or, when no
+/// task is waiting, lets the next call to [`notified`](Self::notified) complete right
+/// away: a notification sent just before a task starts waiting isn't lost. At most one
+/// such notification is stored. [`notify_waiters`](Self::notify_waiters) wakes every
+/// task waiting at the time of the call and stores nothing.
+///
+/// ```ignore
+/// let notify = Arc::new(Notify::new());
+/// task::spawn({
+///     let notify = notify.clone();
+///     async move {
+///         notify.notified().await;
+///         // ...
+///     }
+/// });
+/// notify.notify_one();
+/// ```
+pub struct Notify {
+    state: Mutex<State>,
+}
+
+struct State {
+    permit: bool,
+    // Bumped by `notify_waiters`, so that futures created before the call complete even
+    // if they weren't polled yet.
+    generation: u64,
+    next_id: u64,
+    waiters: VecDeque<Waiter>,
+}
+
+struct Waiter {
+    id: u64,
+    waker: Waker,
+    notified: bool,
+}
+
+impl Notify {
+    pub const fn new() -> Self {
+        Notify {
+            state: Mutex::new(State {
+                permit: false,
+                generation: 0,
+                next_id: 0,
+                waiters: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub fn notify_one(&self) {
+        self.state.lock().unwrap().notify_one();
+    }
+
+    pub fn notify_waiters(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.generation += 1;
+        for waiter in state.waiters.drain(..) {
+            waiter.waker.wake();
+        }
+    }
+
+    /// Waits for a notification. The future takes part in
+    /// [`notify_waiters`](Self::notify_waiters) from its creation, and in
+    /// [`notify_one`](Self::notify_one) once first polled.
+    pub fn notified(&self) -> Notified<'_> {
+        Notified {
+            notify: self,
+            generation: self.state.lock().unwrap().generation,
+            id: None,
+        }
+    }
+
+    /// Blocks the current worker until a notification.
+    pub fn notified_blocking(&self) -> Result<(), BlockingContextError> {
+        block_on(self.notified())
+    }
+}
+
+impl State {
+    fn notify_one(&mut self) {
+        match self.waiters.iter_mut().find(|waiter| !waiter.notified) {
+            Some(waiter) => {
+                waiter.notified = true;
+                waiter.waker.wake_by_ref();
+            }
+            None => self.permit = true,
+        }
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Notify::new()
+    }
+}
+
+impl std::fmt::Debug for Notify {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("Notify")
+            .field("permit", &state.permit)
+            .field("waiters", &state.waiters.len())
+            .finish()
+    }
+}
+
+/// Future returned by [`Notify::notified`].
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    generation: u64,
+    id: Option<u64>,
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.notify.state.lock().unwrap();
+        if state.generation != self.generation {
+            self.id = None;
+            return Poll::Ready(());
+        }
+        match self.id {
+            Some(id) => {
+                let index = state.waiters.iter().position(|waiter| waiter.id == id);
+                let Some(index) = index else {
+                    // Only `notify_waiters` removes waiters, and it bumps the generation.
+                    unreachable!("notified waiter was removed");
+                };
+                if state.waiters[index].notified {
+                    state.waiters.remove(index);
+                    self.id = None;
+                    return Poll::Ready(());
+                }
+                state.waiters[index].waker.clone_from(cx.waker());
+            }
+            None if state.permit => {
+                state.permit = false;
+                return Poll::Ready(());
+            }
+            None => {
+                let id = state.next_id;
+                state.next_id += 1;
+                state.waiters.push_back(Waiter {
+                    id,
+                    waker: cx.waker().clone(),
+                    notified: false,
+                });
+                self.id = Some(id);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        let Some(id) = self.id else {
+            return;
+        };
+        let mut state = self.notify.state.lock().unwrap();
+        let Some(index) = state.waiters.iter().position(|waiter| waiter.id == id) else {
+            return;
+        };
+        // A notification this future received but never returned goes to the next waiter.
+        if state
+            .waiters
+            .remove(index)
+            .is_some_and(|waiter| waiter.notified)
+        {
+            state.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::task;
+    use crate::time::sleep;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_notify_one_stores_a_permit() {
+        let notify = Notify::new();
+        notify.notify_one();
+        notify.notify_one();
+        notify.notified().await;
+        // Only one notification was stored.
+        let mut notified = Box::pin(notify.notified());
+        assert!(futures::poll!(notified.as_mut()).is_pending());
+        notify.notify_one();
+        notified.await;
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_notify_waiters() {
+        let notify = Notify::new();
+        let mut first = Box::pin(notify.notified());
+        assert!(futures::poll!(first.as_mut()).is_pending());
+        // Not polled yet, but created before the call.
+        let second = notify.notified();
+        notify.notify_waiters();
+        first.await;
+        second.await;
+        let mut later = Box::pin(notify.notified());
+        assert!(futures::poll!(later.as_mut()).is_pending());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_dropped_waiter_passes_the_notification_on() {
+        let notify = Notify::new();
+        let mut first = Box::pin(notify.notified());
+        let mut second = Box::pin(notify.notified());
+        assert!(futures::poll!(first.as_mut()).is_pending());
+        assert!(futures::poll!(second.as_mut()).is_pending());
+        notify.notify_one();
+        drop(first);
+        second.await;
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_notify_across_workers() {
+        // Each waiter passes the notification on to the other one.
+        let notify = Arc::new(Notify::new());
+        let handle = task::spawn({
+            let notify = notify.clone();
+            async move {
+                notify.notified().await;
+                notify.notify_one();
+            }
+        });
+        let blocking = task::spawn_blocking({
+            let notify = notify.clone();
+            move || {
+                notify.notified_blocking()?;
+                notify.notify_one();
+                Ok::<_, BlockingContextError>(())
+            }
+        });
+        sleep(Duration::from_millis(50)).await.unwrap();
+        notify.notify_one();
+        handle.join().await.unwrap();
+        assert_eq!(blocking.join().await.unwrap(), Ok(()));
+    }
+}
@@ -0,0 +1,382 @@
+use std::cell::UnsafeCell;
+use std::future::Future;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use super::blocking::BlockingContextError;
+use super::futex::Futex;
+
+const EMPTY: u8 = 0;
+const INITIALIZING: u8 = 1;
+const READY: u8 = 2;
+
+/// A cell written once, usable from any thread, with an async initializer.
+///
+/// When several tasks race to initialize the cell, on the same thread or on different
+/// workers, a single initializer runs while the others wait for its value without
+/// blocking their thread. If the initializer panics or its future is dropped, the next
+/// waiter runs its own initializer instead.
+///
+/// ```ignore
+/// static MODEL: OnceCell<Model> = OnceCell::new();
+/// let model = MODEL.get_or_init_async(|| async { Model::fetch("/model.bin").await }).await;
+/// ```
+pub struct OnceCell<T> {
+    state: AtomicU8,
+    futex: Futex,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+// SAFETY: the value is written once, by the initializer holding the `INITIALIZING` state,
+// and only read once `READY`.
+unsafe impl<T: Send> Send for OnceCell<T> {}
+unsafe impl<T: Send + Sync> Sync for OnceCell<T> {}
+
+// Held while running an initializer, resetting the cell for the next one if the
+// initializer doesn't complete.
+struct Initializing<'a, T> {
+    cell: &'a OnceCell<T>,
+}
+
+impl<T> Initializing<'_, T> {
+    fn complete(self, value: T) {
+        // SAFETY: the `INITIALIZING` state gives exclusive access to the value.
+        unsafe { (*self.cell.value.get()).write(value) };
+        self.cell.state.store(READY, Ordering::Release);
+        self.cell.futex.notify();
+        std::mem::forget(self);
+    }
+}
+
+impl<T> Drop for Initializing<'_, T> {
+    fn drop(&mut self) {
+        self.cell.state.store(EMPTY, Ordering::Release);
+        self.cell.futex.notify();
+    }
+}
+
+// What a thread finds when trying to initialize the cell.
+enum Attempt<'a, T> {
+    Ready(&'a T),
+    Initialize(Initializing<'a, T>),
+}
+
+impl<T> OnceCell<T> {
+    pub const fn new() -> Self {
+        OnceCell {
+            state: AtomicU8::new(EMPTY),
+            futex: Futex::new(),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        (self.state.load(Ordering::Acquire) == READY).then(|| {
+            // SAFETY: the value was written before the state became `READY`.
+            unsafe { (*self.value.get()).assume_init_ref() }
+        })
+    }
+
+    /// Sets the value, unless the cell is set or being initialized, in which case `value`
+    /// is returned.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        match self.try_start() {
+            Some(Attempt::Initialize(initializing)) => {
+                initializing.complete(value);
+                Ok(())
+            }
+            _ => Err(value),
+        }
+    }
+
+    /// Returns the value, initializing it with `f` if the cell is empty, and otherwise
+    /// waiting for the initializer running elsewhere without blocking the current thread.
+    pub async fn get_or_init_async<F, Fut>(&self, f: F) -> &T
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        match self.futex.wait_until_async(|| self.try_start()).await {
+            Attempt::Ready(value) => value,
+            Attempt::Initialize(initializing) => {
+                let value = f().await;
+                initializing.complete(value);
+                self.get().unwrap()
+            }
+        }
+    }
+
+    /// Like [`get_or_init_async`](Self::get_or_init_async) with a synchronous
+    /// initializer, blocking the current worker while another thread initializes the
+    /// cell.
+    pub fn get_or_init_blocking(&self, f: impl FnOnce() -> T) -> Result<&T, BlockingContextError> {
+        match self.futex.wait_until(|| self.try_start())? {
+            Attempt::Ready(value) => Ok(value),
+            Attempt::Initialize(initializing) => {
+                initializing.complete(f());
+                Ok(self.get().unwrap())
+            }
+        }
+    }
+
+    // Returns `None` while another initializer runs.
+    fn try_start(&self) -> Option<Attempt<'_, T>> {
+        match self
+            .state
+            .compare_exchange(EMPTY, INITIALIZING, Ordering::Acquire, Ordering::Acquire)
+        {
+            Ok(_) => Some(Attempt::Initialize(Initializing { cell: self })),
+            Err(READY) => self.get().map(Attempt::Ready),
+            Err(_) => None,
+        }
+    }
+
+    pub fn into_inner(mut self) -> Option<T> {
+        self.take()
+    }
+
+    pub fn take(&mut self) -> Option<T> {
+        if *self.state.get_mut() != READY {
+            return None;
+        }
+        *self.state.get_mut() = EMPTY;
+        // SAFETY: the value was initialized, and the state no longer says so.
+        Some(unsafe { self.value.get_mut().assume_init_read() })
+    }
+}
+
+impl<T> Drop for OnceCell<T> {
+    fn drop(&mut self) {
+        self.take();
+    }
+}
+
+impl<T> Default for OnceCell<T> {
+    fn default() -> Self {
+        OnceCell::new()
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for OnceCell<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut d = f.debug_tuple("OnceCell");
+        match self.get() {
+            Some(value) => d.field(value),
+            None => d.field(&format_args!("<uninit>")),
+        };
+        d.finish()
+    }
+}
+
+/// A cell written once with a synchronous initializer, usable from any thread.
+///
+/// Unlike `std::sync::OnceLock`, whose `get_or_init` traps the main thread when it has
+/// to wait for an initializer running on a worker, [`get_or_init`](Self::get_or_init)
+/// fails with [`BlockingContextError`] there, and
+/// [`get_or_init_async`](Self::get_or_init_async) waits without blocking.
+pub struct OnceLock<T> {
+    cell: OnceCell<T>,
+}
+
+impl<T> OnceLock<T> {
+    pub const fn new() -> Self {
+        OnceLock {
+            cell: OnceCell::new(),
+        }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        self.cell.get()
+    }
+
+    pub fn set(&self, value: T) -> Result<(), T> {
+        self.cell.set(value)
+    }
+
+    /// Returns the value, initializing it with `f` if the cell is empty, and otherwise
+    /// blocking the current worker while another thread initializes it.
+    pub fn get_or_init(&self, f: impl FnOnce() -> T) -> Result<&T, BlockingContextError> {
+        self.cell.get_or_init_blocking(f)
+    }
+
+    /// Like [`get_or_init`](Self::get_or_init), waiting for another thread's initializer
+    /// without blocking the current thread.
+    pub async fn get_or_init_async(&self, f: impl FnOnce() -> T) -> &T {
+        self.cell.get_or_init_async(|| async { f() }).await
+    }
+
+    pub fn into_inner(self) -> Option<T> {
+        self.cell.into_inner()
+    }
+
+    pub fn take(&mut self) -> Option<T> {
+        self.cell.take()
+    }
+}
+
+impl<T> Default for OnceLock<T> {
+    fn default() -> Self {
+        OnceLock::new()
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for OnceLock<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut d = f.debug_tuple("OnceLock");
+        match self.get() {
+            Some(value) => d.field(value),
+            None => d.field(&format_args!("<uninit>")),
+        };
+        d.finish()
+    }
+}
+
+/// A value initialized on first access with the function it was created with, usable
+/// from any thread like [`OnceLock`].
+///
+/// There's no `Deref`, since waiting for the initializer may fail or need to be async:
+/// the value is accessed with [`force`](Self::force) or
+/// [`force_async`](Self::force_async).
+///
+/// ```ignore
+/// static TABLE: LazyLock<Vec<f32>> = LazyLock::new(|| build_table());
+/// let table = TABLE.force_async().await;
+/// ```
+pub struct LazyLock<T, F = fn() -> T> {
+    cell: OnceCell<T>,
+    init: UnsafeCell<Option<F>>,
+}
+
+// SAFETY: `init` is only taken by the initializer, which the cell runs at most once at a
+// time, on whichever thread gets there first.
+unsafe impl<T: Send + Sync, F: Send> Sync for LazyLock<T, F> {}
+
+impl<T, F: FnOnce() -> T> LazyLock<T, F> {
+    pub const fn new(init: F) -> Self {
+        LazyLock {
+            cell: OnceCell::new(),
+            init: UnsafeCell::new(Some(init)),
+        }
+    }
+
+    /// Returns the value, initializing it on the current thread if needed, and otherwise
+    /// blocking the current worker while another thread initializes it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a previous initialization panicked.
+    pub fn force(&self) -> Result<&T, BlockingContextError> {
+        self.cell.get_or_init_blocking(|| self.take_init()())
+    }
+
+    /// Like [`force`](Self::force), waiting for another thread's initializer without
+    /// blocking the current thread.
+    pub async fn force_async(&self) -> &T {
+        self.cell
+            .get_or_init_async(|| async { self.take_init()() })
+            .await
+    }
+
+    fn take_init(&self) -> F {
+        // SAFETY: only called by the initializer of the cell.
+        unsafe { (*self.init.get()).take() }.expect("LazyLock initializer panicked")
+    }
+}
+
+impl<T: std::fmt::Debug, F> std::fmt::Debug for LazyLock<T, F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut d = f.debug_tuple("LazyLock");
+        match self.cell.get() {
+            Some(value) => d.field(value),
+            None => d.field(&format_args!("<uninit>")),
+        };
+        d.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::task;
+    use crate::time::sleep;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_single_initialization_across_workers() {
+        let cell = Arc::new(OnceCell::new());
+        let runs = Arc::new(AtomicUsize::new(0));
+        let init = |runs: Arc<AtomicUsize>| {
+            move || async move {
+                runs.fetch_add(1, Ordering::SeqCst);
+                sleep(Duration::from_millis(50)).await.unwrap();
+                42
+            }
+        };
+        let handles = (0..3)
+            .map(|_| {
+                let (cell, runs) = (cell.clone(), runs.clone());
+                task::spawn(async move { *cell.get_or_init_async(init(runs)).await })
+            })
+            .collect::<Vec<_>>();
+        let blocking = task::spawn_blocking({
+            let (cell, runs) = (cell.clone(), runs.clone());
+            move || {
+                cell.get_or_init_blocking(|| {
+                    runs.fetch_add(1, Ordering::SeqCst);
+                    42
+                })
+                .copied()
+            }
+        });
+        assert_eq!(*cell.get_or_init_async(init(runs.clone())).await, 42);
+        for handle in handles {
+            assert_eq!(handle.join().await.unwrap(), 42);
+        }
+        assert_eq!(blocking.join().await.unwrap(), Ok(42));
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(cell.set(1), Err(1));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_dropped_initializer_lets_the_next_one_run() {
+        let cell = OnceCell::new();
+        let mut first = Box::pin(cell.get_or_init_async(futures::future::pending));
+        assert!(futures::poll!(first.as_mut()).is_pending());
+        assert!(cell.get().is_none());
+        drop(first);
+        assert_eq!(*cell.get_or_init_async(|| async { 2 }).await, 2);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_once_lock_on_main_thread() {
+        static LOCK: OnceLock<u32> = OnceLock::new();
+        let holder = task::spawn_blocking(|| {
+            *LOCK
+                .get_or_init(|| {
+                    crate::time::sleep_blocking(Duration::from_millis(100));
+                    7
+                })
+                .unwrap()
+        });
+        sleep(Duration::from_millis(50)).await.unwrap();
+        // Being initialized by the worker, which the main thread can't block on.
+        assert!(matches!(LOCK.get_or_init(|| 0), Err(BlockingContextError)));
+        assert_eq!(*LOCK.get_or_init_async(|| 0).await, 7);
+        assert_eq!(holder.join().await.unwrap(), 7);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_lazy_lock() {
+        static SQUARES: LazyLock<Vec<u32>> = LazyLock::new(|| (0..4).map(|i| i * i).collect());
+        let handle = task::spawn_blocking(|| SQUARES.force().unwrap().len());
+        assert_eq!(SQUARES.force_async().await, &[0, 1, 4, 9]);
+        assert_eq!(handle.join().await.unwrap(), 4);
+    }
+}
@@ -0,0 +1,359 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use super::blocking::{block_on, BlockingContextError};
+
+/// An async counting semaphore.
+///
+/// Waiters are served in FIFO order and may live on any worker: the state is kept in
+/// shared memory and wake-ups go through the waiter's own `Waker`.
+pub struct Semaphore {
+    state: Mutex<State>,
+}
+
+struct State {
+    permits: isize,
+    next_id: u64,
+    waiters: VecDeque<Waiter>,
+}
+
+struct Waiter {
+    id: u64,
+    permits: usize,
+    waker: Waker,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            state: Mutex::new(State {
+                permits: permits as isize,
+                next_id: 0,
+                waiters: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.state.lock().unwrap().permits.max(0) as usize
+    }
+
+    pub fn add_permits(&self, n: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.permits += n as isize;
+        state.wake_front();
+    }
+
+    /// Removes `n` permits. Permits currently held are not revoked, so the number of
+    /// available permits may temporarily drop below zero until they are released.
+    pub fn forget_permits(&self, n: usize) {
+        self.state.lock().unwrap().permits -= n as isize;
+    }
+
+    pub fn acquire(&self) -> Acquire<'_> {
+        self.acquire_many(1)
+    }
+
+    /// Acquires `n` permits at once. The request waits in line like any other: smaller
+    /// requests queued behind it aren't served before it.
+    pub fn acquire_many(&self, n: usize) -> Acquire<'_> {
+        Acquire {
+            semaphore: self,
+            id: None,
+            permits: n,
+        }
+    }
+
+    pub async fn acquire_owned(self: Arc<Self>) -> OwnedSemaphorePermit {
+        self.acquire_many_owned(1).await
+    }
+
+    pub async fn acquire_many_owned(self: Arc<Self>, n: usize) -> OwnedSemaphorePermit {
+        self.acquire_many(n).await.forget();
+        OwnedSemaphorePermit {
+            semaphore: self,
+            permits: n,
+        }
+    }
+
+    /// Blocks the current worker until a permit is available.
+    pub fn acquire_blocking(&self) -> Result<SemaphorePermit<'_>, BlockingContextError> {
+        block_on(self.acquire())
+    }
+
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        self.try_acquire_many(1)
+    }
+
+    pub fn try_acquire_many(&self, n: usize) -> Option<SemaphorePermit<'_>> {
+        let mut state = self.state.lock().unwrap();
+        if state.permits >= n as isize && state.waiters.is_empty() {
+            state.permits -= n as isize;
+            Some(SemaphorePermit {
+                semaphore: self,
+                permits: n,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl State {
+    fn wake_front(&mut self) {
+        if let Some(waiter) = self.waiters.front() {
+            if self.permits >= waiter.permits as isize {
+                waiter.waker.wake_by_ref();
+            }
+        }
+    }
+}
+
+pub struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    id: Option<u64>,
+    permits: usize,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = SemaphorePermit<'a>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let semaphore = self.semaphore;
+        let mut state = semaphore.state.lock().unwrap();
+        let permits = self.permits;
+        let first = match (self.id, state.waiters.front()) {
+            (_, None) => true,
+            (Some(id), Some(front)) => id == front.id,
+            (None, Some(_)) => false,
+        };
+        if first && state.permits >= permits as isize {
+            state.permits -= permits as isize;
+            if self.id.take().is_some() {
+                state.waiters.pop_front();
+            }
+            state.wake_front();
+            return Poll::Ready(SemaphorePermit { semaphore, permits });
+        }
+        match self.id {
+            Some(id) => {
+                if let Some(waiter) = state.waiters.iter_mut().find(|waiter| waiter.id == id) {
+                    waiter.waker.clone_from(cx.waker());
+                }
+            }
+            None => {
+                let id = state.next_id;
+                state.next_id += 1;
+                state.waiters.push_back(Waiter {
+                    id,
+                    permits,
+                    waker: cx.waker().clone(),
+                });
+                self.id = Some(id);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            let mut state = self.semaphore.state.lock().unwrap();
+            state.waiters.retain(|waiter| waiter.id != id);
+            state.wake_front();
+        }
+    }
+}
+
+#[must_use]
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+}
+
+impl SemaphorePermit<'_> {
+    /// Consumes the permit without releasing it back to the semaphore.
+    pub fn forget(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.add_permits(self.permits);
+    }
+}
+
+#[must_use]
+pub struct OwnedSemaphorePermit {
+    semaphore: Arc<Semaphore>,
+    permits: usize,
+}
+
+impl Drop for OwnedSemaphorePermit {
+    fn drop(&mut self) {
+        self.semaphore.add_permits(self.permits);
+    }
+}
+
+/// A semaphore whose tasks acquire a share of a budget proportional to their weight, e.g.
+/// the size in bytes of the data they process, so that the total weight of the tasks
+/// running at once never exceeds the budget.
+///
+/// A task weighing more than the whole budget is clamped to it and runs alone, rather
+/// than waiting forever.
+pub struct WeightedSemaphore {
+    semaphore: Semaphore,
+    budget: usize,
+}
+
+impl WeightedSemaphore {
+    pub const fn new(budget: usize) -> Self {
+        WeightedSemaphore {
+            semaphore: Semaphore::new(budget),
+            budget,
+        }
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    /// Part of the budget not acquired by running tasks.
+    pub fn available(&self) -> usize {
+        self.semaphore.available_permits()
+    }
+
+    pub async fn acquire(&self, weight: usize) -> SemaphorePermit<'_> {
+        self.semaphore.acquire_many(weight.min(self.budget)).await
+    }
+
+    pub fn try_acquire(&self, weight: usize) -> Option<SemaphorePermit<'_>> {
+        self.semaphore.try_acquire_many(weight.min(self.budget))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::task;
+    use crate::time::sleep;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_acquire_release() {
+        let semaphore = Semaphore::new(2);
+        let a = semaphore.acquire().await;
+        let b = semaphore.acquire().await;
+        assert_eq!(semaphore.available_permits(), 0);
+        assert!(semaphore.try_acquire().is_none());
+        drop(a);
+        assert_eq!(semaphore.available_permits(), 1);
+        drop(b);
+        assert_eq!(semaphore.available_permits(), 2);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_forget_and_add_permits() {
+        let semaphore = Semaphore::new(1);
+        let permit = semaphore.acquire().await;
+        semaphore.forget_permits(1);
+        drop(permit);
+        assert_eq!(semaphore.available_permits(), 0);
+        semaphore.add_permits(1);
+        assert_eq!(semaphore.available_permits(), 1);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_acquire_many_is_fifo() {
+        let semaphore = Semaphore::new(3);
+        let permit = semaphore.acquire_many(2).await;
+        let mut large = Box::pin(semaphore.acquire_many(3));
+        assert!(futures::poll!(large.as_mut()).is_pending());
+        // The single permit left isn't handed to requests queued behind the large one.
+        assert!(semaphore.try_acquire().is_none());
+        drop(permit);
+        let large = large.await;
+        assert_eq!(semaphore.available_permits(), 0);
+        drop(large);
+        assert_eq!(semaphore.available_permits(), 3);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_weighted_semaphore_budget() {
+        let semaphore = Arc::new(WeightedSemaphore::new(100));
+        let in_use = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let peak = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let handles = [60, 30, 50, 20, 200]
+            .into_iter()
+            .map(|weight| {
+                let semaphore = semaphore.clone();
+                let in_use = in_use.clone();
+                let peak = peak.clone();
+                task::spawn(async move {
+                    let _permit = semaphore.acquire(weight).await;
+                    let weight = weight.min(semaphore.budget());
+                    let total =
+                        in_use.fetch_add(weight, std::sync::atomic::Ordering::SeqCst) + weight;
+                    peak.fetch_max(total, std::sync::atomic::Ordering::SeqCst);
+                    sleep(Duration::from_millis(30)).await.unwrap();
+                    in_use.fetch_sub(weight, std::sync::atomic::Ordering::SeqCst);
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        assert!(peak.load(std::sync::atomic::Ordering::SeqCst) <= 100);
+        assert_eq!(semaphore.available(), 100);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_acquire_blocking() {
+        let semaphore = Arc::new(Semaphore::new(1));
+        assert!(matches!(
+            semaphore.acquire_blocking(),
+            Err(BlockingContextError)
+        ));
+        let permit = semaphore.clone().acquire_owned().await;
+        let handle = task::spawn_blocking({
+            let semaphore = semaphore.clone();
+            move || semaphore.acquire_blocking().map(SemaphorePermit::forget)
+        });
+        sleep(Duration::from_millis(50)).await.unwrap();
+        drop(permit);
+        assert_eq!(handle.join().await.unwrap(), Ok(()));
+        assert_eq!(semaphore.available_permits(), 0);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_limits_concurrent_tasks() {
+        let semaphore = Arc::new(Semaphore::new(1));
+        let running = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let handles = (0..3)
+            .map(|_| {
+                let semaphore = semaphore.clone();
+                let running = running.clone();
+                task::spawn(async move {
+                    let _permit = semaphore.acquire_owned().await;
+                    let concurrent = running.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    sleep(Duration::from_millis(50)).await.unwrap();
+                    running.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+                    concurrent
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            assert_eq!(handle.join().await.unwrap(), 0);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+struct Shared<T> {
+    version: AtomicU64,
+    latest: Mutex<Arc<T>>,
+}
+
+/// Publishes successive versions of a value to readers on any worker, e.g. configuration
+/// or state fanned out to many tasks reading it at a high rate.
+///
+/// Readers keep their own copy of the latest version they saw and only check an atomic
+/// version counter on every read, so reads don't lock unless a new version was published.
+/// Versions are shared through the module's memory: nothing is serialized or copied.
+///
+/// ```ignore
+/// let config = Snapshot::new(Config::default());
+/// let mut reader = config.reader();
+/// task::spawn(async move { loop { handle(reader.get()).await } });
+/// config.publish(Config::reloaded());
+/// ```
+pub struct Snapshot<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Snapshot<T> {
+    pub fn new(value: T) -> Self {
+        Snapshot {
+            shared: Arc::new(Shared {
+                version: AtomicU64::new(0),
+                latest: Mutex::new(Arc::new(value)),
+            }),
+        }
+    }
+
+    /// Makes `value` the latest version and returns its version number. Readers see it on
+    /// their next read.
+    pub fn publish(&self, value: T) -> u64 {
+        let mut latest = self.shared.latest.lock().unwrap();
+        *latest = Arc::new(value);
+        self.shared.version.fetch_add(1, Ordering::AcqRel) + 1
+    }
+
+    /// Version number of the latest published value, starting at 0 for the initial value.
+    pub fn version(&self) -> u64 {
+        self.shared.version.load(Ordering::Acquire)
+    }
+
+    pub fn reader(&self) -> SnapshotReader<T> {
+        let latest = self.shared.latest.lock().unwrap();
+        SnapshotReader {
+            shared: self.shared.clone(),
+            cached: latest.clone(),
+            version: self.version(),
+        }
+    }
+}
+
+/// Reads the versions published by a [`Snapshot`]. Clone it to get readers for other
+/// tasks.
+pub struct SnapshotReader<T> {
+    shared: Arc<Shared<T>>,
+    cached: Arc<T>,
+    version: u64,
+}
+
+impl<T> SnapshotReader<T> {
+    /// Returns the latest published version, refreshing the reader's copy if a newer one
+    /// was published since the last read.
+    pub fn get(&mut self) -> &T {
+        if self.has_changed() {
+            let latest = self.shared.latest.lock().unwrap();
+            // Read under the lock, so that the version matches the value.
+            self.version = self.shared.version.load(Ordering::Acquire);
+            self.cached = latest.clone();
+        }
+        &self.cached
+    }
+
+    /// Returns the reader's copy without checking for a newer version. Never locks.
+    pub fn get_stale(&self) -> &T {
+        &self.cached
+    }
+
+    /// Version number of the reader's copy.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Whether a version newer than the reader's copy was published.
+    pub fn has_changed(&self) -> bool {
+        self.shared.version.load(Ordering::Acquire) != self.version
+    }
+}
+
+impl<T> Clone for SnapshotReader<T> {
+    fn clone(&self) -> Self {
+        SnapshotReader {
+            shared: self.shared.clone(),
+            cached: self.cached.clone(),
+            version: self.version,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_snapshot() {
+        let snapshot = Snapshot::new(vec![0]);
+        let mut reader = snapshot.reader();
+        assert_eq!(reader.get(), &[0]);
+        assert_eq!(snapshot.publish(vec![1]), 1);
+        assert!(reader.has_changed());
+        assert_eq!(reader.get_stale(), &[0]);
+        assert_eq!(reader.get(), &[1]);
+        assert_eq!(reader.version(), 1);
+
+        let mut worker_reader = reader.clone();
+        snapshot.publish(vec![2]);
+        let handle = task::spawn_blocking(move || worker_reader.get().clone());
+        assert_eq!(handle.join().await.unwrap(), [2]);
+    }
+}
@@ -0,0 +1,236 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use super::notify::Notify;
+use crate::task::{self, blocking, r#async};
+
+/// Keeps count of a group of tasks, on any worker, so that an application can wait for
+/// all of them to finish before shutting down, e.g. before the page unloads or the wasm
+/// module is swapped.
+///
+/// Tasks are tracked from the moment they are spawned with the tracker's `spawn`
+/// methods, or wrapped with [`track_future`](Self::track_future), until they complete or
+/// are aborted. [`wait`](Self::wait) returns once the tracker is [`close`](Self::close)d
+/// and no tracked task is left, so closing it is what marks the end of the group: tasks
+/// can still be tracked after that, as long as the tracker isn't empty.
+///
+/// Tasks whose worker is terminated, e.g. with `abort_hard`, stay tracked forever.
+///
+/// ```ignore
+/// let tracker = TaskTracker::new();
+/// for upload in uploads {
+///     tracker.spawn(async move { upload.send().await });
+/// }
+/// tracker.close();
+/// tracker.wait().await;
+/// runtime::shutdown();
+/// ```
+#[derive(Clone, Default)]
+pub struct TaskTracker {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    tasks: AtomicUsize,
+    closed: AtomicBool,
+    // Notified when the tracker may have become closed and empty.
+    done: Notify,
+}
+
+impl Inner {
+    fn is_done(&self) -> bool {
+        self.closed.load(Ordering::SeqCst) && self.tasks.load(Ordering::SeqCst) == 0
+    }
+}
+
+impl TaskTracker {
+    pub fn new() -> Self {
+        TaskTracker::default()
+    }
+
+    /// Lets [`wait`](Self::wait) return once the tracked tasks are done. Returns whether
+    /// the tracker was open.
+    pub fn close(&self) -> bool {
+        let was_open = !self.inner.closed.swap(true, Ordering::SeqCst);
+        self.inner.done.notify_waiters();
+        was_open
+    }
+
+    /// Opens the tracker again, e.g. to reuse it for the next group of tasks. Returns
+    /// whether it was closed.
+    pub fn reopen(&self) -> bool {
+        self.inner.closed.swap(false, Ordering::SeqCst)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.closed.load(Ordering::SeqCst)
+    }
+
+    /// The number of tracked tasks still running.
+    pub fn len(&self) -> usize {
+        self.inner.tasks.load(Ordering::SeqCst)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Waits until the tracker is closed and every tracked task is done.
+    pub async fn wait(&self) {
+        loop {
+            // Created before checking, so that the last task finishing in between isn't
+            // missed.
+            let notified = self.inner.done.notified();
+            if self.inner.is_done() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Tracks the task holding the returned token until the token is dropped.
+    pub fn token(&self) -> TaskTrackerToken {
+        self.inner.tasks.fetch_add(1, Ordering::SeqCst);
+        TaskTrackerToken {
+            inner: self.inner.clone(),
+        }
+    }
+
+    /// Tracks `future` until it completes or is dropped.
+    pub fn track_future<F: Future>(&self, future: F) -> TrackedFuture<F> {
+        TrackedFuture {
+            future: Box::pin(future),
+            _token: self.token(),
+        }
+    }
+
+    /// Like [`task::spawn`], tracking the task.
+    #[track_caller]
+    pub fn spawn<F>(&self, future: F) -> r#async::JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        task::spawn(self.track_future(future))
+    }
+
+    /// Like [`task::spawn_local`], tracking the task.
+    #[track_caller]
+    pub fn spawn_local<F>(&self, future: F) -> r#async::JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        task::spawn_local(self.track_future(future))
+    }
+
+    /// Like [`task::spawn_blocking`], tracking the closure until it returns.
+    #[track_caller]
+    pub fn spawn_blocking<T>(&self, f: impl FnOnce() -> T + 'static) -> blocking::JoinHandle<T>
+    where
+        T: 'static,
+    {
+        let token = self.token();
+        task::spawn_blocking(move || {
+            let _token = token;
+            f()
+        })
+    }
+}
+
+impl std::fmt::Debug for TaskTracker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TaskTracker")
+            .field("len", &self.len())
+            .field("closed", &self.is_closed())
+            .finish()
+    }
+}
+
+/// Counts as a task of a [`TaskTracker`] until dropped.
+pub struct TaskTrackerToken {
+    inner: Arc<Inner>,
+}
+
+impl Drop for TaskTrackerToken {
+    fn drop(&mut self) {
+        if self.inner.tasks.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.inner.done.notify_waiters();
+        }
+    }
+}
+
+impl std::fmt::Debug for TaskTrackerToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TaskTrackerToken").finish_non_exhaustive()
+    }
+}
+
+/// Future returned by [`TaskTracker::track_future`].
+pub struct TrackedFuture<F> {
+    future: Pin<Box<F>>,
+    _token: TaskTrackerToken,
+}
+
+impl<F: Future> Future for TrackedFuture<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        self.future.as_mut().poll(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::time::{sleep, sleep_blocking};
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_wait_for_tracked_tasks() {
+        let tracker = TaskTracker::new();
+        let finished = Arc::new(AtomicUsize::new(0));
+        for i in 0..3 {
+            let finished = finished.clone();
+            tracker.spawn(async move {
+                sleep(Duration::from_millis(10 * i)).await.unwrap();
+                finished.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        tracker.spawn_blocking({
+            let finished = finished.clone();
+            move || {
+                sleep_blocking(Duration::from_millis(30));
+                finished.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        assert_eq!(tracker.len(), 4);
+        // Still open, so waiting doesn't return even once the tasks are done.
+        let mut wait = Box::pin(tracker.wait());
+        assert!(futures::poll!(wait.as_mut()).is_pending());
+        assert!(tracker.close());
+        wait.await;
+        assert_eq!(finished.load(Ordering::SeqCst), 4);
+        assert!(tracker.is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_aborted_tasks_are_untracked() {
+        let tracker = TaskTracker::new();
+        let mut handle = tracker.spawn_local(futures::future::pending::<()>());
+        tracker.close();
+        assert_eq!(tracker.len(), 1);
+        handle.abort();
+        tracker.wait().await;
+    }
+}
@@ -0,0 +1,128 @@
+use wasm_bindgen::prelude::{Closure, JsValue};
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+use crate::Error;
+
+/// Acquires the exclusive Web Lock called `name` through `navigator.locks.request`.
+///
+/// Web Locks are shared by every tab, window and worker of the origin, which makes them
+/// suitable to serialize access to origin-wide resources such as OPFS files or IndexedDB
+/// databases. The lock is released when the returned guard is dropped.
+pub async fn web_lock(name: &str) -> Result<WebLockGuard, Error> {
+    let guard = request(name, false).await?;
+    Ok(guard.expect("lock requests without `ifAvailable` are always granted"))
+}
+
+/// Acquires the Web Lock called `name` if it is free, without waiting for it otherwise.
+pub(crate) async fn try_web_lock(name: &str) -> Result<Option<WebLockGuard>, Error> {
+    request(name, true).await
+}
+
+async fn request(name: &str, if_available: bool) -> Result<Option<WebLockGuard>, Error> {
+    let navigator = js_sys::Reflect::get(&js_sys::global(), &"navigator".into())?;
+    let locks = js_sys::Reflect::get(&navigator, &"locks".into())?;
+    if locks.is_undefined() {
+        return Err(Error::Js(JsValue::from_str(
+            "Web Locks API is not available",
+        )));
+    }
+    let request =
+        js_sys::Reflect::get(&locks, &"request".into())?.dyn_into::<js_sys::Function>()?;
+    let options = js_sys::Object::new();
+    js_sys::Reflect::set(&options, &"ifAvailable".into(), &if_available.into())?;
+
+    let mut release = None;
+    let held = js_sys::Promise::new(&mut |resolve, _| release = Some(resolve));
+    let mut grant = None;
+    let granted = js_sys::Promise::new(&mut |resolve, _| grant = Some(resolve));
+    let (release, grant) = (release.unwrap(), grant.unwrap());
+
+    // Created before waiting for the lock so that a cancelled request releases the lock as
+    // soon as it's granted instead of holding it forever.
+    let guard = WebLockGuard {
+        name: name.to_owned(),
+        release,
+    };
+    // Called with `null` instead of a lock when `ifAvailable` is set and the lock is taken.
+    let callback = Closure::once_into_js(move |lock: JsValue| {
+        grant
+            .call1(&JsValue::UNDEFINED, &(!lock.is_null()).into())
+            .ok();
+        held
+    });
+    let request = request
+        .call3(&locks, &JsValue::from_str(name), &options, &callback)?
+        .dyn_into::<js_sys::Promise>()?;
+    // `request` only settles before the callback is called if the request failed.
+    let granted = JsFuture::from(js_sys::Promise::race(&js_sys::Array::of2(
+        &granted, &request,
+    )))
+    .await?;
+    Ok(granted.as_bool().unwrap_or(false).then_some(guard))
+}
+
+#[must_use]
+pub struct WebLockGuard {
+    name: String,
+    release: js_sys::Function,
+}
+
+impl WebLockGuard {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Drop for WebLockGuard {
+    fn drop(&mut self) {
+        self.release.call0(&JsValue::UNDEFINED).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::task;
+    use crate::time::sleep;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_web_lock_is_exclusive() {
+        let guard = web_lock("wasmt-test").await.unwrap();
+        assert_eq!(guard.name(), "wasmt-test");
+        let acquired = Rc::new(Cell::new(false));
+        let handle = task::spawn_local({
+            let acquired = acquired.clone();
+            async move {
+                let _guard = web_lock("wasmt-test").await.unwrap();
+                acquired.set(true);
+            }
+        });
+        sleep(Duration::from_millis(50)).await.unwrap();
+        assert!(!acquired.get());
+        drop(guard);
+        handle.join().await.unwrap();
+        assert!(acquired.get());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_web_lock_in_task() {
+        let guard = web_lock("wasmt-test-task").await.unwrap();
+        let handle = task::spawn(async move {
+            let guard = web_lock("wasmt-test-task").await.unwrap();
+            guard.name().to_owned()
+        });
+        sleep(Duration::from_millis(50)).await.unwrap();
+        assert!(!handle.is_finished());
+        drop(guard);
+        assert_eq!(handle.join().await.unwrap(), "wasmt-test-task");
+    }
+}
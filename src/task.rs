@@ -1,125 +1,1279 @@
 use futures::future::{AbortHandle, Abortable};
+use futures::StreamExt;
+use std::any::Any;
+use std::cell::Cell;
 use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
 use wasm_bindgen::JsValue;
 
-use crate::worker;
+use crate::sync::futex::Futex;
+use crate::time::{self, sleep, Elapsed, Instant, TimeoutError};
+use crate::{runtime, utils, worker};
 
+mod builder;
+mod checkpoint;
+mod join_handles;
+mod join_set;
+mod local;
+mod local_set;
+mod memo;
+pub(crate) mod panic;
+pub(crate) mod schedule;
+pub(crate) mod state;
+mod task_local;
+pub(crate) mod tree;
+mod wake;
+mod worker_ref;
+
+pub use builder::Builder;
+pub use checkpoint::{
+    checkpoint, clear_checkpoint, resume, set_checkpoint_store, CheckpointStore, IndexedDbStore,
+};
+pub use join_handles::{Abort, AbortOnDrop};
+pub use join_set::JoinSet;
+pub use local_set::LocalSet;
+pub use memo::{invalidate_memo, memo};
+pub use schedule::{spawn_local_with, Schedule};
+pub use state::TaskState;
+pub use task_local::{AccessError, LocalKey, TaskLocalFuture};
+pub use tree::Id;
+pub use worker_ref::{worker_messages, Messages, WorkerMessages, WorkerRef};
+
+/// The id of the task running on the current thread, or `None` outside of tasks spawned
+/// by this crate.
+pub fn try_id() -> Option<Id> {
+    panic::current_task()
+}
+
+// Cancels the tasks spawned by the task `id` if the runtime propagates cancellation.
+fn propagate_abort(id: Id) {
+    if runtime::CONFIG.lock().unwrap().propagate_cancellation {
+        tree::cancel_descendants(id);
+    }
+}
+
+/// Runs `f` on a worker of its own, where it may block, e.g. with
+/// [`time::sleep_blocking`](crate::time::sleep_blocking) or the blocking locks of
+/// [`sync`](crate::sync).
+///
+/// The output is sent to the joining thread through the join channel. Closures returning
+/// bytes can hand them over without it with [`spawn_blocking_bytes`].
+#[track_caller]
 pub fn spawn_blocking<T>(f: impl FnOnce() -> T + 'static) -> blocking::JoinHandle<T>
 where
     T: 'static,
 {
-    let (tx, rx) = futures::channel::oneshot::channel();
-    worker::spawn_blocking(move || {
-        tx.send(f()).ok();
+    try_spawn_blocking(f).unwrap_or_else(|err| panic!("{err}"))
+}
+
+/// Like [`spawn_blocking`], but returns an error instead of panicking when the worker
+/// can't be created, so that callers can fall back to running `f` on the current thread,
+/// or before [`runtime::init`] under the `explicit-init` feature.
+///
+/// Only failures of workers spawned right away are returned: when the spawn is queued
+/// behind [`max_blocking_workers`](runtime::Builder::max_blocking_workers) or handed to
+/// the coordinating thread, a failure fails the join instead.
+#[track_caller]
+pub fn try_spawn_blocking<T>(
+    f: impl FnOnce() -> T + 'static,
+) -> Result<blocking::JoinHandle<T>, SpawnError>
+where
+    T: 'static,
+{
+    try_spawn_blocking_named(None, f)
+}
+
+/// Like [`spawn_blocking`], for closures returning bytes, e.g. a `Vec<u8>` or a
+/// `Box<[u8]>`.
+///
+/// The bytes aren't sent through the join channel: the worker writes the pointer, length
+/// and capacity of the buffer to the shared memory, and the joining thread takes
+/// ownership of the buffer where `f` allocated it, without copying it, whatever its size.
+#[track_caller]
+pub fn spawn_blocking_bytes<B>(f: impl FnOnce() -> B + 'static) -> blocking::BytesJoinHandle
+where
+    B: Into<Vec<u8>>,
+{
+    let handoff = Arc::new(blocking::Handoff::default());
+    let inner = spawn_blocking({
+        let handoff = handoff.clone();
+        move || handoff.put(f().into())
+    });
+    blocking::BytesJoinHandle { inner, handoff }
+}
+
+#[track_caller]
+fn try_spawn_blocking_named<T>(
+    name: Option<String>,
+    f: impl FnOnce() -> T + 'static,
+) -> Result<blocking::JoinHandle<T>, SpawnError>
+where
+    T: 'static,
+{
+    try_spawn_blocking_gated(name, None::<futures::future::Ready<()>>, f)
+}
+
+// Like `try_spawn_blocking_named`, but the worker first awaits `gate`, whose output it holds
+// until `f` returned. `f` is skipped if the handle is aborted in the meantime.
+#[track_caller]
+pub(crate) fn try_spawn_blocking_gated<G, T>(
+    name: Option<String>,
+    gate: Option<impl Future<Output = G> + 'static>,
+    f: impl FnOnce() -> T + 'static,
+) -> Result<blocking::JoinHandle<T>, SpawnError>
+where
+    G: 'static,
+    T: 'static,
+{
+    runtime::ensure_initialized()?;
+    let (completion, rx) = panic::Completion::new();
+    let id = completion.id();
+    let header = completion.header().clone();
+    #[cfg(feature = "alloc-accounting")]
+    let f = {
+        let memory = crate::alloc::TaskMemory::register(crate::alloc::TaskKind::Blocking);
+        move || memory.enter(f)
+    };
+    let slot = Arc::new(Mutex::new(WorkerSlot::default()));
+    spawn_worker(&runtime::BLOCKING_WORKERS, {
+        let slot = slot.clone();
+        move |permit| {
+            slot.lock().unwrap().permit = Some(permit);
+            let job = {
+                let (slot, completion) = (slot.clone(), completion.clone());
+                move || {
+                    panic::catch_panic_blocking(completion, f);
+                    slot.lock().unwrap().permit.take();
+                }
+            };
+            let worker = match gate {
+                None => worker::spawn_blocking(name.as_deref(), job),
+                Some(gate) => {
+                    let header = completion.header().clone();
+                    let gate = async move {
+                        // Only done before `f` started if the handle was aborted.
+                        let aborted = async move {
+                            let mut changes = Box::pin(header.changes());
+                            while let Some(state) = changes.next().await {
+                                if state.is_done() {
+                                    break;
+                                }
+                            }
+                        };
+                        match futures::future::select(Box::pin(gate), Box::pin(aborted)).await {
+                            futures::future::Either::Left((gate, _)) => Some(gate),
+                            futures::future::Either::Right(_) => None,
+                        }
+                    };
+                    let slot = slot.clone();
+                    worker::spawn_blocking_after(name.as_deref(), gate, move |gate| match gate {
+                        Some(gate) => {
+                            job();
+                            drop(gate);
+                        }
+                        None => {
+                            slot.lock().unwrap().permit.take();
+                        }
+                    })
+                }
+            };
+            WorkerSlot::spawned(&slot, worker, &completion)
+        }
+    })?;
+    let mut handle = blocking::JoinHandle::new(rx, id, header);
+    handle.worker = Some(slot);
+    Ok(handle)
+}
+
+/// A cancellation flag handed to the closures of [`spawn_blocking_cancellable`], set when
+/// their join handle is aborted.
+#[derive(Clone, Debug, Default)]
+pub struct CancelFlag(Arc<AtomicBool>);
+
+impl CancelFlag {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn cancel(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+/// Like [`spawn_blocking`], but `f` can check the given flag to stop early once the
+/// returned handle is [`abort`](blocking::JoinHandle::abort)ed, e.g. between iterations
+/// of a long loop.
+#[track_caller]
+pub fn spawn_blocking_cancellable<T>(
+    f: impl FnOnce(&CancelFlag) -> T + 'static,
+) -> blocking::JoinHandle<T>
+where
+    T: 'static,
+{
+    let flag = CancelFlag::default();
+    let mut handle = spawn_blocking({
+        let flag = flag.clone();
+        move || f(&flag)
     });
-    blocking::JoinHandle { rx }
+    tree::set_cancel(handle.id(), tree::Cancel::Flag(flag.clone()));
+    handle.cancel = Some(flag);
+    handle
+}
+
+// The dedicated worker running a task, once spawned, with the permit it holds, so that
+// `abort_hard` can terminate the worker without leaking the permit.
+#[derive(Default)]
+pub(crate) struct WorkerSlot {
+    worker: Option<u32>,
+    // The thread that spawned the worker, whose registry it is in.
+    thread: u64,
+    permit: Option<runtime::WorkerPermit>,
+    // Hooks to set with `worker::on_terminate` once the worker is spawned.
+    on_terminate: Vec<Box<dyn FnOnce() + Send>>,
 }
 
+impl WorkerSlot {
+    // Records the worker spawned for a task, or releases the permit if the worker couldn't
+    // be created. Failed spawns also fail the join, for spawns that were deferred and
+    // can't return the error to their caller.
+    fn spawned<T: 'static>(
+        slot: &Mutex<WorkerSlot>,
+        worker: Result<web_sys::Worker, JsValue>,
+        completion: &panic::Completion<T>,
+    ) -> Result<(), SpawnError> {
+        let mut slot = slot.lock().unwrap();
+        match worker {
+            Ok(worker) => {
+                let id = worker::id(&worker);
+                slot.worker = Some(id);
+                slot.thread = crate::utils::thread_id();
+                for hook in slot.on_terminate.drain(..) {
+                    worker::on_terminate(id, hook);
+                }
+                Ok(())
+            }
+            Err(err) => {
+                slot.permit.take();
+                let err = SpawnError::new(err);
+                completion.complete(Err(Box::new(err.to_string())));
+                Err(err)
+            }
+        }
+    }
+
+    // Whether the worker is still running, as far as the current thread can tell: workers
+    // not spawned yet, or spawned from another thread, are assumed to be.
+    pub(crate) fn is_alive(slot: &Mutex<WorkerSlot>) -> bool {
+        let slot = slot.lock().unwrap();
+        match slot.worker {
+            Some(id) if slot.thread == crate::utils::thread_id() => worker::is_alive(id),
+            _ => true,
+        }
+    }
+
+    // Runs `f` if the worker is terminated, see `worker::on_terminate`, returning a guard
+    // forgetting the hook when dropped.
+    pub(crate) fn on_terminate(
+        slot: &Arc<Mutex<WorkerSlot>>,
+        f: impl FnOnce() + Send + 'static,
+    ) -> impl Drop {
+        struct Forget(Arc<Mutex<WorkerSlot>>);
+
+        impl Drop for Forget {
+            fn drop(&mut self) {
+                let mut slot = self.0.lock().unwrap();
+                match slot.worker {
+                    Some(id) => worker::forget_on_terminate(id),
+                    None => slot.on_terminate.clear(),
+                }
+            }
+        }
+
+        let mut locked = slot.lock().unwrap();
+        match locked.worker {
+            Some(id) => worker::on_terminate(id, f),
+            None => locked.on_terminate.push(Box::new(f)),
+        }
+        Forget(slot.clone())
+    }
+
+    // Terminates the worker if it was spawned from the current thread, releasing its
+    // permit.
+    fn terminate(slot: &Mutex<WorkerSlot>) -> bool {
+        let mut slot = slot.lock().unwrap();
+        match slot.worker {
+            Some(id) if worker::terminate(id) => {
+                slot.permit.take();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+// Spawns a worker once `limit` allows it, queuing the spawn otherwise. The permit is
+// handed to the worker, which holds it until its task is done. Errors are only returned
+// for spawns that happen right away.
+//
+// The spawn happens on the coordinating thread when there is a coordinator worker, or
+// when called from a worker while nested spawning is disabled.
+fn spawn_worker(
+    limit: &'static runtime::WorkerLimit,
+    spawn: impl FnOnce(runtime::WorkerPermit) -> Result<(), SpawnError> + 'static,
+) -> Result<(), SpawnError> {
+    let job = move || match limit.try_acquire() {
+        Some(permit) => spawn(permit),
+        None => {
+            wasm_bindgen_futures::spawn_local(async move {
+                let permit = limit.acquire().await;
+                spawn(permit).ok();
+            });
+            Ok(())
+        }
+    };
+    let coordinated = {
+        let config = runtime::CONFIG.lock().unwrap();
+        config.coordinator_worker || (utils::is_worker_scope() && !config.allow_nested_spawn)
+    };
+    if !coordinated {
+        return job();
+    }
+    match runtime::coordinator::submit(job) {
+        Ok(()) => Ok(()),
+        Err(job) => job(),
+    }
+}
+
+/// Runs `f` on a dedicated worker with a borrowed view of `data`, without copying it.
+///
+/// The slice is handed to the worker as a pointer into the shared memory, so `data` must
+/// stay borrowed until the worker is done with it, which the returned future ensures for
+/// as long as it exists: if it is dropped before the closure started, the closure is
+/// skipped, and if it is dropped while the closure runs, the drop blocks until the
+/// closure has returned. Callers that can borrow for the whole run should prefer
+/// [`thread::scope`](crate::thread::scope).
+///
+/// A worker terminated while running the closure, e.g. by [`runtime::shutdown`], is done
+/// with `data` too: the future then resolves to [`JoinError::Aborted`].
+///
+/// # Safety
+///
+/// The returned future must not be leaked, e.g. with [`std::mem::forget`], once polled:
+/// the closure would then keep reading `data` after it was freed.
+///
+/// On threads that can't block, like the main thread, the future must not be dropped
+/// while the closure runs, since the drop can't wait there: it aborts instead. Awaiting
+/// the future to completion is always fine.
+pub async unsafe fn spawn_blocking_scoped<T, R, F>(data: &[T], f: F) -> Result<R, JoinError>
+where
+    T: Sync + 'static,
+    F: FnOnce(&[T]) -> R + 'static,
+    R: 'static,
+{
+    const PENDING: u8 = 0;
+    const RUNNING: u8 = 1;
+    const FINISHED: u8 = 2;
+    const CANCELLED: u8 = 3;
+    const TERMINATED: u8 = 4;
+
+    struct State {
+        state: AtomicU8,
+        futex: Futex,
+    }
+
+    impl State {
+        fn finish(&self) {
+            self.state.store(FINISHED, Ordering::Release);
+            self.futex.notify();
+        }
+
+        // Called once the worker is terminated, unless the closure returned already.
+        fn terminated(&self) {
+            for from in [PENDING, RUNNING] {
+                self.state
+                    .compare_exchange(from, TERMINATED, Ordering::AcqRel, Ordering::Acquire)
+                    .ok();
+            }
+            self.futex.notify();
+        }
+
+        fn done(&self) -> Option<()> {
+            matches!(self.state.load(Ordering::Acquire), FINISHED | TERMINATED).then_some(())
+        }
+    }
+
+    struct Finished(Arc<State>);
+
+    impl Drop for Finished {
+        fn drop(&mut self) {
+            self.0.finish();
+        }
+    }
+
+    struct Scope(Arc<State>);
+
+    impl Drop for Scope {
+        fn drop(&mut self) {
+            if self
+                .0
+                .state
+                .compare_exchange(PENDING, CANCELLED, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                return;
+            }
+            if self.0.futex.wait_until(|| self.0.done()).is_err() {
+                // The closure may still read `data`, which is about to be freed.
+                std::process::abort();
+            }
+        }
+    }
+
+    let state = Arc::new(State {
+        state: AtomicU8::new(PENDING),
+        futex: Futex::new(),
+    });
+    let (ptr, len) = (data.as_ptr() as usize, data.len());
+    let _scope = Scope(state.clone());
+    let handle = spawn_blocking({
+        let state = state.clone();
+        move || {
+            state
+                .state
+                .compare_exchange(PENDING, RUNNING, Ordering::AcqRel, Ordering::Acquire)
+                .ok()?;
+            // Destructors don't run when a panic traps the worker.
+            panic::on_abort({
+                let state = state.clone();
+                move || state.finish()
+            });
+            let _finished = Finished(state);
+            // SAFETY: `_scope` keeps `data` borrowed until `_finished` has been dropped, or
+            // the worker terminated.
+            let data = unsafe { std::slice::from_raw_parts(ptr as *const T, len) };
+            Some(f(data))
+        }
+    });
+    // The join never completes if the worker is terminated.
+    let (terminated_tx, terminated) = futures::channel::oneshot::channel();
+    let slot = handle
+        .worker
+        .clone()
+        .expect("blocking tasks have a worker slot");
+    let _hook = WorkerSlot::on_terminate(&slot, move || {
+        state.terminated();
+        terminated_tx.send(()).ok();
+    });
+    let result = match futures::future::select(handle, terminated).await {
+        futures::future::Either::Left((result, _)) => result,
+        futures::future::Either::Right((Ok(()), _)) => Err(JoinError::Aborted),
+        futures::future::Either::Right((Err(_), handle)) => handle.await,
+    };
+    result?.ok_or(JoinError::Aborted)
+}
+
+#[track_caller]
 pub fn spawn<F>(future: F) -> r#async::JoinHandle<F::Output>
 where
     F: Future + 'static,
     F::Output: 'static,
 {
-    let (tx, rx) = futures::channel::oneshot::channel();
+    runtime::ensure_initialized().unwrap_or_else(|err| panic!("{err}"));
+    if runtime::CONFIG.lock().unwrap().local_fallback && !runtime::workers_supported() {
+        static WARNING: std::sync::Once = std::sync::Once::new();
+        WARNING.call_once(|| {
+            web_sys::console::warn_1(
+                &"wasmt: workers are unavailable, running spawned tasks on the current thread"
+                    .into(),
+            );
+        });
+        return spawn_local(future);
+    }
+    try_spawn(future).unwrap_or_else(|err| panic!("{err}"))
+}
+
+/// Like [`spawn`], but returns an error instead of panicking when the worker can't be
+/// created, e.g. because a content security policy forbids workers, so that callers can
+/// fall back to [`spawn_local`].
+///
+/// Also fails if the `explicit-init` feature is enabled and the runtime wasn't
+/// initialized with [`runtime::init`] yet.
+///
+/// Only failures of workers spawned right away are returned, including the replacement
+/// of a broken [shared worker](runtime::Builder::shared_async_workers): when the spawn is
+/// queued behind [`max_async_workers`](runtime::Builder::max_async_workers) or handed to
+/// the coordinating thread, a failure fails the join instead.
+#[track_caller]
+pub fn try_spawn<F>(future: F) -> Result<r#async::JoinHandle<F::Output>, SpawnError>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    try_spawn_named(None, future)
+}
+
+// Named tasks skip the shared workers, which can't be renamed.
+#[track_caller]
+fn try_spawn_named<F>(
+    name: Option<String>,
+    future: F,
+) -> Result<r#async::JoinHandle<F::Output>, SpawnError>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    runtime::ensure_initialized()?;
+    let (completion, task, mut handle) = async_task(future);
+    let slot = Arc::new(Mutex::new(WorkerSlot::default()));
+    let task = match name {
+        Some(_) => Err(task),
+        None => runtime::executor::submit(task)?,
+    };
+    if let Err(task) = task {
+        let slot = slot.clone();
+        spawn_worker(&runtime::ASYNC_WORKERS, move |permit| {
+            slot.lock().unwrap().permit = Some(permit);
+            let worker = worker::spawn(name.as_deref(), {
+                let slot = slot.clone();
+                async move {
+                    task.await;
+                    slot.lock().unwrap().permit.take();
+                }
+            });
+            WorkerSlot::spawned(&slot, worker, &completion)
+        })?;
+    }
+    handle.worker = Some(slot);
+    Ok(handle)
+}
+
+/// Like [`spawn`], but always runs `future` on a dedicated worker spawned right away,
+/// and also returns a reference to that worker, e.g. to transfer objects to it with
+/// [`WorkerRef::post_message_with_transfer`] or to listen to its events.
+///
+/// Since the worker has to exist when this returns, the task skips the shared workers,
+/// the coordinator worker and the [`max_async_workers`](runtime::Builder::max_async_workers)
+/// queue. Messages posted to the worker are read by the task with [`worker_messages`].
+///
+/// # Panics
+///
+/// Panics if the worker can't be created.
+#[track_caller]
+pub fn spawn_with_worker<F>(future: F) -> (r#async::JoinHandle<F::Output>, WorkerRef)
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    runtime::ensure_initialized().unwrap_or_else(|err| panic!("{err}"));
+    let (_, task, mut handle) = async_task(future);
+    let worker = worker::spawn(None, task).unwrap_or_else(|err| panic!("{}", SpawnError::new(err)));
+    let slot = WorkerSlot {
+        worker: Some(worker::id(&worker)),
+        thread: crate::utils::thread_id(),
+        permit: None,
+        on_terminate: Vec::new(),
+    };
+    handle.worker = Some(Arc::new(Mutex::new(slot)));
+    (handle, WorkerRef::new(worker))
+}
+
+// Wraps `future` into a task reporting to the returned handle, which isn't attached to
+// any worker yet.
+#[track_caller]
+fn async_task<F>(
+    future: F,
+) -> (
+    panic::Completion<F::Output>,
+    panic::CatchPanic<impl Future<Output = ()>>,
+    r#async::JoinHandle<F::Output>,
+)
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    let (completion, rx) = panic::Completion::new();
+    let id = completion.id();
     let (abort_handle, abort_registration) = AbortHandle::new_pair();
-    let abortable_future = Abortable::new(future, abort_registration);
-    worker::spawn(async move {
-        if let Ok(result) = abortable_future.await {
-            tx.send(result).ok();
+    tree::set_cancel(id, tree::Cancel::Abort(abort_handle.clone()));
+    let abortable_future = Abortable::new(wake::Coalesced::new(future), abort_registration);
+    #[cfg(feature = "alloc-accounting")]
+    let abortable_future =
+        crate::alloc::Accounted::new(crate::alloc::TaskKind::Async, abortable_future);
+    let task = panic::CatchPanic::new(&completion.clone(), {
+        let completion = completion.clone();
+        async move {
+            match abortable_future.await {
+                Ok(result) => completion.complete(Ok(result)),
+                Err(_) => completion.header().set(TaskState::Aborted),
+            }
         }
     });
-    r#async::JoinHandle {
+    let handle = r#async::JoinHandle {
         abort_handle,
         aborted: false,
         rx,
-    }
+        id,
+        header: completion.header().clone(),
+        worker: None,
+        #[cfg(feature = "spawn-location")]
+        location: completion.location(),
+    };
+    (completion, task, handle)
 }
 
+#[track_caller]
 pub fn spawn_local<F>(future: F) -> r#async::JoinHandle<F::Output>
 where
     F: Future + 'static,
     F::Output: 'static,
 {
-    let (tx, rx) = futures::channel::oneshot::channel();
+    let (completion, rx) = panic::Completion::new();
+    #[cfg(feature = "spawn-location")]
+    let location = completion.location();
+    let id = completion.id();
     let (abort_handle, abort_registration) = AbortHandle::new_pair();
-    let abortable_future = Abortable::new(future, abort_registration);
-    wasm_bindgen_futures::spawn_local(async move {
-        if let Ok(result) = abortable_future.await {
-            tx.send(result).ok();
+    tree::set_cancel(id, tree::Cancel::Abort(abort_handle.clone()));
+    let abortable_future = Abortable::new(wake::Coalesced::new(future), abort_registration);
+    #[cfg(feature = "alloc-accounting")]
+    let abortable_future =
+        crate::alloc::Accounted::new(crate::alloc::TaskKind::Local, abortable_future);
+    let header = completion.header().clone();
+    local::spawn(panic::CatchPanic::new(&completion.clone(), async move {
+        match abortable_future.await {
+            Ok(result) => completion.complete(Ok(result)),
+            Err(_) => completion.header().set(TaskState::Aborted),
         }
-    });
+    }));
     r#async::JoinHandle {
         abort_handle,
         aborted: false,
         rx,
+        id,
+        header,
+        worker: None,
+        #[cfg(feature = "spawn-location")]
+        location,
+    }
+}
+
+/// Spawns `future` on a worker once `delay` has elapsed. Aborting the handle before then
+/// cancels it.
+#[track_caller]
+pub fn spawn_after<F>(delay: Duration, future: F) -> r#async::JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    spawn(async move {
+        // Only cut short by a shutdown, which also terminates the worker.
+        sleep(delay).await.ok();
+        future.await
+    })
+}
+
+/// Runs the futures made by `factory` on a worker, one every `period`, until the handle
+/// is aborted or the runtime shuts down.
+///
+/// Each future is awaited before the next tick, so runs never overlap: ticks missed while
+/// a run takes longer than `period` are skipped.
+///
+/// # Panics
+///
+/// Panics if `period` is zero.
+#[track_caller]
+pub fn spawn_interval<F, Fut>(period: Duration, mut factory: F) -> r#async::JoinHandle<()>
+where
+    F: FnMut() -> Fut + 'static,
+    Fut: Future<Output = ()> + 'static,
+{
+    // Checked here rather than by the worker, where the interval is created.
+    assert!(period > Duration::ZERO, "`period` must be non-zero.");
+    spawn(async move {
+        let mut interval = time::interval(period);
+        while interval.tick().await.is_ok() {
+            factory().await;
+        }
+    })
+}
+
+thread_local! {
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Runs `future` until `deadline`, failing with [`TimeoutError::Elapsed`] if it doesn't
+/// complete in time, or with [`TimeoutError::Shutdown`] if the runtime shuts down first.
+///
+/// While `future` is being polled, [`remaining_time`] returns the time left before the
+/// deadline, so long computations can checkpoint or yield before running out of time.
+#[track_caller]
+pub fn with_deadline<F>(deadline: Instant, future: F) -> WithDeadline<F>
+where
+    F: Future,
+{
+    WithDeadline {
+        future: Box::pin(future),
+        deadline,
+        timer: None,
+        origin: time::Origin::caller(None),
+    }
+}
+
+/// Time left before the deadline of the innermost [`with_deadline`] being polled, or
+/// `None` when called outside of one.
+pub fn remaining_time() -> Option<Duration> {
+    DEADLINE
+        .with(Cell::get)
+        .map(|deadline| deadline.duration_since(Instant::now()))
+}
+
+pub struct WithDeadline<F: Future> {
+    future: Pin<Box<F>>,
+    deadline: Instant,
+    timer: Option<time::Sleep>,
+    origin: time::Origin,
+}
+
+impl<F: Future> Future for WithDeadline<F> {
+    type Output = Result<F::Output, TimeoutError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let deadline = self.deadline;
+        let outer = DEADLINE.with(|current| {
+            current.replace(Some(match current.get() {
+                Some(outer) if outer < deadline => outer,
+                _ => deadline,
+            }))
+        });
+        let poll = self.future.as_mut().poll(cx);
+        DEADLINE.with(|current| current.set(outer));
+        if let Poll::Ready(output) = poll {
+            return Poll::Ready(Ok(output));
+        }
+        let origin = self.origin;
+        let timer = self
+            .timer
+            .get_or_insert_with(|| time::Sleep::new(deadline, origin));
+        Pin::new(timer).poll(cx).map(|result| {
+            Err(match result {
+                Ok(()) => Elapsed.into(),
+                Err(shutdown) => shutdown.into(),
+            })
+        })
     }
 }
 
 pub mod r#async {
-    use futures::{future::FusedFuture, stream::AbortHandle};
+    use futures::future::{FusedFuture, LocalBoxFuture};
+    use futures::stream::{AbortHandle, Stream};
 
     use super::*;
 
+    /// Handle to join or abort a task.
+    ///
+    /// Handles are `Send` when the task's output is, so they can be handed to another
+    /// worker, e.g. for a worker to supervise tasks of the main thread spawned with
+    /// [`spawn_local`]: joining or aborting from there wakes the task's thread through
+    /// shared memory.
     pub struct JoinHandle<T> {
         pub(crate) abort_handle: AbortHandle,
         pub(crate) aborted: bool,
-        pub(crate) rx: futures::channel::oneshot::Receiver<T>,
+        pub(crate) rx: futures::channel::oneshot::Receiver<Result<T, panic::Payload>>,
+        pub(crate) id: Id,
+        pub(crate) header: state::Header,
+        pub(crate) worker: Option<Arc<Mutex<WorkerSlot>>>,
+        #[cfg(feature = "spawn-location")]
+        pub(crate) location: &'static std::panic::Location<'static>,
     }
 
     impl<T> JoinHandle<T> {
-        pub async fn join(self) -> Result<T, JoinError> {
-            self.rx.await.map_err(|_| {
-                if self.aborted {
-                    JoinError::Aborted
-                } else {
-                    JoinError::Panic
-                }
+        pub async fn join(mut self) -> Result<T, JoinError> {
+            futures::future::poll_fn(|cx| self.poll_join(cx)).await
+        }
+
+        pub(crate) fn poll_join(&mut self, cx: &mut Context<'_>) -> Poll<Result<T, JoinError>> {
+            Pin::new(&mut self.rx).poll(cx).map(|result| match result {
+                Ok(Ok(output)) => Ok(output),
+                Ok(Err(payload)) => Err(JoinError::Panic(payload)),
+                // Also aborted when cancellation propagates from an ancestor.
+                Err(_) if self.aborted || self.abort_handle.is_aborted() => Err(JoinError::Aborted),
+                Err(_) => Err(JoinError::unknown_panic()),
             })
         }
 
+        /// Aborts the task, and the tasks it spawned if the runtime
+        /// [propagates cancellation](crate::runtime::Builder::propagate_cancellation).
         pub fn abort(&mut self) {
             self.abort_handle.abort();
             self.aborted = true;
             self.rx.close();
+            self.header.set(TaskState::Aborted);
+            propagate_abort(self.id);
+        }
+
+        pub fn id(&self) -> Id {
+            self.id
         }
 
         pub fn is_finished(&self) -> bool {
             self.rx.is_terminated()
         }
+
+        /// Where the task is in its life, e.g. for a progress UI to show which step of a
+        /// pipeline is running. Tasks the runtime lost track of, e.g. because their
+        /// worker was terminated from outside, stay [`Running`](TaskState::Running).
+        pub fn state(&self) -> TaskState {
+            self.header.get()
+        }
+
+        /// The current [`state`](Self::state), then every later one until the task is
+        /// done. States the task went through while the stream wasn't polled are
+        /// skipped, so the stream always ends with the final state.
+        pub fn state_changes(&self) -> impl Stream<Item = TaskState> + 'static {
+            self.header.changes()
+        }
+
+        /// Where the task was spawned, to tell tasks apart when debugging.
+        #[cfg(feature = "spawn-location")]
+        pub fn spawn_location(&self) -> &'static std::panic::Location<'static> {
+            self.location
+        }
+
+        /// Aborts the task like [`abort`](Self::abort), and also terminates the worker
+        /// running it, so that a task stuck in blocking code stops right away and its
+        /// worker is reclaimed. Returns whether a worker was terminated.
+        ///
+        /// Terminating a worker doesn't run any destructor: whatever the task owned is
+        /// leaked, and locks it held stay locked forever, so only use this on tasks that
+        /// don't share state with others. Only the dedicated worker of a task spawned
+        /// from the current thread can be terminated: tasks on shared workers, tasks
+        /// handed to the coordinating thread and tasks not started yet are aborted
+        /// softly.
+        pub fn abort_hard(&mut self) -> bool {
+            self.abort();
+            self.worker.as_deref().is_some_and(WorkerSlot::terminate)
+        }
+
+        // Whether the task's dedicated worker is gone while the task never completed, e.g.
+        // after it was terminated from outside or crashed without reporting.
+        pub(crate) fn worker_died(&self) -> bool {
+            !self.is_finished() && !self.worker.as_deref().is_none_or(WorkerSlot::is_alive)
+        }
+
+        /// Transforms the output of the task once joined, without spawning anything.
+        pub fn map<U>(self, f: impl FnOnce(T) -> U + 'static) -> MappedJoinHandle<T, U>
+        where
+            T: 'static,
+        {
+            MappedJoinHandle {
+                handle: self,
+                f: Box::new(move |output| Box::pin(async move { f(output) })),
+            }
+        }
+
+        /// Chains an async continuation to the output of the task, run by the joiner.
+        pub fn and_then<U, Fut>(self, f: impl FnOnce(T) -> Fut + 'static) -> MappedJoinHandle<T, U>
+        where
+            Fut: Future<Output = U> + 'static,
+        {
+            MappedJoinHandle {
+                handle: self,
+                f: Box::new(move |output| Box::pin(f(output))),
+            }
+        }
+    }
+
+    /// Awaiting a handle is the same as [`join`](JoinHandle::join)ing it, which lets
+    /// handles be used with `select!`, `join!` or `FuturesUnordered` directly.
+    impl<T> Future for JoinHandle<T> {
+        type Output = Result<T, JoinError>;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            self.get_mut().poll_join(cx)
+        }
+    }
+
+    type Continuation<T, U> = Box<dyn FnOnce(T) -> LocalBoxFuture<'static, U>>;
+
+    /// A [`JoinHandle`] whose output goes through [`JoinHandle::map`] or
+    /// [`JoinHandle::and_then`] continuations.
+    pub struct MappedJoinHandle<T, U> {
+        handle: JoinHandle<T>,
+        f: Continuation<T, U>,
+    }
+
+    impl<T: 'static, U: 'static> MappedJoinHandle<T, U> {
+        pub async fn join(self) -> Result<U, JoinError> {
+            let output = self.handle.join().await?;
+            Ok((self.f)(output).await)
+        }
+
+        pub fn abort(&mut self) {
+            self.handle.abort();
+        }
+
+        pub fn is_finished(&self) -> bool {
+            self.handle.is_finished()
+        }
+
+        pub fn map<V>(self, g: impl FnOnce(U) -> V + 'static) -> MappedJoinHandle<T, V> {
+            let f = self.f;
+            MappedJoinHandle {
+                handle: self.handle,
+                f: Box::new(move |output| Box::pin(async move { g(f(output).await) })),
+            }
+        }
+
+        pub fn and_then<V, Fut>(self, g: impl FnOnce(U) -> Fut + 'static) -> MappedJoinHandle<T, V>
+        where
+            Fut: Future<Output = V> + 'static,
+        {
+            let f = self.f;
+            MappedJoinHandle {
+                handle: self.handle,
+                f: Box::new(move |output| Box::pin(async move { g(f(output).await).await })),
+            }
+        }
     }
 }
 
 pub mod blocking {
+    use std::mem::ManuallyDrop;
+    use std::ptr;
+    use std::sync::atomic::{AtomicPtr, AtomicUsize};
+
     use futures::future::FusedFuture;
+    use futures::Stream;
 
     use super::*;
 
     pub struct JoinHandle<T> {
-        pub(crate) rx: futures::channel::oneshot::Receiver<T>,
+        pub(crate) rx: futures::channel::oneshot::Receiver<Result<T, panic::Payload>>,
+        pub(crate) cancel: Option<CancelFlag>,
+        pub(crate) aborted: bool,
+        id: Id,
+        header: state::Header,
+        pub(crate) worker: Option<Arc<Mutex<WorkerSlot>>>,
+        #[cfg(feature = "spawn-location")]
+        location: &'static std::panic::Location<'static>,
     }
 
     impl<T> JoinHandle<T> {
+        #[track_caller]
+        pub(crate) fn new(
+            rx: futures::channel::oneshot::Receiver<Result<T, panic::Payload>>,
+            id: Id,
+            header: state::Header,
+        ) -> Self {
+            JoinHandle {
+                rx,
+                cancel: None,
+                aborted: false,
+                id,
+                header,
+                worker: None,
+                #[cfg(feature = "spawn-location")]
+                location: std::panic::Location::caller(),
+            }
+        }
+
         pub async fn join(self) -> Result<T, JoinError> {
-            self.rx.await.map_err(|_| JoinError::Panic)
+            self.await
+        }
+
+        /// Stops waiting for the closure, which fails the join with [`JoinError::Aborted`]
+        /// unless it already returned. Closures spawned with
+        /// [`spawn_blocking_cancellable`] see their flag set; others keep running until
+        /// they return, unless aborted with [`abort_hard`](Self::abort_hard). Tasks the
+        /// closure spawned are aborted too if the runtime
+        /// [propagates cancellation](crate::runtime::Builder::propagate_cancellation).
+        pub fn abort(&mut self) {
+            if let Some(cancel) = &self.cancel {
+                cancel.cancel();
+            }
+            self.aborted = true;
+            self.rx.close();
+            self.header.set(TaskState::Aborted);
+            propagate_abort(self.id);
+        }
+
+        pub fn id(&self) -> Id {
+            self.id
+        }
+
+        /// Where the closure is in its life, see [`r#async::JoinHandle::state`]. Aborted
+        /// closures that keep running are reported as aborted.
+        pub fn state(&self) -> TaskState {
+            self.header.get()
+        }
+
+        /// See [`r#async::JoinHandle::state_changes`].
+        pub fn state_changes(&self) -> impl Stream<Item = TaskState> + 'static {
+            self.header.changes()
+        }
+
+        /// Aborts the closure like [`abort`](Self::abort), and also terminates the worker
+        /// running it, so that the closure stops right away and its worker is reclaimed.
+        /// Returns whether a worker was terminated.
+        ///
+        /// As with [`r#async::JoinHandle::abort_hard`], nothing owned by the closure is
+        /// dropped and locks it held stay locked forever. Closures queued behind
+        /// [`max_blocking_workers`](crate::runtime::Builder::max_blocking_workers) or run
+        /// by a worker spawned from another thread are aborted softly.
+        pub fn abort_hard(&mut self) -> bool {
+            self.abort();
+            self.worker.as_deref().is_some_and(WorkerSlot::terminate)
+        }
+
+        /// Joins the closure, aborting it with [`abort_hard`](Self::abort_hard) if it
+        /// hasn't returned within `timeout`.
+        pub async fn abort_after(mut self, timeout: Duration) -> Result<T, JoinError> {
+            match futures::future::select(&mut self, sleep(timeout)).await {
+                futures::future::Either::Left((result, _)) => result,
+                futures::future::Either::Right((_, _)) => {
+                    self.abort_hard();
+                    Err(JoinError::Aborted)
+                }
+            }
         }
 
         pub fn is_finished(&self) -> bool {
             self.rx.is_terminated()
         }
+
+        /// Where the closure was spawned, to tell tasks apart when debugging.
+        #[cfg(feature = "spawn-location")]
+        pub fn spawn_location(&self) -> &'static std::panic::Location<'static> {
+            self.location
+        }
+    }
+
+    // The buffer output by a closure of `spawn_blocking_bytes`, as the parts of a `Vec`.
+    #[derive(Default)]
+    pub(crate) struct Handoff {
+        ptr: AtomicPtr<u8>,
+        len: AtomicUsize,
+        capacity: AtomicUsize,
+    }
+
+    impl Handoff {
+        pub(crate) fn put(&self, bytes: Vec<u8>) {
+            let mut bytes = ManuallyDrop::new(bytes);
+            self.len.store(bytes.len(), Ordering::Relaxed);
+            self.capacity.store(bytes.capacity(), Ordering::Relaxed);
+            self.ptr.store(bytes.as_mut_ptr(), Ordering::Release);
+        }
+
+        fn take(&self) -> Option<Vec<u8>> {
+            let ptr = self.ptr.swap(ptr::null_mut(), Ordering::Acquire);
+            if ptr.is_null() {
+                return None;
+            }
+            let (len, capacity) = (
+                self.len.load(Ordering::Relaxed),
+                self.capacity.load(Ordering::Relaxed),
+            );
+            // SAFETY: the parts of a `Vec` given up by `put`, taken once.
+            Some(unsafe { Vec::from_raw_parts(ptr, len, capacity) })
+        }
+    }
+
+    // Frees the buffer if it wasn't joined.
+    impl Drop for Handoff {
+        fn drop(&mut self) {
+            self.take();
+        }
+    }
+
+    /// Handle of a closure spawned with [`spawn_blocking_bytes`], resolving to its bytes.
+    pub struct BytesJoinHandle {
+        pub(crate) inner: JoinHandle<()>,
+        pub(crate) handoff: Arc<Handoff>,
+    }
+
+    impl BytesJoinHandle {
+        pub async fn join(self) -> Result<Vec<u8>, JoinError> {
+            self.await
+        }
+
+        /// See [`JoinHandle::abort`].
+        pub fn abort(&mut self) {
+            self.inner.abort();
+        }
+
+        /// See [`JoinHandle::abort_hard`].
+        pub fn abort_hard(&mut self) -> bool {
+            self.inner.abort_hard()
+        }
+
+        pub fn id(&self) -> Id {
+            self.inner.id()
+        }
+
+        /// See [`JoinHandle::state`].
+        pub fn state(&self) -> TaskState {
+            self.inner.state()
+        }
+
+        pub fn is_finished(&self) -> bool {
+            self.inner.is_finished()
+        }
+    }
+
+    impl Future for BytesJoinHandle {
+        type Output = Result<Vec<u8>, JoinError>;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            Pin::new(&mut self.inner).poll(cx).map(|result| {
+                result.map(|()| {
+                    self.handoff
+                        .take()
+                        .expect("the closure returned without handing over its bytes")
+                })
+            })
+        }
+    }
+
+    impl<T> Future for JoinHandle<T> {
+        type Output = Result<T, JoinError>;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            Pin::new(&mut self.rx).poll(cx).map(|result| match result {
+                Ok(Ok(output)) => Ok(output),
+                Ok(Err(payload)) => Err(JoinError::Panic(payload)),
+                Err(_) if self.aborted => Err(JoinError::Aborted),
+                Err(_) => Err(JoinError::unknown_panic()),
+            })
+        }
     }
 }
 
-#[derive(PartialEq)]
 pub enum JoinError {
     Aborted,
-    Panic,
+    /// The task panicked, carrying the panic's payload.
+    ///
+    /// With the default `panic = "abort"` strategy, the payload is the panic message as a
+    /// `String`, recovered by a panic hook the crate installs on first spawn. Hooks set
+    /// after that replace it, and the payload is then lost.
+    Panic(Box<dyn Any + Send + 'static>),
+}
+
+impl JoinError {
+    pub(crate) fn unknown_panic() -> Self {
+        JoinError::Panic(Box::new(panic::LostPayload))
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, JoinError::Aborted)
+    }
+
+    pub fn is_panic(&self) -> bool {
+        matches!(self, JoinError::Panic(_))
+    }
+
+    /// The message the task panicked with, if any.
+    pub fn panic_message(&self) -> Option<&str> {
+        match self {
+            JoinError::Panic(payload) => match payload.downcast_ref::<&str>() {
+                Some(message) => Some(message),
+                None => payload.downcast_ref::<String>().map(String::as_str),
+            },
+            JoinError::Aborted => None,
+        }
+    }
+
+    /// Consumes the error, returning the payload of the task's panic.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the task was aborted rather than panicking.
+    #[track_caller]
+    pub fn into_panic(self) -> Box<dyn Any + Send + 'static> {
+        self.try_into_panic()
+            .expect("`JoinError::into_panic` called on an aborted task")
+    }
+
+    /// Returns the payload of the task's panic, or the error itself if the task was
+    /// aborted.
+    pub fn try_into_panic(self) -> Result<Box<dyn Any + Send + 'static>, JoinError> {
+        match self {
+            JoinError::Panic(payload) => Ok(payload),
+            JoinError::Aborted => Err(self),
+        }
+    }
+
+    /// Rethrows the task's panic on the current thread, like
+    /// `std::panic::resume_unwind(err.into_panic())`. Aborted tasks panic with the
+    /// error's message instead.
+    pub fn resume_unwind(self) -> ! {
+        match self.try_into_panic() {
+            Ok(payload) => std::panic::resume_unwind(payload),
+            Err(err) => panic!("{err}"),
+        }
+    }
+}
+
+/// Panics compare equal regardless of their payload.
+impl PartialEq for JoinError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (JoinError::Aborted, JoinError::Aborted) | (JoinError::Panic(_), JoinError::Panic(_))
+        )
+    }
+}
+
+/// Error returned by [`try_spawn`] and [`try_spawn_blocking`] when the task can't be
+/// spawned.
+#[derive(Debug)]
+pub enum SpawnError {
+    /// The runtime wasn't initialized with [`runtime::init`], which the `explicit-init`
+    /// feature requires before spawning.
+    NotInitialized(runtime::NotInitialized),
+    /// The worker couldn't be created, with the message of the JS error.
+    Worker(String),
+}
+
+impl SpawnError {
+    pub(crate) fn new(err: JsValue) -> Self {
+        SpawnError::Worker(crate::Error::Js(err).to_string())
+    }
+}
+
+impl From<runtime::NotInitialized> for SpawnError {
+    fn from(err: runtime::NotInitialized) -> Self {
+        SpawnError::NotInitialized(err)
+    }
+}
+
+impl std::fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpawnError::NotInitialized(err) => err.fmt(f),
+            SpawnError::Worker(message) => write!(f, "failed to spawn worker: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for SpawnError {}
+
+impl From<SpawnError> for JsValue {
+    fn from(err: SpawnError) -> Self {
+        js_sys::Error::new(&err.to_string()).into()
+    }
 }
 
 impl std::fmt::Display for JoinError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             JoinError::Aborted => write!(f, "thread was aborted"),
-            JoinError::Panic => write!(f, "thread panicked"),
+            JoinError::Panic(_) => match self.panic_message() {
+                Some(message) => write!(f, "thread panicked: {message}"),
+                None => write!(f, "thread panicked"),
+            },
         }
     }
 }
@@ -128,7 +1282,10 @@ impl std::fmt::Debug for JoinError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             JoinError::Aborted => write!(f, "JoinError::Aborted"),
-            JoinError::Panic => write!(f, "JoinError::Panic"),
+            JoinError::Panic(_) => match self.panic_message() {
+                Some(message) => write!(f, "JoinError::Panic({message:?})"),
+                None => write!(f, "JoinError::Panic(..)"),
+            },
         }
     }
 }
@@ -137,21 +1294,13 @@ impl std::error::Error for JoinError {}
 
 impl From<JoinError> for JsValue {
     fn from(err: JoinError) -> Self {
-        match err {
-            JoinError::Aborted => JsValue::from_str("thread was aborted"),
-            JoinError::Panic => JsValue::from_str("thread panicked"),
-        }
+        JsValue::from_str(&err.to_string())
     }
 }
 
 impl From<JoinError> for std::io::Error {
     fn from(err: JoinError) -> Self {
-        match err {
-            JoinError::Aborted => {
-                std::io::Error::new(std::io::ErrorKind::Other, "thread was aborted")
-            }
-            JoinError::Panic => std::io::Error::new(std::io::ErrorKind::Other, "thread panicked"),
-        }
+        std::io::Error::other(err.to_string())
     }
 }
 
@@ -159,7 +1308,7 @@ impl From<JoinError> for std::io::Error {
 mod tests {
     use std::time::Duration;
 
-    use crate::time::{sleep, sleep_blocking};
+    use crate::time::{sleep, sleep_blocking, Instant};
 
     use super::*;
 
@@ -186,11 +1335,33 @@ mod tests {
         assert!(end - start >= 100.0);
     }
 
+    #[wasm_bindgen_test]
+    async fn test_try_spawn() {
+        let handle = try_spawn(async move { 1 }).unwrap();
+        assert_eq!(handle.join().await.unwrap(), 1);
+        let handle = try_spawn_blocking(|| 2).unwrap();
+        assert_eq!(handle.join().await.unwrap(), 2);
+    }
+
+    #[cfg(feature = "spawn-location")]
+    #[wasm_bindgen_test]
+    async fn test_spawn_location() {
+        let line = line!() + 1;
+        let handle = spawn(async move { 1 });
+        assert_eq!(handle.spawn_location().file(), file!());
+        assert_eq!(handle.spawn_location().line(), line);
+        let handle = spawn_blocking(|| 1);
+        assert_eq!(handle.spawn_location().line(), line + 3);
+        let handle = spawn_after(Duration::from_millis(1), async move { 1 });
+        assert_eq!(handle.spawn_location().line(), line + 5);
+        handle.join().await.unwrap();
+    }
+
     #[wasm_bindgen_test]
     async fn test_spawn_local_task() {
         let start = PERFORMANCE.now();
         let handle = spawn_local(async move {
-            sleep(Duration::from_millis(100)).await;
+            sleep(Duration::from_millis(100)).await.unwrap();
             1
         });
         assert_eq!(handle.join().await.unwrap(), 1);
@@ -210,6 +1381,84 @@ mod tests {
         assert!(end - start >= 100.0);
     }
 
+    // Hands over buffers of growing sizes, logging how long the joins took: the handoff
+    // takes ownership of the buffer where it was allocated, so the time doesn't depend on
+    // the size.
+    #[wasm_bindgen_test]
+    async fn test_spawn_blocking_bytes_zero_copy() {
+        for len in [1 << 10, 1 << 20, 64 << 20] {
+            let (tx, rx) = futures::channel::oneshot::channel();
+            let handle = spawn_blocking_bytes(move || {
+                let bytes = vec![7u8; len];
+                tx.send((bytes.as_ptr() as usize, PERFORMANCE.now())).ok();
+                bytes
+            });
+            let bytes = handle.join().await.unwrap();
+            let joined = PERFORMANCE.now();
+            let (address, returned) = rx.await.unwrap();
+            // Same allocation, so nothing was copied on the way.
+            assert_eq!(bytes.as_ptr() as usize, address);
+            assert_eq!(bytes.len(), len);
+            assert_eq!(bytes[len - 1], 7);
+            web_sys::console::log_1(
+                &format!("{len} bytes handed over in {:.3}ms", joined - returned).into(),
+            );
+        }
+        let boxed = vec![1u8, 2, 3].into_boxed_slice();
+        let address = boxed.as_ptr() as usize;
+        let bytes = spawn_blocking_bytes(move || boxed).join().await.unwrap();
+        assert_eq!((bytes.as_ptr() as usize, bytes), (address, vec![1, 2, 3]));
+        // Buffers of handles dropped without joining are freed.
+        drop(spawn_blocking_bytes(|| vec![0u8; 1 << 20]));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_spawn_blocking_scoped() {
+        let data = (0..1024u32).collect::<Vec<_>>();
+        // SAFETY: awaited to completion.
+        let sum = unsafe { spawn_blocking_scoped(&data, |slice| slice.iter().sum::<u32>()) };
+        assert_eq!(sum.await, Ok(data.iter().sum()));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_spawn_blocking_scoped_drop_waits() {
+        // Dropped while the closure runs, which only a worker may wait for.
+        let waited = spawn_blocking(|| {
+            futures::executor::block_on(async {
+                let data = vec![1u8; 16];
+                let started = Arc::new(AtomicU8::new(0));
+                // SAFETY: dropped on a worker, which can block.
+                let mut future = Box::pin(unsafe {
+                    spawn_blocking_scoped(&data, {
+                        let started = started.clone();
+                        move |slice| {
+                            started.store(1, Ordering::SeqCst);
+                            sleep_blocking(Duration::from_millis(100));
+                            slice.len()
+                        }
+                    })
+                });
+                assert!(futures::poll!(&mut future).is_pending());
+                while started.load(Ordering::SeqCst) == 0 {
+                    sleep_blocking(Duration::from_millis(10));
+                }
+                let start = Instant::now();
+                drop(future);
+                start.elapsed()
+            })
+        });
+        assert!(waited.join().await.unwrap() >= Duration::from_millis(50));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_spawn_blocking_scoped_drop_before_start() {
+        let data = vec![1u8; 16];
+        // SAFETY: dropped before the closure could start.
+        let mut future = Box::pin(unsafe { spawn_blocking_scoped(&data, |_| unreachable!()) });
+        assert!(futures::poll!(&mut future).is_pending());
+        drop(future);
+    }
+
     #[wasm_bindgen_test]
     async fn test_task_in_task() {
         let start = PERFORMANCE.now();
@@ -230,7 +1479,7 @@ mod tests {
         let start = PERFORMANCE.now();
         let handle = spawn(async move {
             let handle = spawn_local(async move {
-                sleep(Duration::from_millis(100)).await;
+                sleep(Duration::from_millis(100)).await.unwrap();
                 1
             });
             handle.join().await.unwrap()
@@ -275,7 +1524,7 @@ mod tests {
         let start = PERFORMANCE.now();
         let handle = spawn_local(async move {
             let handle = spawn_local(async move {
-                sleep(Duration::from_millis(100)).await;
+                sleep(Duration::from_millis(100)).await.unwrap();
                 1
             });
             handle.join().await.unwrap()
@@ -300,6 +1549,173 @@ mod tests {
         assert!(end - start >= 100.0);
     }
 
+    #[wasm_bindgen_test]
+    async fn test_with_deadline() {
+        let deadline = Instant::now() + Duration::from_millis(1000);
+        let result = with_deadline(deadline, async {
+            let remaining = remaining_time().unwrap();
+            assert!(remaining <= Duration::from_millis(1000));
+            sleep(Duration::from_millis(100)).await.unwrap();
+            assert!(remaining_time().unwrap() < remaining);
+            1
+        })
+        .await;
+        assert_eq!(result, Ok(1));
+        assert_eq!(remaining_time(), None);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_with_deadline_elapsed() {
+        let start = PERFORMANCE.now();
+        let deadline = Instant::now() + Duration::from_millis(100);
+        let result = with_deadline(deadline, sleep(Duration::from_millis(1000))).await;
+        assert_eq!(result, Err(Elapsed.into()));
+        let end = PERFORMANCE.now();
+        assert!(end - start < 1000.0);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_with_deadline_in_task() {
+        let deadline = Instant::now() + Duration::from_millis(100);
+        let handle = spawn(with_deadline(deadline, async {
+            while remaining_time().unwrap() > Duration::ZERO {
+                sleep(Duration::from_millis(10)).await.unwrap();
+            }
+            1
+        }));
+        assert_eq!(handle.join().await.unwrap(), Ok(1));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_await_handles() {
+        assert_eq!(spawn(async move { 1 }).await, Ok(1));
+        assert_eq!(spawn_blocking(|| 2).await, Ok(2));
+        let slow = spawn_local(async move {
+            sleep(Duration::from_millis(100)).await.unwrap();
+            3
+        });
+        let fast = spawn(async move { 4 });
+        let first = futures::future::select(slow, fast).await;
+        match first {
+            futures::future::Either::Right((result, mut slow)) => {
+                assert_eq!(result, Ok(4));
+                slow.abort();
+                assert_eq!(slow.await, Err(JoinError::Aborted));
+            }
+            futures::future::Either::Left(_) => panic!("slow task completed first"),
+        }
+        let all = [spawn(async move { 5 }), spawn(async move { 6 })]
+            .into_iter()
+            .collect::<futures::stream::FuturesUnordered<_>>();
+        let mut results = futures::StreamExt::collect::<Vec<_>>(all)
+            .await
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        results.sort();
+        assert_eq!(results, [5, 6]);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_join_error_into_panic() {
+        assert!(JoinError::unknown_panic().is_panic());
+        assert!(JoinError::Aborted.is_cancelled());
+        let err = JoinError::Panic(Box::new("boom"));
+        assert_eq!(err.to_string(), "thread panicked: boom");
+        assert_eq!(err.into_panic().downcast_ref::<&str>(), Some(&"boom"));
+        assert_eq!(JoinError::unknown_panic().to_string(), "thread panicked");
+        assert!(matches!(
+            JoinError::Aborted.try_into_panic(),
+            Err(JoinError::Aborted)
+        ));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_panic_payload() {
+        let err = spawn(async move {
+            if true {
+                panic!("boom in task");
+            }
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(err.panic_message(), Some("boom in task"));
+        let err = spawn_blocking(|| -> u32 { panic!("boom in blocking task") })
+            .await
+            .unwrap_err();
+        assert_eq!(err.panic_message(), Some("boom in blocking task"));
+        // The broken workers are closed and unregistered.
+        sleep(Duration::from_millis(100)).await.unwrap();
+        assert_eq!(crate::runtime::live_workers(), 0);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_map_task() {
+        let handle = spawn(async move { 1 })
+            .map(|x| x + 1)
+            .map(|x| x.to_string());
+        assert_eq!(handle.join().await.unwrap(), "2");
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_and_then_task() {
+        let handle = spawn_local(async move { 1 })
+            .and_then(|x| async move {
+                sleep(Duration::from_millis(10)).await.unwrap();
+                x * 10
+            })
+            .map(|x| x + 1);
+        assert_eq!(handle.join().await.unwrap(), 11);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_abort_mapped_task() {
+        let mut handle = spawn_local(async move {
+            sleep(Duration::from_millis(100)).await.unwrap();
+            1
+        })
+        .map(|x| x + 1);
+        handle.abort();
+        assert!(handle.is_finished());
+        assert!(handle.join().await == Err(JoinError::Aborted));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_spawn_after() {
+        let start = Instant::now();
+        let handle = spawn_after(Duration::from_millis(100), async move { 1 });
+        assert_eq!(handle.join().await.unwrap(), 1);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        let ran = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let mut handle = spawn_after(Duration::from_millis(50), {
+            let ran = ran.clone();
+            async move { ran.store(true, Ordering::SeqCst) }
+        });
+        handle.abort();
+        sleep(Duration::from_millis(100)).await.unwrap();
+        assert!(!ran.load(Ordering::SeqCst));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_spawn_interval() {
+        let runs = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut handle = spawn_interval(Duration::from_millis(20), {
+            let runs = runs.clone();
+            move || {
+                runs.fetch_add(1, Ordering::SeqCst);
+                async {}
+            }
+        });
+        sleep(Duration::from_millis(110)).await.unwrap();
+        handle.abort();
+        let after_abort = runs.load(Ordering::SeqCst);
+        assert!(after_abort >= 3);
+        sleep(Duration::from_millis(60)).await.unwrap();
+        assert!(runs.load(Ordering::SeqCst) <= after_abort + 1);
+        assert!(handle.join().await == Err(JoinError::Aborted));
+    }
+
     #[wasm_bindgen_test]
     async fn test_abort_task() {
         let start = PERFORMANCE.now();
@@ -316,11 +1732,69 @@ mod tests {
         assert!(end - start < 1000.0);
     }
 
+    #[wasm_bindgen_test]
+    async fn test_abort_hard_task() {
+        let start = PERFORMANCE.now();
+        let mut handle = spawn(async move {
+            sleep_blocking(Duration::from_millis(1000));
+            1
+        });
+        sleep(Duration::from_millis(50)).await.unwrap();
+        assert!(handle.abort_hard());
+        assert!(handle.is_finished());
+        assert!(handle.join().await == Err(JoinError::Aborted));
+        assert!(PERFORMANCE.now() - start < 1000.0);
+        assert_eq!(crate::runtime::live_workers(), 0);
+
+        let mut handle = spawn_local(async move { 1 });
+        assert!(!handle.abort_hard());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_abort_hard_blocking_task() {
+        let start = PERFORMANCE.now();
+        let mut handle = spawn_blocking(|| sleep_blocking(Duration::from_millis(1000)));
+        sleep(Duration::from_millis(50)).await.unwrap();
+        assert!(handle.abort_hard());
+        assert!(handle.join().await == Err(JoinError::Aborted));
+        assert!(PERFORMANCE.now() - start < 1000.0);
+        assert_eq!(crate::runtime::live_workers(), 0);
+
+        let handle = spawn_blocking(|| sleep_blocking(Duration::from_millis(1000)));
+        assert!(handle.abort_after(Duration::from_millis(50)).await == Err(JoinError::Aborted));
+        assert!(PERFORMANCE.now() - start < 1000.0);
+        let handle = spawn_blocking(|| 1);
+        assert!(handle.abort_after(Duration::from_millis(1000)).await == Ok(1));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_abort_cancellable_blocking_task() {
+        let iterations = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut handle = spawn_blocking_cancellable({
+            let iterations = iterations.clone();
+            move |flag| {
+                while !flag.is_cancelled() {
+                    iterations.fetch_add(1, Ordering::SeqCst);
+                    sleep_blocking(Duration::from_millis(10));
+                }
+            }
+        });
+        sleep(Duration::from_millis(100)).await.unwrap();
+        handle.abort();
+        assert!(handle.is_finished());
+        assert!(handle.join().await == Err(JoinError::Aborted));
+        sleep(Duration::from_millis(50)).await.unwrap();
+        let stopped_at = iterations.load(Ordering::SeqCst);
+        assert!(stopped_at > 0);
+        sleep(Duration::from_millis(50)).await.unwrap();
+        assert_eq!(iterations.load(Ordering::SeqCst), stopped_at);
+    }
+
     #[wasm_bindgen_test]
     async fn test_abort_local_task() {
         let start = PERFORMANCE.now();
         let mut handle = spawn_local(async move {
-            sleep(Duration::from_millis(100)).await;
+            sleep(Duration::from_millis(100)).await.unwrap();
             1
         });
         assert!(!handle.is_finished());
@@ -399,7 +1873,7 @@ mod tests {
         let start = PERFORMANCE.now();
         let handle = spawn(async move {
             let mut handle = spawn_local(async move {
-                sleep(Duration::from_millis(1000)).await;
+                sleep(Duration::from_millis(1000)).await.unwrap();
                 1
             });
             assert!(!handle.is_finished());
@@ -415,12 +1889,37 @@ mod tests {
         assert!(end - start < 1000.0);
     }
 
+    #[wasm_bindgen_test]
+    async fn test_supervise_local_task_from_worker() {
+        fn assert_send<T: Send>(_: &T) {}
+
+        let start = PERFORMANCE.now();
+        let local = spawn_local(async move { 1 });
+        let mut stuck = spawn_local(async move {
+            sleep(Duration::from_millis(1000)).await.unwrap();
+            2
+        });
+        assert_send(&local);
+        assert_send(&stuck);
+        let handle = spawn_blocking(move || {
+            stuck.abort();
+            futures::executor::block_on(async move {
+                (
+                    local.join().await,
+                    stuck.join().await == Err(JoinError::Aborted),
+                )
+            })
+        });
+        assert_eq!(handle.join().await.unwrap(), (Ok(1), true));
+        assert!(PERFORMANCE.now() - start < 1000.0);
+    }
+
     #[wasm_bindgen_test]
     async fn test_abort_local_task_in_local_task() {
         let start = PERFORMANCE.now();
         let mut handle = spawn_local(async move {
             let handle = spawn_local(async move {
-                sleep(Duration::from_millis(1000)).await;
+                sleep(Duration::from_millis(1000)).await.unwrap();
                 1
             });
             handle.join().await.unwrap()
@@ -440,7 +1939,7 @@ mod tests {
         let handle = spawn_blocking(|| {
             futures::executor::block_on(async move {
                 let mut handle = spawn_local(async move {
-                    sleep(Duration::from_millis(1000)).await;
+                    sleep(Duration::from_millis(1000)).await.unwrap();
                     1
                 });
                 assert!(!handle.is_finished());
@@ -455,4 +1954,54 @@ mod tests {
         let end = PERFORMANCE.now();
         assert!(end - start < 1000.0);
     }
+
+    #[wasm_bindgen_test]
+    async fn test_task_states() {
+        use futures::StreamExt;
+
+        let handle = spawn_blocking(|| sleep_blocking(Duration::from_millis(50)));
+        let changes = handle.state_changes();
+        let states = changes.collect::<Vec<_>>();
+        let (states, result) = futures::join!(states, handle.join());
+        assert_eq!(result, Ok(()));
+        assert_eq!(states.last(), Some(&TaskState::Finished));
+        assert!(states
+            .iter()
+            .any(|state| matches!(state, TaskState::Running { worker_id: Some(_) })));
+
+        // Reported with the id of the worker running the task, or none on the main thread.
+        let (handle, worker) = spawn_with_worker(async {
+            sleep(Duration::from_millis(50)).await.unwrap();
+        });
+        let mut changes = Box::pin(handle.state_changes())
+            .filter(|state| futures::future::ready(*state != TaskState::Queued));
+        assert_eq!(
+            changes.next().await,
+            Some(TaskState::Running {
+                worker_id: Some(worker.id())
+            })
+        );
+        handle.await.unwrap();
+        let handle = spawn_local(async {
+            sleep(Duration::from_millis(50)).await.unwrap();
+        });
+        sleep(Duration::from_millis(10)).await.unwrap();
+        assert_eq!(handle.state(), TaskState::Running { worker_id: None });
+        handle.await.unwrap();
+
+        let handle = spawn(async { panic!("boom") });
+        let states = handle.state_changes().collect::<Vec<_>>().await;
+        assert_eq!(states.last(), Some(&TaskState::Panicked));
+        assert_eq!(handle.state(), TaskState::Panicked);
+
+        let mut handle = spawn_local(async {
+            sleep(Duration::from_millis(1000)).await.unwrap();
+        });
+        handle.abort();
+        assert_eq!(handle.state(), TaskState::Aborted);
+        assert_eq!(
+            handle.state_changes().collect::<Vec<_>>().await,
+            [TaskState::Aborted]
+        );
+    }
 }
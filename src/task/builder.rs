@@ -0,0 +1,89 @@
+use std::future::Future;
+
+use super::{blocking, r#async, try_spawn_blocking_named, try_spawn_named, SpawnError};
+
+/// Configures a task before spawning it.
+///
+/// ```ignore
+/// let handle = task::Builder::new().name("image-decoder").spawn(decode(bytes))?;
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Builder {
+    name: Option<String>,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Builder::default()
+    }
+
+    /// Names the worker of the task, so that it is listed under that name in the
+    /// browser's thread and debugger panels instead of as an anonymous "Worker". The
+    /// worker also sees it as `self.name`.
+    ///
+    /// Named tasks always get a dedicated worker, even when the runtime has
+    /// [`shared_async_workers`](crate::runtime::Builder::shared_async_workers).
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Spawns `future` like [`try_spawn`](super::try_spawn).
+    #[track_caller]
+    pub fn spawn<F>(self, future: F) -> Result<r#async::JoinHandle<F::Output>, SpawnError>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        try_spawn_named(self.name, future)
+    }
+
+    /// Spawns `f` like [`try_spawn_blocking`](super::try_spawn_blocking).
+    #[track_caller]
+    pub fn spawn_blocking<T>(
+        self,
+        f: impl FnOnce() -> T + 'static,
+    ) -> Result<blocking::JoinHandle<T>, SpawnError>
+    where
+        T: 'static,
+    {
+        try_spawn_blocking_named(self.name, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn worker_name() -> Option<String> {
+        js_sys::Reflect::get(&js_sys::global(), &"name".into())
+            .ok()?
+            .as_string()
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_named_workers() {
+        // Named tasks don't go to the shared workers.
+        let config = runtime::Builder::new()
+            .shared_async_workers(1)
+            .build_for_test();
+        let name = Builder::new()
+            .name("image-decoder")
+            .spawn(async { worker_name() })
+            .unwrap();
+        assert_eq!(name.await.unwrap().as_deref(), Some("image-decoder"));
+        drop(config);
+        let name = Builder::new()
+            .name("blocking-decoder")
+            .spawn_blocking(worker_name)
+            .unwrap();
+        assert_eq!(name.await.unwrap().as_deref(), Some("blocking-decoder"));
+        let unnamed = Builder::new().spawn_blocking(worker_name).unwrap();
+        assert_eq!(unnamed.await.unwrap().as_deref(), Some(""));
+    }
+}
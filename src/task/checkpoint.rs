@@ -0,0 +1,162 @@
+use std::sync::{Arc, Mutex};
+
+use futures::future::LocalBoxFuture;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use wasm_bindgen::prelude::JsValue;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+use crate::Error;
+
+/// Where [`checkpoint`] persists the progress of tasks, as JSON strings keyed by the
+/// names given by the tasks.
+pub trait CheckpointStore {
+    fn save(&self, key: &str, value: String) -> LocalBoxFuture<'static, Result<(), Error>>;
+    fn load(&self, key: &str) -> LocalBoxFuture<'static, Result<Option<String>, Error>>;
+    fn remove(&self, key: &str) -> LocalBoxFuture<'static, Result<(), Error>>;
+}
+
+type Store = Arc<dyn CheckpointStore + Send + Sync>;
+
+static STORE: Mutex<Option<Store>> = Mutex::new(None);
+
+/// Replaces the store used by every thread, [`IndexedDbStore`] by default.
+pub fn set_checkpoint_store(store: impl CheckpointStore + Send + Sync + 'static) {
+    *STORE.lock().unwrap() = Some(Arc::new(store));
+}
+
+fn store() -> Store {
+    STORE
+        .lock()
+        .unwrap()
+        .get_or_insert_with(|| Arc::new(IndexedDbStore))
+        .clone()
+}
+
+/// Persists `state` as the progress of the task called `key`, replacing the previous
+/// checkpoint, so that the task can pick up from there with [`resume`] after a reload.
+///
+/// ```ignore
+/// let mut next = task::resume::<usize>("import").await?.unwrap_or(0);
+/// while next < rows.len() {
+///     import(&rows[next..next + 100]).await;
+///     next += 100;
+///     task::checkpoint("import", &next).await?;
+/// }
+/// task::clear_checkpoint("import").await?;
+/// ```
+pub async fn checkpoint<T: Serialize>(key: &str, state: &T) -> Result<(), Error> {
+    let json = serde_json::to_string(state).map_err(std::io::Error::from)?;
+    store().save(key, json).await
+}
+
+/// Returns the last state checkpointed under `key`, if any.
+pub async fn resume<T: DeserializeOwned>(key: &str) -> Result<Option<T>, Error> {
+    match store().load(key).await? {
+        Some(json) => Ok(Some(
+            serde_json::from_str(&json).map_err(std::io::Error::from)?,
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Deletes the checkpoint of `key`, typically once the task is done.
+pub async fn clear_checkpoint(key: &str) -> Result<(), Error> {
+    store().remove(key).await
+}
+
+/// Stores checkpoints in the `checkpoints` object store of the `wasmt` IndexedDB
+/// database of the origin, which is reachable from the main thread and workers alike.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IndexedDbStore;
+
+impl IndexedDbStore {
+    async fn request(op: &str, key: &str, value: JsValue) -> Result<JsValue, Error> {
+        let promise = INDEXED_DB
+            .with(|request| request.call3(&JsValue::UNDEFINED, &op.into(), &key.into(), &value))?;
+        Ok(JsFuture::from(promise.unchecked_into::<js_sys::Promise>()).await?)
+    }
+}
+
+impl CheckpointStore for IndexedDbStore {
+    fn save(&self, key: &str, value: String) -> LocalBoxFuture<'static, Result<(), Error>> {
+        let key = key.to_owned();
+        Box::pin(async move {
+            Self::request("save", &key, value.into()).await?;
+            Ok(())
+        })
+    }
+
+    fn load(&self, key: &str) -> LocalBoxFuture<'static, Result<Option<String>, Error>> {
+        let key = key.to_owned();
+        Box::pin(async move {
+            Ok(Self::request("load", &key, JsValue::UNDEFINED)
+                .await?
+                .as_string())
+        })
+    }
+
+    fn remove(&self, key: &str) -> LocalBoxFuture<'static, Result<(), Error>> {
+        let key = key.to_owned();
+        Box::pin(async move {
+            Self::request("remove", &key, JsValue::UNDEFINED).await?;
+            Ok(())
+        })
+    }
+}
+
+thread_local! {
+    // `request(op, key, value)`, running a single-request transaction and resolving with
+    // the loaded value, if any.
+    static INDEXED_DB: js_sys::Function = js_sys::Function::new_with_args(
+        "op, key, value",
+        "
+        return new Promise((resolve, reject) => {
+            const open = indexedDB.open('wasmt', 1);
+            open.onupgradeneeded = () => open.result.createObjectStore('checkpoints');
+            open.onerror = () => reject(open.error);
+            open.onsuccess = () => {
+                const db = open.result;
+                const tx = db.transaction('checkpoints', op === 'load' ? 'readonly' : 'readwrite');
+                const store = tx.objectStore('checkpoints');
+                const request = op === 'load'
+                    ? store.get(key)
+                    : op === 'save' ? store.put(value, key) : store.delete(key);
+                tx.oncomplete = () => {
+                    db.close();
+                    resolve(op === 'load' ? request.result : undefined);
+                };
+                tx.onerror = () => {
+                    db.close();
+                    reject(tx.error);
+                };
+            };
+        });
+        ",
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_checkpoint_resume() {
+        clear_checkpoint("test").await.unwrap();
+        assert_eq!(resume::<Vec<u32>>("test").await.unwrap(), None);
+        let handle = task::spawn(async move {
+            checkpoint("test", &vec![1, 2]).await.unwrap();
+        });
+        handle.join().await.unwrap();
+        assert_eq!(resume::<Vec<u32>>("test").await.unwrap(), Some(vec![1, 2]));
+        assert!(resume::<String>("test").await.is_err());
+        clear_checkpoint("test").await.unwrap();
+        assert_eq!(resume::<Vec<u32>>("test").await.unwrap(), None);
+    }
+}
@@ -0,0 +1,156 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use super::{blocking, r#async, JoinError};
+
+/// Handles whose task can be aborted, whatever its kind, e.g. by
+/// [`try_join_handles!`](crate::try_join_handles).
+pub trait Abort {
+    fn abort(&mut self);
+}
+
+impl<T> Abort for r#async::JoinHandle<T> {
+    fn abort(&mut self) {
+        r#async::JoinHandle::abort(self);
+    }
+}
+
+impl<T> Abort for blocking::JoinHandle<T> {
+    fn abort(&mut self) {
+        blocking::JoinHandle::abort(self);
+    }
+}
+
+impl<T: 'static, U: 'static> Abort for r#async::MappedJoinHandle<T, U> {
+    fn abort(&mut self) {
+        r#async::MappedJoinHandle::abort(self);
+    }
+}
+
+/// Joins a handle, aborting its task if dropped before the task completed, e.g. because
+/// a sibling of a [`try_join_handles!`](crate::try_join_handles) failed.
+#[derive(Debug)]
+pub struct AbortOnDrop<H: Abort> {
+    handle: H,
+    done: bool,
+}
+
+impl<H: Abort> AbortOnDrop<H> {
+    pub fn new(handle: H) -> Self {
+        AbortOnDrop {
+            handle,
+            done: false,
+        }
+    }
+}
+
+impl<H, T> Future for AbortOnDrop<H>
+where
+    H: Abort + Future<Output = Result<T, JoinError>> + Unpin,
+{
+    type Output = Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let result = Pin::new(&mut self.handle).poll(cx);
+        if result.is_ready() {
+            self.done = true;
+        }
+        result
+    }
+}
+
+impl<H: Abort> Drop for AbortOnDrop<H> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.handle.abort();
+        }
+    }
+}
+
+/// Waits for every handle, of any kind, and returns the tuple of their results, like
+/// `futures::join!`.
+///
+/// ```ignore
+/// let (decoded, thumbnail) = join_handles!(task::spawn(decode(bytes)), task::spawn_blocking(thumb));
+/// ```
+#[macro_export]
+macro_rules! join_handles {
+    ($($handle:expr),+ $(,)?) => {
+        $crate::__private::futures::join!($($handle),+)
+    };
+}
+
+/// Waits for every handle, of any kind, and returns the tuple of their outputs, or the
+/// first [`JoinError`](crate::task::JoinError), in which case the tasks still running are
+/// aborted, unlike with `futures::try_join!`, which would leave them running detached.
+///
+/// ```ignore
+/// let (left, right) = try_join_handles!(task::spawn(left), task::spawn(right))?;
+/// ```
+#[macro_export]
+macro_rules! try_join_handles {
+    ($($handle:expr),+ $(,)?) => {
+        $crate::__private::futures::try_join!(
+            $($crate::task::AbortOnDrop::new($handle)),+
+        )
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::task::{spawn, spawn_blocking, spawn_local, JoinError};
+    use crate::time::{sleep, sleep_blocking};
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_join_handles_of_several_kinds() {
+        let (a, b, c) = join_handles!(
+            spawn(async { 1 }),
+            spawn_blocking(|| "two"),
+            spawn_local(async { panic!("three") }),
+        );
+        assert_eq!(a, Ok(1));
+        assert_eq!(b, Ok("two"));
+        assert!(matches!(c, Err(JoinError::Panic(_))));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_try_join_handles_aborts_siblings() {
+        struct SetOnDrop(Arc<AtomicBool>);
+
+        impl Drop for SetOnDrop {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let dropped = Arc::new(AtomicBool::new(false));
+        let slow = spawn({
+            let guard = SetOnDrop(dropped.clone());
+            async move {
+                let _guard = guard;
+                sleep(Duration::from_secs(10)).await.unwrap();
+            }
+        });
+        let result = try_join_handles!(
+            slow,
+            spawn_blocking(|| {
+                sleep_blocking(Duration::from_millis(10));
+                panic!("boom")
+            }),
+        );
+        assert!(matches!(result, Err(JoinError::Panic(_))));
+        sleep(Duration::from_millis(50)).await.unwrap();
+        assert!(dropped.load(Ordering::SeqCst));
+        let ok = try_join_handles!(spawn(async { 1 }), spawn_local(async { 2 }));
+        assert_eq!(ok, Ok((1, 2)));
+    }
+}
@@ -0,0 +1,154 @@
+use std::future::Future;
+use std::task::Poll;
+
+use futures::stream::{FuturesOrdered, Stream};
+
+use super::{r#async::JoinHandle, spawn, spawn_local, JoinError};
+
+/// A collection of tasks spawned on workers or locally.
+pub struct JoinSet<T> {
+    handles: Vec<JoinHandle<T>>,
+}
+
+impl<T: 'static> JoinSet<T> {
+    pub fn new() -> Self {
+        JoinSet {
+            handles: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    #[track_caller]
+    pub fn spawn<F>(&mut self, future: F)
+    where
+        F: Future<Output = T> + 'static,
+    {
+        self.handles.push(spawn(future));
+    }
+
+    #[track_caller]
+    pub fn spawn_local<F>(&mut self, future: F)
+    where
+        F: Future<Output = T> + 'static,
+    {
+        self.handles.push(spawn_local(future));
+    }
+
+    /// Waits for any of the tasks to complete and returns its output, or `None` if the set
+    /// is empty. The task is removed from the set.
+    pub async fn join_next(&mut self) -> Option<Result<T, JoinError>> {
+        futures::future::poll_fn(|cx| {
+            if self.handles.is_empty() {
+                return Poll::Ready(None);
+            }
+            for i in 0..self.handles.len() {
+                if let Poll::Ready(result) = self.handles[i].poll_join(cx) {
+                    self.handles.swap_remove(i);
+                    return Poll::Ready(Some(result));
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Aborts every task of the set. They stay in the set, and are returned by
+    /// [`join_next`](Self::join_next) with [`JoinError::Aborted`] unless they completed
+    /// before being aborted.
+    pub fn abort_all(&mut self) {
+        for handle in &mut self.handles {
+            handle.abort();
+        }
+    }
+
+    /// Yields the output of every task in the order they were spawned.
+    ///
+    /// Tasks keep running in parallel: outputs of tasks completing before the ones spawned
+    /// earlier are buffered until their turn comes.
+    pub fn join_all_ordered(self) -> impl Stream<Item = Result<T, JoinError>> {
+        self.handles
+            .into_iter()
+            .map(JoinHandle::join)
+            .collect::<FuturesOrdered<_>>()
+    }
+}
+
+impl<T: 'static> Default for JoinSet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::time::sleep;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_join_all_ordered() {
+        let mut set = JoinSet::new();
+        for i in 0..4u64 {
+            set.spawn(async move {
+                sleep(Duration::from_millis(100 - i * 25)).await.unwrap();
+                i
+            });
+        }
+        set.spawn_local(async move { 4 });
+        assert_eq!(set.len(), 5);
+        let results = set
+            .join_all_ordered()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(results, [0, 1, 2, 3, 4]);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_join_next() {
+        let mut set = JoinSet::new();
+        for i in 0..3u64 {
+            set.spawn(async move {
+                sleep(Duration::from_millis(90 - i * 30)).await.unwrap();
+                i
+            });
+        }
+        let mut results = Vec::new();
+        while let Some(result) = set.join_next().await {
+            results.push(result.unwrap());
+            assert_eq!(set.len(), 3 - results.len());
+        }
+        assert_eq!(results, [2, 1, 0]);
+        assert!(set.join_next().await.is_none());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_abort_all() {
+        let mut set = JoinSet::new();
+        for _ in 0..3 {
+            set.spawn_local(async move {
+                sleep(Duration::from_millis(100)).await.unwrap();
+            });
+        }
+        set.abort_all();
+        assert_eq!(set.len(), 3);
+        while let Some(result) = set.join_next().await {
+            assert_eq!(result, Err(JoinError::Aborted));
+        }
+        assert!(set.is_empty());
+    }
+}
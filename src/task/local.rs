@@ -0,0 +1,298 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::Context;
+
+use futures::task::{waker_ref, ArcWake};
+use wasm_bindgen::prelude::{Closure, JsValue};
+
+use super::schedule::{turn, Schedule};
+use crate::runtime::{self, metrics};
+use crate::sync::futex::Futex;
+use crate::time::Instant;
+
+type LocalTask = Pin<Box<dyn Future<Output = ()>>>;
+
+// The executor of `spawn_local`. The tasks of a thread are polled in runs, each scheduled
+// on the microtask queue by the first wake-up after the previous run, and polling the
+// tasks woken before it started: a task waking itself is polled again by the next run,
+// after the other tasks.
+//
+// Wake-ups go through the inbox of the thread owning the task. Those coming from other
+// threads also notify the inbox's futex, which a listener task of the owning thread waits
+// on with `Atomics.waitAsync` while the thread has tasks, and which schedules a run once
+// woken. Where `waitAsync` isn't supported, the listener checks the inbox on every turn of
+// the event loop instead.
+struct Executor {
+    tasks: RefCell<HashMap<u64, Task>>,
+    next_id: Cell<u64>,
+    inbox: Arc<Inbox>,
+    // Whether a run is scheduled or in progress.
+    scheduled: Cell<bool>,
+    listener: Cell<Option<u64>>,
+    run: Closure<dyn FnMut(JsValue)>,
+}
+
+struct Task {
+    future: LocalTask,
+    waker: Arc<TaskWaker>,
+}
+
+struct Inbox {
+    ready: Mutex<Vec<u64>>,
+    futex: Futex,
+}
+
+struct TaskWaker {
+    id: u64,
+    // Whether the task is in the inbox, so that it is queued once per run.
+    queued: AtomicBool,
+    inbox: Arc<Inbox>,
+}
+
+thread_local! {
+    static EXECUTOR: Executor = Executor {
+        tasks: RefCell::new(HashMap::new()),
+        next_id: Cell::new(0),
+        inbox: Arc::new(Inbox {
+            ready: Mutex::new(Vec::new()),
+            futex: Futex::new(),
+        }),
+        scheduled: Cell::new(false),
+        listener: Cell::new(None),
+        run: Closure::new(|_| run()),
+    };
+}
+
+impl ArcWake for TaskWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        if arc_self.queued.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        arc_self.inbox.ready.lock().unwrap().push(arc_self.id);
+        let local = EXECUTOR
+            .try_with(|executor| {
+                let local = Arc::ptr_eq(&executor.inbox, &arc_self.inbox);
+                if local {
+                    executor.schedule(Schedule::Microtask);
+                }
+                local
+            })
+            .unwrap_or(false);
+        if !local {
+            arc_self.inbox.futex.notify();
+        }
+    }
+}
+
+/// Runs `future` on the current thread, polling it from the microtask queue.
+pub(crate) fn spawn(future: impl Future<Output = ()> + 'static) {
+    EXECUTOR.with(|executor| {
+        executor.insert(Box::pin(future));
+        if executor.listener.get().is_none() {
+            let listener = executor.insert(Box::pin(listen(executor.inbox.clone())));
+            executor.listener.set(Some(listener));
+        }
+    });
+}
+
+impl Executor {
+    fn insert(&self, future: LocalTask) -> u64 {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        let waker = Arc::new(TaskWaker {
+            id,
+            queued: AtomicBool::new(false),
+            inbox: self.inbox.clone(),
+        });
+        self.tasks.borrow_mut().insert(
+            id,
+            Task {
+                future,
+                waker: waker.clone(),
+            },
+        );
+        waker.wake();
+        id
+    }
+
+    fn schedule(&self, schedule: Schedule) {
+        if self.scheduled.replace(true) {
+            return;
+        }
+        let promise = match schedule {
+            Schedule::Microtask => js_sys::Promise::resolve(&JsValue::UNDEFINED),
+            schedule => turn(schedule),
+        };
+        let _ = promise.then(&self.run);
+    }
+
+    // Polls the task, unless it already completed. Taken out of the map meanwhile, so
+    // that it can spawn tasks.
+    fn poll(&self, id: u64) {
+        let Some(mut task) = self.tasks.borrow_mut().remove(&id) else {
+            return;
+        };
+        task.waker.queued.store(false, Ordering::Release);
+        let waker = waker_ref(&task.waker);
+        let started = Instant::performance_now();
+        let poll = task.future.as_mut().poll(&mut Context::from_waker(&waker));
+        if self.listener.get() != Some(id) {
+            metrics::record_local_poll(Instant::performance_now().duration_since(started));
+        }
+        if poll.is_pending() {
+            self.tasks.borrow_mut().insert(id, task);
+        }
+    }
+}
+
+fn run() {
+    EXECUTOR.with(|executor| {
+        let budget = runtime::CONFIG.lock().unwrap().local_budget;
+        let started = Instant::performance_now();
+        let ready = std::mem::take(&mut *executor.inbox.ready.lock().unwrap());
+        let mut ready = ready.into_iter();
+        let mut over_budget = false;
+        while let Some(id) = ready.next() {
+            executor.poll(id);
+            let elapsed = Instant::performance_now().duration_since(started);
+            if budget.is_some_and(|budget| elapsed >= budget) {
+                // The remaining tasks go first in the next run.
+                executor.inbox.ready.lock().unwrap().splice(0..0, ready);
+                over_budget = true;
+                break;
+            }
+        }
+        executor.scheduled.set(false);
+        if !executor.inbox.ready.lock().unwrap().is_empty() {
+            executor.schedule(if over_budget {
+                Schedule::Macrotask
+            } else {
+                Schedule::Microtask
+            });
+        }
+    });
+}
+
+// Waits for wake-ups from other threads, until the thread has no other tasks. The tasks
+// they queued are polled by the run that polls the listener, or by the next one.
+async fn listen(inbox: Arc<Inbox>) {
+    loop {
+        let seen = inbox.futex.seq();
+        let idle = EXECUTOR.with(|executor| {
+            let idle = executor.tasks.borrow().is_empty();
+            if idle {
+                executor.listener.set(None);
+            }
+            idle
+        });
+        if idle {
+            return;
+        }
+        inbox.futex.wait_async(seen).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::task::{self, spawn_local};
+    use crate::time::{sleep, Instant};
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    async fn yield_now() {
+        let mut yielded = false;
+        futures::future::poll_fn(|cx| {
+            if yielded {
+                std::task::Poll::Ready(())
+            } else {
+                yielded = true;
+                cx.waker().wake_by_ref();
+                std::task::Poll::Pending
+            }
+        })
+        .await
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_self_waking_tasks_take_turns() {
+        let order = Rc::new(RefCell::new(Vec::new()));
+        let tasks = (0..3)
+            .map(|i| {
+                let order = order.clone();
+                spawn_local(async move {
+                    for _ in 0..3 {
+                        order.borrow_mut().push(i);
+                        yield_now().await;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(*order.borrow(), [0, 1, 2, 0, 1, 2, 0, 1, 2]);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_budget_yields_to_event_loop() {
+        let _config = runtime::Builder::new()
+            .local_budget(Duration::from_millis(2))
+            .build_for_test();
+        let timer_fired = Rc::new(Cell::new(false));
+        let timer = spawn_local({
+            let timer_fired = timer_fired.clone();
+            async move {
+                sleep(Duration::ZERO).await.unwrap();
+                timer_fired.set(true);
+            }
+        });
+        let busy = spawn_local({
+            let timer_fired = timer_fired.clone();
+            async move {
+                // Without a budget, the microtask queue would never drain and the timer
+                // would never fire.
+                while !timer_fired.get() {
+                    let start = Instant::performance_now();
+                    while Instant::performance_now().duration_since(start)
+                        < Duration::from_millis(1)
+                    {}
+                    yield_now().await;
+                }
+            }
+        });
+        busy.await.unwrap();
+        timer.await.unwrap();
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_woken_from_workers() {
+        runtime::reset_metrics();
+        let (tx, mut rx) = futures::channel::mpsc::unbounded();
+        let receiver = spawn_local(async move {
+            let mut sum = 0;
+            while let Some(value) = futures::StreamExt::next(&mut rx).await {
+                sum += value;
+            }
+            sum
+        });
+        let sender = task::spawn_blocking(move || {
+            for i in 0..100 {
+                tx.unbounded_send(i).unwrap();
+                crate::time::sleep_blocking(Duration::from_micros(100));
+            }
+        });
+        sender.join().await.unwrap();
+        assert_eq!(receiver.await.unwrap(), 4950);
+        assert!(runtime::metrics().local_poll_time.count() > 0);
+    }
+}
@@ -0,0 +1,215 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::Context;
+
+use futures::future::{AbortHandle, Abortable};
+use futures::task::{waker, ArcWake};
+
+use super::r#async::JoinHandle;
+use super::state::{Header, TaskState};
+use super::tree::{self, Registration};
+
+type LocalTask = Pin<Box<dyn Future<Output = ()>>>;
+
+/// A set of local tasks that only make progress when explicitly driven.
+///
+/// Unlike [`spawn_local`](super::spawn_local), tasks spawned here are never polled by
+/// the browser's event loop: each call to [`poll_once`](LocalSet::poll_once) polls the
+/// tasks woken since the previous call exactly once, which lets a game loop interleave
+/// task progress with rendering deterministically, e.g. from a `requestAnimationFrame`
+/// callback.
+pub struct LocalSet {
+    tasks: RefCell<HashMap<u64, LocalTask>>,
+    ready: Arc<Mutex<Vec<u64>>>,
+    next_id: RefCell<u64>,
+}
+
+struct TaskWaker {
+    id: u64,
+    ready: Arc<Mutex<Vec<u64>>>,
+}
+
+impl ArcWake for TaskWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        let mut ready = arc_self.ready.lock().unwrap();
+        if !ready.contains(&arc_self.id) {
+            ready.push(arc_self.id);
+        }
+    }
+}
+
+impl LocalSet {
+    pub fn new() -> Self {
+        LocalSet {
+            tasks: RefCell::new(HashMap::new()),
+            ready: Arc::new(Mutex::new(Vec::new())),
+            next_id: RefCell::new(0),
+        }
+    }
+
+    #[track_caller]
+    pub fn spawn_local<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        let (tx, rx) = futures::channel::oneshot::channel();
+        let task = Registration::new(super::try_id());
+        let task_id = task.id();
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        tree::set_cancel(task_id, tree::Cancel::Abort(abort_handle.clone()));
+        let abortable_future = Abortable::new(future, abort_registration);
+        let id = {
+            let mut next_id = self.next_id.borrow_mut();
+            *next_id += 1;
+            *next_id
+        };
+        let header = Header::new();
+        self.tasks.borrow_mut().insert(
+            id,
+            Box::pin({
+                let header = header.clone();
+                async move {
+                    let _task = task;
+                    header.started();
+                    match abortable_future.await {
+                        Ok(result) => {
+                            header.set(TaskState::Finished);
+                            tx.send(Ok(result)).ok();
+                        }
+                        Err(_) => header.set(TaskState::Aborted),
+                    }
+                }
+            }),
+        );
+        self.ready.lock().unwrap().push(id);
+        JoinHandle {
+            abort_handle,
+            aborted: false,
+            rx,
+            id: task_id,
+            header,
+            worker: None,
+            #[cfg(feature = "spawn-location")]
+            location: std::panic::Location::caller(),
+        }
+    }
+
+    /// Polls every task woken since the last call once, without waiting for anything.
+    ///
+    /// Tasks woken while this runs are polled on the next call. Returns the number of
+    /// tasks that were polled.
+    pub fn poll_once(&self) -> usize {
+        let ready = std::mem::take(&mut *self.ready.lock().unwrap());
+        let mut polled = 0;
+        for id in ready {
+            // Taken out of the map so that tasks can spawn into the set while being polled.
+            let Some(mut task) = self.tasks.borrow_mut().remove(&id) else {
+                continue;
+            };
+            let waker = waker(Arc::new(TaskWaker {
+                id,
+                ready: self.ready.clone(),
+            }));
+            polled += 1;
+            if task
+                .as_mut()
+                .poll(&mut Context::from_waker(&waker))
+                .is_pending()
+            {
+                self.tasks.borrow_mut().insert(id, task);
+            }
+        }
+        polled
+    }
+
+    /// Number of tasks that haven't completed yet.
+    pub fn len(&self) -> usize {
+        self.tasks.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.borrow().is_empty()
+    }
+
+    /// Whether some tasks were woken and are waiting for the next [`poll_once`](Self::poll_once).
+    pub fn has_ready_tasks(&self) -> bool {
+        !self.ready.lock().unwrap().is_empty()
+    }
+}
+
+impl Default for LocalSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::task::Poll;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::time::sleep;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    async fn yield_now() {
+        let mut yielded = false;
+        futures::future::poll_fn(|cx| {
+            if yielded {
+                Poll::Ready(())
+            } else {
+                yielded = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    #[wasm_bindgen_test]
+    fn test_poll_once_steps_tasks() {
+        let set = LocalSet::new();
+        let steps = Rc::new(Cell::new(0));
+        let _handle = set.spawn_local({
+            let steps = steps.clone();
+            async move {
+                for _ in 0..3 {
+                    steps.set(steps.get() + 1);
+                    yield_now().await;
+                }
+            }
+        });
+        assert_eq!(steps.get(), 0);
+        for expected in 1..=3 {
+            assert_eq!(set.poll_once(), 1);
+            assert_eq!(steps.get(), expected);
+        }
+        assert_eq!(set.poll_once(), 1);
+        assert!(set.is_empty());
+        assert_eq!(set.poll_once(), 0);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_poll_once_with_timer() {
+        let set = LocalSet::new();
+        let handle = set.spawn_local(async move {
+            sleep(Duration::from_millis(50)).await.unwrap();
+            1
+        });
+        assert_eq!(set.poll_once(), 1);
+        assert!(!set.has_ready_tasks());
+        sleep(Duration::from_millis(100)).await.unwrap();
+        assert!(set.has_ready_tasks());
+        assert_eq!(set.poll_once(), 1);
+        assert_eq!(handle.join().await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,143 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+use crate::sync::Semaphore;
+use crate::time::Instant;
+
+static CACHE: LazyLock<Mutex<HashMap<String, Arc<dyn Any + Send + Sync>>>> =
+    LazyLock::new(Default::default);
+
+struct Entry<T> {
+    lock: Semaphore,
+    value: Mutex<Option<(T, Instant)>>,
+}
+
+impl<T: Clone> Entry<T> {
+    fn get(&self) -> Option<T> {
+        match &*self.value.lock().unwrap() {
+            Some((value, expires_at)) if Instant::now() < *expires_at => Some(value.clone()),
+            _ => None,
+        }
+    }
+}
+
+fn entry<T: Send + 'static>(key: String) -> Arc<Entry<T>> {
+    let mut cache = CACHE.lock().unwrap();
+    let slot = cache.entry(key).or_insert_with(|| {
+        Arc::new(Entry::<T> {
+            lock: Semaphore::new(1),
+            value: Mutex::new(None),
+        })
+    });
+    match slot.clone().downcast::<Entry<T>>() {
+        Ok(entry) => entry,
+        Err(_) => {
+            // The key was previously used with another output type: start over.
+            let entry = Arc::new(Entry::<T> {
+                lock: Semaphore::new(1),
+                value: Mutex::new(None),
+            });
+            *slot = entry.clone();
+            entry
+        }
+    }
+}
+
+/// Deduplicates the computation of `future` under `key`, on every worker.
+///
+/// Concurrent calls with the same key wait for a single execution and share its output,
+/// which is then cached for `ttl`. If the call running the computation is cancelled, one
+/// of the waiting calls takes over with its own `future`.
+pub async fn memo<T, F>(key: impl Into<String>, ttl: Duration, future: F) -> T
+where
+    T: Clone + Send + 'static,
+    F: Future<Output = T>,
+{
+    let entry = entry::<T>(key.into());
+    if let Some(value) = entry.get() {
+        return value;
+    }
+    let _permit = entry.lock.acquire().await;
+    if let Some(value) = entry.get() {
+        return value;
+    }
+    let value = future.await;
+    *entry.value.lock().unwrap() = Some((value.clone(), Instant::now() + ttl));
+    value
+}
+
+/// Drops the cached output for `key`, if any, so the next [`memo`] call recomputes it.
+pub fn invalidate_memo(key: &str) {
+    CACHE.lock().unwrap().remove(key);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::task;
+    use crate::time::sleep;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+    async fn expensive() -> u32 {
+        RUNS.fetch_add(1, Ordering::SeqCst);
+        sleep(Duration::from_millis(100)).await.unwrap();
+        42
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_memo_shares_execution() {
+        RUNS.store(0, Ordering::SeqCst);
+        let handles = (0..3)
+            .map(|_| task::spawn(memo("shared", Duration::from_secs(10), expensive())))
+            .collect::<Vec<_>>();
+        for handle in handles {
+            assert_eq!(handle.join().await.unwrap(), 42);
+        }
+        assert_eq!(
+            memo("shared", Duration::from_secs(10), expensive()).await,
+            42
+        );
+        assert_eq!(RUNS.load(Ordering::SeqCst), 1);
+        invalidate_memo("shared");
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_memo_expires() {
+        assert_eq!(
+            memo("expiring", Duration::from_millis(50), async { 1 }).await,
+            1
+        );
+        assert_eq!(
+            memo("expiring", Duration::from_millis(50), async { 2 }).await,
+            1
+        );
+        sleep(Duration::from_millis(100)).await.unwrap();
+        assert_eq!(
+            memo("expiring", Duration::from_millis(50), async { 3 }).await,
+            3
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_invalidate_memo() {
+        assert_eq!(
+            memo("invalidated", Duration::from_secs(10), async { 1 }).await,
+            1
+        );
+        invalidate_memo("invalidated");
+        assert_eq!(
+            memo("invalidated", Duration::from_secs(10), async { 2 }).await,
+            2
+        );
+    }
+}
@@ -0,0 +1,261 @@
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+#[cfg(feature = "spawn-location")]
+use std::panic::Location;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::ptr;
+use std::sync::{Arc, Mutex, Once};
+use std::task::{Context, Poll};
+
+use futures::channel::oneshot;
+
+use super::state::{Header, TaskState};
+use super::tree::{self, Id, Registration};
+use crate::runtime::{self, PanicPolicy};
+use crate::task::JoinError;
+
+pub(crate) type Payload = Box<dyn Any + Send + 'static>;
+
+// What the panic hook needs to report a panic of the task being run.
+struct Report {
+    complete: Box<dyn Fn(Payload)>,
+    task: Id,
+    header: Header,
+    // Whether the task was marked as running, which happens on its first poll.
+    started: Cell<bool>,
+    // Run when a panic traps the worker, see `on_abort`.
+    on_abort: RefCell<Vec<Box<dyn FnOnce()>>>,
+    #[cfg(feature = "spawn-location")]
+    location: &'static Location<'static>,
+}
+
+// The crate is built with `panic = "abort"` by default, in which case `catch_unwind`
+// never catches anything: a panic traps the worker and the task's sender is never
+// dropped. To still complete the join handle, a panic hook hands the panic message to
+// the task being run on the current thread before the trap happens.
+thread_local! {
+    static CURRENT: Cell<*const Report> = const { Cell::new(ptr::null()) };
+}
+
+type Sender<T> = oneshot::Sender<Result<T, Payload>>;
+
+/// Sends the outcome of a task to its join handle, at most once.
+///
+/// Also keeps the task in the task tree until the last clone is dropped with the task.
+pub(crate) struct Completion<T> {
+    tx: Arc<Mutex<Option<Sender<T>>>>,
+    task: Arc<Registration>,
+    header: Header,
+    #[cfg(feature = "spawn-location")]
+    location: &'static Location<'static>,
+}
+
+impl<T> Clone for Completion<T> {
+    fn clone(&self) -> Self {
+        Completion {
+            tx: self.tx.clone(),
+            task: self.task.clone(),
+            header: self.header.clone(),
+            #[cfg(feature = "spawn-location")]
+            location: self.location,
+        }
+    }
+}
+
+impl<T: 'static> Completion<T> {
+    #[track_caller]
+    pub(crate) fn new() -> (Self, oneshot::Receiver<Result<T, Payload>>) {
+        let (tx, rx) = oneshot::channel();
+        let completion = Completion {
+            tx: Arc::new(Mutex::new(Some(tx))),
+            task: Arc::new(Registration::new(current_task())),
+            header: Header::new(),
+            #[cfg(feature = "spawn-location")]
+            location: Location::caller(),
+        };
+        (completion, rx)
+    }
+
+    pub(crate) fn id(&self) -> Id {
+        self.task.id()
+    }
+
+    /// Where the task was spawned.
+    #[cfg(feature = "spawn-location")]
+    pub(crate) fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+
+    pub(crate) fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub(crate) fn complete(&self, result: Result<T, Payload>) {
+        if let Some(tx) = self.tx.lock().unwrap().take() {
+            self.header.set(match result {
+                Ok(_) => TaskState::Finished,
+                Err(_) => TaskState::Panicked,
+            });
+            tx.send(result).ok();
+        }
+    }
+
+    fn report(&self) -> Report {
+        let completion = self.clone();
+        Report {
+            complete: Box::new(move |payload| completion.complete(Err(payload))),
+            task: self.id(),
+            header: self.header.clone(),
+            started: Cell::new(false),
+            on_abort: RefCell::new(Vec::new()),
+            #[cfg(feature = "spawn-location")]
+            location: self.location,
+        }
+    }
+}
+
+/// The task being run on the current thread, if any.
+pub(crate) fn current_task() -> Option<Id> {
+    CURRENT
+        .try_with(|current| {
+            // SAFETY: `CURRENT` only points to a report while `enter` borrows it.
+            unsafe { current.get().as_ref() }.map(|report| report.task)
+        })
+        .ok()
+        .flatten()
+}
+
+/// Runs `f` if the task being run on the current thread panics with the `abort` strategy,
+/// where the task's destructors never run, e.g. to close a channel it owns so that the
+/// tasks waiting on it don't wait forever. Does nothing outside of tasks.
+pub(crate) fn on_abort(f: impl FnOnce() + 'static) {
+    CURRENT.with(|current| {
+        // SAFETY: as in `current_task`.
+        if let Some(report) = unsafe { current.get().as_ref() } {
+            report.on_abort.borrow_mut().push(Box::new(f));
+        }
+    });
+}
+
+fn enter<R>(report: &Report, f: impl FnOnce() -> R) -> R {
+    struct Restore(*const Report);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CURRENT.with(|current| current.set(self.0));
+        }
+    }
+
+    if !report.started.replace(true) {
+        report.header.started();
+    }
+    let _restore = Restore(CURRENT.with(|current| current.replace(report)));
+    f()
+}
+
+/// Runs `future`, reporting its panic to `completion` if it panics.
+pub(crate) struct CatchPanic<F> {
+    future: Pin<Box<F>>,
+    report: Report,
+}
+
+impl<F: Future<Output = ()>> CatchPanic<F> {
+    pub(crate) fn new<T: 'static>(completion: &Completion<T>, future: F) -> Self {
+        install_hook();
+        CatchPanic {
+            future: Box::pin(future),
+            report: completion.report(),
+        }
+    }
+}
+
+impl<F: Future<Output = ()>> Future for CatchPanic<F> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = &mut *self;
+        let future = &mut this.future;
+        match enter(&this.report, || {
+            panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(cx)))
+        }) {
+            Ok(poll) => poll,
+            Err(payload) => {
+                (this.report.complete)(payload);
+                Poll::Ready(())
+            }
+        }
+    }
+}
+
+/// Runs `f` and sends its output, or the payload of its panic, to `completion`.
+pub(crate) fn catch_panic_blocking<T: 'static>(completion: Completion<T>, f: impl FnOnce() -> T) {
+    install_hook();
+    let report = completion.report();
+    let result = enter(&report, || panic::catch_unwind(AssertUnwindSafe(f)));
+    completion.complete(result);
+}
+
+fn install_hook() {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let current = CURRENT
+                .try_with(|current| {
+                    if cfg!(panic = "abort") {
+                        current.replace(ptr::null())
+                    } else {
+                        current.get()
+                    }
+                })
+                .unwrap_or(ptr::null());
+            // SAFETY: `CURRENT` is only set while the report it points to is borrowed by
+            // the enclosing `enter` call, which a panic doesn't leave before the hook
+            // returns.
+            let report = unsafe { current.as_ref() };
+            // The lock is only tried, in case the panic happened while it was held.
+            let (policy, on_panic) = runtime::CONFIG
+                .try_lock()
+                .map(|config| (config.panic_policy, config.on_panic.clone()))
+                .unwrap_or_default();
+            if let Some(report) = report {
+                let payload = info.payload();
+                let message = match payload.downcast_ref::<&str>() {
+                    Some(message) => Some(message.to_string()),
+                    None => payload.downcast_ref::<String>().cloned(),
+                };
+                let payload = || -> Payload {
+                    match message.clone() {
+                        Some(message) => Box::new(message),
+                        None => Box::new(LostPayload),
+                    }
+                };
+                if let (PanicPolicy::Propagate, Some(on_panic)) = (policy, on_panic) {
+                    on_panic(&JoinError::Panic(payload()));
+                }
+                if cfg!(panic = "abort") {
+                    (report.complete)(payload());
+                    tree::remove_panicked(report.task);
+                    if let Ok(mut on_abort) = report.on_abort.try_borrow_mut() {
+                        for f in on_abort.drain(..) {
+                            f();
+                        }
+                    }
+                }
+            }
+            if cfg!(panic = "abort") || policy != PanicPolicy::Ignore {
+                runtime::executor::mark_current_worker_broken();
+            }
+            previous(info);
+            #[cfg(feature = "spawn-location")]
+            if let Some(report) = report {
+                web_sys::console::error_1(&format!("task spawned at {}", report.location).into());
+            }
+        }));
+    });
+}
+
+/// Payload of panics whose original payload couldn't be recovered.
+pub(crate) struct LostPayload;
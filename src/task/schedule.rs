@@ -0,0 +1,246 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll, Waker};
+
+use wasm_bindgen::prelude::JsValue;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::MessageChannel;
+
+use super::{r#async, spawn_local, Id};
+
+/// When a local task is polled again after being woken up.
+///
+/// Tasks spawned with a schedule other than [`Schedule::Microtask`] are deferred, i.e.
+/// have a lower priority than other tasks. While one of them holds a
+/// [`Mutex`](crate::sync::Mutex) that a task with a higher priority waits for, it is
+/// polled right away too, until it releases the lock, so that it doesn't hold back the
+/// waiter for several turns of the event loop.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Schedule {
+    /// Right away, on the microtask queue, like [`spawn_local`]. Long chains of wake-ups
+    /// never give the browser a chance to render.
+    #[default]
+    Microtask,
+    /// On a new macrotask, letting the event loop handle input and rendering in between.
+    Macrotask,
+    /// Before the next frame is rendered, through `requestAnimationFrame`. Falls back to
+    /// [`Schedule::Macrotask`] where animation frames aren't available.
+    AnimationFrame,
+}
+
+/// Like [`spawn_local`], but re-polls the task according to `schedule` after every
+/// wake-up.
+#[track_caller]
+pub fn spawn_local_with<F>(schedule: Schedule, future: F) -> r#async::JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    spawn_local(Scheduled {
+        future: Box::pin(future),
+        schedule,
+        polled: false,
+        turn: None,
+        id: None,
+    })
+}
+
+struct Deferred {
+    // The number of locks held by the task that higher priority tasks wait for.
+    boosts: usize,
+    // Wakes the task waiting for its turn when it gets boosted.
+    waker: Option<Waker>,
+}
+
+// The deferred tasks, shared by all workers since locks are.
+static DEFERRED: Mutex<BTreeMap<Id, Deferred>> = Mutex::new(BTreeMap::new());
+
+/// Whether the task `id` is deferred, and not boosted.
+pub(crate) fn is_deferred(id: Id) -> bool {
+    DEFERRED
+        .lock()
+        .unwrap()
+        .get(&id)
+        .is_some_and(|deferred| deferred.boosts == 0)
+}
+
+/// Polls the task `id` right away until as many [`unboost`] calls, if it is deferred.
+/// Returns whether it is.
+pub(crate) fn boost(id: Id) -> bool {
+    let mut tasks = DEFERRED.lock().unwrap();
+    let Some(deferred) = tasks.get_mut(&id) else {
+        return false;
+    };
+    deferred.boosts += 1;
+    let waker = deferred.waker.take();
+    drop(tasks);
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+    true
+}
+
+pub(crate) fn unboost(id: Id) {
+    if let Some(deferred) = DEFERRED.lock().unwrap().get_mut(&id) {
+        deferred.boosts = deferred.boosts.saturating_sub(1);
+    }
+}
+
+struct Scheduled<F> {
+    future: Pin<Box<F>>,
+    schedule: Schedule,
+    polled: bool,
+    turn: Option<JsFuture>,
+    id: Option<Id>,
+}
+
+impl<F> Scheduled<F> {
+    // Whether the task waits for its turn, registering `waker` to be woken up if it gets
+    // boosted in the meantime.
+    fn deferred(&mut self, waker: &Waker) -> bool {
+        if self.schedule == Schedule::Microtask {
+            return false;
+        }
+        if self.id.is_none() {
+            self.id = super::try_id();
+        }
+        let Some(id) = self.id else {
+            return true;
+        };
+        let mut tasks = DEFERRED.lock().unwrap();
+        let deferred = tasks.entry(id).or_insert(Deferred {
+            boosts: 0,
+            waker: None,
+        });
+        if deferred.boosts > 0 {
+            return false;
+        }
+        deferred.waker = Some(waker.clone());
+        true
+    }
+}
+
+impl<F> Drop for Scheduled<F> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            DEFERRED.lock().unwrap().remove(&id);
+        }
+    }
+}
+
+impl<F: Future> Future for Scheduled<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        // Registered before the first poll, so that locks taken then can be boosted.
+        let deferred = this.deferred(cx.waker());
+        if !deferred {
+            this.turn = None;
+        } else if this.polled {
+            let schedule = this.schedule;
+            let turn = this.turn.get_or_insert_with(|| next_turn(schedule));
+            if Pin::new(turn).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            this.turn = None;
+        }
+        this.polled = true;
+        this.future.as_mut().poll(cx)
+    }
+}
+
+pub(crate) fn next_turn(schedule: Schedule) -> JsFuture {
+    JsFuture::from(turn(schedule))
+}
+
+/// A promise resolving on the next turn of `schedule`.
+pub(crate) fn turn(schedule: Schedule) -> js_sys::Promise {
+    let global = js_sys::global();
+    let request_animation_frame = js_sys::Reflect::get(&global, &"requestAnimationFrame".into())
+        .ok()
+        .and_then(|f| f.dyn_into::<js_sys::Function>().ok());
+    js_sys::Promise::new(&mut |resolve, _| {
+        match (schedule, &request_animation_frame) {
+            (Schedule::AnimationFrame, Some(request_animation_frame)) => {
+                request_animation_frame
+                    .call1(&global, &resolve)
+                    .expect("failed to request animation frame");
+            }
+            _ => {
+                // Unlike `setTimeout(0)`, message events aren't clamped to 4ms when nested.
+                let channel = MessageChannel::new().expect("failed to create message channel");
+                channel.port1().set_onmessage(Some(&resolve));
+                channel
+                    .port2()
+                    .post_message(&JsValue::NULL)
+                    .expect("failed to post message");
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::time::sleep;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    async fn yield_now() {
+        let mut yielded = false;
+        futures::future::poll_fn(|cx| {
+            if yielded {
+                Poll::Ready(())
+            } else {
+                yielded = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_macrotask_yields_to_event_loop() {
+        let timer_fired = Rc::new(Cell::new(false));
+        let handle = spawn_local_with(Schedule::Macrotask, {
+            let timer_fired = timer_fired.clone();
+            async move {
+                for _ in 0..1000 {
+                    if timer_fired.get() {
+                        return true;
+                    }
+                    yield_now().await;
+                }
+                false
+            }
+        });
+        let timer = spawn_local({
+            let timer_fired = timer_fired.clone();
+            async move {
+                sleep(std::time::Duration::ZERO).await.unwrap();
+                timer_fired.set(true);
+            }
+        });
+        assert!(handle.join().await.unwrap());
+        timer.join().await.unwrap();
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_animation_frame() {
+        let handle = spawn_local_with(Schedule::AnimationFrame, async move {
+            yield_now().await;
+            1
+        });
+        assert_eq!(handle.join().await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,86 @@
+use std::sync::Arc;
+
+use futures::stream::{self, Stream};
+
+use crate::channel::watch;
+
+/// Where a task is in its life, as seen by
+/// [`JoinHandle::state`](super::r#async::JoinHandle::state).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TaskState {
+    /// Spawned, but not started yet, e.g. while waiting for a worker under the runtime's
+    /// limits.
+    Queued,
+    /// Being run by the worker `worker_id`, the id used in the prefix of its forwarded
+    /// console messages and by [`WorkerRef::id`](super::WorkerRef::id). `None` for tasks
+    /// spawned locally on a thread not spawned by the runtime, like the main thread.
+    Running { worker_id: Option<u32> },
+    /// Completed with an output.
+    Finished,
+    /// Aborted before completing.
+    Aborted,
+    /// Panicked, or its worker couldn't be created.
+    Panicked,
+}
+
+impl TaskState {
+    /// Whether the task is done, in which case its state doesn't change anymore.
+    pub fn is_done(&self) -> bool {
+        matches!(
+            self,
+            TaskState::Finished | TaskState::Aborted | TaskState::Panicked
+        )
+    }
+}
+
+/// The state of a task, shared by its handle and the thread running it.
+#[derive(Clone)]
+pub(crate) struct Header(Arc<watch::Sender<TaskState>>);
+
+impl Header {
+    pub(crate) fn new() -> Self {
+        Header(Arc::new(watch::channel(TaskState::Queued).0))
+    }
+
+    pub(crate) fn get(&self) -> TaskState {
+        *self.0.borrow()
+    }
+
+    /// Moves the task to `state`, unless it is already done.
+    pub(crate) fn set(&self, state: TaskState) {
+        self.0.send_modify(|current| {
+            if !current.is_done() {
+                *current = state;
+            }
+        });
+    }
+
+    /// Marks the task as running on the current worker, if it was queued.
+    pub(crate) fn started(&self) {
+        let worker_id = crate::worker::current_id();
+        self.0.send_modify(|current| {
+            if *current == TaskState::Queued {
+                *current = TaskState::Running { worker_id };
+            }
+        });
+    }
+
+    /// The current state, then every different state until the task is done. States the
+    /// task went through while the stream wasn't polled are skipped.
+    pub(crate) fn changes(&self) -> impl Stream<Item = TaskState> + 'static {
+        let rx = self.0.subscribe();
+        stream::unfold((rx, None), |(mut rx, last)| async move {
+            if last.is_some_and(|last: TaskState| last.is_done()) {
+                return None;
+            }
+            loop {
+                let state = *rx.borrow_and_update();
+                if Some(state) != last {
+                    return Some((state, (rx, Some(state))));
+                }
+                // Only fails once every clone of the header is gone.
+                rx.changed().await.ok()?;
+            }
+        })
+    }
+}
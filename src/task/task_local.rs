@@ -0,0 +1,229 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use wasm_bindgen::JsValue;
+
+/// A value scoped to a task, declared with [`task_local!`](crate::task_local), like
+/// tokio's `task::LocalKey`.
+///
+/// The value is set for the duration of a future with [`scope`](Self::scope), e.g. the
+/// one passed to [`spawn`](super::spawn), and is seen by the future wherever it is
+/// polled, but not by the other tasks of its worker nor by the tasks it spawns.
+pub struct LocalKey<T: 'static> {
+    #[doc(hidden)]
+    pub inner: &'static std::thread::LocalKey<RefCell<Option<T>>>,
+}
+
+impl<T: 'static> LocalKey<T> {
+    /// Sets the value to `value` while `future` is polled.
+    ///
+    /// ```ignore
+    /// task_local! {
+    ///     static REQUEST_ID: u64;
+    /// }
+    ///
+    /// task::spawn(REQUEST_ID.scope(id, handle(request)));
+    /// ```
+    pub fn scope<F: Future>(&'static self, value: T, future: F) -> TaskLocalFuture<T, F> {
+        TaskLocalFuture {
+            key: self,
+            value: Some(value),
+            future: Some(future),
+        }
+    }
+
+    /// Sets the value to `value` while `f` runs.
+    pub fn sync_scope<R>(&'static self, value: T, f: impl FnOnce() -> R) -> R {
+        let mut value = Some(value);
+        self.enter(&mut value, f)
+    }
+
+    // Swaps `value` in for as long as `f` runs, restoring the previous value even if `f`
+    // panics.
+    fn enter<R>(&'static self, value: &mut Option<T>, f: impl FnOnce() -> R) -> R {
+        struct Restore<'a, T: 'static> {
+            key: &'static std::thread::LocalKey<RefCell<Option<T>>>,
+            value: &'a mut Option<T>,
+        }
+
+        impl<T: 'static> Drop for Restore<'_, T> {
+            fn drop(&mut self) {
+                self.key
+                    .with(|slot| std::mem::swap(&mut *slot.borrow_mut(), self.value));
+            }
+        }
+
+        self.inner
+            .with(|slot| std::mem::swap(&mut *slot.borrow_mut(), value));
+        let _restore = Restore {
+            key: self.inner,
+            value,
+        };
+        f()
+    }
+
+    /// Runs `f` with the value of the current task.
+    ///
+    /// # Panics
+    ///
+    /// Panics outside of a [`scope`](Self::scope) of the key.
+    #[track_caller]
+    pub fn with<R>(&'static self, f: impl FnOnce(&T) -> R) -> R {
+        self.try_with(f).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Runs `f` with the value of the current task, or returns an error outside of a
+    /// [`scope`](Self::scope) of the key.
+    pub fn try_with<R>(&'static self, f: impl FnOnce(&T) -> R) -> Result<R, AccessError> {
+        self.inner.with(|slot| match &*slot.borrow() {
+            Some(value) => Ok(f(value)),
+            None => Err(AccessError),
+        })
+    }
+
+    /// A copy of the value of the current task.
+    ///
+    /// # Panics
+    ///
+    /// Panics outside of a [`scope`](Self::scope) of the key.
+    #[track_caller]
+    pub fn get(&'static self) -> T
+    where
+        T: Clone,
+    {
+        self.with(T::clone)
+    }
+}
+
+/// Future returned by [`LocalKey::scope`].
+pub struct TaskLocalFuture<T: 'static, F> {
+    key: &'static LocalKey<T>,
+    // Taken while the future is polled, and `None` afterwards.
+    value: Option<T>,
+    // Dropped within the scope too, once completed or with the `TaskLocalFuture`.
+    future: Option<F>,
+}
+
+impl<T: 'static, F: Future> Future for TaskLocalFuture<T, F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `future` is never moved out of, only dropped in place.
+        let this = unsafe { self.get_unchecked_mut() };
+        let key = this.key;
+        let (value, future) = (&mut this.value, &mut this.future);
+        key.enter(value, || {
+            let Some(inner) = future.as_mut() else {
+                panic!("`TaskLocalFuture` polled after completion");
+            };
+            // SAFETY: pinned along with `self`.
+            let result = unsafe { Pin::new_unchecked(inner) }.poll(cx);
+            if result.is_ready() {
+                *future = None;
+            }
+            result
+        })
+    }
+}
+
+impl<T: 'static, F> Drop for TaskLocalFuture<T, F> {
+    fn drop(&mut self) {
+        if self.future.is_some() {
+            let (value, future) = (&mut self.value, &mut self.future);
+            self.key.enter(value, || *future = None);
+        }
+    }
+}
+
+/// Error returned by [`LocalKey::try_with`] outside of a scope of the key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AccessError;
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "task-local value not set")
+    }
+}
+
+impl std::error::Error for AccessError {}
+
+impl From<AccessError> for JsValue {
+    fn from(err: AccessError) -> Self {
+        js_sys::Error::new(&err.to_string()).into()
+    }
+}
+
+/// Declares [`LocalKey`]s, whose value is set per task with [`LocalKey::scope`].
+///
+/// ```ignore
+/// task_local! {
+///     pub static REQUEST_ID: u64;
+///     static USER: String;
+/// }
+/// ```
+#[macro_export]
+macro_rules! task_local {
+    () => {};
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty; $($rest:tt)*) => {
+        $crate::task_local!($(#[$attr])* $vis static $name: $t);
+        $crate::task_local!($($rest)*);
+    };
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty) => {
+        $(#[$attr])* $vis static $name: $crate::task::LocalKey<$t> = {
+            ::std::thread_local! {
+                static SLOT: ::std::cell::RefCell<::std::option::Option<$t>> =
+                    const { ::std::cell::RefCell::new(::std::option::Option::None) };
+            }
+            $crate::task::LocalKey { inner: &SLOT }
+        };
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::task::{spawn, spawn_local};
+    use crate::time::sleep;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    task_local! {
+        static REQUEST_ID: u32;
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_task_locals_are_scoped_to_tasks() {
+        let handles = (0..3)
+            .map(|id| {
+                spawn_local(REQUEST_ID.scope(id, async move {
+                    assert_eq!(REQUEST_ID.get(), id);
+                    sleep(Duration::from_millis(10 * (3 - id) as u64))
+                        .await
+                        .unwrap();
+                    // Interleaved with the other tasks of the thread in the meantime.
+                    REQUEST_ID.get()
+                }))
+            })
+            .collect::<Vec<_>>();
+        for (id, handle) in handles.into_iter().enumerate() {
+            assert_eq!(handle.await, Ok(id as u32));
+        }
+        assert_eq!(REQUEST_ID.try_with(|id| *id), Err(AccessError));
+
+        let nested = spawn(REQUEST_ID.scope(7, async {
+            let outer = REQUEST_ID.get();
+            let inner = REQUEST_ID.sync_scope(8, || REQUEST_ID.get());
+            // Not inherited by spawned tasks.
+            let spawned = spawn_local(async { REQUEST_ID.try_with(|id| *id) });
+            (outer, inner, REQUEST_ID.get(), spawned.await.unwrap())
+        }));
+        assert_eq!(nested.await, Ok((7, 8, 7, Err(AccessError))));
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+#[cfg(feature = "spawn-location")]
+use std::panic::Location;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use futures::future::AbortHandle;
+
+use super::CancelFlag;
+
+/// Identifies a task among all the tasks of the module instance, across workers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Id(u64);
+
+impl std::fmt::Display for Id {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Id {
+    // For atomics holding an optional id, with 0 for none since ids start at 1.
+    pub(crate) fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    pub(crate) fn from_u64(id: u64) -> Option<Self> {
+        (id != 0).then_some(Id(id))
+    }
+}
+
+/// How a task is told to stop when one of its ancestors is aborted.
+pub(crate) enum Cancel {
+    None,
+    Abort(AbortHandle),
+    Flag(CancelFlag),
+}
+
+impl Cancel {
+    fn cancel(&self) {
+        match self {
+            Cancel::None => {}
+            Cancel::Abort(handle) => handle.abort(),
+            Cancel::Flag(flag) => flag.cancel(),
+        }
+    }
+}
+
+struct Node {
+    parent: Option<Id>,
+    cancel: Cancel,
+    #[cfg(feature = "spawn-location")]
+    location: &'static Location<'static>,
+}
+
+// Every task that is alive, shared by all workers so that the tree can be walked from
+// any of them.
+static TASKS: Mutex<BTreeMap<Id, Node>> = Mutex::new(BTreeMap::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Keeps a task in the tree until dropped, once the task is done.
+pub(crate) struct Registration(Id);
+
+impl Registration {
+    #[track_caller]
+    pub(crate) fn new(parent: Option<Id>) -> Self {
+        let id = Id(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        let node = Node {
+            parent,
+            cancel: Cancel::None,
+            #[cfg(feature = "spawn-location")]
+            location: Location::caller(),
+        };
+        TASKS.lock().unwrap().insert(id, node);
+        Registration(id)
+    }
+
+    pub(crate) fn id(&self) -> Id {
+        self.0
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        remove(&mut TASKS.lock().unwrap(), self.0);
+    }
+}
+
+// The children of a finished task are handed to its parent, so that they are still
+// cancelled along with their other ancestors.
+fn remove(tasks: &mut BTreeMap<Id, Node>, id: Id) {
+    let Some(node) = tasks.remove(&id) else {
+        return;
+    };
+    for child in tasks.values_mut() {
+        if child.parent == Some(id) {
+            child.parent = node.parent;
+        }
+    }
+}
+
+/// Removes a task whose worker is about to trap on a panic, and so won't drop its
+/// registration. The lock is only tried, in case the panic happened while it was held.
+pub(crate) fn remove_panicked(id: Id) {
+    if let Ok(mut tasks) = TASKS.try_lock() {
+        remove(&mut tasks, id);
+    }
+}
+
+/// Sets how the task `id` is cancelled along with its ancestors.
+pub(crate) fn set_cancel(id: Id, cancel: Cancel) {
+    if let Some(node) = TASKS.lock().unwrap().get_mut(&id) {
+        node.cancel = cancel;
+    }
+}
+
+/// Cancels every task spawned, directly or not, by the task `id`.
+pub(crate) fn cancel_descendants(id: Id) {
+    let tasks = TASKS.lock().unwrap();
+    let mut cancelled = vec![id];
+    // Ids grow with spawn order, so children come after their parent.
+    for (&child, node) in tasks.range(id..) {
+        if node
+            .parent
+            .is_some_and(|parent| cancelled.contains(&parent))
+        {
+            cancelled.push(child);
+            node.cancel.cancel();
+        }
+    }
+}
+
+/// A task as listed by [`runtime::dump`](crate::runtime::dump).
+#[derive(Clone, Debug)]
+pub struct TaskInfo {
+    pub id: Id,
+    /// The task that spawned this one, or the closest of its ancestors still alive.
+    pub parent: Option<Id>,
+    #[cfg(feature = "spawn-location")]
+    pub location: &'static Location<'static>,
+}
+
+pub(crate) fn snapshot() -> Vec<TaskInfo> {
+    TASKS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(&id, node)| TaskInfo {
+            id,
+            parent: node.parent,
+            #[cfg(feature = "spawn-location")]
+            location: node.location,
+        })
+        .collect()
+}
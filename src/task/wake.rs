@@ -0,0 +1,240 @@
+use std::cell::{OnceCell, RefCell};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock, Weak};
+use std::task::{Context, Poll};
+
+use futures::task::{waker_ref, ArcWake, AtomicWaker};
+
+use crate::sync::futex::Futex;
+
+// Cross-thread wake-ups of spawned tasks.
+//
+// Each task has a flag in the shared memory, set by the first wake-up after a poll: the
+// following ones, which channel-heavy tasks get many of between two polls, are a single
+// atomic swap. A wake-up from another thread then bumps the wake port of the thread
+// polling the task and calls `Atomics.notify` on it. Each thread has one port, with a
+// listener waiting on it with `Atomics.waitAsync`, which wakes the flagged tasks of the
+// thread like any local wake-up. Wake-ups from the polling thread itself skip the port.
+//
+// Where `Atomics.waitAsync` isn't supported, the port has no listener, and wake-ups go
+// through the waker of the executor polling the task instead.
+pub(crate) struct Coalesced<F> {
+    future: Pin<Box<F>>,
+    state: Arc<WakeState>,
+}
+
+struct WakeState {
+    notified: AtomicBool,
+    // Set while the task is in the pending list of its port.
+    registered: AtomicBool,
+    waker: AtomicWaker,
+    // The port of the thread polling the task, set on the first poll.
+    port: OnceLock<Arc<Port>>,
+}
+
+struct Port {
+    futex: Futex,
+    listening: bool,
+}
+
+thread_local! {
+    static PORT: OnceCell<Arc<Port>> = const { OnceCell::new() };
+    // The tasks of the thread waiting for a wake-up.
+    static PENDING: RefCell<Vec<Weak<WakeState>>> = const { RefCell::new(Vec::new()) };
+}
+
+fn wait_async_supported() -> bool {
+    js_sys::Reflect::get(&js_sys::global(), &"Atomics".into())
+        .and_then(|atomics| js_sys::Reflect::has(&atomics, &"waitAsync".into()))
+        .unwrap_or(false)
+}
+
+// The port of the current thread, starting its listener on the first call.
+fn port() -> Arc<Port> {
+    PORT.with(|port| {
+        port.get_or_init(|| {
+            let port = Arc::new(Port {
+                futex: Futex::new(),
+                listening: wait_async_supported(),
+            });
+            if port.listening {
+                wasm_bindgen_futures::spawn_local(listen(port.clone()));
+            }
+            port
+        })
+        .clone()
+    })
+}
+
+async fn listen(port: Arc<Port>) {
+    loop {
+        let seen = port.futex.seq();
+        let notified = PENDING.with(|pending| {
+            let mut notified = Vec::new();
+            pending.borrow_mut().retain(|state| {
+                let Some(state) = state.upgrade() else {
+                    return false;
+                };
+                if !state.notified.load(Ordering::Acquire) {
+                    return true;
+                }
+                state.registered.store(false, Ordering::Release);
+                notified.push(state);
+                false
+            });
+            notified
+        });
+        for state in notified {
+            state.waker.wake();
+        }
+        port.futex.wait_async(seen).await;
+    }
+}
+
+impl ArcWake for WakeState {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        if arc_self.notified.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        match arc_self.port.get() {
+            Some(port) if port.listening && !PORT.with(|own| is_own(own, port)) => {
+                port.futex.notify();
+            }
+            _ => arc_self.waker.wake(),
+        }
+    }
+}
+
+fn is_own(own: &OnceCell<Arc<Port>>, port: &Arc<Port>) -> bool {
+    own.get().is_some_and(|own| Arc::ptr_eq(own, port))
+}
+
+impl<F: Future> Coalesced<F> {
+    pub(crate) fn new(future: F) -> Self {
+        Coalesced {
+            future: Box::pin(future),
+            state: Arc::new(WakeState {
+                notified: AtomicBool::new(false),
+                registered: AtomicBool::new(false),
+                waker: AtomicWaker::new(),
+                port: OnceLock::new(),
+            }),
+        }
+    }
+}
+
+impl<F: Future> Future for Coalesced<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let port = this.state.port.get_or_init(port);
+        this.state.waker.register(cx.waker());
+        this.state.notified.store(false, Ordering::Release);
+        let waker = waker_ref(&this.state);
+        let result = this.future.as_mut().poll(&mut Context::from_waker(&waker));
+        if result.is_pending() && port.listening {
+            if !this.state.registered.swap(true, Ordering::AcqRel) {
+                PENDING.with(|pending| pending.borrow_mut().push(Arc::downgrade(&this.state)));
+            }
+            // Woken from another thread before it was registered, when the listener may
+            // have already looked for it.
+            if this.state.notified.load(Ordering::Acquire) {
+                cx.waker().wake_by_ref();
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use futures::channel::{mpsc, oneshot};
+    use futures::{SinkExt, StreamExt};
+
+    use super::*;
+    use crate::task;
+
+    use wasm_bindgen::prelude::wasm_bindgen;
+    use wasm_bindgen_test::*;
+
+    #[wasm_bindgen]
+    extern "C" {
+        #[wasm_bindgen(js_name = "performance")]
+        pub static PERFORMANCE: web_sys::Performance;
+    }
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    // Wake-to-poll latency between the main thread and a worker, measured as the round
+    // trip of a message bounced back by a task, and logged for comparison.
+    #[wasm_bindgen_test]
+    async fn test_cross_worker_wake_latency() {
+        const ROUND_TRIPS: u32 = 100;
+        let (mut ping_tx, mut ping_rx) = mpsc::channel::<u32>(1);
+        let (mut pong_tx, mut pong_rx) = mpsc::channel::<u32>(1);
+        let handle = task::spawn(async move {
+            let mut bounced = 0;
+            while let Some(i) = ping_rx.next().await {
+                pong_tx.send(i).await.unwrap();
+                bounced += 1;
+            }
+            bounced
+        });
+        let start = PERFORMANCE.now();
+        for i in 0..ROUND_TRIPS {
+            ping_tx.send(i).await.unwrap();
+            assert_eq!(pong_rx.next().await, Some(i));
+        }
+        let latency = (PERFORMANCE.now() - start) / f64::from(ROUND_TRIPS) / 2.0;
+        web_sys::console::log_1(
+            &format!("cross-worker wake-to-poll latency: {latency:.3}ms").into(),
+        );
+        drop(ping_tx);
+        assert_eq!(handle.join().await, Ok(ROUND_TRIPS));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_cross_worker_wakes_are_coalesced() {
+        const WAKES: usize = 1000;
+        let (waker_tx, waker_rx) = oneshot::channel();
+        let done = Arc::new(AtomicBool::new(false));
+        let waker = task::spawn_blocking({
+            let done = done.clone();
+            move || {
+                let waker: std::task::Waker = futures::executor::block_on(waker_rx).unwrap();
+                for _ in 0..WAKES {
+                    waker.wake_by_ref();
+                }
+                done.store(true, Ordering::SeqCst);
+                waker.wake();
+            }
+        });
+        let polls = Rc::new(Cell::new(0));
+        let mut waker_tx = Some(waker_tx);
+        let task = task::spawn_local({
+            let polls = polls.clone();
+            futures::future::poll_fn(move |cx| {
+                polls.set(polls.get() + 1);
+                if let Some(waker_tx) = waker_tx.take() {
+                    waker_tx.send(cx.waker().clone()).ok();
+                }
+                if done.load(Ordering::SeqCst) {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            })
+        });
+        task.await.unwrap();
+        waker.join().await.unwrap();
+        // Woken a thousand times in a burst, but only polled again for some of them.
+        assert!(polls.get() >= 2);
+        assert!(polls.get() < WAKES / 2);
+    }
+}
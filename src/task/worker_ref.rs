@@ -0,0 +1,281 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::channel::mpsc;
+use futures::Stream;
+use wasm_bindgen::prelude::{Closure, JsValue};
+use wasm_bindgen::JsCast;
+
+use crate::worker;
+
+/// The dedicated worker running a task spawned with
+/// [`spawn_with_worker`](super::spawn_with_worker).
+///
+/// Messages posted to it are read by the task with [`worker_messages`], while messages
+/// the task posts with `postMessage` are read with [`messages`](Self::messages). They
+/// also reach the listeners added here, along with the runtime's own messages, which are
+/// objects with a `wasmt` prefixed key, `null` or `"wasmt:"` prefixed strings.
+#[derive(Clone, Debug)]
+pub struct WorkerRef {
+    worker: web_sys::Worker,
+    id: u32,
+}
+
+impl WorkerRef {
+    pub(crate) fn new(worker: web_sys::Worker) -> Self {
+        let id = worker::id(&worker);
+        WorkerRef { worker, id }
+    }
+
+    /// The id of the worker, as used in the prefix of its forwarded console messages.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Whether the worker is still running: it stops once its task completes, or when
+    /// terminated.
+    pub fn is_alive(&self) -> bool {
+        worker::registry().has(&self.worker)
+    }
+
+    pub fn post_message(&self, message: &JsValue) -> Result<(), JsValue> {
+        self.post_message_with_transfer(message, &js_sys::Array::new())
+    }
+
+    /// Posts `message`, transferring the ownership of the objects of `transfer`, like
+    /// `ArrayBuffer`s or `OffscreenCanvas`es, to the worker. Fails once the worker has
+    /// stopped, rather than posting into the void.
+    pub fn post_message_with_transfer(
+        &self,
+        message: &JsValue,
+        transfer: &js_sys::Array,
+    ) -> Result<(), JsValue> {
+        if !self.is_alive() {
+            return Err(JsValue::from_str("the worker has stopped"));
+        }
+        self.worker.post_message_with_transfer(message, transfer)
+    }
+
+    /// Like [`post_message_with_transfer`](Self::post_message_with_transfer), taking the
+    /// objects to transfer as a slice.
+    pub fn send(&self, message: &JsValue, transfer: &[JsValue]) -> Result<(), JsValue> {
+        let transfer = transfer.iter().collect::<js_sys::Array>();
+        self.post_message_with_transfer(message, &transfer)
+    }
+
+    /// Messages posted by the task with `postMessage`, in order, without the runtime's
+    /// own messages. Only those posted after this is called are received, and the stream
+    /// ends once the worker stops after its task completes.
+    ///
+    /// ```ignore
+    /// let (handle, worker) = task::spawn_with_worker(async move {
+    ///     let mut requests = task::worker_messages();
+    ///     while let Some(request) = requests.next().await {
+    ///         post_message(&handle_request(request));
+    ///     }
+    /// });
+    /// let mut responses = worker.messages();
+    /// worker.send(&request, &[])?;
+    /// let response = responses.next().await;
+    /// ```
+    pub fn messages(&self) -> Messages {
+        let (tx, rx) = mpsc::unbounded();
+        let listener = Closure::<dyn FnMut(web_sys::MessageEvent)>::new(
+            move |event: web_sys::MessageEvent| {
+                let data = event.data();
+                if data.is_null() {
+                    tx.close_channel();
+                } else if !is_runtime_message(&data) {
+                    tx.unbounded_send(data).ok();
+                }
+            },
+        );
+        self.add_event_listener("message", listener.as_ref().unchecked_ref())
+            .ok();
+        Messages {
+            rx,
+            worker: self.worker.clone(),
+            listener,
+        }
+    }
+
+    pub fn add_event_listener(
+        &self,
+        event: &str,
+        listener: &js_sys::Function,
+    ) -> Result<(), JsValue> {
+        self.worker
+            .add_event_listener_with_callback(event, listener)
+    }
+
+    pub fn remove_event_listener(
+        &self,
+        event: &str,
+        listener: &js_sys::Function,
+    ) -> Result<(), JsValue> {
+        self.worker
+            .remove_event_listener_with_callback(event, listener)
+    }
+
+    /// Terminates the worker, like the task's
+    /// [`abort_hard`](super::r#async::JoinHandle::abort_hard), with the same caveats.
+    /// Returns whether the worker was still running. The task's join handle never
+    /// resolves afterwards unless aborted.
+    pub fn terminate(&self) -> bool {
+        worker::terminate(self.id)
+    }
+}
+
+// Whether `data` is one of the messages the runtime's scripts post from a worker.
+fn is_runtime_message(data: &JsValue) -> bool {
+    if data.is_null() {
+        return true;
+    }
+    if let Some(data) = data.as_string() {
+        return data.starts_with("wasmt:");
+    }
+    data.is_object()
+        && js_sys::Object::keys(data.unchecked_ref::<js_sys::Object>())
+            .iter()
+            .any(|key| key.as_string().is_some_and(|key| key.starts_with("wasmt")))
+}
+
+/// Stream returned by [`WorkerRef::messages`]. Stops listening to the worker once
+/// dropped.
+pub struct Messages {
+    rx: mpsc::UnboundedReceiver<JsValue>,
+    worker: web_sys::Worker,
+    listener: Closure<dyn FnMut(web_sys::MessageEvent)>,
+}
+
+impl Stream for Messages {
+    type Item = JsValue;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<JsValue>> {
+        Pin::new(&mut self.rx).poll_next(cx)
+    }
+}
+
+impl Drop for Messages {
+    fn drop(&mut self) {
+        self.worker
+            .remove_event_listener_with_callback("message", self.listener.as_ref().unchecked_ref())
+            .ok();
+    }
+}
+
+/// Messages posted to the worker running the current task with
+/// [`WorkerRef::post_message`], in order, including those posted before this is called.
+///
+/// Only one stream receives messages at a time: creating a new one takes over. Outside
+/// of tasks spawned with [`spawn_with_worker`](super::spawn_with_worker), the stream
+/// never yields anything.
+pub fn worker_messages() -> WorkerMessages {
+    let (tx, rx) = mpsc::unbounded();
+    let global = js_sys::global();
+    let deliver = Closure::<dyn FnMut(JsValue)>::new(move |message| {
+        tx.unbounded_send(message).ok();
+    });
+    if let Ok(inbox) = js_sys::Reflect::get(&global, &"wasmtInbox".into()) {
+        if let Some(inbox) = inbox.dyn_ref::<js_sys::Array>() {
+            for message in inbox.splice(0, inbox.length(), &JsValue::UNDEFINED).iter() {
+                deliver
+                    .as_ref()
+                    .unchecked_ref::<js_sys::Function>()
+                    .call1(&JsValue::UNDEFINED, &message)
+                    .ok();
+            }
+        }
+    }
+    js_sys::Reflect::set(&global, &"wasmtDeliver".into(), deliver.as_ref()).ok();
+    WorkerMessages { rx, deliver }
+}
+
+/// Stream returned by [`worker_messages`]. Messages arriving after it is dropped are kept
+/// for the next one.
+pub struct WorkerMessages {
+    rx: mpsc::UnboundedReceiver<JsValue>,
+    deliver: Closure<dyn FnMut(JsValue)>,
+}
+
+impl Stream for WorkerMessages {
+    type Item = JsValue;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<JsValue>> {
+        Pin::new(&mut self.rx).poll_next(cx)
+    }
+}
+
+impl Drop for WorkerMessages {
+    fn drop(&mut self) {
+        let global = js_sys::global();
+        let current = js_sys::Reflect::get(&global, &"wasmtDeliver".into());
+        if current.is_ok_and(|current| &current == self.deliver.as_ref()) {
+            js_sys::Reflect::delete_property(&global, &"wasmtDeliver".into()).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::task::spawn_with_worker;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_spawn_with_worker() {
+        let (handle, worker) = spawn_with_worker(async move {
+            let mut messages = worker_messages();
+            let first = messages.next().await.unwrap();
+            let buffer = messages.next().await.unwrap();
+            let buffer = js_sys::Uint8Array::new(&buffer);
+            (first.as_f64().unwrap(), buffer.to_vec())
+        });
+        // Posted before the task asked for messages, and kept for it.
+        worker.post_message(&1.into()).unwrap();
+        let buffer = js_sys::Uint8Array::from(&[1u8, 2, 3][..]).buffer();
+        worker
+            .post_message_with_transfer(&buffer, &js_sys::Array::of1(&buffer))
+            .unwrap();
+        // Transferred away.
+        assert_eq!(buffer.byte_length(), 0);
+        assert_eq!(handle.join().await.unwrap(), (1.0, vec![1, 2, 3]));
+        crate::time::sleep(std::time::Duration::from_millis(100))
+            .await
+            .unwrap();
+        assert!(!worker.is_alive());
+        assert!(worker.post_message(&2.into()).is_err());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_messages_from_worker() {
+        let (handle, worker) = spawn_with_worker(async move {
+            let post_message = js_sys::Reflect::get(&js_sys::global(), &"postMessage".into())
+                .unwrap()
+                .unchecked_into::<js_sys::Function>();
+            let mut requests = worker_messages();
+            while let Some(request) = requests.next().await {
+                let Some(n) = request.as_f64() else { break };
+                // Forwarded to the console as a runtime message, not received.
+                web_sys::console::log_1(&"received".into());
+                post_message
+                    .call1(&js_sys::global(), &(n * 2.0).into())
+                    .unwrap();
+            }
+        });
+        let mut responses = worker.messages();
+        for n in 1..=3 {
+            worker.send(&n.into(), &[]).unwrap();
+            let response = responses.next().await.unwrap();
+            assert_eq!(response.as_f64(), Some(n as f64 * 2.0));
+        }
+        worker.send(&JsValue::from_str("stop"), &[]).unwrap();
+        handle.join().await.unwrap();
+        assert_eq!(responses.next().await, None);
+    }
+}
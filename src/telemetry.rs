@@ -0,0 +1,208 @@
+//! An append-only log of telemetry records shared by every thread of the module
+//! instance, e.g. for per-frame timings or counters sampled by many workers.
+//!
+//! Records are written to a fixed ring in the shared memory without locks or
+//! allocations, so recording costs a few atomic operations instead of a `postMessage`.
+//! One thread, usually the main thread, reads them back in batches with [`drain`] or
+//! [`drain_every`]. Records written while the ring is full are dropped and counted by
+//! [`dropped`], so that a reader falling behind never slows down the writers.
+
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::task::{self, r#async::JoinHandle};
+use crate::thread::{self, ThreadId};
+use crate::time::{self, Instant};
+
+/// Number of records the ring holds before new ones are dropped.
+pub const CAPACITY: usize = 4096;
+
+/// A telemetry record, as written by [`record`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Record {
+    pub name: &'static str,
+    pub value: f64,
+    pub at: Instant,
+    pub thread: ThreadId,
+}
+
+// Slot `i` holds the records of the positions `i + n * CAPACITY`. Its stamp is `2 * lap`
+// while it is free for the position of lap `lap`, and `2 * lap + 1` once that position
+// is written, so that zeroed slots are free for the first lap.
+struct Slot {
+    stamp: AtomicU64,
+    record: UnsafeCell<MaybeUninit<Record>>,
+}
+
+// SAFETY: a record is only written by the writer that claimed its position, and only read
+// by the single reader once its stamp says it is written.
+unsafe impl Sync for Slot {}
+
+static SLOTS: [Slot; CAPACITY] = [const {
+    Slot {
+        stamp: AtomicU64::new(0),
+        record: UnsafeCell::new(MaybeUninit::uninit()),
+    }
+}; CAPACITY];
+// The position of the next record to write, and of the next one to read.
+static TAIL: AtomicU64 = AtomicU64::new(0);
+static HEAD: AtomicU64 = AtomicU64::new(0);
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+// Makes concurrent drains take turns, since the ring only supports one reader.
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+fn slot(position: u64) -> (&'static Slot, u64) {
+    let lap = position / CAPACITY as u64;
+    (&SLOTS[(position % CAPACITY as u64) as usize], 2 * lap)
+}
+
+/// Appends a record of `value` under `name`, from any thread. Returns `false` if the
+/// ring is full, in which case the record is dropped.
+pub fn record(name: &'static str, value: f64) -> bool {
+    let record = Record {
+        name,
+        value,
+        at: Instant::now(),
+        thread: thread::current().id(),
+    };
+    let mut tail = TAIL.load(Ordering::Relaxed);
+    loop {
+        let (slot, free) = slot(tail);
+        let stamp = slot.stamp.load(Ordering::Acquire);
+        if stamp == free {
+            match TAIL.compare_exchange_weak(tail, tail + 1, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => {
+                    // SAFETY: the position was claimed above, and its slot is free.
+                    unsafe { (*slot.record.get()).write(record) };
+                    slot.stamp.store(free + 1, Ordering::Release);
+                    return true;
+                }
+                Err(current) => tail = current,
+            }
+        } else if stamp < free {
+            // Still holds the record of the previous lap, not read yet.
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+            return false;
+        } else {
+            // Another writer claimed the position first.
+            tail = TAIL.load(Ordering::Relaxed);
+        }
+    }
+}
+
+/// Removes the records written so far from the ring and returns them, in the order
+/// their positions were claimed. Returns nothing while another thread is draining.
+pub fn drain() -> Vec<Record> {
+    if DRAINING.swap(true, Ordering::Acquire) {
+        return Vec::new();
+    }
+    let mut records = Vec::new();
+    let mut head = HEAD.load(Ordering::Relaxed);
+    loop {
+        let (slot, free) = slot(head);
+        // Also stops at a record still being written, read on the next drain.
+        if slot.stamp.load(Ordering::Acquire) != free + 1 {
+            break;
+        }
+        // SAFETY: the stamp says the record is written, and nobody else reads it.
+        records.push(unsafe { (*slot.record.get()).assume_init() });
+        slot.stamp.store(free + 2, Ordering::Release);
+        head += 1;
+    }
+    HEAD.store(head, Ordering::Relaxed);
+    DRAINING.store(false, Ordering::Release);
+    records
+}
+
+/// Drains the ring every `period` on the current thread, passing the records to `f`
+/// when there are some, until the returned handle is aborted.
+///
+/// # Panics
+///
+/// Panics if `period` is zero.
+#[track_caller]
+pub fn drain_every(period: Duration, mut f: impl FnMut(Vec<Record>) + 'static) -> JoinHandle<()> {
+    let mut interval = time::interval(period);
+    task::spawn_local(async move {
+        while interval.tick().await.is_ok() {
+            let records = drain();
+            if !records.is_empty() {
+                f(records);
+            }
+        }
+    })
+}
+
+/// The number of records dropped because the ring was full, since the module was
+/// instantiated.
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_records_from_workers() {
+        drain();
+        let writers = (0..4)
+            .map(|_| {
+                thread::spawn(|| {
+                    for i in 0..100 {
+                        assert!(record("telemetry-test", i as f64));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut ids = Vec::new();
+        for writer in writers {
+            ids.push(writer.thread().id());
+            writer.join_async().await.unwrap();
+        }
+        let records = drain()
+            .into_iter()
+            .filter(|record| record.name == "telemetry-test")
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), 400);
+        for id in ids {
+            // In order for each writer.
+            let values = records
+                .iter()
+                .filter(|record| record.thread == id)
+                .map(|record| record.value)
+                .collect::<Vec<_>>();
+            assert_eq!(values, (0..100).map(f64::from).collect::<Vec<_>>());
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_full_ring_drops_records() {
+        drain();
+        let before = dropped();
+        let written = (0..CAPACITY + 10)
+            .filter(|&i| record("telemetry-full", i as f64))
+            .count();
+        assert_eq!(written, CAPACITY);
+        assert_eq!(dropped() - before, 10);
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let mut drainer = drain_every(Duration::from_millis(10), {
+            let received = received.clone();
+            move |records| received.borrow_mut().extend(records)
+        });
+        time::sleep(Duration::from_millis(50)).await.unwrap();
+        drainer.abort();
+        let received = received.borrow();
+        assert_eq!(received.len(), CAPACITY);
+        assert_eq!(received.last().unwrap().value, (CAPACITY - 1) as f64);
+    }
+}
@@ -0,0 +1,84 @@
+//! Helpers for tests exercising the runtime's workers, enabled by the `test-util` feature.
+
+use futures::future::join_all;
+
+use crate::runtime;
+use crate::task::panic::{CatchPanic, Completion};
+use crate::task::JoinError;
+
+/// Runs `f` on every shared worker, see
+/// [`Builder::shared_async_workers`](runtime::Builder::shared_async_workers), and waits for
+/// all of them, e.g. to check that some thread-local state is set up everywhere.
+///
+/// Returns the number of workers `f` ran on, or the errors of those where it panicked,
+/// such as failed assertions. Workers broken by a panic are replaced before the next task
+/// is dispatched.
+///
+/// ```ignore
+/// let workers = on_all_workers(|| assert!(my_tls_is_initialized())).await.unwrap();
+/// ```
+pub async fn on_all_workers<F>(f: F) -> Result<usize, Vec<JoinError>>
+where
+    F: Fn() + Clone + 'static,
+{
+    runtime::ensure_initialized().unwrap_or_else(|err| panic!("{err}"));
+    let mut receivers = Vec::new();
+    runtime::executor::broadcast(|| {
+        let (completion, rx) = Completion::new();
+        receivers.push(rx);
+        let f = f.clone();
+        CatchPanic::new(&completion.clone(), async move {
+            f();
+            completion.complete(Ok(()));
+        })
+    });
+    let workers = receivers.len();
+    let errors = join_all(receivers)
+        .await
+        .into_iter()
+        .filter_map(|result| match result {
+            Ok(Ok(())) => None,
+            Ok(Err(payload)) => Some(JoinError::Panic(payload)),
+            Err(_) => Some(JoinError::unknown_panic()),
+        })
+        .collect::<Vec<_>>();
+    if errors.is_empty() {
+        Ok(workers)
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_on_all_workers() {
+        let _config = runtime::Builder::new()
+            .shared_async_workers(3)
+            .build_for_test();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let result = on_all_workers({
+            let runs = runs.clone();
+            move || {
+                runs.fetch_add(1, Ordering::SeqCst);
+                assert!(crate::utils::is_worker_scope());
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+
+        let errors = on_all_workers(|| panic!("boom")).await.unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[0].panic_message(), Some("boom"));
+    }
+}
@@ -0,0 +1,639 @@
+//! A drop-in replacement for the parts of `std::thread` that native code relies on, with
+//! threads backed by workers, so that such code can be ported by swapping the import.
+//!
+//! Threads are [`spawn_blocking`](crate::task::spawn_blocking) closures, which run on a
+//! worker of their own. The blocking functions of this module, like
+//! [`JoinHandle::join`] or [`park`], can only be called from workers: the main thread has
+//! [`JoinHandle::join_async`] instead.
+//!
+//! Threads spawned in a [`scope`] may borrow from the stack of the scope's caller, which
+//! waits for all of them before returning, like [`std::thread::scope`]. The main thread
+//! uses [`scope_async`] instead.
+
+use std::cell::OnceCell;
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::runtime;
+use crate::sync::blocking::{block_on, can_block};
+use crate::sync::futex::Futex;
+use crate::task::{self, blocking, panic, JoinError};
+
+mod worker_local;
+
+pub(crate) use worker_local::teardown as teardown_worker_locals;
+pub use worker_local::WorkerLocalKey;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static CURRENT: OnceCell<Thread> = const { OnceCell::new() };
+}
+
+/// Identifies a thread among all the threads of the module instance, including the ones
+/// not spawned by this module.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    fn next() -> Self {
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+/// A handle to a thread, to read its name and id or unpark it.
+#[derive(Clone)]
+pub struct Thread {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    id: ThreadId,
+    name: Option<String>,
+    // The token of `park`, set by `unpark`.
+    notified: AtomicBool,
+    parker: Futex,
+}
+
+impl Thread {
+    fn new(name: Option<String>) -> Self {
+        Thread {
+            inner: Arc::new(Inner {
+                id: ThreadId::next(),
+                name,
+                notified: AtomicBool::new(false),
+                parker: Futex::new(),
+            }),
+        }
+    }
+
+    pub fn id(&self) -> ThreadId {
+        self.inner.id
+    }
+
+    /// The name given with [`Builder::name`], or `"main"` for the main thread.
+    pub fn name(&self) -> Option<&str> {
+        self.inner.name.as_deref()
+    }
+
+    /// Wakes the thread if it is parked, or makes its next [`park`] return right away.
+    /// Can be called from any thread, including the main thread.
+    pub fn unpark(&self) {
+        self.inner.notified.store(true, Ordering::Release);
+        self.inner.parker.notify();
+    }
+}
+
+impl fmt::Debug for Thread {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Thread")
+            .field("id", &self.id())
+            .field("name", &self.name())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The handle of the calling thread.
+pub fn current() -> Thread {
+    CURRENT.with(|current| {
+        current
+            .get_or_init(|| {
+                let name = crate::utils::is_main_thread().then(|| "main".to_owned());
+                Thread::new(name)
+            })
+            .clone()
+    })
+}
+
+/// Runs `f` on a new thread, like [`std::thread::spawn`].
+///
+/// # Panics
+///
+/// Panics if the worker can't be created, see [`Builder::spawn`] to handle the error.
+#[track_caller]
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + 'static,
+    T: 'static,
+{
+    Builder::new()
+        .spawn(f)
+        .unwrap_or_else(|err| panic!("{err}"))
+}
+
+/// Configures a thread before spawning it, like [`std::thread::Builder`].
+#[derive(Debug, Default)]
+pub struct Builder {
+    name: Option<String>,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Builder::default()
+    }
+
+    /// Names the thread, and its worker in the browser's devtools, see
+    /// [`task::Builder::name`].
+    pub fn name(mut self, name: String) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// Accepted for compatibility, and ignored: workers get the stack size the module
+    /// was linked with.
+    pub fn stack_size(self, _size: usize) -> Self {
+        self
+    }
+
+    /// Runs `f` on a new thread. Fails if its worker can't be created right away.
+    #[track_caller]
+    pub fn spawn<F, T>(self, f: F) -> io::Result<JoinHandle<T>>
+    where
+        F: FnOnce() -> T + 'static,
+        T: 'static,
+    {
+        let mut builder = task::Builder::new();
+        if let Some(name) = &self.name {
+            builder = builder.name(name);
+        }
+        let thread = Thread::new(self.name);
+        let handle = builder
+            .spawn_blocking({
+                let thread = thread.clone();
+                move || {
+                    CURRENT.with(|current| current.set(thread).ok());
+                    f()
+                }
+            })
+            .map_err(io::Error::other)?;
+        Ok(JoinHandle { handle, thread })
+    }
+
+    /// Runs `f` on a new thread of `scope`, see [`Scope::spawn`]. Fails if its worker
+    /// can't be created right away.
+    #[track_caller]
+    pub fn spawn_scoped<'scope, 'env, F, T>(
+        self,
+        scope: &'scope Scope<'scope, 'env>,
+        f: F,
+    ) -> io::Result<ScopedJoinHandle<'scope, T>>
+    where
+        F: FnOnce() -> T + 'scope,
+        T: 'scope,
+    {
+        let packet = Arc::new(Mutex::new(None));
+        let running = Running::new(scope.data.clone());
+        let main: Box<dyn FnOnce() + 'scope> = Box::new({
+            let packet = packet.clone();
+            move || {
+                // Destructors don't run when a panic traps the worker.
+                panic::on_abort({
+                    let data = running.0.clone();
+                    move || data.finish(true)
+                });
+                let value = f();
+                *packet.lock().unwrap() = Some(value);
+                // Nothing borrowed from the scope may be touched once the thread is
+                // marked as finished.
+                drop(packet);
+                drop(running);
+            }
+        });
+        // SAFETY: the scope doesn't end before `running` is dropped, i.e. before the
+        // closure and what it borrows are done with, whether it ran or not.
+        let main: Box<dyn FnOnce() + 'static> = unsafe { std::mem::transmute(main) };
+        Ok(ScopedJoinHandle {
+            handle: self.spawn(main)?,
+            packet,
+            data: scope.data.clone(),
+            _scope: PhantomData,
+        })
+    }
+}
+
+/// An owned permission to join a thread, like [`std::thread::JoinHandle`]. Dropping it
+/// detaches the thread.
+pub struct JoinHandle<T> {
+    handle: blocking::JoinHandle<T>,
+    thread: Thread,
+}
+
+impl<T> JoinHandle<T> {
+    /// Blocks the current worker until the thread finishes, returning the payload of
+    /// its panic if it panicked.
+    ///
+    /// # Panics
+    ///
+    /// Panics on threads that can't block, like the main thread.
+    #[track_caller]
+    pub fn join(self) -> std::thread::Result<T> {
+        block_on(self.join_async())
+            .expect("cannot block the main browser thread, use `join_async` instead of `join`")
+    }
+
+    /// Waits for the thread to finish without blocking the current thread.
+    pub async fn join_async(self) -> std::thread::Result<T> {
+        self.handle.await.map_err(|err| match err {
+            JoinError::Panic(payload) => payload,
+            JoinError::Aborted => Box::new(err.to_string()),
+        })
+    }
+
+    pub fn thread(&self) -> &Thread {
+        &self.thread
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+}
+
+impl<T> fmt::Debug for JoinHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JoinHandle")
+            .field("thread", &self.thread)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Spawns threads that may borrow non-`'static` data, for as long as the scope lasts.
+pub struct Scope<'scope, 'env: 'scope> {
+    data: Arc<ScopeData>,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+struct ScopeData {
+    running: AtomicUsize,
+    // Panics of threads whose handles didn't report them through a join.
+    unjoined_panics: AtomicUsize,
+    futex: Futex,
+}
+
+impl ScopeData {
+    fn finish(&self, panicked: bool) {
+        if panicked {
+            self.unjoined_panics.fetch_add(1, Ordering::SeqCst);
+        }
+        self.running.fetch_sub(1, Ordering::SeqCst);
+        self.futex.notify();
+    }
+
+    fn finished(&self) -> Option<()> {
+        (self.running.load(Ordering::SeqCst) == 0).then_some(())
+    }
+}
+
+// Counts a thread of a scope as running until dropped.
+struct Running(Arc<ScopeData>);
+
+impl Running {
+    fn new(data: Arc<ScopeData>) -> Self {
+        data.running.fetch_add(1, Ordering::SeqCst);
+        Running(data)
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.finish(std::thread::panicking());
+    }
+}
+
+// Waits for the threads of a scope when dropped, even on a panic of the scope's closure
+// or when the future of `scope_async` is dropped, since they may borrow from the stack.
+struct Wait<'a>(&'a ScopeData);
+
+impl Drop for Wait<'_> {
+    fn drop(&mut self) {
+        if self.0.futex.wait_until(|| self.0.finished()).is_err() {
+            while self.0.finished().is_none() {
+                std::hint::spin_loop();
+            }
+        }
+    }
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    fn new() -> Self {
+        Scope {
+            data: Arc::new(ScopeData {
+                running: AtomicUsize::new(0),
+                unjoined_panics: AtomicUsize::new(0),
+                futex: Futex::new(),
+            }),
+            scope: PhantomData,
+            env: PhantomData,
+        }
+    }
+
+    /// Runs `f` on a new thread, which the scope waits for before it ends.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the worker can't be created, see [`Builder::spawn_scoped`] to handle the
+    /// error.
+    #[track_caller]
+    pub fn spawn<F, T>(&'scope self, f: F) -> ScopedJoinHandle<'scope, T>
+    where
+        F: FnOnce() -> T + 'scope,
+        T: 'scope,
+    {
+        Builder::new()
+            .spawn_scoped(self, f)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    fn check_panics(&self) {
+        if self.data.unjoined_panics.load(Ordering::SeqCst) > 0 {
+            panic!("a scoped thread panicked");
+        }
+    }
+}
+
+impl fmt::Debug for Scope<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scope")
+            .field("running", &self.data.running.load(Ordering::Relaxed))
+            .finish_non_exhaustive()
+    }
+}
+
+/// Runs `f` with a [`Scope`] to spawn threads borrowing from the current stack, then
+/// blocks the current worker until all of them finished, like [`std::thread::scope`].
+///
+/// # Panics
+///
+/// Panics on threads that can't block, like the main thread, before running `f`. Also
+/// panics if `f` does, or if a thread panicked and wasn't joined.
+#[track_caller]
+pub fn scope<'env, F, T>(f: F) -> T
+where
+    F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+{
+    assert!(
+        can_block(),
+        "cannot block the main browser thread, use `scope_async` instead of `scope`"
+    );
+    let scope = Scope::new();
+    let result = {
+        let _wait = Wait(&scope.data);
+        f(&scope)
+    };
+    scope.check_panics();
+    result
+}
+
+/// Like [`scope`], waiting for the threads without blocking the current thread.
+///
+/// If the returned future is dropped while threads of the scope are still running, the
+/// drop blocks until they finished, spinning where blocking isn't allowed, since they
+/// may borrow from the caller's stack.
+pub async fn scope_async<'env, F, T>(f: F) -> T
+where
+    F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+{
+    let scope = Scope::new();
+    let result = {
+        let _wait = Wait(&scope.data);
+        let result = f(&scope);
+        scope
+            .data
+            .futex
+            .wait_until_async(|| scope.data.finished())
+            .await;
+        result
+    };
+    scope.check_panics();
+    result
+}
+
+/// An owned permission to join a thread of a [`Scope`].
+pub struct ScopedJoinHandle<'scope, T> {
+    handle: JoinHandle<()>,
+    packet: Arc<Mutex<Option<T>>>,
+    data: Arc<ScopeData>,
+    _scope: PhantomData<&'scope ()>,
+}
+
+impl<T> ScopedJoinHandle<'_, T> {
+    /// Blocks the current worker until the thread finishes, see [`JoinHandle::join`].
+    /// Panics joined this way aren't reported again by the scope.
+    ///
+    /// # Panics
+    ///
+    /// Panics on threads that can't block, like the main thread.
+    #[track_caller]
+    pub fn join(self) -> std::thread::Result<T> {
+        block_on(self.join_async())
+            .expect("cannot block the main browser thread, use `join_async` instead of `join`")
+    }
+
+    /// Waits for the thread to finish without blocking the current thread.
+    pub async fn join_async(self) -> std::thread::Result<T> {
+        match self.handle.join_async().await {
+            Ok(()) => Ok(self.packet.lock().unwrap().take().unwrap()),
+            Err(payload) => {
+                self.data
+                    .unjoined_panics
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                    .ok();
+                Err(payload)
+            }
+        }
+    }
+
+    pub fn thread(&self) -> &Thread {
+        self.handle.thread()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+}
+
+impl<T> fmt::Debug for ScopedJoinHandle<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScopedJoinHandle")
+            .field("thread", self.thread())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Blocks the current worker for `dur`, see [`time::sleep_blocking`](crate::time::sleep_blocking).
+#[track_caller]
+pub fn sleep(dur: Duration) {
+    crate::time::sleep_blocking(dur);
+}
+
+pub fn yield_now() {
+    std::hint::spin_loop();
+}
+
+/// Blocks the current worker until its [`Thread::unpark`] is called, unless it already
+/// was since the last park. May also return spuriously, like [`std::thread::park`].
+///
+/// Each thread parks with `Atomics.wait` on a slot of its own in the shared memory,
+/// which `unpark` sets and wakes with `Atomics.notify`, so any worker can park, including
+/// the ones not spawned by this module, e.g. to port spinlocks that park once contended.
+///
+/// # Panics
+///
+/// Panics on threads that can't block, like the main thread.
+#[track_caller]
+pub fn park() {
+    park_inner(None);
+}
+
+/// Like [`park`], returning after `dur` at the latest.
+#[track_caller]
+pub fn park_timeout(dur: Duration) {
+    park_inner(Some(dur));
+}
+
+#[track_caller]
+fn park_inner(timeout: Option<Duration>) {
+    let thread = current();
+    let inner = &thread.inner;
+    let seen = inner.parker.seq();
+    if inner.notified.swap(false, Ordering::Acquire) {
+        return;
+    }
+    inner
+        .parker
+        .wait(seen, timeout)
+        .expect("cannot park the main browser thread");
+    inner.notified.store(false, Ordering::Release);
+}
+
+/// The number of threads that can run in parallel, from `navigator.hardwareConcurrency`,
+/// clamped by the runtime's [`max_blocking_workers`](crate::runtime::Builder::max_blocking_workers)
+/// and [`max_workers`](crate::runtime::Builder::max_workers), e.g. to size a pool of
+/// threads.
+pub fn available_parallelism() -> io::Result<NonZeroUsize> {
+    let concurrency = crate::pool::hardware_concurrency().min(runtime::blocking_worker_limit());
+    NonZeroUsize::new(concurrency).ok_or_else(|| {
+        io::Error::other("unknown hardware concurrency, or no worker allowed to run")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_spawn_and_join_from_worker() {
+        let main = current();
+        assert_eq!(main.name(), Some("main"));
+        let outer = task::spawn_blocking(|| {
+            let handle = Builder::new()
+                .name("inner".to_owned())
+                .spawn(|| (current().id(), current().name().map(str::to_owned)))
+                .unwrap();
+            let id = handle.thread().id();
+            let (inner_id, name) = handle.join().unwrap();
+            let panicked = spawn(|| panic!("boom")).join().unwrap_err();
+            (
+                id == inner_id,
+                name,
+                panicked.downcast_ref::<String>().cloned(),
+            )
+        });
+        let (same_id, name, panicked) = outer.join().await.unwrap();
+        assert!(same_id);
+        assert_eq!(name.as_deref(), Some("inner"));
+        assert_eq!(panicked.as_deref(), Some("boom"));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_scope_borrows_from_stack() {
+        let mut data = vec![1, 2, 3, 4];
+        let mut total = 0;
+        let spawned = scope_async(|s| {
+            let (left, right) = data.split_at_mut(2);
+            s.spawn(|| left.iter_mut().for_each(|n| *n *= 10));
+            s.spawn(|| right.iter_mut().for_each(|n| *n *= 10));
+            s.spawn(|| total = 100);
+            3
+        })
+        .await;
+        // Not joined, yet done once the scope is.
+        assert_eq!(spawned, 3);
+        assert_eq!(data, [10, 20, 30, 40]);
+        assert_eq!(total, 100);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_scope_from_worker() {
+        let outer = spawn(|| {
+            let names = ["a", "b"].map(str::to_owned);
+            scope(|s| {
+                let handles = names
+                    .iter()
+                    .map(|name| s.spawn(move || name.len()))
+                    .collect::<Vec<_>>();
+                let panicked = s.spawn(|| panic!("boom"));
+                let total = handles
+                    .into_iter()
+                    .map(|handle| handle.join().unwrap())
+                    .sum::<usize>();
+                (total, panicked.join().is_err())
+            })
+        });
+        assert_eq!(outer.join_async().await.unwrap(), (2, true));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_park_token_and_timeout() {
+        let handle = spawn(|| {
+            // The token set before parking makes the park return right away, once.
+            current().unpark();
+            park();
+            let start = crate::time::Instant::now();
+            park_timeout(Duration::from_millis(50));
+            start.elapsed()
+        });
+        let elapsed = handle.join_async().await.unwrap();
+        assert!(elapsed >= Duration::from_millis(40));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_available_parallelism_follows_limits() {
+        let unlimited = available_parallelism().unwrap().get();
+        let config = runtime::Builder::new()
+            .max_blocking_workers(1)
+            .build_for_test();
+        assert_eq!(available_parallelism().unwrap().get(), 1);
+        drop(config);
+        let _config = runtime::Builder::new()
+            .max_workers(unlimited + 1)
+            .build_for_test();
+        assert_eq!(available_parallelism().unwrap().get(), unlimited);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_unpark_from_main_thread() {
+        let handle = spawn(|| {
+            park();
+            current().id()
+        });
+        crate::time::sleep(Duration::from_millis(20)).await.unwrap();
+        handle.thread().unpark();
+        let id = handle.thread().id();
+        assert_eq!(handle.join_async().await.unwrap(), id);
+        assert_ne!(id, current().id());
+    }
+}
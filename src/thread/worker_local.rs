@@ -0,0 +1,189 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+thread_local! {
+    // The keys initialized on the current thread, in order, to tear them down in reverse.
+    static INITIALIZED: RefCell<Vec<&'static dyn Teardown>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A value of which each worker has its own copy, declared with
+/// [`worker_local!`](crate::worker_local).
+///
+/// Like a [`std::thread::LocalKey`], the value is initialized on the first access from a
+/// worker. Unlike it, the value is also torn down: its teardown hook runs and it is
+/// dropped once the worker is done, before it exits or is replaced, so that e.g.
+/// connections or file handles it holds get closed. Thread-locals are never dropped on
+/// workers, whose memory is freed as a whole. Most workers are done once their task or
+/// closure returned, while the shared async workers and the worker of the FIFO lane are
+/// done once the runtime stops them.
+///
+/// Values are torn down in the reverse order of their initialization. Teardown doesn't
+/// run on workers trapped by a `panic = "abort"` panic or terminated from outside.
+pub struct WorkerLocalKey<T: 'static> {
+    slot: &'static std::thread::LocalKey<RefCell<Option<Rc<T>>>>,
+    init: fn() -> T,
+    teardown: Option<fn(&T)>,
+}
+
+trait Teardown {
+    fn teardown(&self);
+}
+
+impl<T: 'static> WorkerLocalKey<T> {
+    #[doc(hidden)]
+    pub const fn new(
+        slot: &'static std::thread::LocalKey<RefCell<Option<Rc<T>>>>,
+        init: fn() -> T,
+        teardown: Option<fn(&T)>,
+    ) -> Self {
+        WorkerLocalKey {
+            slot,
+            init,
+            teardown,
+        }
+    }
+
+    /// Runs `f` with the current worker's value, initializing it first if needed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the initializer accesses the key being initialized.
+    pub fn with<R>(&'static self, f: impl FnOnce(&T) -> R) -> R {
+        let value = match self.slot.with(|slot| slot.borrow().clone()) {
+            Some(value) => value,
+            None => {
+                let value = Rc::new((self.init)());
+                self.slot.with(|slot| {
+                    let mut slot = slot.borrow_mut();
+                    assert!(slot.is_none(), "worker-local initialized recursively");
+                    *slot = Some(value.clone());
+                });
+                INITIALIZED.with(|keys| keys.borrow_mut().push(self));
+                value
+            }
+        };
+        f(&value)
+    }
+
+    /// Whether the current worker's value is initialized.
+    pub fn is_initialized(&'static self) -> bool {
+        self.slot.with(|slot| slot.borrow().is_some())
+    }
+}
+
+impl<T: 'static> Teardown for WorkerLocalKey<T> {
+    fn teardown(&self) {
+        let Some(value) = self.slot.with(|slot| slot.borrow_mut().take()) else {
+            return;
+        };
+        if let Some(teardown) = self.teardown {
+            teardown(&value);
+        }
+    }
+}
+
+/// Tears down the worker-locals initialized on the current thread, including the ones
+/// initialized by the teardown of others. Accessing a key afterwards initializes it again.
+pub(crate) fn teardown() {
+    while let Some(key) = INITIALIZED.with(|keys| keys.borrow_mut().pop()) {
+        key.teardown();
+    }
+}
+
+/// Declares [`WorkerLocalKey`]s, like [`thread_local!`], optionally with a hook run on
+/// each worker's value before it is dropped.
+///
+/// ```ignore
+/// worker_local! {
+///     static SCRATCH: RefCell<Vec<u8>> = RefCell::new(Vec::with_capacity(1 << 20));
+///     pub static DB: Connection = Connection::open("app.db"), teardown = |db| db.flush();
+/// }
+///
+/// SCRATCH.with(|scratch| scratch.borrow_mut().clear());
+/// ```
+#[macro_export]
+macro_rules! worker_local {
+    () => {};
+    (
+        $(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr,
+        teardown = $teardown:expr; $($rest:tt)*
+    ) => {
+        $crate::worker_local!(@key $(#[$attr])* $vis $name, $t, $init, ::std::option::Option::Some($teardown));
+        $crate::worker_local!($($rest)*);
+    };
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr; $($rest:tt)*) => {
+        $crate::worker_local!(@key $(#[$attr])* $vis $name, $t, $init, ::std::option::Option::None);
+        $crate::worker_local!($($rest)*);
+    };
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr, teardown = $teardown:expr) => {
+        $crate::worker_local!(@key $(#[$attr])* $vis $name, $t, $init, ::std::option::Option::Some($teardown));
+    };
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr) => {
+        $crate::worker_local!(@key $(#[$attr])* $vis $name, $t, $init, ::std::option::Option::None);
+    };
+    (@key $(#[$attr:meta])* $vis:vis $name:ident, $t:ty, $init:expr, $teardown:expr) => {
+        $(#[$attr])* $vis static $name: $crate::thread::WorkerLocalKey<$t> = {
+            ::std::thread_local! {
+                static SLOT: ::std::cell::RefCell<::std::option::Option<::std::rc::Rc<$t>>> =
+                    const { ::std::cell::RefCell::new(::std::option::Option::None) };
+            }
+            fn init() -> $t {
+                $init
+            }
+            let teardown: ::std::option::Option<fn(&$t)> = $teardown;
+            $crate::thread::WorkerLocalKey::new(&SLOT, init, teardown)
+        };
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use crate::task;
+    use crate::time::sleep;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    static INITS: AtomicUsize = AtomicUsize::new(0);
+    static TEARDOWNS: AtomicUsize = AtomicUsize::new(0);
+
+    worker_local! {
+        static COUNTER: Cell<usize> = {
+            INITS.fetch_add(1, Ordering::SeqCst);
+            Cell::new(0)
+        }, teardown = |counter| {
+            TEARDOWNS.fetch_add(counter.get(), Ordering::SeqCst);
+        };
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_worker_locals_are_torn_down() {
+        let handles = (0..3)
+            .map(|i| {
+                task::spawn_blocking(move || {
+                    for _ in 0..=i {
+                        COUNTER.with(|counter| counter.set(counter.get() + 1));
+                    }
+                    COUNTER.with(Cell::get)
+                })
+            })
+            .collect::<Vec<_>>();
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(handle.join().await, Ok(i + 1));
+        }
+        let async_task = task::spawn(async {
+            COUNTER.with(|counter| counter.set(10));
+            COUNTER.is_initialized()
+        });
+        assert_eq!(async_task.await, Ok(true));
+        // Torn down once the closures returned, which is after their handles completed.
+        sleep(Duration::from_millis(50)).await.unwrap();
+        assert_eq!(INITS.load(Ordering::SeqCst), 4);
+        assert_eq!(TEARDOWNS.load(Ordering::SeqCst), 1 + 2 + 3 + 10);
+    }
+}
@@ -1,52 +1,631 @@
+use std::future::Future;
+use std::marker::PhantomData;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+use std::panic::Location;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicI32, AtomicU32, Ordering};
+use std::task::{Context, Poll};
 use std::time::Duration;
 
-use wasm_bindgen::prelude::wasm_bindgen;
+use futures::stream::FusedStream;
+use futures::Stream;
+
+use wasm_bindgen::prelude::{wasm_bindgen, JsValue};
 use wasm_bindgen::JsCast;
-use web_sys::{Window, WorkerGlobalScope};
-
-pub async fn sleep(dur: Duration) {
-    wasm_bindgen_futures::JsFuture::from(js_sys::Promise::new(&mut |resolve, _| {
-        match js_sys::global().dyn_into::<Window>() {
-            Ok(window) => window
-                .set_timeout_with_callback_and_timeout_and_arguments_0(
-                    &resolve,
-                    dur.as_millis() as i32,
-                )
-                .expect("failed to set timeout"),
-            Err(_) => {
-                let worker_scope = js_sys::global().dyn_into::<WorkerGlobalScope>().unwrap();
-                worker_scope
-                    .set_timeout_with_callback_and_timeout_and_arguments_0(
-                        &resolve,
-                        dur.as_millis() as i32,
-                    )
-                    .expect("failed to set timeout")
+use web_sys::Performance;
+
+use crate::runtime;
+use crate::sync::{can_block, BlockingContextError};
+
+#[cfg(feature = "test-util")]
+mod clock;
+#[cfg(feature = "futures-timer")]
+pub mod compat;
+mod driver;
+
+#[cfg(feature = "test-util")]
+pub use clock::{advance, pause, resume, set_clock, Clock, SystemClock};
+
+/// Waits until `dur` has elapsed, or fails with [`Shutdown`] if the runtime shuts down
+/// first.
+#[track_caller]
+pub fn sleep(dur: Duration) -> Sleep {
+    sleep_until(Instant::now() + dur)
+}
+
+/// Waits until `deadline` is reached, completing right away if it already was.
+#[track_caller]
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep::new(deadline, Origin::caller(None))
+}
+
+// Number of times the runtime was shut down. Timers remember it when they start waiting,
+// so that every timer pending at a shutdown fails, while timers started afterwards work as
+// usual.
+static SHUTDOWNS: AtomicU32 = AtomicU32::new(0);
+
+/// Fails the timers that are pending on any thread, and wakes those of the current thread.
+/// The timers of workers are only failed when next polled, which the workers being
+/// terminated along with the runtime usually prevents.
+pub(crate) fn shut_down() {
+    SHUTDOWNS.fetch_add(1, Ordering::SeqCst);
+    driver::wake_all();
+}
+
+/// Future returned by [`sleep`], completing once its deadline is reached.
+///
+/// Sleeps pending when [`runtime::shutdown`](crate::runtime::shutdown) is called complete
+/// right away with [`Shutdown`], rather than waiting for timers that may never fire.
+///
+/// The deadline can be moved with [`reset`](Self::reset), e.g. to debounce events. All
+/// the sleeps of a thread share a single JS timeout, driven by a hierarchical timer wheel,
+/// so that thousands of concurrent timers don't flood the event loop.
+pub struct Sleep {
+    deadline: Instant,
+    key: Option<driver::Key>,
+    // `SHUTDOWNS` when it started waiting.
+    shutdowns: Option<u32>,
+    origin: Origin,
+    // Registered in the timers of the thread that polled it.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Sleep {
+    pub(crate) fn new(deadline: Instant, origin: Origin) -> Sleep {
+        Sleep {
+            deadline,
+            key: None,
+            shutdowns: None,
+            origin,
+            _not_send: PhantomData,
+        }
+    }
+
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    pub fn is_elapsed(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+
+    /// Moves the deadline to `deadline`, even if the previous one was already reached.
+    pub fn reset(&mut self, deadline: Instant) {
+        if let Some(key) = self.key.take() {
+            driver::cancel(key);
+        }
+        self.deadline = deadline;
+        self.shutdowns = None;
+    }
+}
+
+impl Future for Sleep {
+    type Output = Result<(), Shutdown>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let shutdowns = SHUTDOWNS.load(Ordering::SeqCst);
+        let shut_down = self.shutdowns.is_some_and(|started| started != shutdowns);
+        if shut_down || Instant::now() >= self.deadline {
+            if let Some(key) = self.key.take() {
+                driver::cancel(key);
             }
-        };
-    }))
-    .await
-    .expect("failed to sleep");
+            return Poll::Ready(if shut_down { Err(Shutdown) } else { Ok(()) });
+        }
+        self.shutdowns = Some(shutdowns);
+        self.key = Some(driver::register(
+            self.deadline,
+            self.key,
+            cx.waker(),
+            self.origin,
+        ));
+        Poll::Pending
+    }
 }
 
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            driver::cancel(key);
+        }
+    }
+}
+
+impl std::fmt::Debug for Sleep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sleep")
+            .field("deadline", &self.deadline)
+            .finish_non_exhaustive()
+    }
+}
+
+/// What created a timer, reported by [`pending_timers`] in debug builds.
+#[derive(Clone, Copy)]
+#[cfg_attr(not(debug_assertions), allow(dead_code))]
+pub(crate) struct Origin {
+    location: &'static Location<'static>,
+    period: Option<Duration>,
+}
+
+impl Origin {
+    #[track_caller]
+    pub(crate) fn caller(period: Option<Duration>) -> Origin {
+        Origin {
+            location: Location::caller(),
+            period,
+        }
+    }
+}
+
+/// The timers pending on the current thread: [`Sleep`]s, [`Interval`]s and timeouts
+/// waiting for their deadline.
+///
+/// Timers are only registered while being awaited, so an interval that keeps ticking
+/// in a forgotten task shows up here, e.g. when checking that a page doesn't leave
+/// tickers burning CPU in the background after a component is torn down. The list of
+/// timers, with where each of them was created, is only kept in debug builds.
+pub fn pending_timers() -> PendingTimers {
+    driver::pending()
+}
+
+/// Returned by [`pending_timers`].
+#[derive(Clone, Debug, Default)]
+pub struct PendingTimers {
+    pub count: usize,
+    pub earliest: Option<Instant>,
+    /// Every pending timer, earliest first. Empty in release builds.
+    pub timers: Vec<TimerInfo>,
+}
+
+/// A pending timer, listed by [`pending_timers`] in debug builds.
+#[derive(Clone, Copy, Debug)]
+pub struct TimerInfo {
+    pub deadline: Instant,
+    /// Where the sleep, interval or timeout was created.
+    pub location: &'static Location<'static>,
+    /// The period of intervals, `None` for other timers.
+    pub period: Option<Duration>,
+}
+
+impl std::fmt::Display for PendingTimers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} pending timers", self.count)?;
+        if let Some(earliest) = self.earliest {
+            let due = earliest.saturating_duration_since(Instant::now());
+            write!(f, ", the earliest due in {due:?}")?;
+        }
+        writeln!(f)?;
+        for timer in &self.timers {
+            let due = timer.deadline.saturating_duration_since(Instant::now());
+            write!(f, "  timer at {}, due in {due:?}", timer.location)?;
+            if let Some(period) = timer.period {
+                write!(f, ", every {period:?}")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Rejects with a "runtime was shut down" error if the runtime shuts down first.
 #[wasm_bindgen]
-pub async fn sleep_ms(ms: u32) {
-    sleep(Duration::from_millis(ms as u64)).await;
+pub async fn sleep_ms(ms: u32) -> Result<(), JsValue> {
+    Ok(sleep(Duration::from_millis(ms as u64)).await?)
 }
 
+/// Waits until `timestampMs`, a `performance.now()` timestamp of the calling thread.
+#[wasm_bindgen(js_name = sleepUntil)]
+pub async fn sleep_until_ms(timestamp_ms: f64) -> Result<(), JsValue> {
+    Ok(sleep_until(Instant::from_performance_now(timestamp_ms)).await?)
+}
+
+/// Resolves like `promise` if it settles before `timestampMs`, a `performance.now()`
+/// timestamp of the calling thread, and rejects with a "deadline has elapsed" error
+/// otherwise.
+#[wasm_bindgen(js_name = timeoutAt)]
+pub async fn timeout_at_ms(
+    promise: js_sys::Promise,
+    timestamp_ms: f64,
+) -> Result<JsValue, JsValue> {
+    let deadline = Instant::from_performance_now(timestamp_ms);
+    timeout_at(deadline, wasm_bindgen_futures::JsFuture::from(promise)).await?
+}
+
+/// Blocks the current thread for `dur`, which only workers can do.
+///
+/// # Panics
+///
+/// Panics on threads that can't block, like the main thread, when the runtime
+/// [checks blocking](crate::runtime::Builder::check_blocking), as it does in debug builds.
+/// Otherwise the engine throws on `Atomics.wait` and traps the module.
+#[track_caller]
 pub fn sleep_blocking(dur: Duration) {
+    if runtime::CONFIG.lock().unwrap().check_blocking && !can_block() {
+        panic!(
+            "cannot block the main browser thread, use `time::sleep` instead of `sleep_blocking`"
+        );
+    }
+    crate::sync::held::warn_if_held("sleep_blocking");
     std::thread::sleep(dur);
 }
 
+/// Blocks the current thread for `dur` with `Atomics.wait`, whose timeout isn't rounded
+/// to whole milliseconds, e.g. to pace a worker producing audio samples.
+///
+/// Unlike [`sleep_blocking`], returns an error instead of trapping on threads that can't
+/// block, like the main thread. Time is always measured with `performance.now()`, even
+/// while paused with the `test-util` feature.
+#[track_caller]
+pub fn sleep_blocking_precise(dur: Duration) -> Result<(), BlockingContextError> {
+    if !can_block() {
+        return Err(BlockingContextError);
+    }
+    crate::sync::held::warn_if_held("sleep_blocking_precise");
+    // Nothing ever notifies this address, so waits only return on timeout.
+    static PARKED: AtomicI32 = AtomicI32::new(0);
+    let memory = wasm_bindgen::memory().unchecked_into::<js_sys::WebAssembly::Memory>();
+    let array = js_sys::Int32Array::new(&memory.buffer());
+    let index = PARKED.as_ptr() as u32 / 4;
+    let deadline = Instant::performance_now() + dur;
+    loop {
+        let remaining = deadline.checked_duration_since(Instant::performance_now());
+        let Some(remaining) = remaining.filter(|remaining| !remaining.is_zero()) else {
+            return Ok(());
+        };
+        // The timeout is in milliseconds, fractions included.
+        let timeout = remaining.as_nanos() as f64 / 1_000_000.0;
+        js_sys::Atomics::wait_with_timeout(&array, index, 0, timeout)
+            .map_err(|_| BlockingContextError)?;
+    }
+}
+
 #[wasm_bindgen]
 pub fn sleep_blocking_ms(ms: u32) {
     sleep_blocking(Duration::from_millis(ms as u64));
 }
 
+fn performance() -> Performance {
+    js_sys::Reflect::get(&js_sys::global(), &"performance".into())
+        .expect("failed to get performance")
+        .unchecked_into()
+}
+
+/// A point in time measured with `performance.now()`.
+///
+/// Instants are offset by `performance.timeOrigin`, so they can be compared across the
+/// main thread and workers even though each of them has its own time origin.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Instant(f64);
+
+impl Instant {
+    pub fn now() -> Self {
+        #[cfg(feature = "test-util")]
+        if let Some(now) = clock::now() {
+            return now;
+        }
+        Instant::performance_now()
+    }
+
+    // The current time, regardless of the clock set with the `test-util` feature.
+    pub(crate) fn performance_now() -> Self {
+        let performance = performance();
+        Instant(performance.time_origin() + performance.now())
+    }
+
+    /// The instant of `timestamp`, a `performance.now()` value of the current thread, e.g.
+    /// to schedule against an audio clock.
+    pub fn from_performance_now(timestamp: f64) -> Self {
+        Instant(performance().time_origin() + timestamp)
+    }
+
+    /// Time elapsed from `earlier` to `self`, or zero if `earlier` is later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.saturating_duration_since(earlier)
+    }
+
+    /// Time elapsed from `earlier` to `self`, or `None` if `earlier` is later.
+    pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+        let ms = self.0 - earlier.0;
+        (ms >= 0.0).then(|| Duration::from_secs_f64(ms / 1000.0))
+    }
+
+    pub fn saturating_duration_since(&self, earlier: Instant) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or_default()
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    /// `self + duration`, or `None` if the result isn't representable.
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        let ms = self.0 + duration.as_secs_f64() * 1000.0;
+        ms.is_finite().then_some(Instant(ms))
+    }
+
+    /// `self - duration`, or `None` if the result is before the epoch of
+    /// `performance.timeOrigin`.
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        let ms = self.0 - duration.as_secs_f64() * 1000.0;
+        (ms >= 0.0).then_some(Instant(ms))
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        self.checked_add(rhs)
+            .expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, rhs: Duration) -> Instant {
+        self.checked_sub(rhs)
+            .expect("overflow when subtracting duration from instant")
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, rhs: Duration) {
+        *self = *self - rhs;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    /// Saturates to zero if `rhs` is later, like [`Instant::duration_since`].
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+/// Creates an [`Interval`] whose first tick completes after `period`.
+///
+/// # Panics
+///
+/// Panics if `period` is zero.
+#[track_caller]
+pub fn interval(period: Duration) -> Interval {
+    interval_at(Instant::now() + period, period)
+}
+
+/// Creates an [`Interval`] whose first tick completes at `start`.
+///
+/// # Panics
+///
+/// Panics if `period` is zero.
+#[track_caller]
+pub fn interval_at(start: Instant, period: Duration) -> Interval {
+    assert!(period > Duration::ZERO, "`period` must be non-zero.");
+    Interval {
+        period,
+        next: start,
+        missed_tick_behavior: MissedTickBehavior::default(),
+        sleep: None,
+        origin: Origin::caller(Some(period)),
+        terminated: false,
+    }
+}
+
+/// How an [`Interval`] catches up with ticks it missed, e.g. because the thread was busy
+/// or its timers were throttled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MissedTickBehavior {
+    /// Completes the missed ticks immediately, one after the other, then resumes the
+    /// original cadence.
+    Burst,
+    /// Completes one tick immediately and restarts the cadence from there, unless the
+    /// tick was late by less than a period.
+    #[default]
+    Delay,
+    /// Drops the missed ticks and completes the next one on the original cadence.
+    Skip,
+}
+
+/// Ticks at a fixed cadence, tracking absolute deadlines so that the time spent between
+/// two ticks doesn't make the interval drift.
+///
+/// Ticks are awaited with [`tick`](Self::tick), or by using the interval as a `Stream`,
+/// which ends once the runtime shuts down. Missed ticks are handled according to its
+/// [`MissedTickBehavior`].
+pub struct Interval {
+    period: Duration,
+    next: Instant,
+    missed_tick_behavior: MissedTickBehavior,
+    sleep: Option<Sleep>,
+    origin: Origin,
+    // Whether the stream ended, so that it doesn't resume once timers work again.
+    terminated: bool,
+}
+
+impl Interval {
+    /// Waits until the next deadline and returns it, or fails with [`Shutdown`] if the
+    /// runtime shuts down first.
+    pub async fn tick(&mut self) -> Result<Instant, Shutdown> {
+        futures::future::poll_fn(|cx| self.poll_tick(cx)).await
+    }
+
+    /// Polls for the next deadline, returning it once it is reached.
+    pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Result<Instant, Shutdown>> {
+        let deadline = self.next;
+        let now = Instant::now();
+        if deadline > now {
+            let origin = self.origin;
+            let sleep = self
+                .sleep
+                .get_or_insert_with(|| Sleep::new(deadline, origin));
+            match Pin::new(sleep).poll(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(Shutdown)) => {
+                    self.sleep = None;
+                    return Poll::Ready(Err(Shutdown));
+                }
+                Poll::Ready(Ok(())) => {}
+            }
+        }
+        self.sleep = None;
+        let now = Instant::now();
+        self.next = match self.missed_tick_behavior {
+            _ if deadline + self.period > now => deadline + self.period,
+            MissedTickBehavior::Burst => deadline + self.period,
+            MissedTickBehavior::Delay => now + self.period,
+            MissedTickBehavior::Skip => {
+                let missed = (now.duration_since(deadline).as_secs_f64()
+                    / self.period.as_secs_f64())
+                .floor();
+                deadline + self.period.mul_f64(missed + 1.0)
+            }
+        };
+        Poll::Ready(Ok(deadline))
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    pub fn missed_tick_behavior(&self) -> MissedTickBehavior {
+        self.missed_tick_behavior
+    }
+
+    pub fn set_missed_tick_behavior(&mut self, behavior: MissedTickBehavior) {
+        self.missed_tick_behavior = behavior;
+    }
+}
+
+impl Stream for Interval {
+    type Item = Instant;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Instant>> {
+        let this = self.get_mut();
+        if this.terminated {
+            return Poll::Ready(None);
+        }
+        let poll = this.poll_tick(cx).map(Result::ok);
+        this.terminated = matches!(poll, Poll::Ready(None));
+        poll
+    }
+}
+
+impl FusedStream for Interval {
+    fn is_terminated(&self) -> bool {
+        self.terminated
+    }
+}
+
+/// Runs `future` for at most `dur`, failing with [`TimeoutError::Elapsed`] if it doesn't
+/// complete in time, or with [`TimeoutError::Shutdown`] if the runtime shuts down first.
+/// The future is dropped in both cases.
+///
+/// This is [`task::with_deadline`](crate::task::with_deadline) with a deadline `dur` from
+/// now.
+#[track_caller]
+pub fn timeout<F>(dur: Duration, future: F) -> crate::task::WithDeadline<F>
+where
+    F: std::future::Future,
+{
+    crate::task::with_deadline(Instant::now() + dur, future)
+}
+
+/// Runs `future` until `deadline`, failing like [`timeout`] if it doesn't complete in
+/// time. Same as [`task::with_deadline`](crate::task::with_deadline).
+#[track_caller]
+pub fn timeout_at<F>(deadline: Instant, future: F) -> crate::task::WithDeadline<F>
+where
+    F: std::future::Future,
+{
+    crate::task::with_deadline(deadline, future)
+}
+
+/// The deadline of a [`timeout`] elapsed before its future completed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Elapsed;
+
+impl std::fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "deadline has elapsed")
+    }
+}
+
+impl std::error::Error for Elapsed {}
+
+impl From<Elapsed> for JsValue {
+    fn from(err: Elapsed) -> Self {
+        JsValue::from_str(&err.to_string())
+    }
+}
+
+/// Returned by timers that were pending when the runtime was shut down with
+/// [`runtime::shutdown`](crate::runtime::shutdown).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Shutdown;
+
+impl std::fmt::Display for Shutdown {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "runtime was shut down")
+    }
+}
+
+impl std::error::Error for Shutdown {}
+
+impl From<Shutdown> for JsValue {
+    fn from(err: Shutdown) -> Self {
+        JsValue::from_str(&err.to_string())
+    }
+}
+
+/// Returned by [`timeout`], [`timeout_at`] and [`with_deadline`](crate::task::with_deadline)
+/// when the future didn't complete, either in time or before the runtime shut down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeoutError {
+    Elapsed(Elapsed),
+    Shutdown(Shutdown),
+}
+
+impl From<Elapsed> for TimeoutError {
+    fn from(err: Elapsed) -> Self {
+        TimeoutError::Elapsed(err)
+    }
+}
+
+impl From<Shutdown> for TimeoutError {
+    fn from(err: Shutdown) -> Self {
+        TimeoutError::Shutdown(err)
+    }
+}
+
+impl std::fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TimeoutError::Elapsed(err) => write!(f, "{err}"),
+            TimeoutError::Shutdown(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for TimeoutError {}
+
+impl From<TimeoutError> for JsValue {
+    fn from(err: TimeoutError) -> Self {
+        JsValue::from_str(&err.to_string())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::Duration;
 
     use crate::task;
+    use crate::time::Instant;
 
     use super::*;
 
@@ -63,19 +642,230 @@ mod tests {
     #[wasm_bindgen_test]
     async fn test_sleep() {
         let start = PERFORMANCE.now();
-        sleep(Duration::from_millis(100)).await;
+        sleep(Duration::from_millis(100)).await.unwrap();
         let end = PERFORMANCE.now();
         assert!(end - start >= 100.0);
     }
 
+    #[wasm_bindgen_test]
+    async fn test_sleep_reset() {
+        let start = Instant::now();
+        let mut sleep = sleep(Duration::from_millis(1000));
+        futures::FutureExt::now_or_never(&mut sleep);
+        sleep.reset(start + Duration::from_millis(50));
+        (&mut sleep).await.unwrap();
+        assert!(sleep.is_elapsed());
+        assert!(start.elapsed() < Duration::from_millis(1000));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_concurrent_sleeps() {
+        let start = Instant::now();
+        let woken = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let sleeps = (0..1000u64).rev().map(|i| {
+            let woken = woken.clone();
+            async move {
+                sleep(Duration::from_millis(i % 10 * 10)).await.unwrap();
+                woken.borrow_mut().push(i % 10);
+            }
+        });
+        futures::future::join_all(sleeps).await;
+        let woken = woken.borrow();
+        assert_eq!(woken.len(), 1000);
+        assert!(woken.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(start.elapsed() >= Duration::from_millis(90));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_sleep_until() {
+        let deadline = Instant::now() + Duration::from_millis(50);
+        sleep_until(deadline).await.unwrap();
+        assert!(Instant::now() >= deadline);
+        let result = timeout_at(deadline + Duration::from_millis(50), async {
+            sleep_until(deadline + Duration::from_millis(1000)).await
+        })
+        .await;
+        assert_eq!(result, Err(Elapsed.into()));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_deadline_js_api() {
+        let deadline = PERFORMANCE.now() + 50.0;
+        sleep_until_ms(deadline).await.unwrap();
+        assert!(PERFORMANCE.now() >= deadline);
+        let resolved = js_sys::Promise::resolve(&JsValue::from(1));
+        let result = timeout_at_ms(resolved, PERFORMANCE.now() + 50.0).await;
+        assert_eq!(result, Ok(1.into()));
+        let pending = js_sys::Promise::new(&mut |_, _| {});
+        let result = timeout_at_ms(pending, PERFORMANCE.now() + 50.0).await;
+        assert_eq!(result, Err(Elapsed.into()));
+    }
+
     #[wasm_bindgen_test]
     async fn test_sleep_ms() {
         let start = PERFORMANCE.now();
-        sleep_ms(100).await;
+        sleep_ms(100).await.unwrap();
         let end = PERFORMANCE.now();
         assert!(end - start >= 100.0);
     }
 
+    #[wasm_bindgen_test]
+    async fn test_interval() {
+        let start = Instant::now();
+        let mut interval = interval(Duration::from_millis(50));
+        for i in 1..=4 {
+            let deadline = interval.tick().await.unwrap();
+            assert_eq!(deadline, start + Duration::from_millis(50 * i));
+            assert!(Instant::now() >= deadline);
+        }
+        assert!(start.elapsed() < Duration::from_millis(400));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_timeout() {
+        let result = timeout(Duration::from_millis(100), async { 1 }).await;
+        assert_eq!(result, Ok(1));
+        let result = timeout(
+            Duration::from_millis(50),
+            sleep(Duration::from_millis(1000)),
+        )
+        .await;
+        assert_eq!(result, Err(Elapsed.into()));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_pending_timers() {
+        let before = pending_timers().count;
+        let mut ticker = task::spawn_local(async {
+            let mut interval = interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await.unwrap();
+            }
+        });
+        let mut sleeping = task::spawn_local(sleep(Duration::from_secs(30)));
+        sleep(Duration::from_millis(10)).await.unwrap();
+        let pending = pending_timers();
+        assert_eq!(pending.count, before + 2);
+        assert!(pending.earliest.unwrap() <= Instant::now() + Duration::from_secs(30));
+        if cfg!(debug_assertions) {
+            let ticker = pending
+                .timers
+                .iter()
+                .find(|timer| timer.period == Some(Duration::from_secs(60)))
+                .unwrap();
+            assert_eq!(ticker.location.file(), file!());
+            assert!(pending.to_string().contains("every 60s"));
+        }
+        ticker.abort();
+        sleeping.abort();
+        sleep(Duration::from_millis(10)).await.unwrap();
+        assert_eq!(pending_timers().count, before);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_interval_missed_ticks() {
+        use futures::StreamExt;
+
+        for (behavior, expected) in [
+            (MissedTickBehavior::Burst, [0, 50, 100]),
+            (MissedTickBehavior::Skip, [0, 50, 150]),
+        ] {
+            let start = Instant::now() + Duration::from_millis(20);
+            let mut interval = interval_at(start, Duration::from_millis(50));
+            interval.set_missed_tick_behavior(behavior);
+            interval.tick().await.unwrap();
+            // Busy, since the main thread can't block.
+            let busy = Instant::now();
+            while busy.elapsed() < Duration::from_millis(120) {}
+            let deadlines = interval
+                .by_ref()
+                .take(2)
+                .map(|deadline| (deadline.duration_since(start).as_secs_f64() * 1000.0).round())
+                .collect::<Vec<_>>()
+                .await;
+            assert_eq!([0.0, deadlines[0], deadlines[1]], expected.map(f64::from));
+        }
+
+        // Restarts the cadence from the late tick.
+        let start = Instant::now() + Duration::from_millis(20);
+        let mut interval = interval_at(start, Duration::from_millis(50));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        interval.tick().await.unwrap();
+        let busy = Instant::now();
+        while busy.elapsed() < Duration::from_millis(120) {}
+        let late = Instant::now();
+        assert_eq!(
+            interval.tick().await.unwrap(),
+            start + Duration::from_millis(50)
+        );
+        let next = interval.tick().await.unwrap();
+        assert!(next >= late + Duration::from_millis(50));
+        assert!(next < late + Duration::from_millis(70));
+    }
+
+    #[wasm_bindgen_test]
+    #[should_panic(expected = "`period` must be non-zero.")]
+    fn test_interval_zero_period() {
+        interval(Duration::ZERO);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_sleep_blocking_precise() {
+        assert_eq!(
+            sleep_blocking_precise(Duration::from_micros(500)),
+            Err(BlockingContextError)
+        );
+        let handle = task::spawn_blocking(|| {
+            let start = Instant::now();
+            sleep_blocking_precise(Duration::from_micros(500)).unwrap();
+            start.elapsed()
+        });
+        let elapsed = handle.join().await.unwrap();
+        assert!(elapsed >= Duration::from_micros(500));
+        assert!(elapsed < Duration::from_millis(50));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_worker_timers() {
+        let _config = runtime::Builder::new().worker_timers(true).build_for_test();
+        let start = PERFORMANCE.now();
+        sleep(Duration::from_millis(100)).await.unwrap();
+        assert!(PERFORMANCE.now() - start >= 100.0);
+        let mut interval = interval(Duration::from_millis(20));
+        for _ in 0..3 {
+            interval.tick().await.unwrap();
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_instant() {
+        let start = Instant::now();
+        sleep(Duration::from_millis(100)).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(start + Duration::from_millis(100) <= Instant::now());
+        assert_eq!(start.duration_since(Instant::now()), Duration::ZERO);
+        assert_eq!(start.checked_duration_since(Instant::now()), None);
+        let mut later = start;
+        later += Duration::from_millis(100);
+        // Instants are floating-point milliseconds, so arithmetic is only accurate to
+        // about a microsecond.
+        let diff = (later - start).as_secs_f64() * 1000.0;
+        assert!((diff - 100.0).abs() < 0.01);
+        assert!(
+            (later - Duration::from_millis(100)).duration_since(start) < Duration::from_micros(10)
+        );
+        assert_eq!(start.checked_sub(Duration::from_secs(u64::MAX)), None);
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_instant_across_workers() {
+        let start = Instant::now();
+        let handle = task::spawn(async move { Instant::now() });
+        let in_worker = handle.join().await.unwrap();
+        assert!(in_worker >= start);
+        assert!(in_worker.duration_since(start) < Duration::from_secs(10));
+    }
+
     #[wasm_bindgen_test]
     async fn test_sleep_blocking() {
         let handle = task::spawn(async move {
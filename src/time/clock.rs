@@ -0,0 +1,168 @@
+//! Control over the time seen by [`Instant::now`] and the timers of the current thread,
+//! enabled by the `test-util` feature.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use wasm_bindgen::prelude::JsValue;
+use wasm_bindgen_futures::JsFuture;
+
+use super::{driver, Instant};
+
+/// A source of time for [`Instant::now`], installed with [`set_clock`].
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+/// The clock backed by `performance.now()`, used unless another one is installed.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::performance_now()
+    }
+}
+
+enum Source {
+    System,
+    Paused(Instant),
+    Custom(Rc<dyn Clock>),
+}
+
+thread_local! {
+    static SOURCE: RefCell<Source> = const { RefCell::new(Source::System) };
+}
+
+pub(super) fn now() -> Option<Instant> {
+    let clock = SOURCE.with(|source| match &*source.borrow() {
+        Source::System => Err(None),
+        Source::Paused(now) => Err(Some(*now)),
+        Source::Custom(clock) => Ok(clock.clone()),
+    });
+    // Called outside of the borrow, in case the clock reads the time itself.
+    clock.map_or_else(|now| now, |clock| Some(clock.now()))
+}
+
+pub(super) fn is_paused() -> bool {
+    SOURCE.with(|source| matches!(*source.borrow(), Source::Paused(_)))
+}
+
+fn set_source(source: Source) {
+    SOURCE.with(|current| *current.borrow_mut() = source);
+    driver::rearm();
+}
+
+/// Makes [`Instant::now`] return `clock.now()` on the current thread, timers included.
+///
+/// Timers are still driven by JS timeouts, armed for the time left according to `clock`,
+/// so the clock should move forward at the same pace as real time. Use [`pause`] to
+/// control time by hand instead.
+pub fn set_clock(clock: impl Clock + 'static) {
+    set_source(Source::Custom(Rc::new(clock)));
+}
+
+/// Freezes time on the current thread at its current value.
+///
+/// Sleeps, intervals and timeouts of the thread then only make progress when time is
+/// moved forward with [`advance`], so that tests of timing logic run instantly and
+/// deterministically. Other threads keep their own clock.
+pub fn pause() {
+    let now = Instant::now();
+    set_source(Source::Paused(now));
+}
+
+/// Switches the current thread back to [`SystemClock`], undoing [`pause`] and
+/// [`set_clock`].
+pub fn resume() {
+    set_source(Source::System);
+}
+
+/// Moves paused time forward by `dur`, wakes the timers that became due, and lets the
+/// tasks waiting on them run before returning.
+///
+/// # Panics
+///
+/// Panics if time isn't paused on the current thread.
+pub async fn advance(dur: Duration) {
+    SOURCE.with(|source| match &mut *source.borrow_mut() {
+        Source::Paused(now) => *now += dur,
+        _ => panic!("time::advance called while time is not paused"),
+    });
+    driver::wake_due();
+    // Woken local tasks are polled on the microtask queue, before the promise continuation.
+    JsFuture::from(js_sys::Promise::resolve(&JsValue::UNDEFINED))
+        .await
+        .ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::spawn_local;
+    use crate::time::{self, Instant};
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_paused_time() {
+        pause();
+        let start = Instant::now();
+        let sleeping = spawn_local(time::sleep(Duration::from_secs(60)));
+        let mut interval = time::interval(Duration::from_secs(10));
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Burst);
+        let mut timeout = Box::pin(time::timeout(Duration::from_secs(30), async {}));
+        advance(Duration::from_secs(59)).await;
+        assert!(!sleeping.is_finished());
+        advance(Duration::from_secs(1)).await;
+        assert!(sleeping.is_finished());
+        assert!((start.elapsed().as_secs_f64() - 60.0).abs() < 1e-6);
+        // Already completed futures don't depend on time.
+        assert_eq!((&mut timeout).await, Ok(()));
+        let ticks = (0..7)
+            .map(|_| futures::FutureExt::now_or_never(interval.tick()).is_some())
+            .collect::<Vec<_>>();
+        assert_eq!(ticks.iter().filter(|&&tick| tick).count(), 6);
+        resume();
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_paused_time_far_deadlines() {
+        pause();
+        // Spread over the levels of the timer wheel.
+        let deadlines = [1, 60, 3600, 30 * 86_400];
+        let sleeps = deadlines.map(|secs| spawn_local(time::sleep(Duration::from_secs(secs))));
+        let mut elapsed = 0;
+        for (sleeping, secs) in sleeps.iter().zip(deadlines) {
+            advance(Duration::from_secs(secs - elapsed) - Duration::from_millis(1)).await;
+            assert!(!sleeping.is_finished());
+            advance(Duration::from_millis(1)).await;
+            assert!(sleeping.is_finished());
+            elapsed = secs;
+        }
+        resume();
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_custom_clock() {
+        struct Skewed;
+
+        impl Clock for Skewed {
+            fn now(&self) -> Instant {
+                SystemClock.now() + Duration::from_secs(3600)
+            }
+        }
+
+        let before = Instant::now();
+        set_clock(Skewed);
+        assert!(Instant::now().duration_since(before) >= Duration::from_secs(3600));
+        let deadline = Instant::now() + Duration::from_millis(20);
+        time::sleep_until(deadline).await.unwrap();
+        assert!(Instant::now() >= deadline);
+        resume();
+        assert!(Instant::now().duration_since(before) < Duration::from_secs(3600));
+    }
+}
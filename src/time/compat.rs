@@ -0,0 +1,64 @@
+//! A stand-in for the `Delay` of the `futures-timer` crate, backed by [`sleep`], for code
+//! written against that API.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use super::{sleep, Instant, Sleep};
+
+/// A future completing once its duration has elapsed, with the API of
+/// `futures_timer::Delay`.
+///
+/// Unlike the original, it isn't `Send`, since browser timers belong to the thread that
+/// set them. It also completes early when the runtime shuts down.
+pub struct Delay {
+    sleep: Sleep,
+}
+
+impl Delay {
+    #[track_caller]
+    pub fn new(dur: Duration) -> Delay {
+        Delay { sleep: sleep(dur) }
+    }
+
+    /// Restarts the delay so that it completes `dur` from now, even if it already did.
+    pub fn reset(&mut self, dur: Duration) {
+        self.sleep.reset(Instant::now() + dur);
+    }
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        Pin::new(&mut self.sleep).poll(cx).map(|_| ())
+    }
+}
+
+impl std::fmt::Debug for Delay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Delay").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::Instant;
+
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn test_delay_reset() {
+        let start = Instant::now();
+        let mut delay = Delay::new(Duration::from_millis(10));
+        (&mut delay).await;
+        delay.reset(Duration::from_millis(50));
+        delay.await;
+        assert!(start.elapsed() >= Duration::from_millis(60));
+    }
+}
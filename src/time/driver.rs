@@ -0,0 +1,463 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::task::Waker;
+
+use wasm_bindgen::prelude::{Closure, JsValue};
+use wasm_bindgen::JsCast;
+use web_sys::{Window, WorkerGlobalScope};
+
+use super::{Instant, Origin, PendingTimers};
+use crate::runtime;
+
+// The sleeps of a thread are kept in a hierarchical timer wheel, and a single JS timeout
+// is armed for the wheel's next expiring slot. Level 0 has a slot per millisecond, and the
+// slots of every level above span 64 slots of the level below, so that 6 levels cover
+// about two years. A sleep goes into the lowest level whose current span contains its
+// deadline. When a slot of level 0 expires its sleeps are woken, while the sleeps of an
+// expiring slot of a higher level are moved down to the levels matching their deadline.
+// Registering or cancelling a sleep takes the same time however many sleeps are pending.
+
+const LEVELS: usize = 6;
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+// Deadlines further away than this from the wheel's time go into the top level, and are
+// moved down as it turns.
+const MAX_SPAN: u64 = 1 << (SLOT_BITS * LEVELS as u32);
+
+/// Identifies a registered sleep: its deadline in microseconds, rounded up, and a sequence
+/// number telling apart sleeps with the same deadline.
+pub(super) type Key = (u64, u64);
+
+struct Driver {
+    wheel: Wheel,
+    next_seq: u64,
+    // The timeout armed for the next expiring slot, along with the slot's start in
+    // milliseconds.
+    armed: Option<(u64, Timer)>,
+    fire: Closure<dyn FnMut()>,
+}
+
+struct Wheel {
+    // The millisecond up to which the slots were processed.
+    elapsed: u64,
+    entries: HashMap<Key, Entry>,
+    levels: [Level; LEVELS],
+}
+
+struct Level {
+    // Bit `i` is set when slot `i` isn't empty.
+    occupied: u64,
+    slots: [HashSet<Key>; SLOTS],
+}
+
+struct Entry {
+    waker: Waker,
+    // The level and the slot of the wheel holding the sleep.
+    position: (usize, usize),
+    #[cfg(debug_assertions)]
+    info: super::TimerInfo,
+}
+
+thread_local! {
+    static DRIVER: RefCell<Driver> = RefCell::new(Driver {
+        wheel: Wheel::new(),
+        next_seq: 0,
+        armed: None,
+        fire: Closure::new(fire),
+    });
+}
+
+fn micros(instant: Instant) -> u64 {
+    (instant.0 * 1000.0).ceil() as u64
+}
+
+// The current time, in microseconds and in milliseconds, rounded down.
+fn now() -> (u64, u64) {
+    let now = (Instant::now().0 * 1000.0).floor() as u64;
+    (now, now / 1000)
+}
+
+/// Registers a sleep until `deadline`, or updates the waker of the sleep with `key` if it
+/// is still registered, and returns its key.
+pub(super) fn register(
+    deadline: Instant,
+    key: Option<Key>,
+    waker: &Waker,
+    #[cfg_attr(not(debug_assertions), allow(unused_variables))] origin: Origin,
+) -> Key {
+    DRIVER.with(|driver| {
+        let mut driver = driver.borrow_mut();
+        let key = match key {
+            Some(key) if driver.wheel.entries.contains_key(&key) => key,
+            _ => {
+                driver.next_seq += 1;
+                (micros(deadline), driver.next_seq)
+            }
+        };
+        match driver.wheel.entries.get_mut(&key) {
+            Some(registered) if registered.waker.will_wake(waker) => {}
+            Some(registered) => registered.waker.clone_from(waker),
+            None => {
+                driver.wheel.catch_up(now().1);
+                let entry = Entry {
+                    waker: waker.clone(),
+                    position: (0, 0),
+                    #[cfg(debug_assertions)]
+                    info: super::TimerInfo {
+                        deadline,
+                        location: origin.location,
+                        period: origin.period,
+                    },
+                };
+                driver.wheel.insert(key, entry);
+            }
+        }
+        driver.arm();
+        key
+    })
+}
+
+pub(super) fn cancel(key: Key) {
+    DRIVER
+        .try_with(|driver| {
+            let mut driver = driver.borrow_mut();
+            driver.wheel.remove(&key);
+            if driver.wheel.entries.is_empty() {
+                driver.armed = None;
+            }
+        })
+        .ok();
+}
+
+/// Wakes every timer of the thread, e.g. so that they notice the runtime shut down.
+pub(super) fn wake_all() {
+    let entries = DRIVER
+        .try_with(|driver| {
+            let mut driver = driver.borrow_mut();
+            driver.armed = None;
+            driver.wheel.clear()
+        })
+        .unwrap_or_default();
+    for entry in entries.into_values() {
+        entry.waker.wake();
+    }
+}
+
+pub(super) fn pending() -> PendingTimers {
+    DRIVER.with(|driver| {
+        let driver = driver.borrow();
+        let mut entries = driver.wheel.entries.iter().collect::<Vec<_>>();
+        entries.sort_unstable_by_key(|&(key, _)| key);
+        PendingTimers {
+            count: entries.len(),
+            earliest: entries
+                .first()
+                .map(|&(&(deadline, _), _)| Instant(deadline as f64 / 1000.0)),
+            #[cfg(debug_assertions)]
+            timers: entries.into_iter().map(|(_, entry)| entry.info).collect(),
+            #[cfg(not(debug_assertions))]
+            timers: Vec::new(),
+        }
+    })
+}
+
+/// Re-arms the timeout after the clock of the thread was changed.
+#[cfg(feature = "test-util")]
+pub(super) fn rearm() {
+    DRIVER.with(|driver| {
+        let mut driver = driver.borrow_mut();
+        driver.armed = None;
+        // The new clock may be behind the wheel.
+        let entries = driver.wheel.clear();
+        let earliest = entries
+            .keys()
+            .map(|&(deadline, _)| deadline.div_ceil(1000))
+            .min();
+        driver.wheel.elapsed =
+            earliest.map_or(now().1, |earliest| now().1.min(earliest.saturating_sub(1)));
+        for (key, entry) in entries {
+            driver.wheel.insert(key, entry);
+        }
+        driver.arm();
+    });
+}
+
+/// Wakes the timers that are due, without waiting for the timeout.
+#[cfg(feature = "test-util")]
+pub(super) fn wake_due() {
+    fire();
+}
+
+impl Driver {
+    // Arms the timeout for the next expiring slot, unless it already is for that one or an
+    // earlier one.
+    fn arm(&mut self) {
+        // Paused time only moves forward through `time::advance`, which wakes due timers.
+        #[cfg(feature = "test-util")]
+        if super::clock::is_paused() {
+            return;
+        }
+        let Some((.., deadline)) = self.wheel.next_expiration() else {
+            return;
+        };
+        if matches!(self.armed, Some((armed, _)) if armed <= deadline) {
+            return;
+        }
+        let ms = (deadline as f64 - Instant::now().0).max(0.0).ceil();
+        let timer = Timer::new(
+            ms.min(i32::MAX as f64) as i32,
+            self.fire.as_ref().unchecked_ref(),
+        );
+        self.armed = Some((deadline, timer));
+    }
+}
+
+impl Wheel {
+    fn new() -> Wheel {
+        Wheel {
+            elapsed: now().1,
+            entries: HashMap::new(),
+            levels: std::array::from_fn(|_| Level {
+                occupied: 0,
+                slots: std::array::from_fn(|_| HashSet::new()),
+            }),
+        }
+    }
+
+    fn insert(&mut self, key: Key, mut entry: Entry) {
+        // Sleeps already due expire with the next slot.
+        let when = key.0.div_ceil(1000).max(self.elapsed + 1);
+        let level = level_for(self.elapsed, when);
+        let slot = ((when >> (level as u32 * SLOT_BITS)) % SLOTS as u64) as usize;
+        self.levels[level].slots[slot].insert(key);
+        self.levels[level].occupied |= 1 << slot;
+        entry.position = (level, slot);
+        self.entries.insert(key, entry);
+    }
+
+    fn remove(&mut self, key: &Key) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+        let (level, slot) = entry.position;
+        let level = &mut self.levels[level];
+        level.slots[slot].remove(key);
+        if level.slots[slot].is_empty() {
+            level.occupied &= !(1 << slot);
+        }
+        Some(entry)
+    }
+
+    fn clear(&mut self) -> HashMap<Key, Entry> {
+        for level in &mut self.levels {
+            level.occupied = 0;
+            level.slots.iter_mut().for_each(HashSet::clear);
+        }
+        std::mem::take(&mut self.entries)
+    }
+
+    // The level, the slot and the start in milliseconds of the next slot to expire. The
+    // slots of a level all start after those of the levels below.
+    fn next_expiration(&self) -> Option<(usize, usize, u64)> {
+        self.levels.iter().enumerate().find_map(|(index, level)| {
+            if level.occupied == 0 {
+                return None;
+            }
+            let slot_span = 1 << (index as u32 * SLOT_BITS);
+            let level_span = slot_span << SLOT_BITS;
+            let current = ((self.elapsed / slot_span) % SLOTS as u64) as u32;
+            let slot =
+                (level.occupied.rotate_right(current).trailing_zeros() + current) as usize % SLOTS;
+            let mut start = (self.elapsed & !(level_span - 1)) + slot as u64 * slot_span;
+            // Only slots of the top level holding deadlines past its span can start at or
+            // before the wheel's time.
+            if start <= self.elapsed {
+                start += level_span;
+            }
+            Some((index, slot, start))
+        })
+    }
+
+    // Moves the wheel's time to `now` if no slot expires in between.
+    fn catch_up(&mut self, now: u64) {
+        if self.next_expiration().is_none_or(|(.., start)| start > now) {
+            self.elapsed = self.elapsed.max(now);
+        }
+    }
+
+    // Expires the slots starting until `now`, moving the wheel's time to `now`, and
+    // returns the sleeps due at `now_micros`.
+    fn advance(&mut self, now_micros: u64, now: u64) -> Vec<Entry> {
+        let mut due = Vec::new();
+        while let Some((level, slot, start)) =
+            self.next_expiration().filter(|&(.., start)| start <= now)
+        {
+            self.elapsed = start;
+            let keys = std::mem::take(&mut self.levels[level].slots[slot]);
+            self.levels[level].occupied &= !(1 << slot);
+            for key in keys {
+                let entry = self
+                    .entries
+                    .remove(&key)
+                    .expect("sleep in a slot but not registered");
+                if key.0 <= now_micros {
+                    due.push(entry);
+                } else {
+                    self.insert(key, entry);
+                }
+            }
+        }
+        self.elapsed = self.elapsed.max(now);
+        // Deadlines are rounded up to the next millisecond, so the sleeps due within the
+        // current one are in the slot starting with the next.
+        if let Some((level, slot, _)) = self
+            .next_expiration()
+            .filter(|&(.., start)| start == now + 1)
+        {
+            let keys = self.levels[level].slots[slot]
+                .iter()
+                .filter(|key| key.0 <= now_micros)
+                .copied()
+                .collect::<Vec<_>>();
+            due.extend(keys.iter().filter_map(|key| self.remove(key)));
+        }
+        due
+    }
+}
+
+// The level whose current span contains `when`, i.e. the level of the highest bit in
+// which `when` and `elapsed` differ.
+fn level_for(elapsed: u64, when: u64) -> usize {
+    let masked = ((elapsed ^ when) | (SLOTS as u64 - 1)).min(MAX_SPAN - 1);
+    (63 - masked.leading_zeros()) as usize / SLOT_BITS as usize
+}
+
+fn fire() {
+    let due = DRIVER.with(|driver| {
+        let mut driver = driver.borrow_mut();
+        driver.armed = None;
+        let (now_micros, now) = now();
+        let due = driver.wheel.advance(now_micros, now);
+        driver.arm();
+        due
+    });
+    // Woken outside of the borrow, in case a waker polls its task right away.
+    for entry in due {
+        entry.waker.wake();
+    }
+}
+
+// An armed JS timeout, cleared on drop. Clearing a timeout that already fired is a no-op.
+struct Timer {
+    id: JsValue,
+    scope: TimerScope,
+}
+
+#[derive(Clone, Copy)]
+enum TimerScope {
+    Window,
+    Worker,
+    TimerWorker,
+}
+
+impl Timer {
+    fn new(ms: i32, callback: &js_sys::Function) -> Timer {
+        let (id, scope) = match js_sys::global().dyn_into::<Window>() {
+            Ok(_) if runtime::CONFIG.lock().unwrap().worker_timers => {
+                let id = TIMER_WORKER
+                    .with(|schedule| schedule.call2(&JsValue::UNDEFINED, &ms.into(), callback))
+                    .expect("failed to set timeout");
+                (id, TimerScope::TimerWorker)
+            }
+            Ok(window) => {
+                let id = window
+                    .set_timeout_with_callback_and_timeout_and_arguments_0(callback, ms)
+                    .expect("failed to set timeout");
+                (id.into(), TimerScope::Window)
+            }
+            Err(_) => {
+                let worker_scope = js_sys::global().dyn_into::<WorkerGlobalScope>().unwrap();
+                let id = worker_scope
+                    .set_timeout_with_callback_and_timeout_and_arguments_0(callback, ms)
+                    .expect("failed to set timeout");
+                (id.into(), TimerScope::Worker)
+            }
+        };
+        Timer { id, scope }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let id = self.id.as_f64().unwrap_or_default() as i32;
+        match self.scope {
+            TimerScope::Window => {
+                if let Ok(window) = js_sys::global().dyn_into::<Window>() {
+                    window.clear_timeout_with_handle(id);
+                }
+            }
+            TimerScope::Worker => {
+                if let Ok(worker_scope) = js_sys::global().dyn_into::<WorkerGlobalScope>() {
+                    worker_scope.clear_timeout_with_handle(id);
+                }
+            }
+            TimerScope::TimerWorker => {
+                TIMER_WORKER.with(|schedule| {
+                    if let Ok(cancel) = js_sys::Reflect::get(schedule, &"cancel".into()) {
+                        cancel
+                            .unchecked_into::<js_sys::Function>()
+                            .call1(&JsValue::UNDEFINED, &self.id)
+                            .ok();
+                    }
+                });
+            }
+        }
+    }
+}
+
+thread_local! {
+    // `schedule(ms, callback) -> id`, backed by a plain JS worker calling `setTimeout` on
+    // behalf of the main thread, whose timers get clamped to 1s or more while the tab is
+    // hidden. `schedule.cancel(id)` clears the timeout.
+    static TIMER_WORKER: js_sys::Function = js_sys::Function::new_no_args(
+        "
+        const source = `const timers = new Map();
+        self.onmessage = event => {
+            const [id, ms] = event.data;
+            if (ms === null) {
+                clearTimeout(timers.get(id));
+                timers.delete(id);
+            } else {
+                timers.set(id, setTimeout(() => {
+                    timers.delete(id);
+                    self.postMessage(id);
+                }, ms));
+            }
+        };`;
+        const blob = new Blob([source], { type: 'application/javascript' });
+        const worker = new Worker(URL.createObjectURL(blob));
+        const pending = new Map();
+        let nextId = 0;
+        worker.onmessage = event => {
+            const resolve = pending.get(event.data);
+            pending.delete(event.data);
+            if (resolve) {
+                resolve();
+            }
+        };
+        const schedule = (ms, resolve) => {
+            const id = nextId++;
+            pending.set(id, resolve);
+            worker.postMessage([id, ms]);
+            return id;
+        };
+        schedule.cancel = id => {
+            if (pending.delete(id)) {
+                worker.postMessage([id, null]);
+            }
+        };
+        return schedule;
+        ",
+    )
+    .call0(&JsValue::UNDEFINED)
+    .expect("failed to start timer worker")
+    .unchecked_into();
+}
@@ -1,3 +1,7 @@
+use std::mem::ManuallyDrop;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+
 use wasm_bindgen::JsCast;
 use web_sys::WorkerGlobalScope;
 
@@ -5,6 +9,107 @@ pub fn is_worker_scope() -> bool {
     js_sys::global().dyn_into::<WorkerGlobalScope>().is_ok()
 }
 
+/// Whether the current thread is the browser's main thread, the one that can't block and
+/// owns the DOM, rather than a worker.
+pub fn is_main_thread() -> bool {
+    !is_worker_scope()
+}
+
+static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static THREAD_ID: u64 = NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn thread_id() -> u64 {
+    THREAD_ID.with(|id| *id)
+}
+
+/// Wraps a `!Send` value, like a `JsValue` or a DOM handle, so that it can be moved
+/// across tasks and workers, but only used on the thread that created it.
+///
+/// Dereferencing the wrapper on another thread panics. Dropping it on another thread
+/// leaks the value instead, since its destructor can't run there either.
+pub struct ThreadBound<T> {
+    value: ManuallyDrop<T>,
+    thread: u64,
+}
+
+// SAFETY: the value is only ever accessed, and dropped, on the thread that created it.
+unsafe impl<T> Send for ThreadBound<T> {}
+unsafe impl<T> Sync for ThreadBound<T> {}
+
+impl<T> ThreadBound<T> {
+    pub fn new(value: T) -> Self {
+        ThreadBound {
+            value: ManuallyDrop::new(value),
+            thread: thread_id(),
+        }
+    }
+
+    /// Whether the value can be accessed from the current thread.
+    pub fn is_owning_thread(&self) -> bool {
+        self.thread == thread_id()
+    }
+
+    pub fn try_get(&self) -> Option<&T> {
+        self.is_owning_thread().then(|| &*self.value)
+    }
+
+    pub fn try_get_mut(&mut self) -> Option<&mut T> {
+        self.is_owning_thread().then(|| &mut *self.value)
+    }
+
+    /// Unwraps the value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called on another thread than the one that created the wrapper.
+    pub fn into_inner(self) -> T {
+        self.assert_owning_thread();
+        let mut this = ManuallyDrop::new(self);
+        // SAFETY: `this` is never used nor dropped again.
+        unsafe { ManuallyDrop::take(&mut this.value) }
+    }
+
+    #[track_caller]
+    fn assert_owning_thread(&self) {
+        if !self.is_owning_thread() {
+            panic!(
+                "ThreadBound<{}> accessed from another thread than the one that created it",
+                std::any::type_name::<T>()
+            );
+        }
+    }
+}
+
+impl<T> Deref for ThreadBound<T> {
+    type Target = T;
+
+    #[track_caller]
+    fn deref(&self) -> &T {
+        self.assert_owning_thread();
+        &self.value
+    }
+}
+
+impl<T> DerefMut for ThreadBound<T> {
+    #[track_caller]
+    fn deref_mut(&mut self) -> &mut T {
+        self.assert_owning_thread();
+        &mut self.value
+    }
+}
+
+impl<T> Drop for ThreadBound<T> {
+    fn drop(&mut self) {
+        if self.is_owning_thread() {
+            // SAFETY: the value is never accessed again.
+            unsafe { ManuallyDrop::drop(&mut self.value) };
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::task;
@@ -18,11 +123,34 @@ mod tests {
     #[wasm_bindgen_test]
     fn test_is_worker_scope() {
         assert!(!is_worker_scope());
+        assert!(is_main_thread());
         task::spawn(async move {
             assert!(is_worker_scope());
+            assert!(!is_main_thread());
         });
         task::spawn_local(async move {
             assert!(!is_worker_scope());
         });
     }
+
+    #[wasm_bindgen_test]
+    async fn test_thread_bound() {
+        let bound = ThreadBound::new(wasm_bindgen::JsValue::from_str("main"));
+        assert_eq!(*bound, "main");
+        let handle = task::spawn(async move {
+            let owning = bound.is_owning_thread();
+            let accessible = bound.try_get().is_some();
+            (bound, owning, accessible)
+        });
+        let (bound, owning, accessible) = handle.join().await.unwrap();
+        assert!(!owning && !accessible);
+        assert_eq!(bound.into_inner(), "main");
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_thread_bound_wrong_thread_panics() {
+        let bound = ThreadBound::new(wasm_bindgen::JsValue::NULL);
+        let handle = task::spawn(async move { bound.is_null() });
+        assert!(handle.join().await.is_err());
+    }
 }
@@ -1,18 +1,88 @@
+use std::cell::Cell;
 use std::future::Future;
 use std::pin::Pin;
-use wasm_bindgen::prelude::{wasm_bindgen, JsValue};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+
+use wasm_bindgen::prelude::{wasm_bindgen, Closure, JsValue};
+use wasm_bindgen::JsCast;
 use web_sys::{Blob, Url, WorkerOptions};
 
-pub fn spawn_blocking<T>(f: impl FnOnce() -> T + 'static) -> web_sys::Worker
+use crate::runtime;
+use crate::time::Instant;
+
+pub fn spawn_blocking<T>(
+    name: Option<&str>,
+    f: impl FnOnce() -> T + 'static,
+) -> Result<web_sys::Worker, JsValue>
 where
     T: 'static,
 {
+    let spawned_at = Instant::performance_now();
+    let f = move || {
+        runtime::metrics::record_spawn_latency(spawned_at);
+        f()
+    };
+    // Double-boxing because `dyn FnOnce` is unsized and so `Box<dyn FnOnce()>` has
+    // an undefined layout (although I think in practice its a pointer and a length?).
+    let ptr = Box::into_raw(Box::new(Box::new(f) as Box<dyn FnOnce() -> T>));
+    start_blocking(
+        name,
+        "wasm_bindgen.worker_entry_point(ptr, id)",
+        ptr as u32,
+        || std::mem::drop(unsafe { Box::from_raw(ptr) }),
+    )
+}
+
+/// Like [`spawn_blocking`], but the worker first awaits `gate`, then runs `f` with its
+/// output. The worker's event loop is free until `f` is called, so `gate` may wait on
+/// JS promises, e.g. for a Web Lock that the worker then holds while `f` runs.
+pub(crate) fn spawn_blocking_after<G>(
+    name: Option<&str>,
+    gate: impl Future<Output = G> + 'static,
+    f: impl FnOnce(G) + 'static,
+) -> Result<web_sys::Worker, JsValue> {
+    let spawned_at = Instant::performance_now();
+    let future = async move {
+        runtime::metrics::record_spawn_latency(spawned_at);
+        f(gate.await)
+    };
+    let ptr = Box::into_raw(Box::new(
+        Box::pin(future) as Pin<Box<dyn Future<Output = ()>>>
+    ));
+    start_blocking(
+        name,
+        "await wasm_bindgen.async_worker_entry_point(ptr, id)",
+        ptr as u32,
+        || std::mem::drop(unsafe { Box::from_raw(ptr) }),
+    )
+}
+
+// Starts a blocking worker calling `entry` with the boxed work at `ptr`, which `free`
+// deallocates if the worker couldn't be started.
+fn start_blocking(
+    name: Option<&str>,
+    entry: &str,
+    ptr: u32,
+    free: impl FnOnce(),
+) -> Result<web_sys::Worker, JsValue> {
+    let script_path = match script_path() {
+        Ok(script_path) => script_path,
+        Err(err) => {
+            free();
+            return Err(err);
+        }
+    };
     let script = format!(
         "
         import init, * as wasm_bindgen from '{}';
         globalThis.wasm_bindgen = wasm_bindgen;
         self.onmessage = async event => {{
-            const [module, memory, ptr] = event.data;
+            const [module, memory, ptr, forwardConsole, id] = event.data;
+            {report_errors}
+            if (forwardConsole) {{
+                {forward_console}
+            }}
 
             let initialised = await init(module, memory).catch(err => {{
                 // Propagate to main `onerror`:
@@ -23,7 +93,13 @@ where
                 throw err;
             }});
 
-            wasm_bindgen.worker_entry_point(ptr);
+            // Blocking workers can't answer any other message.
+            self.onmessage = null;
+            try {{
+                {entry};
+            }} catch (err) {{
+                {broken_worker}
+            }}
 
             // Clean up thread resources. Depending on what you're doing with the thread, this might
             // not be what you want. (For example, if the thread spawned some javascript tasks
@@ -33,33 +109,33 @@ where
           
             // Free memory (stack, thread-locals) held (in the wasm linear memory) by the thread.
             initialised.__wbindgen_thread_destroy();
+            // Let the spawning thread know this worker is gone.
+            self.postMessage(null);
             // Tell the browser to stop the thread.
             close();
         }};
         ",
-        get_script_path().unwrap()
+        script_path,
+        forward_console = FORWARD_CONSOLE_SCRIPT,
+        report_errors = REPORT_ERRORS_SCRIPT,
+        broken_worker = BROKEN_WORKER_SCRIPT,
     );
-    let blob = Blob::new_with_str_sequence_and_options(
-        &js_sys::Array::of1(&JsValue::from_str(&script)),
-        web_sys::BlobPropertyBag::new().type_("application/javascript"),
-    )
-    .expect("Unable to create blob with JavaScript glue code.");
-    let worker = web_sys::Worker::new_with_options(
-        Url::create_object_url_with_blob(&blob)
-            .expect("failed to create object url")
-            .as_str(),
-        WorkerOptions::new().type_(web_sys::WorkerType::Module),
-    )
-    .expect("failed to create worker");
-    // Double-boxing because `dyn FnOnce` is unsized and so `Box<dyn FnOnce()>` has
-    // an undefined layout (although I think in practice its a pointer and a length?).
-    let ptr = Box::into_raw(Box::new(Box::new(f) as Box<dyn FnOnce() -> T>));
+    let worker = match create(&script, name) {
+        Ok(worker) => worker,
+        Err(err) => {
+            free();
+            return Err(err);
+        }
+    };
+    let forward_console = register(&worker, false);
 
     // See worker script for the format of this message.
     let msg: js_sys::Array = [
         &wasm_bindgen::module(),
         &wasm_bindgen::memory(),
-        &JsValue::from(ptr as u32),
+        &JsValue::from(ptr),
+        &JsValue::from(forward_console),
+        &JsValue::from(id(&worker)),
     ]
     .into_iter()
     .collect();
@@ -67,23 +143,42 @@ where
     if let Err(e) = worker.post_message(&msg) {
         // We expect the worker to deallocate the box, but if there was an error then
         // we'll do it ourselves.
-        std::mem::drop(unsafe { Box::from_raw(ptr) });
-        panic!("failed to post message: {e:?}");
+        free();
+        registry().delete(&worker);
+        worker.terminate();
+        return Err(e);
     }
 
-    worker
+    Ok(worker)
 }
 
-pub fn spawn<F>(future: F) -> web_sys::Worker
+pub fn spawn<F>(name: Option<&str>, future: F) -> Result<web_sys::Worker, JsValue>
 where
     F: Future<Output = ()> + 'static,
 {
+    let script_path = script_path()?;
     let script = format!(
         "
         import init, * as wasm_bindgen from '{}';
         globalThis.wasm_bindgen = wasm_bindgen;
         self.onmessage = async event => {{
-            const [module, memory, ptr] = event.data;
+            const [module, memory, ptr, forwardConsole, id] = event.data;
+            // Answer the watchdog's pings for as long as the event loop isn't blocked, and
+            // keep other messages for the task, see `task::worker_messages`.
+            self.wasmtInbox = [];
+            self.onmessage = event => {{
+                if (event.data === 'wasmt:ping') {{
+                    self.postMessage('wasmt:pong');
+                }} else if (self.wasmtDeliver) {{
+                    self.wasmtDeliver(event.data);
+                }} else {{
+                    self.wasmtInbox.push(event.data);
+                }}
+            }};
+            {report_errors}
+            if (forwardConsole) {{
+                {forward_console}
+            }}
 
             let initialised = await init(module, memory).catch(err => {{
                 // Propagate to main `onerror`:
@@ -94,7 +189,11 @@ where
                 throw err;
             }});
 
-            await wasm_bindgen.async_worker_entry_point(ptr);
+            try {{
+                await wasm_bindgen.async_worker_entry_point(ptr, id);
+            }} catch (err) {{
+                {broken_worker}
+            }}
 
             // Clean up thread resources. Depending on what you're doing with the thread, this might
             // not be what you want. (For example, if the thread spawned some javascript tasks
@@ -104,24 +203,24 @@ where
           
             // Free memory (stack, thread-locals) held (in the wasm linear memory) by the thread.
             initialised.__wbindgen_thread_destroy();
+            // Let the spawning thread know this worker is gone.
+            self.postMessage(null);
             // Tell the browser to stop the thread.
             close();
         }};
         ",
-        get_script_path().unwrap()
+        script_path,
+        forward_console = FORWARD_CONSOLE_SCRIPT,
+        report_errors = REPORT_ERRORS_SCRIPT,
+        broken_worker = BROKEN_WORKER_SCRIPT,
     );
-    let blob = Blob::new_with_str_sequence_and_options(
-        &js_sys::Array::of1(&JsValue::from_str(&script)),
-        web_sys::BlobPropertyBag::new().type_("application/javascript"),
-    )
-    .expect("Unable to create blob with JavaScript glue code.");
-    let worker = web_sys::Worker::new_with_options(
-        Url::create_object_url_with_blob(&blob)
-            .expect("failed to create object url")
-            .as_str(),
-        WorkerOptions::new().type_(web_sys::WorkerType::Module),
-    )
-    .expect("failed to create worker");
+    let worker = create(&script, name)?;
+    let forward_console = register(&worker, true);
+    let spawned_at = Instant::performance_now();
+    let future = async move {
+        runtime::metrics::record_spawn_latency(spawned_at);
+        future.await
+    };
     // Double-boxing because `dyn FnOnce` is unsized and so `Box<dyn FnOnce()>` has
     // an undefined layout (although I think in practice its a pointer and a length?).
     let ptr = Box::into_raw(Box::new(
@@ -133,6 +232,8 @@ where
         &wasm_bindgen::module(),
         &wasm_bindgen::memory(),
         &JsValue::from(ptr as u32),
+        &JsValue::from(forward_console),
+        &JsValue::from(id(&worker)),
     ]
     .into_iter()
     .collect();
@@ -141,10 +242,234 @@ where
         // We expect the worker to deallocate the box, but if there was an error then
         // we'll do it ourselves.
         std::mem::drop(unsafe { Box::from_raw(ptr) });
-        panic!("failed to post message: {e:?}");
+        registry().delete(&worker);
+        worker.terminate();
+        return Err(e);
     }
 
-    worker
+    Ok(worker)
+}
+
+// Named workers are listed under their name in the devtools' thread and debugger panels,
+// and see it as `self.name`.
+fn create(script: &str, name: Option<&str>) -> Result<web_sys::Worker, JsValue> {
+    let blob_options = web_sys::BlobPropertyBag::new();
+    blob_options.set_type("application/javascript");
+    let blob = Blob::new_with_str_sequence_and_options(
+        &js_sys::Array::of1(&JsValue::from_str(script)),
+        &blob_options,
+    )?;
+    let options = WorkerOptions::new();
+    options.set_type(web_sys::WorkerType::Module);
+    if let Some(name) = name {
+        options.set_name(name);
+    }
+    web_sys::Worker::new_with_options(&Url::create_object_url_with_blob(&blob)?, &options)
+}
+
+fn script_path() -> Result<String, JsValue> {
+    get_script_path().ok_or_else(|| JsValue::from_str("failed to find the path of the module"))
+}
+
+// Workers are tracked in a map stored on the global object, keyed by worker and valued
+// with the `WebAssembly.Memory` of the module instance that spawned them. Unlike Rust
+// statics, the map survives re-instantiating the module (e.g. on a hot reload), which
+// lets the new instance find and terminate the workers left behind by the old one.
+const REGISTRY_KEY: &str = "__wasmt_workers";
+
+pub(crate) fn registry() -> js_sys::Map {
+    let global = js_sys::global();
+    let key = JsValue::from_str(REGISTRY_KEY);
+    match js_sys::Reflect::get(&global, &key) {
+        Ok(map) if map.is_instance_of::<js_sys::Map>() => map.unchecked_into(),
+        _ => {
+            let map = js_sys::Map::new();
+            js_sys::Reflect::set(&global, &key, &map).expect("failed to set worker registry");
+            map
+        }
+    }
+}
+
+// Property set on workers that answer pings, i.e. the ones running async tasks.
+pub(crate) const WATCHED_KEY: &str = "wasmtWatched";
+// Property counting the pings a worker didn't answer yet.
+pub(crate) const MISSED_PINGS_KEY: &str = "wasmtMissedPings";
+// Property holding the id of the worker, unique across threads.
+const ID_KEY: &str = "wasmtId";
+
+// Installed in workers when console forwarding is enabled: every `console.*` call is
+// posted to the spawning thread, which logs it with the worker's prefix. Arguments that
+// can't be cloned are sent as strings. Nested workers forward through their parent's
+// patched console, so everything ends up on the main thread.
+const FORWARD_CONSOLE_SCRIPT: &str = "
+                for (const level of ['log', 'info', 'warn', 'error', 'debug', 'trace']) {
+                    console[level] = (...args) => {
+                        try {
+                            self.postMessage({ wasmtConsole: level, args });
+                        } catch {
+                            self.postMessage({ wasmtConsole: level, args: args.map(String) });
+                        }
+                    };
+                }
+";
+
+// Installed in every worker: errors nobody caught and rejections nobody handled are
+// posted to the spawning thread, for the handler set with `runtime::set_error_handler`.
+const REPORT_ERRORS_SCRIPT: &str = "
+            self.addEventListener('error', event => {
+                self.postMessage({ wasmtError: {
+                    kind: 'error',
+                    message: event.message,
+                    filename: event.filename,
+                    lineno: event.lineno,
+                    colno: event.colno,
+                    stack: event.error && event.error.stack,
+                } });
+            });
+            self.addEventListener('unhandledrejection', event => {
+                const reason = event.reason;
+                self.postMessage({ wasmtError: {
+                    kind: 'unhandledrejection',
+                    message: reason instanceof Error ? reason.message : String(reason),
+                    stack: reason && reason.stack,
+                } });
+            });
+";
+
+// Run when the entry point throws, i.e. when the task panicked and trapped the wasm
+// instance of the worker. Its thread state can't be trusted anymore, so it isn't freed:
+// the worker is closed right away, leaking its stack and thread-locals.
+const BROKEN_WORKER_SCRIPT: &str = "
+                self.postMessage(null);
+                close();
+                return;
+";
+
+thread_local! {
+    static REPORT_ERROR: Closure<dyn Fn(u32, JsValue)> =
+        Closure::new(|worker, info: JsValue| runtime::report_worker_error(worker, &info));
+}
+
+static NEXT_WORKER_ID: AtomicU32 = AtomicU32::new(0);
+
+// Returns whether the worker should forward its console.
+fn register(worker: &web_sys::Worker, watched: bool) -> bool {
+    let id = NEXT_WORKER_ID.fetch_add(1, Ordering::Relaxed);
+    let registry = registry();
+    registry.set(worker, &wasm_bindgen::memory());
+    js_sys::Reflect::set(worker, &WATCHED_KEY.into(), &watched.into()).ok();
+    js_sys::Reflect::set(worker, &ID_KEY.into(), &id.into()).ok();
+    // Plain JS rather than a Rust closure, so that nothing is leaked if the worker never
+    // finishes.
+    let on_message = js_sys::Function::new_with_args(
+        "registry, prefix, reportError",
+        &format!(
+            "
+            return function(event) {{
+                if (event.data === 'wasmt:pong') {{
+                    this.{MISSED_PINGS_KEY} = 0;
+                }} else if (event.data && event.data.wasmtConsole) {{
+                    console[event.data.wasmtConsole](prefix, ...event.data.args);
+                }} else if (event.data && event.data.wasmtError) {{
+                    reportError(this.{ID_KEY}, event.data.wasmtError);
+                }} else if (event.data === null) {{
+                    registry.delete(this);
+                }}
+            }};
+            "
+        ),
+    )
+    .call3(
+        &JsValue::UNDEFINED,
+        &registry,
+        &JsValue::from_str(&format!("[wasmt worker {id}]")),
+        &REPORT_ERROR.with(|report| report.as_ref().clone()),
+    )
+    .expect("failed to create worker message handler");
+    worker.set_onmessage(Some(on_message.unchecked_ref()));
+    runtime::CONFIG.lock().unwrap().forward_console
+}
+
+pub(crate) fn id(worker: &web_sys::Worker) -> u32 {
+    js_sys::Reflect::get(worker, &ID_KEY.into())
+        .ok()
+        .and_then(|id| id.as_f64())
+        .expect("worker isn't registered") as u32
+}
+
+/// Terminates the worker with the given id, if it was spawned from the current thread and
+/// is still alive.
+pub(crate) fn terminate(id: u32) -> bool {
+    let registry = registry();
+    let Some(worker) = find(&registry, id) else {
+        return false;
+    };
+    kill(&registry, worker);
+    true
+}
+
+/// Removes `worker` from the registry and terminates it, running its
+/// [`on_terminate`] hooks.
+pub(crate) fn kill(registry: &js_sys::Map, worker: JsValue) {
+    // Workers of previous instances have ids of their own.
+    let owned = registry.get(&worker) == wasm_bindgen::memory();
+    registry.delete(&worker);
+    let id = js_sys::Reflect::get(&worker, &ID_KEY.into())
+        .ok()
+        .and_then(|id| id.as_f64());
+    worker.unchecked_into::<web_sys::Worker>().terminate();
+    if let Some(id) = id.filter(|_| owned) {
+        let hooks = {
+            let mut hooks = ON_TERMINATE.lock().unwrap();
+            let (run, keep) = hooks
+                .drain(..)
+                .partition(|(hooked, _)| *hooked == id as u32);
+            *hooks = keep;
+            run
+        };
+        for (_, hook) in hooks {
+            hook();
+        }
+    }
+}
+
+type Hook = Box<dyn FnOnce() + Send>;
+
+// The hooks set with `on_terminate`, with the id of their worker, which is unique across
+// the threads of the instance.
+static ON_TERMINATE: Mutex<Vec<(u32, Hook)>> = Mutex::new(Vec::new());
+
+/// Runs `f` if the worker with `id` is terminated, when nothing running on the worker
+/// gets to drop. Hooks of workers that exit on their own stay until
+/// [`forget_on_terminate`].
+pub(crate) fn on_terminate(id: u32, f: impl FnOnce() + Send + 'static) {
+    ON_TERMINATE.lock().unwrap().push((id, Box::new(f)));
+}
+
+pub(crate) fn forget_on_terminate(id: u32) {
+    ON_TERMINATE
+        .lock()
+        .unwrap()
+        .retain(|(hooked, _)| *hooked != id);
+}
+
+/// Whether the worker with `id`, spawned from the current thread, is still running.
+pub(crate) fn is_alive(id: u32) -> bool {
+    find(&registry(), id).is_some()
+}
+
+// Only finds workers of the current instance of the module, since the ids of the workers
+// of previous instances may be reused.
+fn find(registry: &js_sys::Map, id: u32) -> Option<JsValue> {
+    let memory = wasm_bindgen::memory();
+    let mut found = None;
+    registry.for_each(&mut |owner, worker| {
+        if owner == memory && js_sys::Reflect::get(&worker, &ID_KEY.into()).ok() == Some(id.into())
+        {
+            found = Some(worker);
+        }
+    });
+    found
 }
 
 fn get_script_path() -> Option<String> {
@@ -164,16 +489,31 @@ fn get_script_path() -> Option<String> {
     .as_string()
 }
 
+thread_local! {
+    // The id of the current worker, set by the entry points.
+    static CURRENT_ID: Cell<Option<u32>> = const { Cell::new(None) };
+}
+
+/// The id of the current worker, or `None` on threads not spawned by this crate, like
+/// the main thread.
+pub(crate) fn current_id() -> Option<u32> {
+    CURRENT_ID.with(Cell::get)
+}
+
 #[wasm_bindgen]
-pub fn worker_entry_point(ptr: u32) {
+pub fn worker_entry_point(ptr: u32, id: u32) {
+    CURRENT_ID.with(|current| current.set(Some(id)));
     let work = unsafe { Box::from_raw(ptr as *mut Box<dyn FnOnce()>) };
     (*work)();
+    crate::thread::teardown_worker_locals();
 }
 
 #[wasm_bindgen]
-pub async fn async_worker_entry_point(ptr: u32) {
+pub async fn async_worker_entry_point(ptr: u32, id: u32) {
+    CURRENT_ID.with(|current| current.set(Some(id)));
     let work = unsafe { Box::from_raw(ptr as *mut Pin<Box<dyn Future<Output = ()>>>) };
     (*work).await;
+    crate::thread::teardown_worker_locals();
 }
 
 #[cfg(test)]
@@ -187,9 +527,10 @@ mod tests {
 
     #[wasm_bindgen_test]
     fn test_spawn() {
-        let worker = spawn(async {
+        let worker = spawn(None, async {
             assert!(js_sys::global().dyn_into::<WorkerGlobalScope>().is_ok());
-        });
+        })
+        .unwrap();
 
         assert!(worker.is_object());
         assert!(worker.to_string().as_string().unwrap().contains("Worker"));
@@ -199,9 +540,10 @@ mod tests {
 
     #[wasm_bindgen_test]
     fn test_spawn_blocking() {
-        let worker = spawn_blocking(|| {
+        let worker = spawn_blocking(None, || {
             assert!(js_sys::global().dyn_into::<WorkerGlobalScope>().is_ok());
-        });
+        })
+        .unwrap();
 
         assert!(worker.is_object());
         assert!(worker.to_string().as_string().unwrap().contains("Worker"));
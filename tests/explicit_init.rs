@@ -0,0 +1,32 @@
+//! Spawns before and after `runtime::init`, in their own instance of the module since the
+//! runtime can't be uninitialized.
+#![cfg(feature = "explicit-init")]
+
+use wasmt::runtime;
+use wasmt::task::{self, SpawnError};
+
+use wasm_bindgen_test::*;
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+#[wasm_bindgen_test]
+async fn test_try_spawn_before_init() {
+    assert!(!runtime::is_initialized());
+    assert!(matches!(
+        task::try_spawn(async { 1 }),
+        Err(SpawnError::NotInitialized(runtime::NotInitialized))
+    ));
+    assert!(matches!(
+        task::try_spawn_blocking(|| 1),
+        Err(SpawnError::NotInitialized(runtime::NotInitialized))
+    ));
+    assert!(!runtime::is_initialized());
+
+    runtime::init(runtime::Builder::new()).unwrap();
+    assert_eq!(task::try_spawn(async { 1 }).unwrap().await, Ok(1));
+    assert_eq!(task::try_spawn_blocking(|| 2).unwrap().await, Ok(2));
+    assert_eq!(
+        runtime::init(runtime::Builder::new()),
+        Err(runtime::AlreadyInitialized)
+    );
+}
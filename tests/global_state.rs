@@ -0,0 +1,127 @@
+//! Tests leaving the runtime in a state the unit tests can't run in, e.g. shut down, so
+//! that they run in their own instance of the module.
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use wasmt::runtime::{self, live_workers, shutdown};
+use wasmt::task::{self, JoinError};
+use wasmt::time::{interval, sleep, sleep_blocking, timeout, Instant, Shutdown};
+
+use wasm_bindgen_test::*;
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+#[wasm_bindgen_test]
+async fn test_shutdown() {
+    let handle = task::spawn(async move {
+        sleep_blocking(Duration::from_millis(1000));
+    });
+    assert!(live_workers() >= 1);
+    shutdown();
+    assert_eq!(live_workers(), 0);
+    assert!(!handle.is_finished());
+}
+
+#[wasm_bindgen_test]
+async fn test_shutdown_ends_scoped_closures() {
+    let data = vec![1u8; 1024];
+    let started = Arc::new(AtomicBool::new(false));
+    let scoped = |started: Arc<AtomicBool>| {
+        // SAFETY: the futures are awaited, or dropped once their worker is terminated.
+        unsafe {
+            task::spawn_blocking_scoped(&data, move |data| {
+                started.store(true, Ordering::SeqCst);
+                sleep_blocking(Duration::from_secs(10));
+                data.len()
+            })
+        }
+    };
+    let wait_started = |started: Arc<AtomicBool>| async move {
+        while !started.swap(false, Ordering::SeqCst) {
+            sleep(Duration::from_millis(10)).await.unwrap();
+        }
+    };
+
+    // Resolves once the worker running the closure is terminated.
+    let mut awaited = Box::pin(scoped(started.clone()));
+    assert!(futures::poll!(awaited.as_mut()).is_pending());
+    wait_started(started.clone()).await;
+    let start = Instant::now();
+    shutdown();
+    assert_eq!(awaited.await, Err(JoinError::Aborted));
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    // Can be dropped on the main thread once the worker is terminated.
+    let mut dropped = Box::pin(scoped(started.clone()));
+    assert!(futures::poll!(dropped.as_mut()).is_pending());
+    wait_started(started).await;
+    shutdown();
+    drop(dropped);
+}
+
+#[wasm_bindgen_test]
+async fn test_shutdown_fails_pending_timers() {
+    struct Guard(Rc<Cell<bool>>);
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            self.0.set(true);
+        }
+    }
+
+    let dropped = Rc::new(Cell::new(false));
+    let sleeping = task::spawn_local(sleep(Duration::from_secs(60)));
+    let ticking = task::spawn_local(async {
+        let mut interval = interval(Duration::from_secs(60));
+        interval.tick().await
+    });
+    let guard = Guard(dropped.clone());
+    let sleeping_with_timeout = task::spawn_local(timeout(Duration::from_secs(30), async {
+        let _guard = guard;
+        sleep(Duration::from_secs(60)).await
+    }));
+    let pending_with_timeout = task::spawn_local(timeout(
+        Duration::from_secs(30),
+        futures::future::pending::<()>(),
+    ));
+    sleep(Duration::from_millis(10)).await.unwrap();
+    shutdown();
+    assert_eq!(sleeping.await.unwrap(), Err(Shutdown));
+    assert_eq!(ticking.await.unwrap(), Err(Shutdown));
+    assert_eq!(sleeping_with_timeout.await.unwrap(), Ok(Err(Shutdown)));
+    assert!(dropped.get());
+    assert_eq!(pending_with_timeout.await.unwrap(), Err(Shutdown.into()));
+    // Timers started after the shutdown work as usual.
+    let start = Instant::now();
+    sleep(Duration::from_millis(20)).await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(20));
+}
+
+#[wasm_bindgen_test]
+async fn test_shutdown_ends_interval_streams() {
+    use futures::stream::FusedStream;
+    use futures::{FutureExt, StreamExt};
+
+    let ticking = task::spawn_local(async {
+        let mut interval = interval(Duration::from_secs(60));
+        assert_eq!(interval.next().await, None);
+        assert!(interval.is_terminated());
+        // Stays ended although timers work again.
+        interval.next().now_or_never()
+    });
+    sleep(Duration::from_millis(10)).await.unwrap();
+    shutdown();
+    assert_eq!(ticking.await.unwrap(), Some(None));
+}
+
+// Panics before it could restore the configuration.
+#[wasm_bindgen_test]
+#[should_panic(expected = "cannot block the main browser thread")]
+fn test_sleep_blocking_main_thread() {
+    runtime::Builder::new().check_blocking(true).build();
+    sleep_blocking(Duration::ZERO);
+}